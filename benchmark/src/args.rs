@@ -7,12 +7,12 @@ use clap::{Parser, Subcommand};
 #[clap(author, version, about, long_about = None)]
 /// Args of Benchmark
 pub struct Benchmark {
-    /// The address of the server
-    #[clap(long, required = true, multiple = true)]
-    pub endpoints: Vec<SocketAddr>,
-    /// Index of leader serer
+    /// Ids and addresses of the servers. eg: 1=192.168.x.x:8080 2=192.168.x.x:8080
+    #[clap(long, required = true, multiple = true, parse(try_from_str = parse_endpoint))]
+    pub endpoints: Vec<(u64, SocketAddr)>,
+    /// Id of the leader server
     #[clap(long, required = true)]
-    pub leader_index: usize,
+    pub leader_id: u64,
     /// Clients number
     #[clap(long, required = true)]
     pub clients: usize,
@@ -49,3 +49,16 @@ pub enum Commands {
         sequential_keys: bool,
     },
 }
+
+/// Parse an endpoint in the format of `<id>=<ip:port>`
+fn parse_endpoint(s: &str) -> Result<(u64, SocketAddr), String> {
+    let (id, addr) = s
+        .split_once('=')
+        .ok_or_else(|| format!("invalid endpoint: {s}, expected <id>=<ip:port>"))?;
+    Ok((
+        id.parse()
+            .map_err(|e| format!("invalid id in endpoint {s}: {e}"))?,
+        addr.parse()
+            .map_err(|e| format!("invalid address in endpoint {s}: {e}"))?,
+    ))
+}
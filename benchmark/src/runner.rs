@@ -167,8 +167,8 @@ impl CommandRunner {
         let mut clients = Vec::with_capacity(self.args.clients);
         for _ in 0..self.args.clients {
             let client = Client::new(
-                self.args.leader_index,
-                self.args.endpoints.clone(),
+                self.args.leader_id,
+                self.args.endpoints.iter().copied().collect(),
                 self.args.use_curp,
             )
             .await?;
@@ -0,0 +1,104 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::sync::{mpsc, oneshot};
+use tracing::debug;
+
+use crate::{
+    client::BatchConfig,
+    error::ProposeError,
+    rpc::{Connect, ProposeRequest, ProposeResponse},
+};
+
+/// A proposal waiting to be batched, and the sender of its response
+type Pending = (
+    Vec<u8>,
+    oneshot::Sender<Result<ProposeResponse, ProposeError>>,
+);
+
+/// Coalesces the proposals to one server issued within a small window into a batched propose
+#[derive(Debug)]
+pub(crate) struct ProposeBatcher {
+    /// Send proposals to the batching task
+    tx: mpsc::UnboundedSender<Pending>,
+}
+
+impl ProposeBatcher {
+    /// Create a new batcher and spawn its batching task, it must be called in a tokio runtime
+    pub(crate) fn new(connect: Arc<Connect>, config: BatchConfig, timeout: Duration) -> Self {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let _handle = tokio::spawn(Self::run(connect, config, timeout, rx));
+        Self { tx }
+    }
+
+    /// Collect the proposals issued within the window, and send them in one batch
+    async fn run(
+        connect: Arc<Connect>,
+        config: BatchConfig,
+        timeout: Duration,
+        mut rx: mpsc::UnboundedReceiver<Pending>,
+    ) {
+        while let Some(first) = rx.recv().await {
+            tokio::time::sleep(config.window).await;
+            let mut batch = vec![first];
+            while batch.len() < config.max_size {
+                match rx.try_recv() {
+                    Ok(pending) => batch.push(pending),
+                    Err(_empty) => break,
+                }
+            }
+            let _handle = tokio::spawn(Self::send_batch(Arc::clone(&connect), batch, timeout));
+        }
+    }
+
+    /// Send the proposals in one batch, and fan the responses out to the callers
+    async fn send_batch(connect: Arc<Connect>, batch: Vec<Pending>, timeout: Duration) {
+        let (cmds, waiters): (Vec<_>, Vec<_>) = batch.into_iter().unzip();
+        let mut cmds = cmds.into_iter();
+        let first = match cmds.next() {
+            Some(first) => first,
+            None => return,
+        };
+        let req = ProposeRequest::new_batch(first, cmds.clone().collect());
+        let (resp, batch_resps) = match connect.propose_multiplexed(req, timeout).await {
+            Ok(resp) => resp.split_batch(),
+            Err(e) => {
+                for waiter in waiters {
+                    let _ignore = waiter.send(Err(e.clone()));
+                }
+                return;
+            }
+        };
+
+        let mut waiters = waiters.into_iter();
+        if let Some(waiter) = waiters.next() {
+            let _ignore = waiter.send(Ok(resp));
+        }
+        let mut batch_resps = batch_resps.into_iter();
+        for (cmd, waiter) in cmds.zip(waiters) {
+            if let Some(resp) = batch_resps.next() {
+                let _ignore = waiter.send(Ok(resp));
+            } else {
+                // the server doesn't support batching, propose the command on its own
+                debug!("server {} ignored the batched commands", connect.id);
+                let connect = Arc::clone(&connect);
+                let _handle = tokio::spawn(async move {
+                    let req = ProposeRequest::new_batch(cmd, vec![]);
+                    let _ignore = waiter.send(connect.propose_multiplexed(req, timeout).await);
+                });
+            }
+        }
+    }
+
+    /// Propose a command in the next batch
+    pub(crate) async fn propose(
+        &self,
+        request: ProposeRequest,
+    ) -> Result<ProposeResponse, ProposeError> {
+        let (tx, rx) = oneshot::channel();
+        self.tx
+            .send((request.command, tx))
+            .map_err(|_closed| ProposeError::RpcError("propose batcher is closed".to_owned()))?;
+        rx.await
+            .map_err(|_closed| ProposeError::RpcError("propose batcher is closed".to_owned()))?
+    }
+}
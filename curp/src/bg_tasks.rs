@@ -1,30 +1,43 @@
-use std::{iter, ops::Range, sync::Arc, time::Duration};
+use std::{collections::HashSet, iter, ops::Range, sync::Arc, time::Duration};
 
-use clippy_utilities::NumericCast;
-use futures::future::Either;
+use clippy_utilities::{NumericCast, OverflowArithmetic};
+use futures::{
+    future::{self, Either},
+    pin_mut,
+};
 use madsim::rand::{thread_rng, Rng};
 use parking_lot::{lock_api::RwLockUpgradableReadGuard, Mutex, RwLock};
-use tokio::{sync::mpsc, time::Instant};
+use tokio::{
+    sync::{mpsc, oneshot},
+    task::JoinHandle,
+    time::Instant,
+};
 use tracing::{debug, error, info, warn};
 
 use crate::{
     channel::{key_mpsc::MpscKeyBasedReceiver, key_spmc, RecvError},
-    cmd::{Command, CommandExecutor},
+    cmd::{Command, CommandExecutor, ProposeId},
     cmd_board::{CmdState, CommandBoard},
-    cmd_execute_worker::{execute_worker, CmdExecuteSender, ExecuteMessage, N_EXECUTE_WORKERS},
+    cmd_execute_worker::{
+        execute_worker, AfterSyncResult, CmdExecuteSender, ExecuteMessage, N_EXECUTE_WORKERS,
+    },
+    codec::WireFormat,
+    group::{GroupTransport, Heartbeat, HeartbeatSource},
     log::LogEntry,
     message::TermNum,
-    rpc::{self, AppendEntriesRequest, Connect, VoteRequest, WaitSyncedResponse},
+    rpc::{
+        AppendEntriesRequest, AppendEntriesResponse, Connect, TimeoutNowRequest, VoteRequest,
+        WaitSyncedResponse,
+    },
     server::{ServerRole, SpeculativePool, State},
     shutdown::Shutdown,
     util::RwLockMap,
-    LogIndex,
 };
 
 /// Run background tasks
 #[allow(clippy::too_many_arguments)] // we call this function once, it's ok
 pub(crate) async fn run_bg_tasks<C: Command + 'static, CE: 'static + CommandExecutor<C>>(
-    state: Arc<RwLock<State<C>>>,
+    state: Arc<State<C>>,
     last_rpc_time: Arc<RwLock<Instant>>,
     sync_chan: MpscKeyBasedReceiver<C::K, SyncMessage<C>>,
     cmd_executor: CE,
@@ -32,12 +45,10 @@ pub(crate) async fn run_bg_tasks<C: Command + 'static, CE: 'static + CommandExec
     cmd_exe_tx: CmdExecuteSender<C>,
     cmd_exe_rx: mpsc::UnboundedReceiver<ExecuteMessage<C>>,
     cmd_board: Arc<Mutex<CommandBoard>>,
+    connects: Vec<Arc<Connect>>,
+    transport: Option<Arc<GroupTransport>>,
     mut shutdown: Shutdown,
 ) {
-    let others = state.read().others.clone();
-    // establish connection with other servers
-    let connects = rpc::try_connect(others.into_iter().collect()).await;
-
     // notify when a broadcast of append_entries is needed immediately
     let (ae_trigger, ae_trigger_rx) = tokio::sync::mpsc::unbounded_channel::<usize>();
 
@@ -50,9 +61,15 @@ pub(crate) async fn run_bg_tasks<C: Command + 'static, CE: 'static + CommandExec
         connects.clone(),
         Arc::clone(&state),
         Arc::clone(&last_rpc_time),
+        Arc::clone(&spec),
+        ae_trigger.clone(),
     ));
     let bg_apply_handle = tokio::spawn(bg_apply(Arc::clone(&state), cmd_exe_tx, spec, cmd_board));
-    let bg_heartbeat_handle = tokio::spawn(bg_heartbeat(connects.clone(), Arc::clone(&state)));
+    let bg_heartbeat_handle = tokio::spawn(bg_heartbeat(
+        connects.clone(),
+        Arc::clone(&state),
+        transport,
+    ));
     let bg_get_sync_cmds_handle =
         tokio::spawn(bg_get_sync_cmds(Arc::clone(&state), sync_chan, ae_trigger));
     let calibrate_handle = tokio::spawn(leader_calibrates_followers(connects, state));
@@ -97,7 +114,7 @@ where
 
 /// Fetch commands need to be synced and add them to the log
 async fn bg_get_sync_cmds<C: Command + 'static>(
-    state: Arc<RwLock<State<C>>>,
+    state: Arc<State<C>>,
     mut sync_chan: MpscKeyBasedReceiver<C::K, SyncMessage<C>>,
     ae_trigger: mpsc::UnboundedSender<usize>,
 ) {
@@ -107,16 +124,27 @@ async fn bg_get_sync_cmds<C: Command + 'static>(
             Err(_) => return,
         };
 
+        // the entry is hashed before the log is locked
+        let entry = LogEntry::new(term, &cmds);
+        let crc = match entry.crc() {
+            Ok(crc) => crc,
+            Err(e) => {
+                error!("unable to append log entry: {}", e);
+                continue;
+            }
+        };
+
         #[allow(clippy::shadow_unrelated)] // clippy false positive
-        state.map_write(|mut state| {
-            state.log.push(LogEntry::new(term, &cmds));
-            if let Err(e) = ae_trigger.send(state.last_log_index()) {
+        state.log.map_write(|mut log| {
+            log.push(entry, crc);
+            let _seq = state.persist_entries(&log, log.last_log_index());
+            if let Err(e) = ae_trigger.send(log.last_log_index()) {
                 error!("ae_trigger failed: {}", e);
             }
 
             debug!(
-                "received new log, index {:?}, contains {} cmds",
-                state.log.len().checked_sub(1),
+                "received new log, index {}, contains {} cmds",
+                log.last_log_index(),
                 cmds.len()
             );
         });
@@ -157,46 +185,68 @@ async fn fetch_sync_msgs<C: Command + 'static>(
 }
 
 /// Interval between sending heartbeats
-const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(150);
+pub(crate) const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(150);
 /// Rpc request timeout
-const RPC_TIMEOUT: Duration = Duration::from_millis(50);
+pub(crate) const RPC_TIMEOUT: Duration = Duration::from_millis(50);
+/// A server is considered healthy if it has responded to the leader within this duration
+const HEALTHY_TIMEOUT: Duration = Duration::from_millis(500);
 
 /// Background `append_entries`, only works for the leader
 async fn bg_append_entries<C: Command + 'static>(
     connects: Vec<Arc<Connect>>,
-    state: Arc<RwLock<State<C>>>,
+    state: Arc<State<C>>,
     mut ae_trigger_rx: mpsc::UnboundedReceiver<usize>,
 ) {
     while let Some(i) = ae_trigger_rx.recv().await {
-        let req = {
-            let state = state.read();
-            if !state.is_leader() {
+        // log.len() >= 1 because we have a fake log[0]
+        #[allow(clippy::integer_arithmetic, clippy::indexing_slicing)]
+        let (term, prev_log_term, prev_log_hash, entry, leader_commit) = {
+            let election = state.election.read();
+            if !election.is_leader() {
                 warn!("Non leader receives sync log[{i}] request");
                 continue;
             }
+            let log = state.log.read();
+            (
+                election.term,
+                log[i - 1].term(),
+                log.hash(i - 1).unwrap_or_default(),
+                log[i].clone(),
+                state.commit.read().commit_index,
+            )
+        };
 
-            // log.len() >= 1 because we have a fake log[0]
-            #[allow(clippy::integer_arithmetic, clippy::indexing_slicing)]
-            match AppendEntriesRequest::new(
-                state.term,
-                i - 1,
-                state.log[i - 1].term(),
-                vec![state.log[i].clone()],
-                state.commit_index,
-            ) {
-                Err(e) => {
-                    error!("unable to serialize append entries request: {}", e);
-                    continue;
-                }
-                Ok(req) => (req),
+        // the leader counts itself when it commits log[i], so it persists log[i] first
+        if let Err(e) = state.wal.wait_all().await {
+            error!("failed to persist log[{i}], {e}");
+            continue;
+        }
+
+        // serialize the request after the locks are released
+        #[allow(clippy::integer_arithmetic)] // i >= 1 because log[0] is never synced
+        let req = match AppendEntriesRequest::new(
+            term,
+            state.id,
+            i - 1,
+            prev_log_term,
+            prev_log_hash,
+            vec![entry],
+            leader_commit,
+            state.wire_format,
+        ) {
+            Err(e) => {
+                error!("unable to serialize append entries request: {}", e);
+                continue;
             }
+            Ok(req) => req,
         };
 
         // send append_entries to each server in parallel
-        for connect in &connects {
+        for (peer, connect) in connects.iter().enumerate() {
             let _handle = tokio::spawn(send_log_until_succeed(
                 i,
                 req.clone(),
+                peer,
                 Arc::clone(connect),
                 Arc::clone(&state),
             ));
@@ -204,280 +254,494 @@ async fn bg_append_entries<C: Command + 'static>(
     }
 }
 
-/// Send `append_entries` containing a single log to a server
-#[allow(clippy::integer_arithmetic, clippy::indexing_slicing)] // log.len() >= 1 because we have a fake log[0], indexing of `next_index` or `match_index` won't panic because we created an entry when initializing the server state
+/// Send `append_entries` containing a single log to a server, `peer` is the position of the server
+/// in `State::others`
+#[allow(clippy::integer_arithmetic, clippy::indexing_slicing)] // log.len() >= 1 because we have a fake log[0], indexing of `next_index` or `match_index` won't panic because they have an entry for each peer
 async fn send_log_until_succeed<C: Command + 'static>(
     i: usize,
-    req: AppendEntriesRequest,
+    mut req: AppendEntriesRequest,
+    peer: usize,
     connect: Arc<Connect>,
-    state: Arc<RwLock<State<C>>>,
+    state: Arc<State<C>>,
 ) {
     // send log[i] until succeed
     loop {
-        debug!("append_entries sent to {}", connect.addr);
+        debug!("append_entries sent to {}", connect.id);
         let resp = connect.append_entries(req.clone(), RPC_TIMEOUT).await;
 
-        #[allow(clippy::unwrap_used)]
-        // indexing of `next_index` or `match_index` won't panic because we created an entry when initializing the server state
         match resp {
             Err(e) => warn!("append_entries error: {}", e),
             Ok(resp) => {
                 let resp = resp.into_inner();
-                let state = state.upgradable_read();
+                let election = state.election.upgradable_read();
 
                 // calibrate term
-                if resp.term > state.term {
-                    let mut state = RwLockUpgradableReadGuard::upgrade(state);
-                    state.update_to_term(resp.term);
+                if resp.term > election.term {
+                    let mut election = RwLockUpgradableReadGuard::upgrade(election);
+                    election.update_to_term(resp.term);
+                    let _seq = state.persist_election(&election);
                     return;
                 }
 
                 if resp.success {
-                    let mut state = RwLockUpgradableReadGuard::upgrade(state);
+                    let log = state.log.read();
+                    let mut replication = state.replication.lock();
                     // update match_index and next_index
-                    let match_index = state.match_index.get_mut(&connect.addr).unwrap();
-                    if *match_index < i {
-                        *match_index = i;
+                    if replication.match_index[peer] < i {
+                        replication.match_index[peer] = i;
                     }
-                    *state.next_index.get_mut(&connect.addr).unwrap() = *match_index + 1;
-
-                    let min_replicated = (state.others.len() + 1) / 2;
-                    // If the majority of servers has replicated the log, commit
-                    if state.commit_index < i
-                        && state.log[i].term() == state.term
-                        && state
-                            .others
-                            .iter()
-                            .filter(|addr| state.match_index[*addr] >= i)
-                            .count()
-                            >= min_replicated
+                    replication.next_index[peer] = replication.match_index[peer] + 1;
+
+                    let mut commit = state.commit.write();
+                    // If a slow quorum of servers has replicated the log, commit
+                    if commit.commit_index < i
+                        && log[i].term() == election.term
+                        && state.quorum.is_slow_quorum(
+                            iter::once(state.id).chain(
+                                state
+                                    .others
+                                    .iter()
+                                    .zip(replication.match_index.iter())
+                                    .filter(|&(_, &match_index)| match_index >= i)
+                                    .map(|(&id, _)| id),
+                            ),
+                        )
                     {
-                        state.commit_index = i;
-                        debug!("commit_index updated to {i}");
-                        state.commit_trigger.notify(1);
+                        commit.update_commit_index(i);
                     }
                     break;
                 }
+
+                // the follower lacks the entries before log[i] or they differ from the leader's
+                // ones, send them together with log[i] starting from its commit index
+                let start = (resp.commit_index + 1).numeric_cast::<usize>().min(i);
+                let entries = {
+                    let log = state.log.read();
+                    log.get(start..=i).map(|entries| {
+                        (
+                            log[start - 1].term(),
+                            log.hash(start - 1).unwrap_or_default(),
+                            entries.to_vec(),
+                            state.commit.read().commit_index,
+                        )
+                    })
+                };
+                let (prev_log_term, prev_log_hash, entries, leader_commit) =
+                    if let Some(entries) = entries {
+                        entries
+                    } else {
+                        // log[i] is removed, the server is not the leader anymore
+                        return;
+                    };
+                req = match AppendEntriesRequest::new(
+                    election.term,
+                    state.id,
+                    start - 1,
+                    prev_log_term,
+                    prev_log_hash,
+                    entries,
+                    leader_commit,
+                    state.wire_format,
+                ) {
+                    Err(e) => {
+                        error!("unable to serialize append entries request: {}", e);
+                        return;
+                    }
+                    Ok(catch_up) => catch_up,
+                };
             }
         }
     }
 }
 
-/// Background `append_entries`, only works for the leader
+/// Background `append_entries`, only works for the leader. The heartbeats are sent by the
+/// transport if the server shares one with other groups, this task only transfers the leadership
+/// then.
 async fn bg_heartbeat<C: Command + 'static>(
     connects: Vec<Arc<Connect>>,
-    state: Arc<RwLock<State<C>>>,
+    state: Arc<State<C>>,
+    transport: Option<Arc<GroupTransport>>,
 ) {
-    let role_trigger = state.read().role_trigger();
+    // the transport only holds a weak reference of the source, so it's kept alive by the task
+    let source = transport.map(|transport| {
+        let source: Arc<dyn HeartbeatSource> = Arc::new(GroupHeartbeats {
+            connects: connects.clone(),
+            state: Arc::clone(&state),
+        });
+        transport.register(&source);
+        source
+    });
+    let role_trigger = state.role_trigger();
+    // when the leadership was transferred the last time
+    let mut last_transfer: Option<Instant> = None;
     #[allow(clippy::integer_arithmetic)] // tokio internal triggered
     loop {
         // only leader should run this task
-        while !state.read().is_leader() {
+        while !state.is_leader() {
             role_trigger.listen().await;
         }
 
         tokio::time::sleep(HEARTBEAT_INTERVAL).await;
 
         // send append_entries to each server in parallel
-        for connect in &connects {
-            let _handle = tokio::spawn(send_heartbeat(Arc::clone(connect), Arc::clone(&state)));
+        if source.is_none() {
+            for (peer, connect) in connects.iter().enumerate() {
+                let _handle = tokio::spawn(send_heartbeat(
+                    peer,
+                    Arc::clone(connect),
+                    Arc::clone(&state),
+                ));
+            }
+        }
+
+        // give the leadership back to a preferred server once it's available, but don't disturb
+        // its election by transferring again too early
+        if last_transfer.map_or(false, |last| last.elapsed() < CANDIDATE_TIMEOUT) {
+            continue;
+        }
+        if let Some(connect) = preferred_leader(&state).and_then(|peer| connects.get(peer)) {
+            last_transfer = Some(Instant::now());
+            let _handle = tokio::spawn(send_timeout_now(Arc::clone(connect), Arc::clone(&state)));
         }
     }
 }
 
-/// Send `append_entries` to a server
-#[allow(clippy::integer_arithmetic, clippy::indexing_slicing)] // log.len() >= 1 because we have a fake log[0], indexing of `next_index` or `match_index` won't panic because we created an entry when initializing the server state
-async fn send_heartbeat<C: Command + 'static>(connect: Arc<Connect>, state: Arc<RwLock<State<C>>>) {
-    // prepare append_entries request args
-    #[allow(clippy::shadow_unrelated)] // clippy false positive
-    let (term, prev_log_index, prev_log_term, leader_commit) = state.map_read(|state| {
-        let next_index = state.next_index[&connect.addr];
-        (
-            state.term,
-            next_index - 1,
-            state.log[next_index - 1].term(),
-            state.commit_index,
-        )
-    });
-    let req =
-        AppendEntriesRequest::new_heartbeat(term, prev_log_index, prev_log_term, leader_commit);
-
-    // send append_entries request and receive response
-    debug!("heartbeat sent to {}", connect.addr);
-    let resp = connect.append_entries(req, RPC_TIMEOUT).await;
+/// The healthy and up-to-date server that has the highest priority among the servers whose
+/// priorities are higher than the leader, return its position in `State::others`
+#[allow(clippy::indexing_slicing)] // indexing of `match_index` or `last_ack` won't panic because they have an entry for each peer
+fn preferred_leader<C: Command + 'static>(state: &State<C>) -> Option<usize> {
+    let last_log_index = state.log.read().last_log_index();
+    let replication = state.replication.lock();
+    state
+        .other_placements
+        .iter()
+        .enumerate()
+        .filter(|&(peer, placement)| {
+            placement.priority > state.placement.priority
+                && replication.match_index[peer] == last_log_index
+                && replication.last_ack[peer].map_or(false, |ack| ack.elapsed() < HEALTHY_TIMEOUT)
+        })
+        .max_by_key(|&(_, placement)| placement.priority)
+        .map(|(peer, _)| peer)
+}
 
-    #[allow(clippy::unwrap_used)]
-    // indexing of `next_index` or `match_index` won't panic because we created an entry when initializing the server state
+/// Send `timeout_now` to a server to transfer the leadership to it
+async fn send_timeout_now<C: Command + 'static>(connect: Arc<Connect>, state: Arc<State<C>>) {
+    let term = state.election.read().term;
+    info!(
+        "transfer leadership to server {} in term {term}",
+        connect.id
+    );
+    let resp = connect
+        .timeout_now(TimeoutNowRequest::new(term, state.id), RPC_TIMEOUT)
+        .await;
     match resp {
-        Err(e) => warn!("append_entries error: {}", e),
+        Err(e) => warn!("timeout_now error: {}", e),
         Ok(resp) => {
             let resp = resp.into_inner();
             // calibrate term
-            let state = state.upgradable_read();
-            if resp.term > state.term {
-                let mut state = RwLockUpgradableReadGuard::upgrade(state);
-                state.update_to_term(resp.term);
-                return;
-            }
-            if !resp.success {
-                let mut state = RwLockUpgradableReadGuard::upgrade(state);
-                *state.next_index.get_mut(&connect.addr).unwrap() -= 1;
+            let election = state.election.upgradable_read();
+            if resp.term > election.term {
+                let mut election = RwLockUpgradableReadGuard::upgrade(election);
+                election.update_to_term(resp.term);
+                let _seq = state.persist_election(&election);
             }
         }
+    }
+}
+
+/// Send an empty `append_entries` to a server, `peer` is the position of the server in
+/// `State::others`
+async fn send_heartbeat<C: Command + 'static>(
+    peer: usize,
+    connect: Arc<Connect>,
+    state: Arc<State<C>>,
+) {
+    let req = heartbeat_request(peer, &state);
+
+    // send append_entries request and receive response
+    debug!("heartbeat sent to {}", connect.id);
+    match connect.append_entries(req.clone(), RPC_TIMEOUT).await {
+        Err(e) => warn!("append_entries error: {}", e),
+        Ok(resp) => handle_heartbeat_response(peer, &state, &req, resp.into_inner()),
+    }
+}
+
+/// The empty `append_entries` to a server, `peer` is the position of the server in
+/// `State::others`
+#[allow(clippy::integer_arithmetic, clippy::indexing_slicing)] // log.len() >= 1 because we have a fake log[0], indexing of `next_index` won't panic because it has an entry for each peer
+fn heartbeat_request<C: Command + 'static>(peer: usize, state: &State<C>) -> AppendEntriesRequest {
+    let term = state.election.read().term;
+    let log = state.log.read();
+    let next_index = state.replication.lock().next_index[peer].clamp(1, log.len());
+    AppendEntriesRequest::new_heartbeat(
+        term,
+        state.id,
+        next_index - 1,
+        log[next_index - 1].term(),
+        log.hash(next_index - 1).unwrap_or_default(),
+        state.commit.read().commit_index,
+    )
+}
+
+/// Handle the response of a server to the heartbeat `req`. A server that lags behind is
+/// calibrated by `calibrate_follower`.
+#[allow(clippy::indexing_slicing)] // indexing of `next_index`, `match_index` or `last_ack` won't panic because they have an entry for each peer
+fn handle_heartbeat_response<C: Command + 'static>(
+    peer: usize,
+    state: &State<C>,
+    req: &AppendEntriesRequest,
+    resp: AppendEntriesResponse,
+) {
+    // calibrate term
+    let election = state.election.upgradable_read();
+    if resp.term > election.term {
+        let mut election = RwLockUpgradableReadGuard::upgrade(election);
+        election.update_to_term(resp.term);
+        let _seq = state.persist_election(&election);
+        return;
+    }
+    let (prev_log_index, leader_commit): (usize, usize) = (
+        req.prev_log_index.numeric_cast(),
+        req.leader_commit.numeric_cast(),
+    );
+    let mut replication = state.replication.lock();
+    replication.last_ack[peer] = Some(Instant::now());
+    let lagging = if resp.success {
+        if replication.match_index[peer] < prev_log_index {
+            replication.match_index[peer] = prev_log_index;
+        }
+        // entries that are being replicated are not counted
+        replication.match_index[peer] < leader_commit
+    } else {
+        // the follower has all the entries up to its commit index
+        let next_index = resp.commit_index.overflow_add(1).numeric_cast();
+        replication.next_index[peer] = replication.next_index[peer].min(next_index);
+        true
     };
+    if lagging {
+        state.calibrate_trigger.notify(1);
+    }
+}
+
+/// The heartbeats of a group that are sent by a `GroupTransport`
+struct GroupHeartbeats<C: Command + 'static> {
+    /// Connects to the other servers of the group
+    connects: Vec<Arc<Connect>>,
+    /// The state of the server
+    state: Arc<State<C>>,
+}
+
+impl<C: Command + 'static> HeartbeatSource for GroupHeartbeats<C> {
+    fn heartbeats(&self) -> Vec<Heartbeat> {
+        if !self.state.is_leader() {
+            return vec![];
+        }
+        self.connects
+            .iter()
+            .enumerate()
+            .map(|(peer, connect)| Heartbeat {
+                group: connect.group(),
+                addr: connect.addr(),
+                peer,
+                request: heartbeat_request(peer, &self.state),
+            })
+            .collect()
+    }
+
+    fn handle_response(&self, heartbeat: &Heartbeat, resp: AppendEntriesResponse) {
+        handle_heartbeat_response(heartbeat.peer, &self.state, &heartbeat.request, resp);
+    }
 }
 
 /// Background apply
 async fn bg_apply<C: Command + 'static>(
-    state: Arc<RwLock<State<C>>>,
+    state: Arc<State<C>>,
     exe_tx: CmdExecuteSender<C>,
     spec: Arc<Mutex<SpeculativePool<C>>>,
     cmd_board: Arc<Mutex<CommandBoard>>,
 ) {
-    let commit_trigger = state.read().commit_trigger();
+    let (commit_trigger, progress_tx) = (state.commit_trigger(), state.progress_tx());
+    // the task that waits for the results of the last batch
+    let mut applied: Option<JoinHandle<()>> = None;
     loop {
         // wait until there is something to commit
-        let state = loop {
+        let (last_applied, commit_index) = loop {
             {
-                let state = state.upgradable_read();
-                if state.need_commit() {
-                    break state;
+                let commit = state.commit.read();
+                if commit.need_commit() {
+                    break (commit.last_applied, commit.commit_index);
                 }
             }
             commit_trigger.listen().await;
         };
 
-        let mut state = RwLockUpgradableReadGuard::upgrade(state);
-        #[allow(clippy::integer_arithmetic, clippy::indexing_slicing)]
-        // TODO: overflow of log index should be prevented
-        for i in (state.last_applied + 1)..=state.commit_index {
-            for cmd in state.log[i].cmds().iter() {
-                let cmd_id = cmd.id();
-                if state.is_leader() {
-                    handle_after_sync_leader(
-                        Arc::clone(&cmd_board),
-                        Arc::clone(cmd),
-                        i.numeric_cast(),
-                        &exe_tx,
-                    );
-                } else {
-                    handle_after_sync_follower(&exe_tx, Arc::clone(cmd), i.numeric_cast());
+        let is_leader = state.is_leader();
+        // all committed cmds are sent to the executor as a single after sync batch
+        let mut batch = vec![];
+        {
+            let log = state.log.read();
+            #[allow(clippy::integer_arithmetic, clippy::indexing_slicing)]
+            // TODO: overflow of log index should be prevented
+            for i in (last_applied + 1)..=commit_index {
+                for cmd in log[i].cmds().iter() {
+                    if is_leader {
+                        if let Some(needs_execute) = leader_needs_execute(&cmd_board, cmd.as_ref())
+                        {
+                            batch.push((Arc::clone(cmd), i.numeric_cast(), needs_execute));
+                        }
+                    } else {
+                        // FIXME: should follower store er and asr in case it becomes leader later?
+                        batch.push((Arc::clone(cmd), i.numeric_cast(), true));
+                    }
+                    spec.lock().mark_ready(cmd.id());
                 }
-                spec.lock().mark_ready(cmd_id);
+                debug!("log[{i}] committed");
             }
-            state.last_applied = i;
-            debug!("log[{i}] committed, last_applied updated to {}", i);
         }
+        // only this task updates `last_applied`, so it can be set without checking
+        state.commit.write().last_applied = commit_index;
+        debug!("last_applied updated to {commit_index}");
+
+        let ids: Vec<_> = batch.iter().map(|entry| entry.0.id().clone()).collect();
+        let results = exe_tx.send_after_sync_batch(batch);
+        // wait for the results in another task so that the next batch is sent without waiting
+        // for this one, the progress is published after the previous batches are done
+        let prev = applied.take();
+        let (cmd_board, progress_tx, format) = (
+            Arc::clone(&cmd_board),
+            Arc::clone(&progress_tx),
+            state.wire_format,
+        );
+        applied = Some(tokio::spawn(async move {
+            for (cmd_id, result) in ids.into_iter().zip(results) {
+                let result = result.await;
+                if is_leader {
+                    handle_after_sync_leader::<C>(&cmd_board, &cmd_id, result, format);
+                }
+            }
+            if let Some(prev) = prev {
+                let _ignore = prev.await;
+            }
+
+            // all cmds in the batch have been executed and after synced
+            let _ignore = progress_tx.send_if_modified(|progress| {
+                let last_applied = commit_index.numeric_cast();
+                let modified = progress.last_applied < last_applied;
+                progress.last_applied = progress.last_applied.max(last_applied);
+                modified
+            });
+        }));
     }
 }
 
-/// The leader handles after sync
-fn handle_after_sync_leader<C: Command + 'static>(
-    cmd_board: Arc<Mutex<CommandBoard>>,
-    cmd: Arc<C>,
-    index: LogIndex,
-    exe_tx: &CmdExecuteSender<C>,
-) {
-    let cmd_id = cmd.id().clone();
-
-    let needs_execute = {
-        // the leader will see if the command needs execution from cmd board
-        let cmd_board = cmd_board.lock();
-        let cmd_state = if let Some(cmd_state) = cmd_board.cmd_states.get(cmd.id()) {
-            cmd_state
-        } else {
-            error!("No cmd {:?} in command board", cmd.id());
-            return;
-        };
-        match *cmd_state {
-            CmdState::Execute => true,
-            CmdState::AfterSync => false,
-            CmdState::EarlyArrive | CmdState::FinalResponse(_) => {
-                error!("should not get state {:?} before after sync", cmd_state);
-                return;
-            }
+/// The leader checks whether a committed cmd still needs execution from cmd board and marks it
+/// applying, return `None` if the cmd is not in a state to be after synced, e.g. it has been
+/// after synced before as a client may propose a cmd more than once. A cmd that is not proposed
+/// to the leader, e.g. it's appended by the previous leader, is not executed yet.
+fn leader_needs_execute<C: Command + 'static>(
+    cmd_board: &Mutex<CommandBoard>,
+    cmd: &C,
+) -> Option<bool> {
+    let mut cmd_board = cmd_board.lock();
+    let needs_execute = match cmd_board.cmd_states.get(cmd.id()) {
+        None | Some(&CmdState::EarlyArrive | &CmdState::Execute) => true,
+        Some(&CmdState::AfterSync) => false,
+        Some(&CmdState::Applying | &CmdState::FinalResponse(_)) => {
+            debug!("cmd {:?} has been after synced, skip it", cmd.id());
+            return None;
         }
     };
+    let _prev = cmd_board
+        .cmd_states
+        .insert(cmd.id().clone(), CmdState::Applying);
+    Some(needs_execute)
+}
 
-    let resp = if needs_execute {
-        let result = exe_tx.send_exe_and_after_sync(cmd, index);
-        Either::Left(async move {
-            result.await.map_or_else(
-                |e| {
-                    WaitSyncedResponse::new_error(&format!(
-                        "can't get execution and after sync result, {e}"
-                    ))
-                },
-                |(er, asr)| WaitSyncedResponse::new_from_result::<C>(Some(er), asr),
-            )
-        })
-    } else {
-        let result = exe_tx.send_after_sync(cmd, index);
-        Either::Right(async move {
-            result.await.map_or_else(
-                |e| WaitSyncedResponse::new_error(&format!("can't get after sync result, {e}")),
-                |asr| WaitSyncedResponse::new_from_result::<C>(None, Some(asr)),
+/// The leader handles the after sync result of a cmd, updating the cmd board so that the waiting
+/// request can get the final response
+fn handle_after_sync_leader<C: Command + 'static>(
+    cmd_board: &Mutex<CommandBoard>,
+    cmd_id: &ProposeId,
+    result: Result<AfterSyncResult<C>, oneshot::error::RecvError>,
+    format: WireFormat,
+) {
+    let resp = result.map_or_else(
+        |e| {
+            WaitSyncedResponse::new_error(
+                &format!("can't get execution and after sync result, {e}"),
+                format,
             )
-        })
-    };
-
-    // update the cmd_board after execution and after_sync is completed
-    let _ignored = tokio::spawn(async move {
-        let resp = resp.await;
+        },
+        |(er, asr)| WaitSyncedResponse::new_from_result::<C>(er, asr, format),
+    );
 
-        let mut cmd_board = cmd_board.lock();
-        let cmd_state = if let Some(cmd_state) = cmd_board.cmd_states.get_mut(&cmd_id) {
-            cmd_state
-        } else {
-            error!("No cmd {:?} in command board", cmd_id);
-            return;
-        };
-        *cmd_state = CmdState::FinalResponse(resp);
-
-        // now we can notify the waiting request
-        if let Some(notify) = cmd_board.notifiers.get(&cmd_id) {
-            notify.notify(usize::MAX);
-        }
-    });
-}
+    // the cmd is not in the board if it's not proposed to the leader, its response is kept for
+    // the client that waits for it on the leader later
+    let mut cmd_board = cmd_board.lock();
+    let _prev = cmd_board
+        .cmd_states
+        .insert(cmd_id.clone(), CmdState::FinalResponse(resp));
 
-/// The follower handles after sync
-fn handle_after_sync_follower<C: Command + 'static>(
-    exe_tx: &CmdExecuteSender<C>,
-    cmd: Arc<C>,
-    index: LogIndex,
-) {
-    // FIXME: should follower store er and asr in case it becomes leader later?
-    let _ignore = exe_tx.send_exe_and_after_sync(cmd, index);
+    // now we can notify the waiting request
+    if let Some(notify) = cmd_board.notifiers.get(cmd_id) {
+        notify.notify(usize::MAX);
+    }
 }
 
 /// How long a candidate should wait before it starts another round of election
 const CANDIDATE_TIMEOUT: Duration = Duration::from_secs(1);
 /// How long a follower should wait before it starts a round of election (in millis)
 const FOLLOWER_TIMEOUT: Range<u64> = 1000..2000;
+/// The longest time a follower waits for the leader before it starts a round of election, a new
+/// leader is usually elected within it after the leader fails
+pub const ELECTION_TIMEOUT: Duration = Duration::from_millis(FOLLOWER_TIMEOUT.end);
+/// How much longer a follower should wait for each distinct higher priority of other servers
+const PRIORITY_ELECTION_DELAY: Duration = Duration::from_millis(1000);
 
 /// Background election
 async fn bg_election<C: Command + 'static>(
     connects: Vec<Arc<Connect>>,
-    state: Arc<RwLock<State<C>>>,
+    state: Arc<State<C>>,
     last_rpc_time: Arc<RwLock<Instant>>,
+    spec: Arc<Mutex<SpeculativePool<C>>>,
+    ae_trigger: mpsc::UnboundedSender<usize>,
 ) {
-    let role_trigger = state.read().role_trigger();
+    if state.placement.priority == 0 {
+        info!("election priority is 0, the server never starts an election");
+        return;
+    }
+    let role_trigger = state.role_trigger();
     loop {
         // only follower or candidate should run this task
-        while state.read().is_leader() {
+        while state.is_leader() {
             role_trigger.listen().await;
         }
 
-        let current_role = state.read().role();
+        let current_role = state.election.read().role();
         let start_vote = match current_role {
             ServerRole::Follower => {
-                let timeout = Duration::from_millis(thread_rng().gen_range(FOLLOWER_TIMEOUT));
-                // wait until it needs to vote
+                let base_timeout = Duration::from_millis(thread_rng().gen_range(FOLLOWER_TIMEOUT));
+                // servers with lower priorities wait longer so that the preferred ones that are
+                // alive are elected first
+                let timeout = || base_timeout + PRIORITY_ELECTION_DELAY * state.priority_rank();
+                // wait until it needs to vote, or the leader transfers its leadership to it
                 loop {
-                    let next_check = last_rpc_time.read().to_owned() + timeout;
-                    tokio::time::sleep_until(next_check).await;
-                    if Instant::now() - *last_rpc_time.read() > timeout {
+                    let next_check = last_rpc_time.read().to_owned() + timeout();
+                    let sleep = tokio::time::sleep_until(next_check);
+                    pin_mut!(sleep);
+                    let timeout_now = state.timeout_now_trigger.listen();
+                    // the transfer may happen before the listener is created
+                    if state.take_timeout_now() {
+                        break;
+                    }
+                    if let Either::Right(_) = future::select(sleep, timeout_now).await {
+                        if state.take_timeout_now() {
+                            break;
+                        }
+                        continue;
+                    }
+                    if Instant::now() - *last_rpc_time.read() > timeout() {
                         break;
                     }
                 }
@@ -487,7 +751,7 @@ async fn bg_election<C: Command + 'static>(
                 let next_check = last_rpc_time.read().to_owned() + CANDIDATE_TIMEOUT;
                 tokio::time::sleep_until(next_check).await;
                 // check election status
-                match state.read().role() {
+                match state.election.read().role() {
                     // election failed, becomes a follower || election succeeded, becomes a leader
                     ServerRole::Follower | ServerRole::Leader => {
                         break false;
@@ -507,21 +771,39 @@ async fn bg_election<C: Command + 'static>(
         // start election
         #[allow(clippy::integer_arithmetic)] // TODO: handle possible overflow
         let req = {
-            let mut state = state.write();
-            let new_term = state.term + 1;
-            state.update_to_term(new_term);
-            state.set_role(ServerRole::Candidate);
-            state.voted_for = Some(state.id.clone());
-            state.votes_received = 1;
+            let mut election = state.election.write();
+            let new_term = election.term + 1;
+            election.update_to_term(new_term);
+            election.set_role(ServerRole::Candidate);
+            election.voted_for = Some(state.id);
+            let _seq = state.persist_election(&election);
+            election.votes_received = state.quorum.weight(state.id);
+            *state.spec_votes.lock() = spec
+                .lock()
+                .pool
+                .iter()
+                .map(|cmd| {
+                    (
+                        cmd.id().clone(),
+                        (Arc::new(cmd.clone()), state.quorum.weight(state.id)),
+                    )
+                })
+                .collect();
+            let log = state.log.read();
             VoteRequest::new(
-                state.term,
-                state.id.clone(),
-                state.last_log_index(),
-                state.last_log_term(),
+                election.term,
+                state.id,
+                log.last_log_index(),
+                log.last_log_term(),
             )
         };
         // reset
         *last_rpc_time.write() = Instant::now();
+        // the server votes for itself before it asks for votes
+        if let Err(e) = state.wal.wait_all().await {
+            error!("failed to persist the vote of term {}, {e}", req.term);
+            continue;
+        }
         debug!("server {} starts election", req.candidate_id);
 
         for connect in &connects {
@@ -529,6 +811,7 @@ async fn bg_election<C: Command + 'static>(
                 Arc::clone(connect),
                 Arc::clone(&state),
                 req.clone(),
+                ae_trigger.clone(),
             ));
         }
     }
@@ -537,8 +820,9 @@ async fn bg_election<C: Command + 'static>(
 /// send vote request
 async fn send_vote<C: Command + 'static>(
     connect: Arc<Connect>,
-    state: Arc<RwLock<State<C>>>,
+    state: Arc<State<C>>,
     req: VoteRequest,
+    ae_trigger: mpsc::UnboundedSender<usize>,
 ) {
     let resp = connect.vote(req, RPC_TIMEOUT).await;
     match resp {
@@ -547,126 +831,222 @@ async fn send_vote<C: Command + 'static>(
             let resp = resp.into_inner();
 
             // calibrate term
-            let state = state.upgradable_read();
-            if resp.term > state.term {
-                let mut state = RwLockUpgradableReadGuard::upgrade(state);
-                state.update_to_term(resp.term);
+            let election = state.election.upgradable_read();
+            if resp.term > election.term {
+                let mut election = RwLockUpgradableReadGuard::upgrade(election);
+                election.update_to_term(resp.term);
+                let _seq = state.persist_election(&election);
                 return;
             }
 
             // is still a candidate
-            if !matches!(state.role(), ServerRole::Candidate) {
+            if !matches!(election.role(), ServerRole::Candidate) {
                 return;
             }
 
             #[allow(clippy::integer_arithmetic)]
             if resp.vote_granted {
-                debug!("vote is granted by server {}", connect.addr);
-                let mut state = RwLockUpgradableReadGuard::upgrade(state);
-                state.votes_received += 1;
+                debug!("vote is granted by server {}", connect.id);
+                let spec_pool = match resp.spec_pool::<C>() {
+                    Ok(spec_pool) => spec_pool,
+                    Err(e) => {
+                        error!("decode spec pool of server {} failed, {e}", connect.id);
+                        return;
+                    }
+                };
+                let mut election = RwLockUpgradableReadGuard::upgrade(election);
+                election.votes_received += state.quorum.weight(connect.id);
+                count_spec_votes(&state, spec_pool, state.quorum.weight(connect.id));
 
-                // the majority has granted the vote
-                let min_granted = (state.others.len() + 1) / 2 + 1;
-                if state.votes_received >= min_granted {
-                    state.set_role(ServerRole::Leader);
-                    debug!("server becomes leader");
+                // a slow quorum has granted the vote
+                if election.votes_received >= state.quorum.slow_quorum() {
+                    election.ready_index = append_leader_entry(&state, election.term);
+                    election.set_role(ServerRole::Leader);
+                    election.set_leader(state.id);
+                    info!(
+                        "server becomes leader in term {}, region: {:?}, priority: {}",
+                        election.term, state.placement.region, state.placement.priority
+                    );
 
                     // init next_index
-                    let last_log_index = state.last_log_index();
-                    for index in state.next_index.values_mut() {
+                    let last_log_index = state.log.read().last_log_index();
+                    for index in &mut state.replication.lock().next_index {
                         *index = last_log_index + 1; // iter from the end to front is more likely to match the follower
                     }
 
                     // trigger heartbeat immediately to establish leadership
                     state.calibrate_trigger.notify(1);
+                    // the entries of previous terms are committed with the entry of this term
+                    if let Err(e) = ae_trigger.send(election.ready_index) {
+                        error!("ae_trigger failed: {}", e);
+                    }
                 }
             }
         }
     }
 }
 
-/// Leader should first enforce followers to be consistent with it when it comes to power
-#[allow(clippy::integer_arithmetic, clippy::indexing_slicing)] // log.len() >= 1 because we have a fake log[0], indexing of `next_index` or `match_index` won't panic because we created an entry when initializing the server state
+/// Count the commands in the speculative pool of a voter whose weight is `weight`
+fn count_spec_votes<C: Command + 'static>(state: &State<C>, spec_pool: Vec<C>, weight: u64) {
+    let mut spec_votes = state.spec_votes.lock();
+    for cmd in spec_pool {
+        let &mut (_, ref mut total) = spec_votes
+            .entry(cmd.id().clone())
+            .or_insert_with(|| (Arc::new(cmd), 0));
+        *total = total.saturating_add(weight);
+    }
+}
+
+/// Append an entry of the new term to the log of the new leader, so that the entries of
+/// previous terms are committed once it's replicated. It carries the commands that may have
+/// been accepted by a fast quorum in previous terms, so that they are not lost. Returns the
+/// index of the last log entry.
+fn append_leader_entry<C: Command + 'static>(state: &State<C>, term: TermNum) -> usize {
+    let spec_votes = std::mem::take(&mut *state.spec_votes.lock());
+    let cmds: Vec<_> = {
+        let log = state.log.read();
+        let logged: HashSet<_> = log
+            .iter()
+            .flat_map(|entry| entry.cmds().iter().map(|cmd| cmd.id()))
+            .collect();
+        spec_votes
+            .into_values()
+            .filter(|&(ref cmd, weight)| {
+                weight >= state.quorum.recover_quorum() && !logged.contains(cmd.id())
+            })
+            .map(|(cmd, _)| cmd)
+            .collect()
+    };
+    if !cmds.is_empty() {
+        info!("recover {} speculative cmds in term {term}", cmds.len());
+    }
+    let entry = LogEntry::new(term, &cmds);
+    match entry.crc() {
+        Ok(crc) => {
+            let mut log = state.log.write();
+            log.push(entry, crc);
+            let _seq = state.persist_entries(&log, log.last_log_index());
+            log.last_log_index()
+        }
+        Err(e) => {
+            error!("unable to append the entry of term {term}: {}", e);
+            state.log.read().last_log_index()
+        }
+    }
+}
+
+/// How many entries are sent in one `append_entries` request at most when a follower is calibrated
+const MAX_CALIBRATE_ENTRIES: usize = 64;
+
+/// Leader should first enforce followers to be consistent with it when it comes to power, the
+/// followers that the heartbeats find lagging behind are calibrated as well
+#[allow(clippy::indexing_slicing)] // `calibrating` has an entry for each peer
 async fn leader_calibrates_followers<C: Command + 'static>(
     connects: Vec<Arc<Connect>>,
-    state: Arc<RwLock<State<C>>>,
+    state: Arc<State<C>>,
 ) {
-    let calibrate_trigger = Arc::clone(&state.read().calibrate_trigger);
+    let calibrate_trigger = Arc::clone(&state.calibrate_trigger);
     loop {
         calibrate_trigger.listen().await;
-        for connect in connects.iter().cloned() {
-            let state = Arc::clone(&state);
-            let _handle = tokio::spawn(async move {
-                loop {
-                    // send append entry
-                    #[allow(clippy::shadow_unrelated)] // clippy false positive
-                    let (
-                        term,
-                        prev_log_index,
-                        prev_log_term,
-                        entries,
-                        leader_commit,
-                        last_sent_index,
-                    ) = state.map_read(|state| {
-                        let next_index = state.next_index[&connect.addr];
-                        (
-                            state.term,
-                            next_index - 1,
-                            state.log[next_index - 1].term(),
-                            state.log[next_index..].to_vec(),
-                            state.commit_index,
-                            state.log.len() - 1,
-                        )
-                    });
-                    let req = match AppendEntriesRequest::new(
-                        term,
-                        prev_log_index,
-                        prev_log_term,
-                        entries,
-                        leader_commit,
-                    ) {
-                        Err(e) => {
-                            error!("unable to serialize append entries request: {}", e);
-                            return;
-                        }
-                        Ok(req) => req,
-                    };
-
-                    let resp = connect.append_entries(req, RPC_TIMEOUT).await;
-
-                    #[allow(clippy::unwrap_used)]
-                    // indexing of `next_index` or `match_index` won't panic because we created an entry when initializing the server state
-                    match resp {
-                        Err(e) => warn!("append_entries error: {}", e),
-                        Ok(resp) => {
-                            let resp = resp.into_inner();
-                            // calibrate term
-                            let state = state.upgradable_read();
-                            if resp.term > state.term {
-                                let mut state = RwLockUpgradableReadGuard::upgrade(state);
-                                state.update_to_term(resp.term);
-                                return;
-                            }
-
-                            let mut state = RwLockUpgradableReadGuard::upgrade(state);
-
-                            // successfully calibrate
-                            if resp.success {
-                                *state.next_index.get_mut(&connect.addr).unwrap() =
-                                    last_sent_index + 1;
-                                *state.match_index.get_mut(&connect.addr).unwrap() =
-                                    last_sent_index;
-                                break;
-                            }
-
-                            *state.next_index.get_mut(&connect.addr).unwrap() =
-                                (resp.commit_index + 1).numeric_cast();
-                        }
-                    };
+        for (peer, connect) in connects.iter().enumerate() {
+            // a follower is calibrated by one task at a time
+            {
+                let mut replication = state.replication.lock();
+                if replication.calibrating[peer] {
+                    continue;
                 }
-            });
+                replication.calibrating[peer] = true;
+            }
+            let _handle = tokio::spawn(calibrate_follower(
+                peer,
+                Arc::clone(connect),
+                Arc::clone(&state),
+            ));
+        }
+    }
+}
+
+/// Send the entries that a follower misses to it in batches until it catches up with the leader,
+/// `peer` is the position of the server in `State::others`
+#[allow(clippy::integer_arithmetic, clippy::indexing_slicing)] // log.len() >= 1 because we have a fake log[0], indexing of `next_index` or `match_index` won't panic because they have an entry for each peer
+async fn calibrate_follower<C: Command + 'static>(
+    peer: usize,
+    connect: Arc<Connect>,
+    state: Arc<State<C>>,
+) {
+    loop {
+        #[allow(clippy::shadow_unrelated)] // clippy false positive
+        let (term, prev_log_index, prev_log_term, prev_log_hash, entries, leader_commit) = {
+            let election = state.election.read();
+            if !election.is_leader() {
+                break;
+            }
+            let log = state.log.read();
+            let next_index = state.replication.lock().next_index[peer].clamp(1, log.len());
+            let end = log.len().min(next_index + MAX_CALIBRATE_ENTRIES);
+            (
+                election.term,
+                next_index - 1,
+                log[next_index - 1].term(),
+                log.hash(next_index - 1).unwrap_or_default(),
+                log[next_index..end].to_vec(),
+                state.commit.read().commit_index,
+            )
+        };
+        let last_sent_index = prev_log_index + entries.len();
+        let req = match AppendEntriesRequest::new(
+            term,
+            state.id,
+            prev_log_index,
+            prev_log_term,
+            prev_log_hash,
+            entries,
+            leader_commit,
+            state.wire_format,
+        ) {
+            Err(e) => {
+                error!("unable to serialize append entries request: {}", e);
+                break;
+            }
+            Ok(req) => req,
+        };
+
+        let resp = match connect.append_entries(req, RPC_TIMEOUT).await {
+            Err(e) => {
+                // the next heartbeat triggers the calibration again
+                warn!("append_entries error: {}", e);
+                break;
+            }
+            Ok(resp) => resp.into_inner(),
+        };
+
+        // calibrate term
+        let election = state.election.upgradable_read();
+        if resp.term > election.term {
+            let mut election = RwLockUpgradableReadGuard::upgrade(election);
+            election.update_to_term(resp.term);
+            let _seq = state.persist_election(&election);
+            break;
+        }
+
+        let last_log_index = state.log.read().last_log_index();
+        let mut replication = state.replication.lock();
+        replication.last_ack[peer] = Some(Instant::now());
+        if resp.success {
+            if replication.match_index[peer] < last_sent_index {
+                replication.match_index[peer] = last_sent_index;
+            }
+            replication.next_index[peer] = replication.match_index[peer] + 1;
+            if replication.match_index[peer] >= last_log_index {
+                break;
+            }
+        } else {
+            // the follower has all the entries up to its commit index
+            let next_index = (resp.commit_index + 1).numeric_cast();
+            replication.next_index[peer] = replication.next_index[peer].min(next_index);
         }
     }
+    state.replication.lock().calibrating[peer] = false;
 }
 
 /// The only place where cmds get executed and call `after_sync`
@@ -687,8 +1067,8 @@ async fn bg_execute_cmd<C: Command + 'static, CE: 'static + CommandExecutor<C>>(
 
     // TODO: avoid re-dispatch by using a mpmc channel
     while let Some(msg) = cmd_rx.recv().await {
-        let cmd = Arc::clone(&msg.cmd);
-        if let Err(e) = dispatch_tx.send(cmd.keys(), Some(msg)) {
+        let keys = msg.keys().to_vec();
+        if let Err(e) = dispatch_tx.send(&keys, Some(msg)) {
             warn!("failed to send cmd to execute worker, {e}");
         }
     }
@@ -4,6 +4,8 @@
 //! 1. The message coupled with keys, any two message with conflicting keys are conflicted.
 //! 2. Any message send to the channel is control by a done token returned by the receiver API.
 //! 3. Undone message blocks all the following conflict messages
+//! 4. A message without keys conflicts with all other messages, e.g. the execution and the after
+//!    sync of a command without keys are not reordered
 
 use std::{
     cmp::Eq,
@@ -73,7 +75,10 @@ impl<K: Eq + Hash + Clone + ConflictCheck, M> KeyBasedChannel<SpmcKeysMessage<K,
             .successor
             .iter_mut()
             .filter_map(|(k, v)| {
-                super::keys_conflict(k.keys(), new_km.keys()).then(|| {
+                (k.keys().is_empty()
+                    || new_km.keys().is_empty()
+                    || super::keys_conflict(k.keys(), new_km.keys()))
+                .then(|| {
                     let _ignore = v.insert(new_km.clone());
                 })
             })
@@ -294,6 +299,40 @@ mod test {
         });
     }
 
+    #[allow(clippy::expect_used, unused_must_use)]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn message_without_keys_conflicts_with_all() {
+        let (tx, rx) = channel::<String, String>();
+
+        tx.send(&[], "A".to_owned());
+        tx.send(&["1".to_owned()], "B".to_owned());
+        tx.send(&[], "C".to_owned());
+        let (first, f_done) = rx.recv().await.expect("first message should recv success");
+        first.map_msg(|msg| {
+            assert_eq!(*msg, "A");
+        });
+        assert!(matches!(
+            rx.recv_timeout(Duration::from_secs(1)).await,
+            Err(RecvError::Timeout)
+        ));
+
+        f_done.send(first);
+        let (second, s_done) = rx.recv().await.expect("second message should recv success");
+        second.map_msg(|msg| {
+            assert_eq!(*msg, "B");
+        });
+        assert!(matches!(
+            rx.recv_timeout(Duration::from_secs(1)).await,
+            Err(RecvError::Timeout)
+        ));
+
+        s_done.send(second);
+        let (third, _) = rx.recv().await.expect("third message should recv success");
+        third.map_msg(|msg| {
+            assert_eq!(*msg, "C");
+        });
+    }
+
     // Test the receiver should not block the thread. A bug was found that the receiver might block the whole tokio worker thread when recv().await is called. This test verifies that it was fixed.
     // The test will be blocked should the async_recv() in SpmcKeyBasedReceiver::recv() is changed to recv().
     // The lesson here is that l.wait() should only be called in non-async code. If it is called in async code, it will not hand the control flow back to tokio runtime like l.await and, therefore, block the tokio worker thread.
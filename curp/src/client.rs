@@ -1,20 +1,188 @@
-use std::{fmt::Debug, iter, marker::PhantomData, net::SocketAddr, sync::Arc, time::Duration};
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    iter,
+    marker::PhantomData,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use futures::{pin_mut, stream::FuturesUnordered, StreamExt};
-use opentelemetry::global;
+use futures::{
+    future::{self, BoxFuture, Either},
+    pin_mut,
+    stream::FuturesUnordered,
+    StreamExt,
+};
+use parking_lot::Mutex;
 use tracing::{info_span, instrument, warn, Instrument};
-use tracing_opentelemetry::OpenTelemetrySpanExt;
 
 use crate::{
+    batch::ProposeBatcher,
     cmd::Command,
+    codec::WireFormat,
     error::ProposeError,
+    group::{GroupId, DEFAULT_GROUP},
+    message::ServerId,
+    quorum::QuorumConfig,
     rpc::{self, Connect, ProposeRequest, WaitSyncedRequest},
-    util::InjectMap,
+    server::ServerStatus,
 };
 
+/// Execution result of the fast round, and the ids of the replicas that formed the fast quorum or
+/// why the fast quorum is not formed
+type FastRoundResult<C> = (
+    Option<<C as Command>::ER>,
+    Result<Vec<ServerId>, ProposeError>,
+);
+
 /// Propose request default timeout
 static PROPOSE_TIMEOUT: Duration = Duration::from_secs(1);
 
+/// The weight of the previous smoothed rtt is `RTT_SMOOTHING - 1` of `RTT_SMOOTHING`
+const RTT_SMOOTHING: u32 = 8;
+
+/// The round-trip time measured from the client to a replica
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ReplicaRtt {
+    /// Id of the replica
+    pub id: ServerId,
+    /// Smoothed rtt, `None` if the replica has never responded
+    pub srtt: Option<Duration>,
+}
+
+/// Report of a successful proposal
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ProposeReport {
+    /// Replicas that formed the fast quorum, `None` if the proposal was committed in the slow round
+    pub fast_quorum: Option<Vec<ServerId>>,
+}
+
+/// Config of the client side proposal batching
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct BatchConfig {
+    /// Proposals issued within the window after the first one are sent in the same batch
+    pub window: Duration,
+    /// Max number of proposals in a batch
+    pub max_size: usize,
+}
+
+impl BatchConfig {
+    /// New `BatchConfig`
+    #[inline]
+    #[must_use]
+    pub fn new(window: Duration, max_size: usize) -> Self {
+        Self { window, max_size }
+    }
+}
+
+/// How long a proposal waits before its execution result is returned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ProposeMode {
+    /// Return the speculative result once a fast quorum accepts the command, fall back to the
+    /// slow path if the fast quorum is not formed
+    Speculative,
+    /// Only take the fast path, fail with `ProposeError::KeyConflict` if the fast quorum is not
+    /// formed. The command may still be committed by the leader in this case.
+    FastOnly,
+    /// Always wait for the command to be synced
+    Synced,
+}
+
+impl Default for ProposeMode {
+    #[inline]
+    fn default() -> Self {
+        ProposeMode::Speculative
+    }
+}
+
+/// Options of a single proposal
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ProposeOptions {
+    /// How long the proposal waits before its execution result is returned
+    pub mode: ProposeMode,
+    /// Fail with `ProposeError::Timeout` if the execution result is not returned in time,
+    /// `None` to wait forever
+    pub timeout: Option<Duration>,
+}
+
+impl ProposeOptions {
+    /// New `ProposeOptions` without timeout
+    #[inline]
+    #[must_use]
+    pub fn new(mode: ProposeMode) -> Self {
+        Self {
+            mode,
+            timeout: None,
+        }
+    }
+
+    /// Set the timeout of the proposal
+    #[inline]
+    #[must_use]
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        Self {
+            timeout: Some(timeout),
+            ..self
+        }
+    }
+}
+
+/// A proposal whose execution result is returned, it can be waited until the command is committed
+pub struct Proposal<'a, C: Command> {
+    /// Execution result of the command
+    er: C::ER,
+    /// Resolves to the after sync result when the command is committed
+    commit: BoxFuture<'a, Result<C::ASR, ProposeError>>,
+}
+
+impl<C: Command> Debug for Proposal<'_, C> {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Proposal").field("er", &self.er).finish()
+    }
+}
+
+impl<'a, C: Command> Proposal<'a, C> {
+    /// New `Proposal` that is committed when `commit` resolves
+    fn new(er: C::ER, commit: BoxFuture<'a, Result<C::ASR, ProposeError>>) -> Self {
+        Self { er, commit }
+    }
+
+    /// New `Proposal` whose commit result is already known
+    fn committed_with(er: C::ER, asr: Result<C::ASR, ProposeError>) -> Self
+    where
+        C::ASR: 'a,
+    {
+        Self::new(er, Box::pin(future::ready(asr)))
+    }
+
+    /// Execution result of the command
+    #[inline]
+    pub fn er(&self) -> &C::ER {
+        &self.er
+    }
+
+    /// Wait until the command is committed, and get the after sync result
+    /// # Errors
+    ///   `ProposeError::SyncedError` error met while syncing logs to followers
+    #[inline]
+    pub async fn committed(self) -> Result<C::ASR, ProposeError> {
+        self.commit.await
+    }
+
+    /// Split the proposal into the execution result and the future of the after sync result
+    #[inline]
+    pub fn into_parts(self) -> (C::ER, BoxFuture<'a, Result<C::ASR, ProposeError>>) {
+        (self.er, self.commit)
+    }
+}
+
 #[derive(Debug)]
 /// Protocol client
 pub struct Client<C: Command> {
@@ -22,6 +190,15 @@ pub struct Client<C: Command> {
     leader: usize,
     /// All servers addresses including leader address
     connects: Vec<Arc<Connect>>,
+    /// Smoothed rtt of every connection, in the same order as `connects`
+    rtts: Vec<Mutex<Option<Duration>>>,
+    /// Quorums of the fast path and the slow path
+    quorum: QuorumConfig,
+    /// Batchers of the proposals, in the same order as `connects`. It's empty if batching is
+    /// not enabled.
+    batchers: Vec<ProposeBatcher>,
+    /// The format used to encode the requests
+    wire_format: WireFormat,
     /// To keep Command type
     phatom: PhantomData<C>,
 }
@@ -30,69 +207,207 @@ impl<C> Client<C>
 where
     C: Command + 'static,
 {
-    /// Create a new protocol client based on the addresses
+    /// Create a new protocol client based on the ids and addresses of the servers, they must
+    /// be the same as the ones the servers are configured with. `quorum` must be the same as
+    /// the one of the servers.
+    ///
+    /// # Panics
+    ///   If `leader` is not in `addrs`, or the servers in `quorum` are not the same as `addrs`
     #[inline]
-    pub async fn new(leader: usize, addrs: Vec<SocketAddr>) -> Self {
+    pub async fn new(
+        leader: ServerId,
+        addrs: HashMap<ServerId, SocketAddr>,
+        quorum: QuorumConfig,
+    ) -> Self {
+        Self::new_in_group(DEFAULT_GROUP, leader, addrs, quorum).await
+    }
+
+    /// Create a new client of the consensus `group`, see `Client::new`
+    ///
+    /// # Panics
+    ///   If `leader` is not in `addrs`, or the servers in `quorum` are not the same as `addrs`
+    #[inline]
+    #[allow(clippy::panic)] // a misconfigured client is not safe to run
+    pub async fn new_in_group(
+        group: GroupId,
+        leader: ServerId,
+        addrs: HashMap<ServerId, SocketAddr>,
+        quorum: QuorumConfig,
+    ) -> Self {
+        let mut addrs: Vec<_> = addrs.into_iter().collect();
+        addrs.sort_unstable_by_key(|&(id, _)| id);
+        let leader = addrs
+            .iter()
+            .position(|&(id, _)| id == leader)
+            .unwrap_or_else(|| panic!("leader {leader} should be in the addresses"));
+        let rtts = addrs.iter().map(|_| Mutex::new(None)).collect();
         Self {
             leader,
+            rtts,
+            quorum: quorum.for_members(addrs.iter().map(|&(id, _)| id)),
+            batchers: vec![],
+            wire_format: WireFormat::default(),
             connects: rpc::try_connect(
                 // Addrs must start with "http" to communicate with the server
                 addrs
                     .into_iter()
-                    .map(|addr| {
+                    .map(|(id, addr)| {
                         let addr_str = addr.to_string();
-                        if addr_str.starts_with("http") {
+                        let addr_str = if addr_str.starts_with("http") {
                             addr_str
                         } else {
                             format!("http://{addr_str}")
-                        }
+                        };
+                        (id, addr_str)
                     })
                     .collect(),
+                group,
             )
             .await,
             phatom: PhantomData,
         }
     }
 
+    /// Enable the proposal batching, proposals issued by different tasks within a small window
+    /// are sent to each server in one batch. It must be called in a tokio runtime.
+    #[inline]
+    pub fn enable_batching(&mut self, config: BatchConfig) {
+        self.batchers = self
+            .connects
+            .iter()
+            .map(|connect| ProposeBatcher::new(Arc::clone(connect), config, PROPOSE_TIMEOUT))
+            .collect();
+    }
+
+    /// Encode the requests with `format`, the servers decode them with the codec recorded in
+    /// them, so it doesn't need to be the same as the format of the servers
+    #[inline]
+    pub fn set_wire_format(&mut self, format: WireFormat) {
+        self.wire_format = format;
+    }
+
+    /// Smoothed rtt of every replica measured from the propose latencies, the nearest first.
+    /// Replicas that have never responded are put at the end.
+    #[inline]
+    #[must_use]
+    pub fn replica_rtts(&self) -> Vec<ReplicaRtt> {
+        let mut rtts: Vec<_> = self
+            .connects
+            .iter()
+            .zip(self.rtts.iter())
+            .map(|(connect, srtt)| ReplicaRtt {
+                id: connect.id,
+                srtt: *srtt.lock(),
+            })
+            .collect();
+        rtts.sort_by_key(|rtt| (rtt.srtt.is_none(), rtt.srtt));
+        rtts
+    }
+
+    /// Get the consensus state of the server `id`
+    ///
+    /// # Errors
+    ///   `ProposeError::ProtocolError` if `id` is not a server of the cluster
+    ///   `ProposeError::RpcError` if the server doesn't respond
+    #[inline]
+    pub async fn status(&self, id: ServerId) -> Result<ServerStatus, ProposeError> {
+        let connect = self
+            .connects
+            .iter()
+            .find(|connect| connect.id == id)
+            .ok_or_else(|| ProposeError::ProtocolError(format!("unknown server {id}")))?;
+        Ok(connect.status(PROPOSE_TIMEOUT).await?.into_inner().into())
+    }
+
+    /// Update the smoothed rtt of the connection at `idx` with a new sample
+    fn update_rtt(&self, idx: usize, sample: Duration) {
+        if let Some(srtt) = self.rtts.get(idx) {
+            let mut srtt = srtt.lock();
+            *srtt = Some(srtt.map_or(sample, |prev| {
+                prev.saturating_mul(RTT_SMOOTHING.wrapping_sub(1))
+                    .saturating_add(sample)
+                    .checked_div(RTT_SMOOTHING)
+                    .unwrap_or(sample)
+            }));
+        }
+    }
+
+    /// Indexes of the connections a command is proposed to in the fast round. A read-only
+    /// command is only sent to the leader and the nearest replicas whose total weight is enough
+    /// to form the fast quorum, other commands are broadcast to all replicas.
+    fn fast_round_targets(&self, cmd: &C) -> Vec<usize> {
+        if !cmd.is_read_only() {
+            return (0..self.connects.len()).collect();
+        }
+        let mut others: Vec<_> = (0..self.connects.len())
+            .filter(|&idx| idx != self.leader)
+            .map(|idx| {
+                let srtt = self.rtts.get(idx).and_then(|srtt| *srtt.lock());
+                (srtt.is_none(), srtt, idx)
+            })
+            .collect();
+        others.sort_unstable();
+        let mut targets = vec![];
+        let mut target_ids = vec![];
+        for idx in iter::once(self.leader).chain(others.into_iter().map(|(_, _, idx)| idx)) {
+            if self.quorum.is_fast_quorum(target_ids.iter().copied()) {
+                break;
+            }
+            if let Some(connect) = self.connects.get(idx) {
+                targets.push(idx);
+                target_ids.push(connect.id);
+            }
+        }
+        targets
+    }
+
     /// The fast round of Curp protocol
-    /// It broadcast the requests to all the curp servers.
+    /// It sends the requests to the curp servers chosen by `fast_round_targets`.
+    /// Returns the execution result and the ids of the replicas that formed the fast quorum, or
+    /// why the fast quorum is not formed: `ProposeError::KeyConflict` if a replica reported a
+    /// conflict, otherwise the last error met.
     #[instrument(skip(self))]
-    async fn fast_round(
-        &self,
-        cmd_arc: Arc<C>,
-    ) -> Result<(Option<<C as Command>::ER>, bool), ProposeError> {
-        let max_fault = self.connects.len().wrapping_div(2);
+    async fn fast_round(&self, cmd_arc: Arc<C>) -> Result<FastRoundResult<C>, ProposeError> {
         let rpcs = self
-            .connects
-            .iter()
+            .fast_round_targets(&cmd_arc)
+            .into_iter()
+            .filter_map(|idx| self.connects.get(idx).map(|connect| (idx, connect)))
             .zip(iter::repeat_with(|| Arc::clone(&cmd_arc)))
-            .map(|(connect, cmd_cloned)| async move {
-                connect
-                    .propose(ProposeRequest::new_from_rc(cmd_cloned)?, PROPOSE_TIMEOUT)
-                    .await
+            .map(|((idx, connect), cmd_cloned)| async move {
+                let start = Instant::now();
+                let req = ProposeRequest::new_from_rc(cmd_cloned, self.wire_format)?;
+                let resp = match self.batchers.get(idx) {
+                    Some(batcher) => batcher.propose(req).await,
+                    None => connect.propose_multiplexed(req, PROPOSE_TIMEOUT).await,
+                };
+                if resp.is_ok() {
+                    self.update_rtt(idx, start.elapsed());
+                }
+                resp.map(|r| (connect.id, r))
             });
         let mut rpcs: FuturesUnordered<_> = rpcs.collect();
 
-        let mut ok_cnt: usize = 0;
+        let mut accepted: Vec<ServerId> = vec![];
         let mut max_term = 0;
         let mut execute_result: Option<C::ER> = None;
-        let major_cnt = max_fault
-            .wrapping_add(max_fault.wrapping_add(1).wrapping_div(2))
-            .wrapping_add(1);
+        let mut conflict = false;
+        let mut last_error = None;
         while let Some(resp_result) = rpcs.next().await {
-            let resp = match resp_result {
-                Ok(resp) => resp.into_inner(),
+            let (id, resp) = match resp_result {
+                Ok((id, resp)) => (id, resp),
                 Err(e) => {
                     warn!("Propose error: {}", e);
+                    last_error = Some(e);
                     continue;
                 }
             };
             let term_valid = match resp.term() {
                 t if t > max_term => {
                     // state reset
-                    ok_cnt = 0;
+                    accepted.clear();
                     max_term = resp.term();
                     execute_result = None;
+                    conflict = false;
                     true
                 }
                 t if t < max_term => false,
@@ -104,24 +419,42 @@ where
                         if let Some(er) = er {
                             execute_result = Some(er);
                         }
-                        ok_cnt = ok_cnt.wrapping_add(1);
+                        accepted.push(id);
                         Ok(())
                     },
                     |err| {
-                        if let ProposeError::ExecutionError(_) = err {
-                            // Only `ProposeError::ExecutionError` will be reported to upper function
-                            return Err(err);
+                        match err {
+                            // Only the execution errors will be reported to upper function
+                            ProposeError::ExecutionError(_) | ProposeError::Rejected { .. } => {
+                                return Err(err)
+                            }
+                            ProposeError::KeyConflict => conflict = true,
+                            ProposeError::SyncedError(_)
+                            | ProposeError::RpcError(_)
+                            | ProposeError::RpcStatus(_)
+                            | ProposeError::EncodeError(_)
+                            | ProposeError::ProtocolError(_)
+                            | ProposeError::Timeout
+                            | ProposeError::RouteError(_) => {}
                         }
                         warn!("Propose error: {}", err);
+                        last_error = Some(err);
                         Ok(())
                     },
                 )??;
             }
-            if (ok_cnt >= major_cnt) && execute_result.is_some() {
-                return Ok((execute_result, true));
+            if self.quorum.is_fast_quorum(accepted.iter().copied()) && execute_result.is_some() {
+                return Ok((execute_result, Ok(accepted)));
             }
         }
-        Ok((execute_result, false))
+        let reason = if conflict {
+            ProposeError::KeyConflict
+        } else {
+            last_error.unwrap_or_else(|| {
+                ProposeError::ProtocolError("the fast quorum is not formed".to_owned())
+            })
+        };
+        Ok((execute_result, Err(reason)))
     }
 
     /// The slow round of Curp protocol
@@ -130,11 +463,8 @@ where
         &self,
         cmd_arc: Arc<C>,
     ) -> Result<(<C as Command>::ASR, Option<<C as Command>::ER>), ProposeError> {
-        let mut tr = tonic::Request::new(WaitSyncedRequest::new(cmd_arc.id())?);
+        let req = WaitSyncedRequest::new(cmd_arc.id(), self.wire_format)?;
         let rpc_span = info_span!("client wait_synced");
-        global::get_text_map_propagator(|prop| {
-            prop.inject_context(&rpc_span.context(), &mut InjectMap(tr.metadata_mut()));
-        });
         #[allow(clippy::panic)]
         match self
             .connects
@@ -146,14 +476,11 @@ where
                     self.connects.len()
                 )
             })
-            .get()
-            .await?
-            .wait_synced(tr)
+            .wait_synced_multiplexed(req)
             .instrument(rpc_span)
             .await
         {
             Ok(resp) => {
-                let resp = resp.into_inner();
                 resp.map_success_error::<C, _, _, _>(Ok, |e| Err(ProposeError::SyncedError(e)))
             }
             Err(e) => Err(ProposeError::SyncedError(format!(
@@ -169,52 +496,76 @@ where
     /// # Panics
     ///   If leader index is out of bound of all the connections, panic
     #[inline]
-    #[allow(clippy::too_many_lines)] // FIXME: split to smaller functions
     pub async fn propose(&self, cmd: C) -> Result<C::ER, ProposeError> {
-        let cmd_arc = Arc::new(cmd);
-        let fast_round = self.fast_round(Arc::clone(&cmd_arc));
-        let slow_round = self.slow_round(cmd_arc);
+        self.propose_with_report(cmd).await.map(|(er, _)| er)
+    }
 
+    /// Propose the request to servers, and report which replicas formed the fast quorum
+    /// # Errors
+    ///   `ProposeError::ExecutionError` if execution error is met
+    ///   `ProposeError::SyncedError` error met while syncing logs to followers
+    /// # Panics
+    ///   If leader index is out of bound of all the connections, panic
+    #[inline]
+    pub async fn propose_with_report(
+        &self,
+        cmd: C,
+    ) -> Result<(C::ER, ProposeReport), ProposeError> {
+        self.propose_speculative(Arc::new(cmd))
+            .await
+            .map(|(proposal, report)| (proposal.er, report))
+    }
+
+    /// Propose the request to servers, the speculative result is returned once the fast quorum
+    /// is formed, otherwise the proposal falls back to the slow round
+    async fn propose_speculative(
+        &self,
+        cmd_arc: Arc<C>,
+    ) -> Result<(Proposal<'_, C>, ProposeReport), ProposeError> {
+        let slow_report = || ProposeReport { fast_quorum: None };
+        let no_result = || {
+            ProposeError::ProtocolError(
+                "There's no execution result from both fast and slow round".to_owned(),
+            )
+        };
+        let fast_round = self.fast_round(Arc::clone(&cmd_arc));
+        let slow_round: BoxFuture<'_, _> = Box::pin(self.slow_round(cmd_arc));
         pin_mut!(fast_round);
-        pin_mut!(slow_round);
 
         // Wait for the fast and slow round at the same time
-        match futures::future::select(fast_round, slow_round).await {
-            futures::future::Either::Left((fast_result, slow_round)) => {
-                let (fast_er, success) = fast_result?;
-                if success {
-                    #[allow(clippy::unwrap_used)]
-                    // when success is true fast_er must be Some
-                    Ok(fast_er.unwrap())
-                } else {
-                    let slow_result = slow_round.await?;
-                    if let (_, Some(slow_er)) = slow_result {
-                        return Ok(slow_er);
-                    }
-                    if let Some(er) = fast_er {
-                        return Ok(er);
-                    }
-                    Err(ProposeError::ProtocolError(
-                        "There's no execution result from both fast and slow round".to_owned(),
-                    ))
+        match future::select(fast_round, slow_round).await {
+            Either::Left((fast_result, slow_round)) => match fast_result? {
+                (Some(er), Ok(fast_quorum)) => Ok((
+                    Proposal::new(
+                        er,
+                        Box::pin(async move { slow_round.await.map(|(asr, _)| asr) }),
+                    ),
+                    ProposeReport {
+                        fast_quorum: Some(fast_quorum),
+                    },
+                )),
+                (fast_er, _) => {
+                    let (asr, slow_er) = slow_round.await?;
+                    let er = slow_er.or(fast_er).ok_or_else(no_result)?;
+                    Ok((Proposal::committed_with(er, Ok(asr)), slow_report()))
                 }
-            }
-            futures::future::Either::Right((slow_result, fast_round)) => match slow_result {
-                Ok(slow_er_option) => {
-                    if let (_, Some(slow_er)) = slow_er_option {
-                        return Ok(slow_er);
-                    }
-                    if let (Some(er), _) = fast_round.await? {
-                        Ok(er)
-                    } else {
-                        Err(ProposeError::ProtocolError(
-                            "There's no execution result from both fast and slow round".to_owned(),
-                        ))
-                    }
+            },
+            Either::Right((slow_result, pending_fast_round)) => match slow_result {
+                Ok((asr, slow_er)) => {
+                    let er = match slow_er {
+                        Some(er) => er,
+                        None => pending_fast_round.await?.0.ok_or_else(no_result)?,
+                    };
+                    Ok((Proposal::committed_with(er, Ok(asr)), slow_report()))
                 }
                 Err(e) => {
-                    if let Ok((Some(er), true)) = fast_round.await {
-                        return Ok(er);
+                    if let Ok((Some(er), Ok(fast_quorum))) = pending_fast_round.await {
+                        return Ok((
+                            Proposal::committed_with(er, Err(e)),
+                            ProposeReport {
+                                fast_quorum: Some(fast_quorum),
+                            },
+                        ));
                     }
                     Err(e)
                 }
@@ -222,6 +573,73 @@ where
         }
     }
 
+    /// Propose the request to servers with per-call options. The returned proposal carries the
+    /// execution result, and can be waited until the command is committed.
+    /// # Errors
+    ///   `ProposeError::ExecutionError` if execution error is met
+    ///   `ProposeError::SyncedError` error met while syncing logs to followers
+    ///   `ProposeError::KeyConflict` if the fast quorum is not formed in `ProposeMode::FastOnly`
+    ///   `ProposeError::Timeout` if the execution result is not returned in time
+    /// # Panics
+    ///   If leader index is out of bound of all the connections, panic
+    #[inline]
+    pub async fn propose_with(
+        &self,
+        cmd: C,
+        options: ProposeOptions,
+    ) -> Result<Proposal<'_, C>, ProposeError> {
+        let proposal = self.propose_in_mode(cmd, options.mode);
+        match options.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, proposal)
+                .await
+                .map_err(|_elapsed| ProposeError::Timeout)?,
+            None => proposal.await,
+        }
+    }
+
+    /// Propose the request to servers, the execution result is returned as required by `mode`
+    async fn propose_in_mode(
+        &self,
+        cmd: C,
+        mode: ProposeMode,
+    ) -> Result<Proposal<'_, C>, ProposeError> {
+        let cmd_arc = Arc::new(cmd);
+        match mode {
+            ProposeMode::Speculative => self
+                .propose_speculative(cmd_arc)
+                .await
+                .map(|(proposal, _report)| proposal),
+            ProposeMode::FastOnly => {
+                let slow_round: BoxFuture<'_, _> = Box::pin(self.slow_round(Arc::clone(&cmd_arc)));
+                match self.fast_round(cmd_arc).await? {
+                    (Some(er), Ok(_)) => Ok(Proposal::new(
+                        er,
+                        Box::pin(async move { slow_round.await.map(|(asr, _)| asr) }),
+                    )),
+                    (None, Ok(_)) => Err(ProposeError::ProtocolError(
+                        "There's no execution result from the fast round".to_owned(),
+                    )),
+                    // only a conflict reported by the replicas is a `KeyConflict`
+                    (_, Err(reason)) => Err(reason),
+                }
+            }
+            ProposeMode::Synced => {
+                let (fast_result, slow_result) = future::join(
+                    self.fast_round(Arc::clone(&cmd_arc)),
+                    self.slow_round(cmd_arc),
+                )
+                .await;
+                let (asr, slow_er) = slow_result?;
+                let er = slow_er.or(fast_result?.0).ok_or_else(|| {
+                    ProposeError::ProtocolError(
+                        "There's no execution result from both fast and slow round".to_owned(),
+                    )
+                })?;
+                Ok(Proposal::committed_with(er, Ok(asr)))
+            }
+        }
+    }
+
     /// Propose a command and wait for the synced index
     /// # Errors
     ///   `ProposeError::SyncedError` error met while syncing logs to followers
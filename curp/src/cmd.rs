@@ -33,6 +33,13 @@ pub trait Command:
     /// Get propose id
     fn id(&self) -> &ProposeId;
 
+    /// Whether the command only reads data. The client sends a read-only command only to the
+    /// nearest replicas that are enough to form the fast quorum.
+    #[inline]
+    fn is_read_only(&self) -> bool {
+        false
+    }
+
     /// Execute the command according to the executor
     #[inline]
     async fn execute<E>(&self, e: &E) -> Result<Self::ER, ExecuteError>
@@ -64,6 +71,13 @@ impl ProposeId {
     pub fn new(id: String) -> Self {
         Self(id)
     }
+
+    /// Get the underlying string of the propose id
+    #[inline]
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
 }
 
 /// Check conflict of two keys
@@ -95,4 +109,18 @@ where
 
     /// Execute the after_sync callback
     async fn after_sync(&self, cmd: &C, index: LogIndex) -> Result<C::ASR, ExecuteError>;
+
+    /// Execute the `after_sync` callback for a batch of committed cmds, which are given in log order
+    /// together with their log indices. The returned results must be in the same order as `cmds`.
+    ///
+    /// Executors backed by a persistent storage can override this to commit the whole batch in one
+    /// transaction. The default implementation calls `after_sync` on each cmd one by one.
+    #[inline]
+    async fn after_sync_batch(&self, cmds: &[(&C, LogIndex)]) -> Vec<Result<C::ASR, ExecuteError>> {
+        let mut asrs = Vec::with_capacity(cmds.len());
+        for &(cmd, index) in cmds {
+            asrs.push(self.after_sync(cmd, index).await);
+        }
+        asrs
+    }
 }
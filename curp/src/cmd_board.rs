@@ -2,7 +2,7 @@ use std::collections::HashMap;
 
 use event_listener::Event;
 
-use crate::{cmd::ProposeId, rpc::WaitSyncedResponse};
+use crate::{cmd::ProposeId, error::CodecError, rpc::WaitSyncedResponse};
 
 /// Command board is a buffer to store command execution result for `wait_synced` requests
 // TODO: GC
@@ -24,7 +24,7 @@ impl CommandBoard {
 }
 
 /// The state of a command in cmd watch board
-/// (`EarlyArrive` -> ) `Execute` -> `AfterSync` -> `FinalResponse`
+/// (`EarlyArrive` -> ) `Execute` -> `AfterSync` -> `Applying` -> `FinalResponse`
 // TODO: this struct might me removed. We don't need to store whether the command needs execution after sync in one place. We can attach it to SyncMessage.
 #[derive(Debug)]
 pub(crate) enum CmdState {
@@ -34,6 +34,8 @@ pub(crate) enum CmdState {
     Execute,
     /// Command still needs not execute
     AfterSync,
+    /// Command is sent to the executor to be after synced, a duplicate of it in the log is skipped
+    Applying,
     /// Command gotten the final result
-    FinalResponse(Result<WaitSyncedResponse, bincode::Error>),
+    FinalResponse(Result<WaitSyncedResponse, CodecError>),
 }
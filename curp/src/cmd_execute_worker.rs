@@ -5,7 +5,7 @@ use tracing::{debug, warn};
 
 use crate::{
     channel::key_spmc::SpmcKeyBasedReceiver,
-    cmd::{Command, CommandExecutor},
+    cmd::{Command, CommandExecutor, ConflictCheck},
     error::ExecuteError,
     LogIndex,
 };
@@ -22,29 +22,16 @@ pub(crate) async fn execute_worker<C: Command + 'static, CE: 'static + CommandEx
         #[allow(clippy::unwrap_used)]
         // it's a hack to bypass the map_msg(you can't do await in map_msg)
         // TODO: is there a better way to mark a spmc msg done instead of sending the msg back
-        let ExecuteMessage { cmd, er_tx } = msg_wrapped.map_msg(Option::take).unwrap();
+        let msg = msg_wrapped.map_msg(Option::take).unwrap();
 
-        match er_tx {
-            ExecuteResultSender::Execute(tx) => {
+        match msg {
+            ExecuteMessage::Execute(cmd, tx) => {
                 let er = cmd.execute(ce.as_ref()).await;
                 debug!("cmd {:?} is executed", cmd.id());
                 let _ignore = tx.send(er); // it's ok to ignore the result here because sometimes the result is not needed
             }
-            ExecuteResultSender::AfterSync(tx, index) => {
-                let asr = cmd.after_sync(ce.as_ref(), index).await;
-                debug!("cmd {:?} after sync is called", cmd.id());
-                let _ignore = tx.send(asr); // it's ok to ignore the result here because sometimes the result is not needed
-            }
-            ExecuteResultSender::ExecuteAndAfterSync(tx, index) => {
-                let er = cmd.execute(ce.as_ref()).await;
-                debug!("cmd {:?} is executed", cmd.id());
-                #[allow(clippy::if_then_some_else_none)] // you can't do await in closure
-                let asr = if er.is_ok() {
-                    Some(cmd.after_sync(ce.as_ref(), index).await)
-                } else {
-                    None
-                };
-                let _ignore = tx.send((er, asr)); // it's ok to ignore the result here because sometimes the result is not needed
+            ExecuteMessage::AfterSyncBatch(_, entries) => {
+                after_sync_batch(entries, ce.as_ref()).await;
             }
         }
 
@@ -54,29 +41,95 @@ pub(crate) async fn execute_worker<C: Command + 'static, CE: 'static + CommandEx
     }
 }
 
-/// Messages sent to the background cmd execution task
-pub(crate) struct ExecuteMessage<C: Command + 'static> {
-    /// The cmd to be executed
-    pub(crate) cmd: Arc<C>,
-    /// Send execution result
-    er_tx: ExecuteResultSender<C>,
+/// Handle the cmds in the batch in log order. A cmd that still needs execution is executed after
+/// all cmds before it have been after synced, so consecutive cmds are after synced together with
+/// `after_sync_batch`. `after_sync` won't be called on cmds whose execution failed.
+async fn after_sync_batch<C: Command + 'static, CE: 'static + CommandExecutor<C>>(
+    entries: Vec<AfterSyncEntry<C>>,
+    ce: &CE,
+) {
+    let mut pending: Vec<PendingAfterSync<C>> = vec![];
+    for entry in entries {
+        if !entry.need_execute {
+            pending.push((entry, None));
+            continue;
+        }
+        flush_after_sync(&mut pending, ce).await;
+        let er = entry.cmd.execute(ce).await;
+        debug!("cmd {:?} is executed", entry.cmd.id());
+        if er.is_ok() {
+            pending.push((entry, Some(er)));
+        } else {
+            let _ignore = entry.tx.send((Some(er), None)); // it's ok to ignore the result here because sometimes the result is not needed
+        }
+    }
+    flush_after_sync(&mut pending, ce).await;
+}
+
+/// A cmd waiting for `after_sync` with its execution result, if it's executed in the batch
+type PendingAfterSync<C> = (
+    AfterSyncEntry<C>,
+    Option<Result<<C as Command>::ER, ExecuteError>>,
+);
+
+/// Call `after_sync` on all pending cmds in one go and send back their results
+async fn flush_after_sync<C: Command + 'static, CE: 'static + CommandExecutor<C>>(
+    pending: &mut Vec<PendingAfterSync<C>>,
+    ce: &CE,
+) {
+    if pending.is_empty() {
+        return;
+    }
+    let to_sync: Vec<_> = pending
+        .iter()
+        .map(|pending_cmd| (pending_cmd.0.cmd.as_ref(), pending_cmd.0.index))
+        .collect();
+    let asrs = ce.after_sync_batch(&to_sync).await;
+    debug!("after sync is called on a batch of {} cmds", to_sync.len());
+    for ((entry, er), asr) in pending.drain(..).zip(asrs) {
+        let _ignore = entry.tx.send((er, Some(asr))); // it's ok to ignore the result here because sometimes the result is not needed
+    }
 }
 
-/// Channel for transferring execution results
-pub(crate) enum ExecuteResultSender<C: Command + 'static> {
+/// Result of a cmd in an after sync batch: the execution result (if the cmd needs execution)
+/// and the `after_sync` result (if `after_sync` is called)
+pub(crate) type AfterSyncResult<C> = (
+    Option<Result<<C as Command>::ER, ExecuteError>>,
+    Option<Result<<C as Command>::ASR, ExecuteError>>,
+);
+
+/// A committed cmd in an after sync batch
+pub(crate) struct AfterSyncEntry<C: Command + 'static> {
+    /// The cmd
+    cmd: Arc<C>,
+    /// Log index of the cmd
+    index: LogIndex,
+    /// Whether the cmd needs to be executed before `after_sync`
+    need_execute: bool,
+    /// Send the result of the cmd
+    tx: oneshot::Sender<AfterSyncResult<C>>,
+}
+
+/// Conflicting cmds in an after sync batch with all their keys
+type AfterSyncGroup<C> = (Vec<<C as Command>::K>, Vec<AfterSyncEntry<C>>);
+
+/// Messages sent to the background cmd execution task
+pub(crate) enum ExecuteMessage<C: Command + 'static> {
     /// Only call `execute`
-    Execute(oneshot::Sender<Result<C::ER, ExecuteError>>),
-    /// Only call `after_sync`
-    AfterSync(oneshot::Sender<Result<C::ASR, ExecuteError>>, LogIndex),
-    /// Call both `execute` and `after_sync`
-    #[allow(clippy::type_complexity)] // though complex, it's quite clear
-    ExecuteAndAfterSync(
-        oneshot::Sender<(
-            Result<C::ER, ExecuteError>,
-            Option<Result<C::ASR, ExecuteError>>,
-        )>,
-        LogIndex,
-    ),
+    Execute(Arc<C>, oneshot::Sender<Result<C::ER, ExecuteError>>),
+    /// Call `after_sync` on a batch of conflicting cmds, keys of all cmds in the batch are also
+    /// stored here
+    AfterSyncBatch(Vec<C::K>, Vec<AfterSyncEntry<C>>),
+}
+
+impl<C: Command + 'static> ExecuteMessage<C> {
+    /// Keys of the message, used to dispatch the message to workers
+    pub(crate) fn keys(&self) -> &[C::K] {
+        match *self {
+            ExecuteMessage::Execute(ref cmd, _) => cmd.keys(),
+            ExecuteMessage::AfterSyncBatch(ref keys, _) => keys,
+        }
+    }
 }
 
 /// Send cmd to background execute cmd task
@@ -92,49 +145,53 @@ impl<C: Command + 'static> CmdExecuteSender<C> {
     /// Send cmd to background cmd executor and return a oneshot receiver for the execution result
     pub(crate) fn send_exe(&self, cmd: Arc<C>) -> oneshot::Receiver<Result<C::ER, ExecuteError>> {
         let (tx, rx) = oneshot::channel();
-        if let Err(e) = self.0.send(ExecuteMessage {
-            cmd,
-            er_tx: ExecuteResultSender::Execute(tx),
-        }) {
+        if let Err(e) = self.0.send(ExecuteMessage::Execute(cmd, tx)) {
             warn!("failed to send cmd to background execute cmd task, {e}");
         }
         rx
     }
 
-    /// Send cmd to background cmd executor and return a oneshot receiver for the execution result
-    pub(crate) fn send_after_sync(
+    /// Send a batch of committed cmds, each with its log index and whether it needs execution,
+    /// to background cmd executor and return a oneshot receiver for each cmd's result.
+    ///
+    /// The batch is split into groups of conflicting cmds, each group is sent as a message keyed
+    /// by the keys of its cmds, so that groups that don't conflict are after synced in parallel.
+    pub(crate) fn send_after_sync_batch(
         &self,
-        cmd: Arc<C>,
-        index: LogIndex,
-    ) -> oneshot::Receiver<Result<C::ASR, ExecuteError>> {
-        let (tx, rx) = oneshot::channel();
-        if let Err(e) = self.0.send(ExecuteMessage {
-            cmd,
-            er_tx: ExecuteResultSender::AfterSync(tx, index),
-        }) {
-            warn!("failed to send cmd to background execute cmd task, {e}");
+        cmds: Vec<(Arc<C>, LogIndex, bool)>,
+    ) -> Vec<oneshot::Receiver<AfterSyncResult<C>>> {
+        let mut groups: Vec<AfterSyncGroup<C>> = vec![];
+        let mut rxs = Vec::with_capacity(cmds.len());
+        for (cmd, index, need_execute) in cmds {
+            let (tx, rx) = oneshot::channel();
+            rxs.push(rx);
+            let mut group = (cmd.keys().to_vec(), vec![]);
+            // merge all groups the cmd conflicts with, their cmds are kept in log order
+            let (conflicting, others): (Vec<_>, Vec<_>) =
+                groups.into_iter().partition(|&(ref keys, _)| {
+                    keys.iter()
+                        .any(|key| cmd.keys().iter().any(|k| k.is_conflict(key)))
+                });
+            for (keys, entries) in conflicting {
+                group.0.extend(keys);
+                group.1.extend(entries);
+            }
+            group.1.sort_by_key(|entry| entry.index);
+            group.1.push(AfterSyncEntry {
+                cmd,
+                index,
+                need_execute,
+                tx,
+            });
+            groups = others;
+            groups.push(group);
         }
-        rx
-    }
-
-    /// Send cmd to background cmd executor and return a oneshot receiver for the execution result
-    #[allow(clippy::type_complexity)] // though complex, it's quite clear
-    pub(crate) fn send_exe_and_after_sync(
-        &self,
-        cmd: Arc<C>,
-        index: LogIndex,
-    ) -> oneshot::Receiver<(
-        Result<C::ER, ExecuteError>,
-        Option<Result<C::ASR, ExecuteError>>,
-    )> {
-        let (tx, rx) = oneshot::channel();
-        if let Err(e) = self.0.send(ExecuteMessage {
-            cmd,
-            er_tx: ExecuteResultSender::ExecuteAndAfterSync(tx, index),
-        }) {
-            warn!("failed to send cmd to background execute cmd task, {e}");
+        for (keys, entries) in groups {
+            if let Err(e) = self.0.send(ExecuteMessage::AfterSyncBatch(keys, entries)) {
+                warn!("failed to send cmds to background execute cmd task, {e}");
+            }
         }
-        rx
+        rxs
     }
 }
 
@@ -146,3 +203,129 @@ pub(crate) fn cmd_execute_channel<C: Command + 'static>() -> (
     let (tx, rx) = mpsc::unbounded_channel();
     (CmdExecuteSender(tx), rx)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        cmd::ProposeId,
+        test_utils::test_cmd::{TestCommand, TestCommandResult, TestCommandType, TestExecutor},
+    };
+
+    /// A put of the first key in `keys` whose value is `id`
+    fn put(id: &str, keys: &[&str]) -> Arc<TestCommand> {
+        Arc::new(TestCommand::new(
+            ProposeId::new(id.to_owned()),
+            TestCommandType::Put,
+            keys.iter().map(|&key| key.to_owned()).collect(),
+            Some(id.to_owned()),
+        ))
+    }
+
+    fn ids(entries: &[AfterSyncEntry<TestCommand>]) -> Vec<&str> {
+        entries
+            .iter()
+            .map(|entry| entry.cmd.id().as_str())
+            .collect()
+    }
+
+    /// Receive the keys of the `n` cmds reported by the executor
+    async fn reported(rx: &mut mpsc::Receiver<(TestCommandType, String)>, n: usize) -> Vec<String> {
+        let mut keys = vec![];
+        for _ in 0..n {
+            keys.push(rx.recv().await.unwrap().1);
+        }
+        keys
+    }
+
+    #[tokio::test]
+    async fn default_after_sync_batch_calls_after_sync_in_order() {
+        let (after_sync_tx, mut after_sync_rx) = mpsc::channel(10);
+        let ce = TestExecutor::new(mpsc::channel(1).0, after_sync_tx);
+        let (a, b) = (put("a", &["A"]), put("b", &["B"]));
+        let asrs = ce
+            .after_sync_batch(&[(b.as_ref(), 1), (a.as_ref(), 2)])
+            .await;
+        assert_eq!(
+            asrs.into_iter().map(Result::unwrap).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        assert_eq!(reported(&mut after_sync_rx, 2).await, vec!["B", "A"]);
+    }
+
+    #[tokio::test]
+    async fn cmds_are_executed_after_the_previous_ones_are_after_synced() {
+        // the executions and the after syncs are reported to the same channel, each cmd is
+        // reported by its first key
+        let (report_tx, mut report_rx) = mpsc::channel(10);
+        let ce = TestExecutor::new(report_tx.clone(), report_tx);
+        let (tx, mut rx) = cmd_execute_channel();
+        let rxs = tx.send_after_sync_batch(vec![
+            (put("a", &["a", "A"]), 1, false),
+            (put("b", &["b", "A"]), 2, true),
+            (put("c", &["c", "A"]), 3, false),
+        ]);
+        let entries = match rx.recv().await {
+            Some(ExecuteMessage::AfterSyncBatch(_, entries)) => entries,
+            _ => panic!("an after sync batch should be sent"),
+        };
+        after_sync_batch(entries, &ce).await;
+
+        // after sync a, execute b, after sync b, after sync c
+        assert_eq!(reported(&mut report_rx, 4).await, vec!["a", "b", "b", "c"]);
+        let mut results = vec![];
+        for rx in rxs {
+            let (er, asr) = rx.await.unwrap();
+            results.push((er.map(Result::unwrap), asr.map(Result::unwrap)));
+        }
+        assert_eq!(
+            results,
+            vec![
+                (None, Some(1)),
+                (Some(TestCommandResult::PutResult("b".to_owned())), Some(2)),
+                (None, Some(3))
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn after_sync_batch_is_split_into_conflicting_groups() {
+        let (tx, mut rx) = cmd_execute_channel();
+        let _rxs = tx.send_after_sync_batch(vec![
+            (put("a", &["A"]), 1, false),
+            (put("b", &["B"]), 1, false),
+            (put("c", &["C"]), 2, false),
+            (put("d", &["D"]), 3, false),
+            // joins the groups of a and c
+            (put("e", &["C", "A"]), 4, false),
+        ]);
+        drop(tx);
+
+        let mut groups = vec![];
+        while let Some(msg) = rx.recv().await {
+            let (mut keys, entries) = match msg {
+                ExecuteMessage::AfterSyncBatch(keys, entries) => (keys, entries),
+                ExecuteMessage::Execute(..) => panic!("only after sync batches should be sent"),
+            };
+            keys.sort();
+            groups.push((keys, ids(&entries).join("")));
+        }
+        groups.sort();
+        assert_eq!(
+            groups,
+            vec![
+                (
+                    vec![
+                        "A".to_owned(),
+                        "A".to_owned(),
+                        "C".to_owned(),
+                        "C".to_owned()
+                    ],
+                    "ace".to_owned()
+                ),
+                (vec!["B".to_owned()], "b".to_owned()),
+                (vec!["D".to_owned()], "d".to_owned()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,265 @@
+use std::{fmt::Debug, str::FromStr};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::error::CodecError;
+
+/// Version of the envelope that wraps every encoded value sent on the wire.
+/// An envelope is laid out as `[ENVELOPE_VERSION, codec id, payload..]`.
+pub const ENVELOPE_VERSION: u8 = 1;
+
+/// Length of the envelope header
+const HEADER_LEN: usize = 2;
+
+/// Codec used to serialize commands, results and log entries carried in the opaque `bytes`
+/// fields of curp messages
+pub trait Codec: Debug + Send + Sync + 'static {
+    /// The id written into the envelope, must be unique among all codecs
+    const ID: u8;
+
+    /// Encode a value into the payload of an envelope
+    ///
+    /// # Errors
+    ///   `CodecError::Encode` if the value cannot be encoded
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, CodecError>;
+
+    /// Decode a value from the payload of an envelope
+    ///
+    /// # Errors
+    ///   `CodecError::Decode` if the payload cannot be decoded
+    fn decode<T: DeserializeOwned>(payload: &[u8]) -> Result<T, CodecError>;
+}
+
+/// The compact bincode codec, used by default
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+pub struct Bincode;
+
+impl Codec for Bincode {
+    const ID: u8 = 0;
+
+    #[inline]
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, CodecError> {
+        bincode::serialize(value).map_err(|e| CodecError::Encode(e.to_string()))
+    }
+
+    #[inline]
+    fn decode<T: DeserializeOwned>(payload: &[u8]) -> Result<T, CodecError> {
+        bincode::deserialize(payload).map_err(|e| CodecError::Decode(e.to_string()))
+    }
+}
+
+/// The self-describing json codec. Fields can be added to a struct with `#[serde(default)]`
+/// without breaking servers that run an older version of the struct. Json only has string map
+/// keys, so maps whose keys are not strings or integers must be serialized with `map_as_seq`,
+/// or encoding them fails.
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+pub struct Json;
+
+impl Codec for Json {
+    const ID: u8 = 1;
+
+    #[inline]
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, CodecError> {
+        serde_json::to_vec(value).map_err(|e| CodecError::Encode(e.to_string()))
+    }
+
+    #[inline]
+    fn decode<T: DeserializeOwned>(payload: &[u8]) -> Result<T, CodecError> {
+        serde_json::from_slice(payload).map_err(|e| CodecError::Decode(e.to_string()))
+    }
+}
+
+/// The codec used to encode values sent on the wire. Values are always decoded with the codec
+/// recorded in their envelope, so servers running different formats can talk to each other
+/// during a rolling upgrade.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum WireFormat {
+    /// Use the `Bincode` codec
+    #[default]
+    Bincode,
+    /// Use the `Json` codec
+    Json,
+}
+
+impl WireFormat {
+    /// The id of the codec
+    fn id(self) -> u8 {
+        match self {
+            WireFormat::Bincode => Bincode::ID,
+            WireFormat::Json => Json::ID,
+        }
+    }
+
+    /// Get the format from a codec id
+    fn from_id(id: u8) -> Option<Self> {
+        match id {
+            Bincode::ID => Some(WireFormat::Bincode),
+            Json::ID => Some(WireFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for WireFormat {
+    type Err = String;
+
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bincode" => Ok(WireFormat::Bincode),
+            "json" => Ok(WireFormat::Json),
+            _ => Err(format!(
+                "unknown wire format: {s}, expected bincode or json"
+            )),
+        }
+    }
+}
+
+/// Serialize a map as a sequence of key-value pairs, so that the `Json` codec can encode maps
+/// whose keys are not strings, e.g. `#[serde(with = "curp::codec::map_as_seq")]` on the field
+pub mod map_as_seq {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    /// Serialize the entries of `map` as a sequence of pairs
+    ///
+    /// # Errors
+    ///   If a key or a value cannot be serialized
+    #[inline]
+    pub fn serialize<'a, M, K, V, S>(map: &'a M, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        &'a M: IntoIterator<Item = (&'a K, &'a V)>,
+        K: Serialize + 'a,
+        V: Serialize + 'a,
+        S: Serializer,
+    {
+        serializer.collect_seq(map)
+    }
+
+    /// Deserialize a map from a sequence of pairs
+    ///
+    /// # Errors
+    ///   If the input is not a sequence of pairs
+    #[inline]
+    pub fn deserialize<'de, M, K, V, D>(deserializer: D) -> Result<M, D::Error>
+    where
+        M: FromIterator<(K, V)>,
+        K: Deserialize<'de>,
+        V: Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        Vec::<(K, V)>::deserialize(deserializer).map(|pairs| pairs.into_iter().collect())
+    }
+}
+
+/// Encode a value into an envelope with the codec of `format`
+pub(crate) fn encode<T: Serialize + ?Sized>(
+    value: &T,
+    format: WireFormat,
+) -> Result<Vec<u8>, CodecError> {
+    let payload = match format {
+        WireFormat::Bincode => Bincode::encode(value)?,
+        WireFormat::Json => Json::encode(value)?,
+    };
+    let mut buf = Vec::with_capacity(payload.len().wrapping_add(HEADER_LEN));
+    buf.push(ENVELOPE_VERSION);
+    buf.push(format.id());
+    buf.extend_from_slice(&payload);
+    Ok(buf)
+}
+
+/// Decode a value from an envelope with the codec recorded in it
+pub(crate) fn decode<T: DeserializeOwned>(buf: &[u8]) -> Result<T, CodecError> {
+    let (header, payload) = if buf.len() >= HEADER_LEN {
+        buf.split_at(HEADER_LEN)
+    } else {
+        return Err(CodecError::Decode(format!(
+            "envelope is too short, {} bytes",
+            buf.len()
+        )));
+    };
+    match *header {
+        [ENVELOPE_VERSION, id] => match WireFormat::from_id(id) {
+            Some(WireFormat::Bincode) => Bincode::decode(payload),
+            Some(WireFormat::Json) => Json::decode(payload),
+            None => Err(CodecError::UnknownCodec(id)),
+        },
+        [version, _] => Err(CodecError::UnsupportedVersion(version)),
+        _ => unreachable!("header length is {HEADER_LEN}"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::cmd::ProposeId;
+
+    #[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+    #[test]
+    fn decode_envelope_of_any_format() {
+        let id = ProposeId::new("id".to_owned());
+        let bincode_buf = encode(&id, WireFormat::Bincode).unwrap();
+        assert_eq!(bincode_buf[..HEADER_LEN], [ENVELOPE_VERSION, Bincode::ID]);
+
+        let json_buf = encode(&id, WireFormat::Json).unwrap();
+        assert_eq!(json_buf[..HEADER_LEN], [ENVELOPE_VERSION, Json::ID]);
+
+        assert_eq!(decode::<ProposeId>(&bincode_buf).unwrap(), id);
+        assert_eq!(decode::<ProposeId>(&json_buf).unwrap(), id);
+    }
+
+    /// A value with a map whose keys are not strings
+    #[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+    struct Ranges {
+        /// Serialized as a json object, which fails
+        plain: HashMap<(u32, u32), String>,
+        /// Serialized as a sequence of pairs
+        #[serde(with = "map_as_seq")]
+        adapted: HashMap<(u32, u32), String>,
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn json_encodes_non_string_keys_as_pairs() {
+        let ranges = Ranges {
+            plain: HashMap::new(),
+            adapted: HashMap::from([((1, 2), "a".to_owned()), ((3, 4), "b".to_owned())]),
+        };
+        for format in [WireFormat::Bincode, WireFormat::Json] {
+            let buf = encode(&ranges, format).unwrap();
+            assert_eq!(decode::<Ranges>(&buf).unwrap(), ranges);
+        }
+
+        let unadapted = Ranges {
+            plain: ranges.adapted,
+            adapted: HashMap::new(),
+        };
+        assert!(encode(&unadapted, WireFormat::Bincode).is_ok());
+        assert!(matches!(
+            encode(&unadapted, WireFormat::Json),
+            Err(CodecError::Encode(_))
+        ));
+    }
+
+    #[test]
+    fn reject_unknown_envelope() {
+        assert!(matches!(
+            decode::<ProposeId>(&[ENVELOPE_VERSION + 1, Bincode::ID]),
+            Err(CodecError::UnsupportedVersion(_))
+        ));
+        assert!(matches!(
+            decode::<ProposeId>(&[ENVELOPE_VERSION, 42]),
+            Err(CodecError::UnknownCodec(42))
+        ));
+        assert!(matches!(
+            decode::<ProposeId>(&[ENVELOPE_VERSION]),
+            Err(CodecError::Decode(_))
+        ));
+    }
+}
@@ -10,6 +10,15 @@ pub enum ExecuteError {
     /// Command is invalid
     #[error("invalid command {0} ")]
     InvalidCommand(String),
+    /// The command is rejected by the state machine, `code` is defined by the application so
+    /// that its clients can tell the errors apart without parsing the message
+    #[error("{message}")]
+    Rejected {
+        /// The application defined kind of the error
+        code: u32,
+        /// The message of the error
+        message: String,
+    },
     /// Met I/O error while executing
     #[error("meet io related error")]
     IoError(#[from] io::Error),
@@ -47,8 +56,67 @@ pub enum ServerError {
     RpcError(#[from] tonic::transport::Error),
 }
 
+/// Error met when encoding or decoding values sent on the wire
+#[allow(clippy::module_name_repetitions)] // this-error generate code false-positive
+#[non_exhaustive]
+#[derive(Error, Debug)]
+pub enum CodecError {
+    /// The value cannot be encoded
+    #[error("encode error: {0}")]
+    Encode(String),
+    /// The value cannot be decoded
+    #[error("decode error: {0}")]
+    Decode(String),
+    /// The envelope version is not supported by this server
+    #[error("unsupported envelope version {0}")]
+    UnsupportedVersion(u8),
+    /// The codec recorded in the envelope is unknown to this server
+    #[error("unknown codec {0}")]
+    UnknownCodec(u8),
+    /// The value doesn't match its checksum, it's corrupted
+    #[error("checksum mismatch: {0}")]
+    Checksum(String),
+}
+
+/// Error met when validating a quorum config
+#[allow(clippy::module_name_repetitions)] // this-error generate code false-positive
+#[non_exhaustive]
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuorumError {
+    /// All servers weigh 0
+    #[error("no server has a positive weight")]
+    NoVoter,
+    /// The total weight of the servers overflows
+    #[error("total weight of the servers overflows")]
+    WeightOverflow,
+    /// The quorum can never be formed
+    #[error("quorum {quorum} is larger than the total weight {total}")]
+    QuorumTooLarge {
+        /// The quorum
+        quorum: u64,
+        /// Total weight of the servers
+        total: u64,
+    },
+    /// Two slow quorums may not intersect
+    #[error("slow quorum {slow} is not more than half of the total weight {total}")]
+    SlowQuorumTooSmall {
+        /// The slow quorum
+        slow: u64,
+        /// Total weight of the servers
+        total: u64,
+    },
+    /// A command accepted by the fast quorum may not be recovered from a slow quorum
+    #[error("fast quorum {fast} is smaller than {min_fast}, the minimum for the slow quorum")]
+    FastQuorumTooSmall {
+        /// The fast quorum
+        fast: u64,
+        /// The minimum safe fast quorum
+        min_fast: u64,
+    },
+}
+
 /// The error met during propose phase
-#[derive(Error, Debug, Serialize, Deserialize)]
+#[derive(Error, Debug, Clone, Serialize, Deserialize)]
 #[allow(clippy::module_name_repetitions)] // this-error generate code false-positive
 #[non_exhaustive]
 pub enum ProposeError {
@@ -58,6 +126,14 @@ pub enum ProposeError {
     /// Command execution error
     #[error("command execution error {0}")]
     ExecutionError(String),
+    /// The command is rejected by the state machine, see `ExecuteError::Rejected`
+    #[error("command is rejected: {message}")]
+    Rejected {
+        /// The application defined kind of the error
+        code: u32,
+        /// The message of the error
+        message: String,
+    },
     /// Command syncing error
     #[error("syncing error {0}")]
     SyncedError(String),
@@ -73,6 +149,24 @@ pub enum ProposeError {
     /// Protocol error
     #[error("protocol error {0}")]
     ProtocolError(String),
+    /// The proposal is not finished in time
+    #[error("proposal timeout")]
+    Timeout,
+    /// The command cannot be routed to a consensus group
+    #[error("routing error: {0}")]
+    RouteError(String),
+}
+
+impl From<ExecuteError> for ProposeError {
+    #[inline]
+    fn from(e: ExecuteError) -> Self {
+        match e {
+            ExecuteError::Rejected { code, message } => Self::Rejected { code, message },
+            ExecuteError::InvalidCommand(_) | ExecuteError::IoError(_) => {
+                Self::ExecutionError(e.to_string())
+            }
+        }
+    }
 }
 
 impl From<tonic::transport::Error> for ProposeError {
@@ -95,3 +189,10 @@ impl From<bincode::Error> for ProposeError {
         Self::EncodeError(e.to_string())
     }
 }
+
+impl From<CodecError> for ProposeError {
+    #[inline]
+    fn from(e: CodecError) -> Self {
+        Self::EncodeError(e.to_string())
+    }
+}
@@ -0,0 +1,509 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::{self, Debug},
+    sync::{Arc, Weak},
+};
+
+use futures::future;
+use parking_lot::Mutex;
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::{Channel, Endpoint};
+use tracing::{debug, info, warn};
+
+use crate::{
+    bg_tasks::{HEARTBEAT_INTERVAL, RPC_TIMEOUT},
+    client::Client,
+    cmd::Command,
+    error::{ProposeError, ServerError},
+    rpc::{
+        AppendEntriesRequest, AppendEntriesResponse, GroupHeartbeat, GroupHeartbeatResponse,
+        HeartbeatsRequest, HeartbeatsResponse, ProposeRequest, ProposeResponse,
+        ProposeStreamRequest, Protocol, ProtocolClient, ProtocolServer, StatusRequest,
+        StatusResponse, TimeoutNowRequest, TimeoutNowResponse, VoteRequest, VoteResponse,
+        WaitSyncedRequest, WaitSyncedResponse,
+    },
+    server::Rpc,
+};
+
+/// Id of a consensus group
+pub type GroupId = u64;
+
+/// The group of a server or a client that is not created for a specific group
+pub const DEFAULT_GROUP: GroupId = 0;
+
+/// The metadata key that carries the group id of a request
+const GROUP_METADATA_KEY: &str = "curp-group";
+
+/// Mark the request to be handled by `group`
+pub(crate) fn set_group<T>(request: &mut tonic::Request<T>, group: GroupId) {
+    let _prev = request
+        .metadata_mut()
+        .insert(GROUP_METADATA_KEY, group.into());
+}
+
+/// Get the group that handles the request, requests without a group id are handled by the
+/// `DEFAULT_GROUP`
+fn group_of<T>(request: &tonic::Request<T>) -> Result<GroupId, tonic::Status> {
+    request
+        .metadata()
+        .get(GROUP_METADATA_KEY)
+        .map_or(Ok(DEFAULT_GROUP), |value| {
+            value
+                .to_str()
+                .ok()
+                .and_then(|group| group.parse().ok())
+                .ok_or_else(|| tonic::Status::invalid_argument("invalid group id"))
+        })
+}
+
+/// The heartbeat of a group to one of its peers
+pub(crate) struct Heartbeat {
+    /// The group that sends the heartbeat
+    pub(crate) group: GroupId,
+    /// Address of the peer
+    pub(crate) addr: String,
+    /// Position of the peer in the others of the group
+    pub(crate) peer: usize,
+    /// The empty `append_entries`
+    pub(crate) request: AppendEntriesRequest,
+}
+
+/// A group whose heartbeats are sent by a `GroupTransport`
+pub(crate) trait HeartbeatSource: Send + Sync {
+    /// The heartbeats to the peers of the group, it's empty if the server doesn't lead the group
+    fn heartbeats(&self) -> Vec<Heartbeat>;
+
+    /// Handle the response of a peer to the heartbeat
+    fn handle_response(&self, heartbeat: &Heartbeat, resp: AppendEntriesResponse);
+}
+
+/// The heartbeats of the groups to a node, each with the group that sends it
+type HeartbeatBatch = Vec<(Arc<dyn HeartbeatSource>, Heartbeat)>;
+
+/// The connections and heartbeats shared by the groups hosted in a process. The requests of all
+/// groups to a node are multiplexed on one connection, and the heartbeats of all groups to a node
+/// are sent in one `heartbeats` request every heartbeat interval, so neither of them grows with
+/// the number of groups. The groups must be created by `Rpc::new_in_group` with the same
+/// transport, and the nodes must serve the groups by `MultiGroupRpc`.
+pub struct GroupTransport {
+    /// Channels to the nodes, indexed by their addresses
+    channels: Mutex<HashMap<String, Channel>>,
+    /// The groups that send heartbeats, a group is removed once its server is stopped
+    sources: Mutex<Vec<Weak<dyn HeartbeatSource>>>,
+}
+
+impl Debug for GroupTransport {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GroupTransport")
+            .field("nodes", &self.channels.lock().keys().collect::<Vec<_>>())
+            .field("groups", &self.sources.lock().len())
+            .finish()
+    }
+}
+
+impl GroupTransport {
+    /// New `GroupTransport`, the heartbeats of the groups are sent in the background until the
+    /// transport is dropped
+    #[inline]
+    #[must_use]
+    pub fn new() -> Arc<Self> {
+        let transport = Arc::new(Self {
+            channels: Mutex::new(HashMap::new()),
+            sources: Mutex::new(vec![]),
+        });
+        let _handle = tokio::spawn(Self::bg_heartbeats(Arc::downgrade(&transport)));
+        transport
+    }
+
+    /// Get the channel to the node at `addr`, it connects on the first use and reconnects
+    /// after the connection is broken
+    pub(crate) fn channel(&self, addr: String) -> Result<Channel, tonic::transport::Error> {
+        let mut channels = self.channels.lock();
+        if let Some(channel) = channels.get(&addr) {
+            return Ok(channel.clone());
+        }
+        let channel = Endpoint::new(addr.clone())?.connect_lazy();
+        let _prev = channels.insert(addr, channel.clone());
+        Ok(channel)
+    }
+
+    /// Send the heartbeats of `source` with the heartbeats of the other groups
+    pub(crate) fn register(&self, source: &Arc<dyn HeartbeatSource>) {
+        self.sources.lock().push(Arc::downgrade(source));
+    }
+
+    /// Send the heartbeats of all groups every heartbeat interval, the heartbeats to the same
+    /// node are sent in one request
+    async fn bg_heartbeats(transport: Weak<Self>) {
+        loop {
+            tokio::time::sleep(HEARTBEAT_INTERVAL).await;
+            let Some(transport) = transport.upgrade() else {
+                return;
+            };
+            let mut batches: HashMap<String, HeartbeatBatch> = HashMap::new();
+            let sources: Vec<_> = {
+                let mut sources = transport.sources.lock();
+                sources.retain(|source| source.strong_count() > 0);
+                sources.iter().filter_map(Weak::upgrade).collect()
+            };
+            for source in sources {
+                for heartbeat in source.heartbeats() {
+                    batches
+                        .entry(heartbeat.addr.clone())
+                        .or_default()
+                        .push((Arc::clone(&source), heartbeat));
+                }
+            }
+            for (addr, batch) in batches {
+                let _handle = tokio::spawn(Arc::clone(&transport).send_heartbeats(addr, batch));
+            }
+        }
+    }
+
+    /// Send the heartbeats of the groups to the node at `addr` in one request
+    async fn send_heartbeats(self: Arc<Self>, addr: String, batch: HeartbeatBatch) {
+        let mut client = match self.channel(addr.clone()) {
+            Ok(channel) => ProtocolClient::new(channel),
+            Err(e) => {
+                warn!("invalid address {addr}: {e}");
+                return;
+            }
+        };
+        let mut request = tonic::Request::new(HeartbeatsRequest {
+            heartbeats: batch
+                .iter()
+                .map(|entry| GroupHeartbeat {
+                    group: entry.1.group,
+                    heartbeat: Some(entry.1.request.clone()),
+                })
+                .collect(),
+        });
+        request.set_timeout(RPC_TIMEOUT);
+        debug!("{} heartbeats sent to {addr}", batch.len());
+        match client.heartbeats(request).await {
+            Err(e) => warn!("heartbeats error: {e}"),
+            Ok(resp) => {
+                for (entry, group_resp) in batch.iter().zip(resp.into_inner().responses) {
+                    match group_resp.into_result() {
+                        Ok(append_resp) => entry.0.handle_response(&entry.1, append_resp),
+                        Err(e) => warn!("heartbeat of group {} error: {e}", entry.1.group),
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Several consensus groups served on the same address. Every request is dispatched to the
+/// group recorded in its metadata, so the groups share the listener. The groups share the
+/// connections to their peers and their heartbeats if their servers are created with the same
+/// `GroupTransport`, otherwise each of them opens its own connections and sends its own
+/// heartbeats.
+#[derive(Debug)]
+pub struct MultiGroupRpc<C: Command + 'static> {
+    /// The servers of the groups hosted in this process
+    groups: Arc<HashMap<GroupId, Rpc<C>>>,
+}
+
+impl<C: Command + 'static> Clone for MultiGroupRpc<C> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self {
+            groups: Arc::clone(&self.groups),
+        }
+    }
+}
+
+impl<C: Command + 'static> MultiGroupRpc<C> {
+    /// New `MultiGroupRpc`, every server must be created by `Rpc::new_in_group` with its
+    /// group id so that it talks to the servers of the same group
+    #[inline]
+    #[must_use]
+    pub fn new(groups: HashMap<GroupId, Rpc<C>>) -> Self {
+        Self {
+            groups: Arc::new(groups),
+        }
+    }
+
+    /// Get the server of `group`
+    #[inline]
+    #[must_use]
+    pub fn group(&self, group: GroupId) -> Option<&Rpc<C>> {
+        self.groups.get(&group)
+    }
+
+    /// Serve all the groups from a listener
+    ///
+    /// # Errors
+    ///   `ServerError::RpcError` if any rpc related error met
+    #[inline]
+    pub async fn run_from_listener(self, listener: TcpListener) -> Result<(), ServerError> {
+        info!(
+            "RPC server of {} groups started on {:?}",
+            self.groups.len(),
+            listener.local_addr()
+        );
+        tonic::transport::Server::builder()
+            .add_service(ProtocolServer::new(self))
+            .serve_with_incoming(TcpListenerStream::new(listener))
+            .await?;
+        Ok(())
+    }
+
+    /// Get the server that handles the request
+    fn server_of<T>(&self, request: &tonic::Request<T>) -> Result<&Rpc<C>, tonic::Status> {
+        let group = group_of(request)?;
+        self.groups
+            .get(&group)
+            .ok_or_else(|| tonic::Status::not_found(format!("group {group} is not served here")))
+    }
+}
+
+#[tonic::async_trait]
+impl<C: 'static + Command> Protocol for MultiGroupRpc<C> {
+    async fn propose(
+        &self,
+        request: tonic::Request<ProposeRequest>,
+    ) -> Result<tonic::Response<ProposeResponse>, tonic::Status> {
+        Protocol::propose(self.server_of(&request)?, request).await
+    }
+
+    async fn wait_synced(
+        &self,
+        request: tonic::Request<WaitSyncedRequest>,
+    ) -> Result<tonic::Response<WaitSyncedResponse>, tonic::Status> {
+        Protocol::wait_synced(self.server_of(&request)?, request).await
+    }
+
+    async fn append_entries(
+        &self,
+        request: tonic::Request<AppendEntriesRequest>,
+    ) -> Result<tonic::Response<AppendEntriesResponse>, tonic::Status> {
+        Protocol::append_entries(self.server_of(&request)?, request).await
+    }
+
+    async fn heartbeats(
+        &self,
+        request: tonic::Request<HeartbeatsRequest>,
+    ) -> Result<tonic::Response<HeartbeatsResponse>, tonic::Status> {
+        // the heartbeats are handled by their groups concurrently
+        let responses = future::join_all(request.into_inner().heartbeats.into_iter().map(
+            |heartbeat| {
+                let server = self.groups.get(&heartbeat.group);
+                async move {
+                    let result = match (server, heartbeat.heartbeat) {
+                        (Some(server), Some(req)) => {
+                            Protocol::append_entries(server, tonic::Request::new(req))
+                                .await
+                                .map(tonic::Response::into_inner)
+                                .map_err(|status| status.message().to_owned())
+                        }
+                        (None, _) => Err(format!("group {} is not served here", heartbeat.group)),
+                        (_, None) => Err("empty heartbeat".to_owned()),
+                    };
+                    GroupHeartbeatResponse::new(result)
+                }
+            },
+        ))
+        .await;
+        Ok(tonic::Response::new(HeartbeatsResponse { responses }))
+    }
+
+    async fn vote(
+        &self,
+        request: tonic::Request<VoteRequest>,
+    ) -> Result<tonic::Response<VoteResponse>, tonic::Status> {
+        Protocol::vote(self.server_of(&request)?, request).await
+    }
+
+    async fn timeout_now(
+        &self,
+        request: tonic::Request<TimeoutNowRequest>,
+    ) -> Result<tonic::Response<TimeoutNowResponse>, tonic::Status> {
+        Protocol::timeout_now(self.server_of(&request)?, request).await
+    }
+
+    async fn status(
+        &self,
+        request: tonic::Request<StatusRequest>,
+    ) -> Result<tonic::Response<StatusResponse>, tonic::Status> {
+        Protocol::status(self.server_of(&request)?, request).await
+    }
+
+    type ProposeStreamStream = <Rpc<C> as Protocol>::ProposeStreamStream;
+
+    async fn propose_stream(
+        &self,
+        request: tonic::Request<tonic::Streaming<ProposeStreamRequest>>,
+    ) -> Result<tonic::Response<Self::ProposeStreamStream>, tonic::Status> {
+        Protocol::propose_stream(self.server_of(&request)?, request).await
+    }
+}
+
+/// Find the group that owns a key
+pub trait Router<K>: Debug + Send + Sync {
+    /// The group that owns `key`, `None` if no group owns it
+    fn route(&self, key: &K) -> Option<GroupId>;
+}
+
+/// Route keys by ranges, a group owns the keys from the start of its range to the start of the
+/// next range
+#[derive(Debug, Clone)]
+pub struct RangeRouter<K> {
+    /// Group of each range, indexed by the start of the range
+    starts: BTreeMap<K, GroupId>,
+}
+
+impl<K: Ord> RangeRouter<K> {
+    /// New `RangeRouter` that owns no range
+    #[inline]
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            starts: BTreeMap::new(),
+        }
+    }
+
+    /// Let `group` own the keys from `start` to the start of the next range
+    #[inline]
+    #[must_use]
+    pub fn with_range(mut self, start: K, group: GroupId) -> Self {
+        let _prev = self.starts.insert(start, group);
+        self
+    }
+}
+
+impl<K: Ord> Default for RangeRouter<K> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Ord + Debug + Send + Sync> Router<K> for RangeRouter<K> {
+    #[inline]
+    fn route(&self, key: &K) -> Option<GroupId> {
+        self.starts
+            .range(..=key)
+            .next_back()
+            .map(|(_, &group)| group)
+    }
+}
+
+/// A client of several consensus groups, a command is proposed to the group that owns all of
+/// its keys
+#[derive(Debug)]
+pub struct MultiGroupClient<C: Command, R> {
+    /// Clients of the groups
+    clients: HashMap<GroupId, Client<C>>,
+    /// Find the group of a key
+    router: R,
+}
+
+impl<C, R> MultiGroupClient<C, R>
+where
+    C: Command + 'static,
+    R: Router<C::K>,
+{
+    /// New `MultiGroupClient` without any group
+    #[inline]
+    pub fn new(router: R) -> Self {
+        Self {
+            clients: HashMap::new(),
+            router,
+        }
+    }
+
+    /// Add the client of `group`, it should be created by `Client::new_in_group`
+    #[inline]
+    #[must_use]
+    pub fn with_group(mut self, group: GroupId, client: Client<C>) -> Self {
+        let _prev = self.clients.insert(group, client);
+        self
+    }
+
+    /// Get the client of `group`
+    #[inline]
+    #[must_use]
+    pub fn group(&self, group: GroupId) -> Option<&Client<C>> {
+        self.clients.get(&group)
+    }
+
+    /// Find the group that owns all the keys of `cmd`
+    ///
+    /// # Errors
+    ///   `ProposeError::RouteError` if a key is not owned by any group, the keys are owned by
+    ///   different groups or `cmd` has no key
+    #[inline]
+    pub fn route(&self, cmd: &C) -> Result<GroupId, ProposeError> {
+        let mut group = None;
+        for key in cmd.keys() {
+            let owner = self
+                .router
+                .route(key)
+                .ok_or_else(|| ProposeError::RouteError(format!("no group owns key {key:?}")))?;
+            match group {
+                Some(prev) if prev != owner => {
+                    return Err(ProposeError::RouteError(format!(
+                        "keys are owned by groups {prev} and {owner}"
+                    )));
+                }
+                Some(_) | None => group = Some(owner),
+            }
+        }
+        group.ok_or_else(|| ProposeError::RouteError("command has no key".to_owned()))
+    }
+
+    /// Get the client of the group that owns all the keys of `cmd`
+    fn client_of(&self, cmd: &C) -> Result<&Client<C>, ProposeError> {
+        let group = self.route(cmd)?;
+        self.clients
+            .get(&group)
+            .ok_or_else(|| ProposeError::RouteError(format!("no client of group {group}")))
+    }
+
+    /// Propose the command to the group that owns its keys, see `Client::propose`
+    ///
+    /// # Errors
+    ///   `ProposeError::RouteError` if the command cannot be routed to a group
+    ///   `ProposeError::ExecutionError` if execution error is met
+    ///   `ProposeError::SyncedError` error met while syncing logs to followers
+    #[inline]
+    pub async fn propose(&self, cmd: C) -> Result<C::ER, ProposeError> {
+        self.client_of(&cmd)?.propose(cmd).await
+    }
+
+    /// Propose the command to the group that owns its keys, see `Client::propose_indexed`
+    ///
+    /// # Errors
+    ///   `ProposeError::RouteError` if the command cannot be routed to a group
+    ///   `ProposeError::ExecutionError` if execution error is met
+    ///   `ProposeError::SyncedError` error met while syncing logs to followers
+    #[inline]
+    pub async fn propose_indexed(&self, cmd: C) -> Result<(C::ER, C::ASR), ProposeError> {
+        self.client_of(&cmd)?.propose_indexed(cmd).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn route_keys_by_ranges() {
+        let router = RangeRouter::new()
+            .with_range("".to_owned(), 0)
+            .with_range("m".to_owned(), 1)
+            .with_range("t".to_owned(), 2);
+        assert_eq!(router.route(&"a".to_owned()), Some(0));
+        assert_eq!(router.route(&"m".to_owned()), Some(1));
+        assert_eq!(router.route(&"s".to_owned()), Some(1));
+        assert_eq!(router.route(&"z".to_owned()), Some(2));
+
+        let router = RangeRouter::new().with_range(10, 1);
+        assert_eq!(router.route(&9), None);
+        assert_eq!(router.route(&10), Some(1));
+    }
+}
@@ -112,6 +112,9 @@
 /// Client side, sending requests and determining requests' state
 pub mod client;
 
+/// Client side batching of proposals
+mod batch;
+
 /// Server side, handling request and sync requests to the log
 pub mod server;
 
@@ -121,6 +124,15 @@ pub mod error;
 /// The command to be executed
 pub mod cmd;
 
+/// Versioned codec of values sent on the wire
+pub mod codec;
+
+/// Quorums of the fast path and the slow path
+pub mod quorum;
+
+/// Run several consensus groups in one process and route commands to them
+pub mod group;
+
 /// The util lib
 mod util;
 
@@ -151,5 +163,13 @@ mod gc;
 /// Shutdown related
 mod shutdown;
 
-pub use message::LogIndex;
+/// Write-ahead log of the consensus state
+mod wal;
+
+/// Utilities to test the protocol and the services built on it
+#[cfg(feature = "test-utils")]
+pub mod test_utils;
+
+pub use bg_tasks::ELECTION_TIMEOUT;
+pub use message::{LogIndex, ServerId};
 pub use rpc::ProtocolServer;
@@ -1,7 +1,11 @@
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::{ops::Deref, sync::Arc};
 
-use crate::{cmd::Command, message::TermNum};
+use crate::{
+    codec::{Bincode, Codec},
+    error::CodecError,
+    message::TermNum,
+};
 
 /// Log entry
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -12,7 +16,7 @@ pub(crate) struct LogEntry<C> {
     cmds: Arc<[Arc<C>]>,
 }
 
-impl<C: Command> LogEntry<C> {
+impl<C> LogEntry<C> {
     /// Create a new `LogEntry`
     pub(crate) fn new(term: TermNum, cmds: &[Arc<C>]) -> Self {
         Self {
@@ -31,3 +35,184 @@ impl<C: Command> LogEntry<C> {
         &self.cmds
     }
 }
+
+impl<C: Serialize> LogEntry<C> {
+    /// The crc32 of the entry encoded with bincode, it's computed before the entry is pushed
+    /// so that no encoding happens while the log is locked
+    pub(crate) fn crc(&self) -> Result<u32, CodecError> {
+        Ok(crc32fast::hash(&Bincode::encode(self)?))
+    }
+}
+
+/// Consensus log, a fake `log[0]` is always there so that the boundary check is simplified
+#[derive(Debug)]
+pub(crate) struct Log<C> {
+    /// Log entries
+    entries: Vec<LogEntry<C>>,
+    /// Running hash of the log, `hashes[i]` chains the crcs of the entries `log[1..=i]`
+    /// encoded with bincode. Servers with the same log have the same hashes regardless of their
+    /// wire format, so the leader and followers can compare them to detect divergence. The
+    /// hashes are also recorded in the WAL, and a replayed log must have the same ones.
+    hashes: Vec<u32>,
+}
+
+impl<C> Log<C> {
+    /// Create a new `Log` that only contains the fake `log[0]`
+    pub(crate) fn new() -> Self {
+        Self {
+            entries: vec![LogEntry::new(0, &[])],
+            hashes: vec![0],
+        }
+    }
+
+    /// Last log index
+    #[allow(clippy::integer_arithmetic)] // entries.len() >= 1 because we have a fake log[0]
+    pub(crate) fn last_log_index(&self) -> usize {
+        self.entries.len() - 1
+    }
+
+    /// Last log term
+    #[allow(clippy::integer_arithmetic, clippy::indexing_slicing)] // entries.len() >= 1 because we have a fake log[0]
+    pub(crate) fn last_log_term(&self) -> TermNum {
+        self.entries[self.entries.len() - 1].term()
+    }
+
+    /// Running hash of the log up to `index`, `None` if the entry doesn't exist
+    pub(crate) fn hash(&self, index: usize) -> Option<u32> {
+        self.hashes.get(index).copied()
+    }
+
+    /// Running hash of the log after an entry with `crc` is appended to a log with `hash`
+    pub(crate) fn chain_hash(hash: u32, crc: u32) -> u32 {
+        let mut hasher = crc32fast::Hasher::new_with_initial(hash);
+        hasher.update(&crc.to_le_bytes());
+        hasher.finalize()
+    }
+
+    /// Append an entry whose crc is `crc` to the log and update the running hash
+    pub(crate) fn push(&mut self, entry: LogEntry<C>, crc: u32) {
+        self.hashes.push(Self::chain_hash(self.last_hash(), crc));
+        self.entries.push(entry);
+    }
+
+    /// Remove the entries after `log[len - 1]`, the fake `log[0]` is always kept
+    pub(crate) fn truncate(&mut self, len: usize) {
+        let len = len.max(1);
+        self.entries.truncate(len);
+        self.hashes.truncate(len);
+    }
+
+    /// Running hash of the whole log
+    #[allow(clippy::integer_arithmetic, clippy::indexing_slicing)] // hashes.len() >= 1 because we have a fake log[0]
+    fn last_hash(&self) -> u32 {
+        self.hashes[self.hashes.len() - 1]
+    }
+}
+
+impl<C> Deref for Log<C> {
+    type Target = Vec<LogEntry<C>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.entries
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{codec::WireFormat, rpc::AppendEntriesRequest};
+
+    fn entry(term: TermNum, cmd: &str) -> LogEntry<String> {
+        LogEntry::new(term, &[Arc::new(cmd.to_owned())])
+    }
+
+    #[allow(clippy::unwrap_used)]
+    fn log_of(entries: &[LogEntry<String>]) -> Log<String> {
+        let mut log = Log::new();
+        for e in entries {
+            log.push(e.clone(), e.crc().unwrap());
+        }
+        log
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn hash_covers_the_whole_log() {
+        let log = log_of(&[entry(1, "a"), entry(1, "b")]);
+        assert_eq!(log.hash(0), Some(0));
+        assert_eq!(log.hash(3), None);
+
+        // the same entries produce the same hashes
+        let same = log_of(&[entry(1, "a"), entry(1, "b")]);
+        assert_eq!(log.hash(2), same.hash(2));
+
+        // a different term or command changes the hash of the entry and all the later ones
+        let other_term = log_of(&[entry(2, "a"), entry(1, "b")]);
+        assert_ne!(log.hash(1), other_term.hash(1));
+        assert_ne!(log.hash(2), other_term.hash(2));
+        let other_cmd = log_of(&[entry(1, "a"), entry(1, "c")]);
+        assert_eq!(log.hash(1), other_cmd.hash(1));
+        assert_ne!(log.hash(2), other_cmd.hash(2));
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn truncate_and_rehash() {
+        let mut log = log_of(&[entry(1, "a"), entry(1, "b"), entry(1, "c")]);
+        let hash = log.hash(3);
+
+        log.truncate(3);
+        assert_eq!(log.last_log_index(), 2);
+        assert_eq!(log.hash(3), None);
+
+        // pushing the same entry again restores the hash
+        let e = entry(1, "c");
+        log.push(e.clone(), e.crc().unwrap());
+        assert_eq!(log.hash(3), hash);
+
+        // the fake log[0] is always kept
+        log.truncate(0);
+        assert_eq!(log.last_log_index(), 0);
+        assert_eq!(log.hash(0), Some(0));
+        assert_eq!(log.last_log_term(), 0);
+    }
+
+    #[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+    #[test]
+    fn corrupted_entries_are_rejected() {
+        let log = log_of(&[entry(1, "a")]);
+        let new_req = || {
+            AppendEntriesRequest::new(
+                1,
+                0,
+                1,
+                1,
+                log.hash(1).unwrap(),
+                vec![entry(1, "b"), entry(1, "c")],
+                0,
+                WireFormat::Json,
+            )
+            .unwrap()
+        };
+        let req = new_req();
+        assert_eq!(req.prev_log_hash, log.hash(1));
+        assert_eq!(req.entries::<String>().unwrap().len(), 2);
+
+        // a bit flip in an entry
+        let mut flipped = new_req();
+        let last = flipped.entries[1].len() - 1;
+        flipped.entries[1][last] ^= 1;
+        assert!(matches!(
+            flipped.entries::<String>(),
+            Err(CodecError::Checksum(reason)) if reason.contains("log[3]")
+        ));
+
+        // a checksum is missing
+        let mut missing = new_req();
+        let _crc = missing.entry_crcs.pop();
+        assert!(matches!(
+            missing.entries::<String>(),
+            Err(CodecError::Checksum(_))
+        ));
+    }
+}
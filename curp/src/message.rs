@@ -3,3 +3,6 @@ pub(crate) type TermNum = u64;
 
 /// Log Index
 pub type LogIndex = u64;
+
+/// Server Id, it stays the same when the address of the server changes
+pub type ServerId = u64;
@@ -0,0 +1,251 @@
+use std::collections::HashMap;
+
+use crate::{error::QuorumError, message::ServerId, server::Placement};
+
+/// Weight of a server whose region has no configured weight
+const DEFAULT_WEIGHT: u64 = 1;
+
+/// Quorums of the fast path and the slow path, measured in the total weight of the servers.
+///
+/// The slow quorum is used to commit logs and to elect the leader, so any two slow quorums must
+/// intersect. A command accepted by a fast quorum must be recoverable from any slow quorum, so
+/// a fast quorum must cover the majority of every slow quorum.
+///
+/// The default config uses the classic quorums of CURP, where every server weighs 1, the slow
+/// quorum is a plain majority and the fast quorum is the smallest safe superquorum.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct QuorumConfig {
+    /// Weight of every server, empty if the classic quorums of the cluster are used
+    weights: HashMap<ServerId, u64>,
+    /// Total weight required by the fast path
+    fast: u64,
+    /// Total weight required by the slow path
+    slow: u64,
+}
+
+impl QuorumConfig {
+    /// New `QuorumConfig`, `weights` must contain every server in the cluster
+    ///
+    /// # Errors
+    ///   `QuorumError` if the quorums are not safe for the weights
+    #[inline]
+    pub fn new(weights: HashMap<ServerId, u64>, fast: u64, slow: u64) -> Result<Self, QuorumError> {
+        let total = weights
+            .values()
+            .try_fold(0_u64, |sum, &w| sum.checked_add(w))
+            .ok_or(QuorumError::WeightOverflow)?;
+        if total == 0 {
+            return Err(QuorumError::NoVoter);
+        }
+        for quorum in [fast, slow] {
+            if quorum > total {
+                return Err(QuorumError::QuorumTooLarge { quorum, total });
+            }
+        }
+        if slow <= total.wrapping_sub(slow) {
+            return Err(QuorumError::SlowQuorumTooSmall { slow, total });
+        }
+        let min_fast = Self::min_fast(total, slow);
+        if fast < min_fast {
+            return Err(QuorumError::FastQuorumTooSmall { fast, min_fast });
+        }
+        Ok(Self {
+            weights,
+            fast,
+            slow,
+        })
+    }
+
+    /// New `QuorumConfig` whose servers are weighted by their regions, `placements` must contain
+    /// every server in the cluster. A server weighs 1 if its region is not in `region_weights`.
+    ///
+    /// # Errors
+    ///   `QuorumError` if the quorums are not safe for the weights
+    #[inline]
+    pub fn weighted_by_region(
+        placements: &HashMap<ServerId, Placement>,
+        region_weights: &HashMap<String, u64>,
+        fast: u64,
+        slow: u64,
+    ) -> Result<Self, QuorumError> {
+        let weights = placements
+            .iter()
+            .map(|(&id, placement)| {
+                let weight = region_weights
+                    .get(&placement.region)
+                    .copied()
+                    .unwrap_or(DEFAULT_WEIGHT);
+                (id, weight)
+            })
+            .collect();
+        Self::new(weights, fast, slow)
+    }
+
+    /// The classic quorums of a cluster made up of `members`
+    #[inline]
+    #[must_use]
+    pub fn classic(members: impl IntoIterator<Item = ServerId>) -> Self {
+        let weights: HashMap<_, _> = members.into_iter().map(|id| (id, DEFAULT_WEIGHT)).collect();
+        let total: u64 = weights.values().sum();
+        let slow = total.wrapping_div(2).wrapping_add(1);
+        Self {
+            weights,
+            fast: Self::min_fast(total, slow),
+            slow,
+        }
+    }
+
+    /// The smallest fast quorum whose intersection with any slow quorum is a majority of it
+    fn min_fast(total: u64, slow: u64) -> u64 {
+        total
+            .saturating_sub(slow)
+            .saturating_add(slow.wrapping_div(2))
+            .saturating_add(1)
+    }
+
+    /// Resolve the config for the cluster made up of `members`, the classic quorums are used if
+    /// no quorum is configured
+    ///
+    /// # Panics
+    ///   If the configured servers are not the same as `members`
+    #[allow(clippy::panic)] // a misconfigured cluster is not safe to run
+    pub(crate) fn for_members(self, members: impl IntoIterator<Item = ServerId>) -> Self {
+        if self.weights.is_empty() {
+            return Self::classic(members);
+        }
+        let mut members: Vec<_> = members.into_iter().collect();
+        let mut configured: Vec<_> = self.weights.keys().copied().collect();
+        members.sort_unstable();
+        configured.sort_unstable();
+        if members != configured {
+            panic!(
+                "quorum is configured for servers {configured:?}, but the members are {members:?}"
+            );
+        }
+        self
+    }
+
+    /// Weight of a server, 0 if it's not in the cluster
+    #[inline]
+    #[must_use]
+    pub fn weight(&self, id: ServerId) -> u64 {
+        self.weights.get(&id).copied().unwrap_or(0)
+    }
+
+    /// Total weight required by the fast path
+    #[inline]
+    #[must_use]
+    pub fn fast_quorum(&self) -> u64 {
+        self.fast
+    }
+
+    /// Total weight required by the slow path
+    #[inline]
+    #[must_use]
+    pub fn slow_quorum(&self) -> u64 {
+        self.slow
+    }
+
+    /// Total weight of the servers
+    fn weight_of(&self, ids: impl IntoIterator<Item = ServerId>) -> u64 {
+        ids.into_iter()
+            .fold(0, |sum, id| sum.saturating_add(self.weight(id)))
+    }
+
+    /// Whether the servers form a fast quorum
+    pub(crate) fn is_fast_quorum(&self, ids: impl IntoIterator<Item = ServerId>) -> bool {
+        self.weight_of(ids) >= self.fast
+    }
+
+    /// Whether the servers form a slow quorum
+    pub(crate) fn is_slow_quorum(&self, ids: impl IntoIterator<Item = ServerId>) -> bool {
+        self.weight_of(ids) >= self.slow
+    }
+
+    /// Total weight of the voters of a new leader that must hold a command in their speculative
+    /// pools for the leader to recover it. A command accepted by a fast quorum is held by the
+    /// majority of any slow quorum, so it's always recovered.
+    pub(crate) fn recover_quorum(&self) -> u64 {
+        self.slow.wrapping_div(2).wrapping_add(1)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn classic_quorums() {
+        for (n, fast, slow) in [
+            (1, 1, 1),
+            (2, 2, 2),
+            (3, 3, 2),
+            (4, 3, 3),
+            (5, 4, 3),
+            (7, 6, 4),
+        ] {
+            let config = QuorumConfig::classic(0..n);
+            assert_eq!((config.fast_quorum(), config.slow_quorum()), (fast, slow));
+            let weights = (0..n).map(|id| (id, 1)).collect();
+            assert_eq!(QuorumConfig::new(weights, fast, slow), Ok(config));
+        }
+    }
+
+    #[test]
+    fn fast_quorum_is_recovered_from_any_slow_quorum() {
+        for n in 1..10 {
+            let config = QuorumConfig::classic(0..n);
+            // the smallest intersection of a fast quorum and a slow quorum
+            let held = config.fast_quorum() + config.slow_quorum() - n;
+            assert!(held >= config.recover_quorum());
+            // two conflicting commands can't both be recovered
+            assert!(config.recover_quorum() * 2 > config.slow_quorum());
+        }
+    }
+
+    #[test]
+    fn reject_unsafe_quorums() {
+        let weights: HashMap<_, _> = (0..5).map(|id| (id, 1)).collect();
+        assert_eq!(
+            QuorumConfig::new(weights.clone(), 6, 3),
+            Err(QuorumError::QuorumTooLarge {
+                quorum: 6,
+                total: 5
+            })
+        );
+        assert_eq!(
+            QuorumConfig::new(weights.clone(), 5, 2),
+            Err(QuorumError::SlowQuorumTooSmall { slow: 2, total: 5 })
+        );
+        assert_eq!(
+            QuorumConfig::new(weights.clone(), 3, 3),
+            Err(QuorumError::FastQuorumTooSmall {
+                fast: 3,
+                min_fast: 4
+            })
+        );
+        // a larger slow quorum allows a smaller fast quorum
+        assert!(QuorumConfig::new(weights, 3, 5).is_ok());
+        assert_eq!(
+            QuorumConfig::new(HashMap::from([(0, 0)]), 0, 0),
+            Err(QuorumError::NoVoter)
+        );
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn quorums_weighted_by_region() {
+        let placements = HashMap::from([
+            (0, Placement::new("us".to_owned(), 1)),
+            (1, Placement::new("us".to_owned(), 1)),
+            (2, Placement::new("eu".to_owned(), 1)),
+        ]);
+        let region_weights = HashMap::from([("us".to_owned(), 2)]);
+        let config = QuorumConfig::weighted_by_region(&placements, &region_weights, 4, 4).unwrap();
+        assert_eq!(config.weight(2), 1);
+        assert!(config.is_fast_quorum([0, 1]));
+        assert!(config.is_slow_quorum([0, 1]));
+        assert!(!config.is_slow_quorum([0, 2]));
+    }
+}
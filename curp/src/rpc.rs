@@ -15,17 +15,29 @@ mod proto {
 }
 
 use clippy_utilities::NumericCast;
-use serde::Serialize;
-use std::{sync::Arc, time::Duration};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    collections::HashMap,
+    iter,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
 use opentelemetry::global;
-use tokio::sync::RwLock;
-use tracing::{debug, info_span, Instrument};
+use tokio::sync::{mpsc, oneshot, Mutex, RwLock};
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tracing::{debug, info_span, Instrument, Span};
 use tracing_opentelemetry::OpenTelemetrySpanExt;
 
-use crate::error::ExecuteError;
+use crate::codec::{self, WireFormat};
+use crate::error::{CodecError, ExecuteError};
+use crate::group::{self, GroupId, GroupTransport};
 use crate::log::LogEntry;
-use crate::message::TermNum;
+use crate::message::{ServerId, TermNum};
+use crate::server::{PeerStatus, ServerRole, ServerStatus};
 use crate::{
     cmd::{Command, ProposeId},
     error::ProposeError,
@@ -34,30 +46,48 @@ use crate::{
 
 pub(crate) use self::proto::{
     propose_response::ExeResult,
+    propose_stream_request::Request as StreamRequest,
+    propose_stream_response::Response as StreamResponse,
     protocol_client::ProtocolClient,
     protocol_server::Protocol,
     wait_synced_response::{Success, SyncResult},
-    AppendEntriesRequest, AppendEntriesResponse, ProposeRequest, ProposeResponse, VoteRequest,
-    VoteResponse, WaitSyncedRequest, WaitSyncedResponse,
+    AppendEntriesRequest, AppendEntriesResponse, GroupHeartbeat, GroupHeartbeatResponse,
+    HeartbeatsRequest, HeartbeatsResponse, ProposeRequest, ProposeResponse, ProposeStreamRequest,
+    ProposeStreamResponse, StatusRequest, StatusResponse, StreamError, TimeoutNowRequest,
+    TimeoutNowResponse, VoteRequest, VoteResponse, WaitSyncedRequest, WaitSyncedResponse,
+};
+
+use self::proto::{
+    group_heartbeat_response::Result as HeartbeatResult,
+    status_response::{PeerProgress, Role},
 };
 
 pub use self::proto::protocol_server::ProtocolServer;
 
 impl ProposeRequest {
     /// Create a new `Propose` request
-    pub(crate) fn new_from_rc<C, R>(cmd: R) -> bincode::Result<Self>
+    pub(crate) fn new_from_rc<C, R>(cmd: R, format: WireFormat) -> Result<Self, CodecError>
     where
         C: Command,
         R: AsRef<C>,
     {
         Ok(Self {
-            command: bincode::serialize(cmd.as_ref())?,
+            command: codec::encode(cmd.as_ref(), format)?,
+            batch: vec![],
         })
     }
 
-    /// Get command
-    pub(crate) fn cmd<C: Command>(&self) -> bincode::Result<C> {
-        bincode::deserialize(&self.command)
+    /// Create a batched `Propose` request from the encoded commands
+    pub(crate) fn new_batch(command: Vec<u8>, batch: Vec<Vec<u8>>) -> Self {
+        Self { command, batch }
+    }
+
+    /// Get all the commands in the request, including the batched ones
+    pub(crate) fn cmds<C: Command>(&self) -> Result<Vec<C>, CodecError> {
+        iter::once(&self.command)
+            .chain(self.batch.iter())
+            .map(|cmd| codec::decode(cmd))
+            .collect()
     }
 }
 
@@ -67,21 +97,24 @@ impl ProposeResponse {
         is_leader: bool,
         term: u64,
         result: &C::ER,
-    ) -> bincode::Result<Self> {
+        format: WireFormat,
+    ) -> Result<Self, CodecError> {
         Ok(Self {
             is_leader,
             term,
-            exe_result: Some(ExeResult::Result(bincode::serialize(result)?)),
+            exe_result: Some(ExeResult::Result(codec::encode(result, format)?)),
+            batch: vec![],
         })
     }
 
     /// Create an empty propose response
     #[allow(clippy::unnecessary_wraps)] // To keep the new functions return the same type
-    pub(crate) fn new_empty(is_leader: bool, term: u64) -> bincode::Result<Self> {
+    pub(crate) fn new_empty(is_leader: bool, term: u64) -> Result<Self, CodecError> {
         Ok(Self {
             is_leader,
             term,
             exe_result: None,
+            batch: vec![],
         })
     }
 
@@ -90,14 +123,27 @@ impl ProposeResponse {
         is_leader: bool,
         term: u64,
         error: &ProposeError,
-    ) -> bincode::Result<Self> {
+        format: WireFormat,
+    ) -> Result<Self, CodecError> {
         Ok(Self {
             is_leader,
             term,
-            exe_result: Some(ExeResult::Error(bincode::serialize(error)?)),
+            exe_result: Some(ExeResult::Error(codec::encode(error, format)?)),
+            batch: vec![],
         })
     }
 
+    /// Attach the responses of the batched commands
+    pub(crate) fn with_batch(self, batch: Vec<Self>) -> Self {
+        Self { batch, ..self }
+    }
+
+    /// Split the responses of the batched commands from the response
+    pub(crate) fn split_batch(mut self) -> (Self, Vec<Self>) {
+        let batch = std::mem::take(&mut self.batch);
+        (self, batch)
+    }
+
     /// Response term
     pub(crate) fn term(&self) -> u64 {
         self.term
@@ -108,14 +154,14 @@ impl ProposeResponse {
         &self,
         success: SF,
         failure: FF,
-    ) -> bincode::Result<R>
+    ) -> Result<R, CodecError>
     where
         SF: FnOnce(Option<C::ER>) -> R,
         FF: FnOnce(ProposeError) -> R,
     {
         match self.exe_result {
-            Some(ExeResult::Result(ref rv)) => Ok(success(Some(bincode::deserialize(rv)?))),
-            Some(ExeResult::Error(ref e)) => Ok(failure(bincode::deserialize(e)?)),
+            Some(ExeResult::Result(ref rv)) => Ok(success(Some(codec::decode(rv)?))),
+            Some(ExeResult::Error(ref e)) => Ok(failure(codec::decode(e)?)),
             None => Ok(success(None)),
         }
     }
@@ -123,15 +169,15 @@ impl ProposeResponse {
 
 impl WaitSyncedRequest {
     /// Create a `WaitSynced` request
-    pub(crate) fn new(id: &ProposeId) -> bincode::Result<Self> {
+    pub(crate) fn new(id: &ProposeId, format: WireFormat) -> Result<Self, CodecError> {
         Ok(Self {
-            id: bincode::serialize(id)?,
+            id: codec::encode(id, format)?,
         })
     }
 
     /// Get the propose id
-    pub(crate) fn id(&self) -> bincode::Result<ProposeId> {
-        bincode::deserialize(&self.id)
+    pub(crate) fn id(&self) -> Result<ProposeId, CodecError> {
+        codec::decode(&self.id)
     }
 }
 
@@ -140,19 +186,20 @@ impl WaitSyncedResponse {
     pub(crate) fn new_success<C: Command>(
         asr: &C::ASR,
         er: &Option<C::ER>,
-    ) -> bincode::Result<Self> {
+        format: WireFormat,
+    ) -> Result<Self, CodecError> {
         Ok(Self {
             sync_result: Some(SyncResult::Success(Success {
-                after_sync_result: bincode::serialize(&asr)?,
-                exe_result: bincode::serialize(&er)?,
+                after_sync_result: codec::encode(&asr, format)?,
+                exe_result: codec::encode(&er, format)?,
             })),
         })
     }
 
     /// Create an error response
-    pub(crate) fn new_error(err: &str) -> bincode::Result<Self> {
+    pub(crate) fn new_error(err: &str, format: WireFormat) -> Result<Self, CodecError> {
         Ok(Self {
-            sync_result: Some(SyncResult::Error(bincode::serialize(err)?)),
+            sync_result: Some(SyncResult::Error(codec::encode(err, format)?)),
         })
     }
 
@@ -160,32 +207,41 @@ impl WaitSyncedResponse {
     pub(crate) fn new_from_result<C: Command>(
         er: Option<Result<C::ER, ExecuteError>>,
         asr: Option<Result<C::ASR, ExecuteError>>,
-    ) -> bincode::Result<Self> {
+        format: WireFormat,
+    ) -> Result<Self, CodecError> {
         match (er, asr) {
             (None, Some(Err(err))) => {
-                WaitSyncedResponse::new_error(&format!("after sync error: {:?}", err))
+                WaitSyncedResponse::new_error(&format!("after sync error: {:?}", err), format)
             }
-            (None, Some(Ok(asr))) => WaitSyncedResponse::new_success::<C>(&asr, &None),
-            (None, None) => WaitSyncedResponse::new_error("after sync error: no asr result"), // this is highly unlikely to happen,
+            (None, Some(Ok(asr))) => WaitSyncedResponse::new_success::<C>(&asr, &None, format),
+            (None, None) => {
+                WaitSyncedResponse::new_error("after sync error: no asr result", format)
+            } // this is highly unlikely to happen,
             (Some(Err(_)), Some(_)) => {
                 unreachable!("should not call after_sync when exe failed")
             }
-            (Some(Err(err)), None) => {
-                WaitSyncedResponse::new_error(&format!("execution error: {:?}", err))
-            }
+            (Some(Err(err)), None) => Ok(Self {
+                sync_result: Some(SyncResult::ExecuteError(codec::encode(
+                    &ProposeError::from(err),
+                    format,
+                )?)),
+            }),
             (Some(Ok(_er)), Some(Err(err))) => {
                 // FIXME: should er be returned?
-                WaitSyncedResponse::new_error(&format!("after sync error: {:?}", err))
+                WaitSyncedResponse::new_error(&format!("after sync error: {:?}", err), format)
+            }
+            (Some(Ok(er)), Some(Ok(asr))) => {
+                WaitSyncedResponse::new_success::<C>(&asr, &Some(er), format)
             }
-            (Some(Ok(er)), Some(Ok(asr))) => WaitSyncedResponse::new_success::<C>(&asr, &Some(er)),
             (Some(Ok(_er)), None) => {
                 // FIXME: should er be returned?
-                WaitSyncedResponse::new_error("after sync error: no asr result")
+                WaitSyncedResponse::new_error("after sync error: no asr result", format)
             }
         }
     }
 
-    /// Handle response based on the closures
+    /// Handle response based on the closures, the execution error of the command is returned
+    /// as it is
     pub(crate) fn map_success_error<C: Command, SF, SFR, FF>(
         self,
         sf: SF,
@@ -198,56 +254,90 @@ impl WaitSyncedResponse {
         match self.sync_result {
             None => unreachable!("WaitSyncedResponse should contain valid sync_result"),
             Some(SyncResult::Success(success)) => sf((
-                bincode::deserialize(&success.after_sync_result)?,
-                bincode::deserialize(&success.exe_result)?,
+                codec::decode(&success.after_sync_result)?,
+                codec::decode(&success.exe_result)?,
             )),
-            Some(SyncResult::Error(err)) => ff(bincode::deserialize(&err)?),
+            Some(SyncResult::Error(err)) => ff(codec::decode(&err)?),
+            Some(SyncResult::ExecuteError(err)) => Err(codec::decode(&err)?),
         }
     }
 }
 
 impl AppendEntriesRequest {
     /// Create a new `append_entries` request
-    pub(crate) fn new<C: Command + Serialize>(
+    #[allow(clippy::too_many_arguments)] // the fields of the request
+    pub(crate) fn new<C: Serialize>(
         term: TermNum,
+        leader_id: ServerId,
         prev_log_index: usize,
         prev_log_term: TermNum,
+        prev_log_hash: u32,
         entries: Vec<LogEntry<C>>,
         leader_commit: usize,
-    ) -> bincode::Result<Self> {
+        format: WireFormat,
+    ) -> Result<Self, CodecError> {
+        let entries = entries
+            .into_iter()
+            .map(|e| codec::encode(&e, format))
+            .collect::<Result<Vec<Vec<u8>>, CodecError>>()?;
         Ok(Self {
             term,
+            leader_id,
             prev_log_index: prev_log_index.numeric_cast(),
             prev_log_term: prev_log_term.numeric_cast(),
-            entries: entries
-                .into_iter()
-                .map(|e| bincode::serialize(&e))
-                .collect::<bincode::Result<Vec<Vec<u8>>>>()?,
+            entry_crcs: entries.iter().map(|e| crc32fast::hash(e)).collect(),
+            entries,
             leader_commit: leader_commit.numeric_cast(),
+            prev_log_hash: Some(prev_log_hash),
         })
     }
 
     /// Create a new `append_entries` heartbeat request
     pub(crate) fn new_heartbeat(
         term: TermNum,
+        leader_id: ServerId,
         prev_log_index: usize,
         prev_log_term: TermNum,
+        prev_log_hash: u32,
         leader_commit: usize,
     ) -> Self {
         Self {
             term,
+            leader_id,
             prev_log_index: prev_log_index.numeric_cast(),
             prev_log_term: prev_log_term.numeric_cast(),
             entries: vec![],
             leader_commit: leader_commit.numeric_cast(),
+            entry_crcs: vec![],
+            prev_log_hash: Some(prev_log_hash),
         }
     }
 
-    /// Get log entries
-    pub(crate) fn entries<C: Command>(&self) -> bincode::Result<Vec<LogEntry<C>>> {
+    /// Get log entries, the entries are verified against their checksums if the sender
+    /// computed them
+    pub(crate) fn entries<C: DeserializeOwned>(&self) -> Result<Vec<LogEntry<C>>, CodecError> {
+        if !self.entry_crcs.is_empty() {
+            if self.entry_crcs.len() != self.entries.len() {
+                return Err(CodecError::Checksum(format!(
+                    "{} checksums for {} entries",
+                    self.entry_crcs.len(),
+                    self.entries.len()
+                )));
+            }
+            let prev_log_index: usize = self.prev_log_index.numeric_cast();
+            for (i, (entry, &crc)) in self.entries.iter().zip(&self.entry_crcs).enumerate() {
+                let actual = crc32fast::hash(entry);
+                if actual != crc {
+                    return Err(CodecError::Checksum(format!(
+                        "log[{}] has crc {actual:#010x}, expected {crc:#010x}",
+                        prev_log_index.wrapping_add(i).wrapping_add(1)
+                    )));
+                }
+            }
+        }
         self.entries
             .iter()
-            .map(|entry| bincode::deserialize(entry))
+            .map(|entry| codec::decode(entry))
             .collect()
     }
 }
@@ -272,9 +362,36 @@ impl AppendEntriesResponse {
     }
 }
 
+impl GroupHeartbeatResponse {
+    /// Create a new response to a group heartbeat, the error tells why the heartbeat is not
+    /// handled
+    pub(crate) fn new(result: Result<AppendEntriesResponse, String>) -> Self {
+        Self {
+            result: Some(match result {
+                Ok(resp) => HeartbeatResult::Response(resp),
+                Err(err) => HeartbeatResult::Error(err),
+            }),
+        }
+    }
+
+    /// Get the response of the group, or why the heartbeat is not handled
+    pub(crate) fn into_result(self) -> Result<AppendEntriesResponse, String> {
+        match self.result {
+            Some(HeartbeatResult::Response(resp)) => Ok(resp),
+            Some(HeartbeatResult::Error(err)) => Err(err),
+            None => Err("empty heartbeat response".to_owned()),
+        }
+    }
+}
+
 impl VoteRequest {
     /// Create a new vote request
-    pub fn new(term: u64, candidate_id: String, last_log_index: usize, last_log_term: u64) -> Self {
+    pub fn new(
+        term: u64,
+        candidate_id: ServerId,
+        last_log_index: usize,
+        last_log_term: u64,
+    ) -> Self {
         Self {
             term,
             candidate_id,
@@ -285,51 +402,329 @@ impl VoteRequest {
 }
 
 impl VoteResponse {
-    /// Create a new accepted vote response
-    pub fn new_accept(term: TermNum) -> Self {
-        Self {
+    /// Create a new accepted vote response with the commands in the speculative pool of the
+    /// voter, so that the candidate can recover them once it's elected
+    pub fn new_accept<'a, C: Serialize + 'a>(
+        term: TermNum,
+        spec_pool: impl IntoIterator<Item = &'a C>,
+        format: WireFormat,
+    ) -> Result<Self, CodecError> {
+        Ok(Self {
             term: term.numeric_cast(),
             vote_granted: true,
-        }
+            spec_pool: spec_pool
+                .into_iter()
+                .map(|cmd| codec::encode(cmd, format))
+                .collect::<Result<_, _>>()?,
+        })
     }
     /// Create a new rejected vote response
     pub fn new_reject(term: TermNum) -> Self {
         Self {
             term: term.numeric_cast(),
             vote_granted: false,
+            spec_pool: vec![],
+        }
+    }
+
+    /// Get the commands in the speculative pool of the voter
+    pub(crate) fn spec_pool<C: DeserializeOwned>(&self) -> Result<Vec<C>, CodecError> {
+        self.spec_pool
+            .iter()
+            .map(|cmd| codec::decode(cmd))
+            .collect()
+    }
+}
+
+impl TimeoutNowRequest {
+    /// Create a new `timeout_now` request
+    pub(crate) fn new(term: TermNum, leader_id: ServerId) -> Self {
+        Self { term, leader_id }
+    }
+}
+
+impl TimeoutNowResponse {
+    /// Create a new `timeout_now` response
+    pub(crate) fn new(term: TermNum) -> Self {
+        Self { term }
+    }
+}
+
+impl From<ServerStatus> for StatusResponse {
+    #[inline]
+    fn from(status: ServerStatus) -> Self {
+        let mut resp = Self {
+            id: status.id,
+            role: 0,
+            term: status.term,
+            leader_id: status.leader,
+            commit_index: status.commit_index,
+            last_applied: status.last_applied,
+            last_log_index: status.last_log_index,
+            spec_pool_len: status.spec_pool_len.numeric_cast(),
+            peers: status
+                .peers
+                .into_iter()
+                .map(|peer| PeerProgress {
+                    id: peer.id,
+                    next_index: peer.next_index,
+                    match_index: peer.match_index,
+                    last_ack_ms: peer.last_ack.map(|ack| ack.as_millis().numeric_cast()),
+                })
+                .collect(),
+        };
+        resp.set_role(match status.role {
+            ServerRole::Follower => Role::Follower,
+            ServerRole::Candidate => Role::Candidate,
+            ServerRole::Leader => Role::Leader,
+        });
+        resp
+    }
+}
+
+impl From<StatusResponse> for ServerStatus {
+    #[inline]
+    fn from(resp: StatusResponse) -> Self {
+        Self {
+            id: resp.id,
+            role: match resp.role() {
+                Role::Follower => ServerRole::Follower,
+                Role::Candidate => ServerRole::Candidate,
+                Role::Leader => ServerRole::Leader,
+            },
+            term: resp.term,
+            leader: resp.leader_id,
+            commit_index: resp.commit_index,
+            last_applied: resp.last_applied,
+            last_log_index: resp.last_log_index,
+            spec_pool_len: resp.spec_pool_len.numeric_cast(),
+            peers: resp
+                .peers
+                .into_iter()
+                .map(|peer| PeerStatus {
+                    id: peer.id,
+                    next_index: peer.next_index,
+                    match_index: peer.match_index,
+                    last_ack: peer.last_ack_ms.map(Duration::from_millis),
+                })
+                .collect(),
         }
     }
 }
 
+/// A bidirectional propose stream, the requests sent on it are multiplexed by request ids
+#[derive(Debug)]
+struct ProposeStream {
+    /// Send requests to the stream
+    tx: mpsc::UnboundedSender<ProposeStreamRequest>,
+    /// Waiters of the responses, indexed by request ids
+    waiters: Arc<parking_lot::Mutex<HashMap<u64, oneshot::Sender<ProposeStreamResponse>>>>,
+    /// Id of the next request
+    next_id: AtomicU64,
+    /// Whether the response stream is closed
+    closed: Arc<AtomicBool>,
+}
+
+impl ProposeStream {
+    /// Open a propose stream to `group` on the client, `None` if the server doesn't support it
+    async fn open(
+        client: &mut ProtocolClient<tonic::transport::Channel>,
+        group: GroupId,
+    ) -> Result<Option<Self>, tonic::Status> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mut request = tonic::Request::new(UnboundedReceiverStream::new(rx));
+        group::set_group(&mut request, group);
+        let mut inbound = match client.propose_stream(request).await {
+            Ok(resp) => resp.into_inner(),
+            Err(status) if status.code() == tonic::Code::Unimplemented => return Ok(None),
+            Err(status) => return Err(status),
+        };
+        let waiters: Arc<parking_lot::Mutex<HashMap<u64, oneshot::Sender<_>>>> =
+            Arc::new(parking_lot::Mutex::new(HashMap::new()));
+        let closed = Arc::new(AtomicBool::new(false));
+        let (waiters_c, closed_c) = (Arc::clone(&waiters), Arc::clone(&closed));
+        let _handle = tokio::spawn(async move {
+            while let Ok(Some(resp)) = inbound.message().await {
+                if let Some(waiter) = waiters_c.lock().remove(&resp.request_id) {
+                    // the waiter may have timed out
+                    let _ignore = waiter.send(resp);
+                }
+            }
+            closed_c.store(true, Ordering::Relaxed);
+            // fail all the waiters by dropping their senders
+            waiters_c.lock().clear();
+        });
+        Ok(Some(Self {
+            tx,
+            waiters,
+            next_id: AtomicU64::new(0),
+            closed,
+        }))
+    }
+
+    /// Whether the stream can still be used
+    fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Relaxed) || self.tx.is_closed()
+    }
+
+    /// Send a request on the stream and wait for its response
+    async fn call(
+        &self,
+        request: StreamRequest,
+        timeout: Option<Duration>,
+    ) -> Result<StreamResponse, ProposeError> {
+        let request_id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = oneshot::channel();
+        let _prev = self.waiters.lock().insert(request_id, tx);
+        let closed_err = || ProposeError::RpcError("propose stream is closed".to_owned());
+        let mut trace_context = HashMap::new();
+        global::get_text_map_propagator(|prop| {
+            prop.inject_context(&Span::current().context(), &mut trace_context);
+        });
+        if self
+            .tx
+            .send(ProposeStreamRequest {
+                request_id,
+                request: Some(request),
+                trace_context,
+            })
+            .is_err()
+        {
+            let _prev = self.waiters.lock().remove(&request_id);
+            return Err(closed_err());
+        }
+        let resp = match timeout {
+            Some(timeout) => {
+                if let Ok(resp) = tokio::time::timeout(timeout, rx).await {
+                    resp
+                } else {
+                    let _prev = self.waiters.lock().remove(&request_id);
+                    return Err(ProposeError::Timeout);
+                }
+            }
+            None => rx.await,
+        };
+        match resp.map_err(|_closed| closed_err())?.response {
+            // convert it in the same way as the status of a unary rpc
+            Some(StreamResponse::Error(e)) => {
+                Err(tonic::Status::new(tonic::Code::from(e.code), e.message).into())
+            }
+            Some(resp) => Ok(resp),
+            None => Err(ProposeError::ProtocolError(
+                "propose stream response is empty".to_owned(),
+            )),
+        }
+    }
+}
+
+/// State of the propose stream of a connection
+#[derive(Debug)]
+enum StreamState {
+    /// The stream has not been opened or it has been closed
+    Idle,
+    /// The stream is open
+    Open(Arc<ProposeStream>),
+    /// The server doesn't support the propose stream, unary rpcs are used instead until
+    /// `backoff` has elapsed since `since`, the stream is opened again after that in case the
+    /// server has been upgraded
+    Unsupported {
+        /// When the server refused the stream
+        since: Instant,
+        /// How long to wait before opening the stream again
+        backoff: Duration,
+    },
+}
+
+/// Backoff of opening the propose stream again after the server doesn't support it, it's
+/// doubled every time the server refuses it again
+const STREAM_RETRY_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Max backoff of opening the propose stream again
+const MAX_STREAM_RETRY_BACKOFF: Duration = Duration::from_secs(60);
+
 /// The connection struct to hold the real rpc connections, it may failed to connect, but it also
 /// retries the next time
 #[derive(Debug)]
 pub(crate) struct Connect {
-    /// The rpc connection, if it fails it contains a error, otherwise the rpc client is there
-    rpc_connect: RwLock<Result<ProtocolClient<tonic::transport::Channel>, tonic::transport::Error>>,
+    /// Server id
+    pub(crate) id: ServerId,
+    /// The rpc connection, it's `None` if the last connection attempt failed or the address has
+    /// changed, the next `get` will connect again
+    rpc_connect: RwLock<Option<ProtocolClient<tonic::transport::Channel>>>,
     /// The addr used to connect if failing met
-    pub(crate) addr: String,
+    addr: parking_lot::RwLock<String>,
+    /// The propose stream to the server, it's opened on the first use
+    propose_stream: Mutex<StreamState>,
+    /// The consensus group that the requests are sent to
+    group: GroupId,
+    /// The transport shared with the other groups in the process, the requests are sent on its
+    /// connection to the server if it's set
+    transport: Option<Arc<GroupTransport>>,
 }
 
 impl Connect {
+    /// Create a new `Connect` to the server of `group`, the connection is established on the
+    /// first use
+    pub(crate) fn new(
+        id: ServerId,
+        addr: String,
+        group: GroupId,
+        transport: Option<Arc<GroupTransport>>,
+    ) -> Self {
+        Self {
+            id,
+            rpc_connect: RwLock::new(None),
+            addr: parking_lot::RwLock::new(addr),
+            propose_stream: Mutex::new(StreamState::Idle),
+            group,
+            transport,
+        }
+    }
+
+    /// Get the group of the connection
+    pub(crate) fn group(&self) -> GroupId {
+        self.group
+    }
+
+    /// Wrap a message in a request to the group of the connection
+    fn request<T>(&self, message: T) -> tonic::Request<T> {
+        let mut request = tonic::Request::new(message);
+        group::set_group(&mut request, self.group);
+        request
+    }
+
+    /// Get the address of the server
+    pub(crate) fn addr(&self) -> String {
+        self.addr.read().clone()
+    }
+
+    /// Update the address of the server, the following requests will be sent to the new address
+    pub(crate) async fn update_addr(&self, addr: String) {
+        // lock the propose stream first, it's locked before the connection when it's opened
+        let mut stream_state = self.propose_stream.lock().await;
+        let mut connect_write = self.rpc_connect.write().await;
+        debug!("address of server {} is updated to {addr}", self.id);
+        *self.addr.write() = addr;
+        *connect_write = None;
+        *stream_state = StreamState::Idle;
+    }
+
     /// Get the internal rpc connection/client
     pub(crate) async fn get(
         &self,
     ) -> Result<ProtocolClient<tonic::transport::Channel>, tonic::transport::Error> {
-        if let Ok(ref client) = *self.rpc_connect.read().await {
+        if let Some(ref client) = *self.rpc_connect.read().await {
             return Ok(client.clone());
         }
         let mut connect_write = self.rpc_connect.write().await;
-        if let Ok(ref client) = *connect_write {
+        if let Some(ref client) = *connect_write {
             return Ok(client.clone());
         }
-        let client = ProtocolClient::<_>::connect(self.addr.clone())
-            .await
-            .map(|client| {
-                *connect_write = Ok(client.clone());
-                client
-            })?;
-        *connect_write = Ok(client.clone());
+        let client = match self.transport {
+            Some(ref transport) => ProtocolClient::new(transport.channel(self.addr())?),
+            None => ProtocolClient::<_>::connect(self.addr()).await?,
+        };
+        *connect_write = Some(client.clone());
         Ok(client)
     }
 
@@ -340,7 +735,7 @@ impl Connect {
         timeout: Duration,
     ) -> Result<tonic::Response<ProposeResponse>, ProposeError> {
         let mut client = self.get().await?;
-        let mut tr = tonic::Request::new(request);
+        let mut tr = self.request(request);
         tr.set_timeout(timeout);
 
         let rpc_span = info_span!("client propose");
@@ -355,15 +750,120 @@ impl Connect {
             .map_err(Into::into)
     }
 
+    /// Get the propose stream, it's opened if it's not open yet. `None` if the server doesn't
+    /// support it or it cannot be opened now.
+    async fn propose_stream(&self) -> Option<Arc<ProposeStream>> {
+        let mut state = self.propose_stream.lock().await;
+        let backoff = match *state {
+            StreamState::Open(ref stream) if !stream.is_closed() => {
+                return Some(Arc::clone(stream));
+            }
+            StreamState::Unsupported { since, backoff } if since.elapsed() < backoff => {
+                return None
+            }
+            StreamState::Unsupported { backoff, .. } => {
+                backoff.saturating_mul(2).min(MAX_STREAM_RETRY_BACKOFF)
+            }
+            StreamState::Open(_) | StreamState::Idle => STREAM_RETRY_BACKOFF,
+        };
+        let mut client = self.get().await.ok()?;
+        match ProposeStream::open(&mut client, self.group).await {
+            Ok(Some(stream)) => {
+                let stream = Arc::new(stream);
+                *state = StreamState::Open(Arc::clone(&stream));
+                Some(stream)
+            }
+            Ok(None) => {
+                debug!(
+                    "server {} doesn't support the propose stream, retry in {backoff:?}",
+                    self.id
+                );
+                *state = StreamState::Unsupported {
+                    since: Instant::now(),
+                    backoff,
+                };
+                None
+            }
+            Err(e) => {
+                debug!(
+                    "failed to open the propose stream to server {}: {e}",
+                    self.id
+                );
+                *state = StreamState::Idle;
+                None
+            }
+        }
+    }
+
+    /// Send "propose" request on the propose stream, or in a unary rpc if the stream is not
+    /// available
+    pub(crate) async fn propose_multiplexed(
+        &self,
+        request: ProposeRequest,
+        timeout: Duration,
+    ) -> Result<ProposeResponse, ProposeError> {
+        let stream = match self.propose_stream().await {
+            Some(stream) => stream,
+            None => {
+                return self
+                    .propose(request, timeout)
+                    .await
+                    .map(tonic::Response::into_inner);
+            }
+        };
+        match stream
+            .call(StreamRequest::Propose(request), Some(timeout))
+            .instrument(info_span!("client propose"))
+            .await?
+        {
+            StreamResponse::Propose(resp) => Ok(resp),
+            StreamResponse::WaitSynced(_) | StreamResponse::Error(_) => Err(
+                ProposeError::ProtocolError("unexpected propose stream response".to_owned()),
+            ),
+        }
+    }
+
+    /// Send "wait synced" request on the propose stream, or in a unary rpc if the stream is not
+    /// available
+    pub(crate) async fn wait_synced_multiplexed(
+        &self,
+        request: WaitSyncedRequest,
+    ) -> Result<WaitSyncedResponse, ProposeError> {
+        let stream = match self.propose_stream().await {
+            Some(stream) => stream,
+            None => {
+                return self
+                    .wait_synced(request)
+                    .await
+                    .map(tonic::Response::into_inner);
+            }
+        };
+        match stream
+            .call(StreamRequest::WaitSynced(request), None)
+            .await?
+        {
+            StreamResponse::WaitSynced(resp) => Ok(resp),
+            StreamResponse::Propose(_) | StreamResponse::Error(_) => Err(
+                ProposeError::ProtocolError("unexpected propose stream response".to_owned()),
+            ),
+        }
+    }
+
     /// send "wait synced" request
-    #[allow(dead_code)] // false positive report
     pub(crate) async fn wait_synced(
         &self,
         request: WaitSyncedRequest,
     ) -> Result<tonic::Response<WaitSyncedResponse>, ProposeError> {
         let option_client = self.get().await;
+        let mut tr = self.request(request);
+        global::get_text_map_propagator(|prop| {
+            prop.inject_context(
+                &Span::current().context(),
+                &mut InjectMap(tr.metadata_mut()),
+            );
+        });
         match option_client {
-            Ok(mut client) => Ok(client.wait_synced(tonic::Request::new(request)).await?),
+            Ok(mut client) => Ok(client.wait_synced(tr).await?),
             Err(e) => Err(e.into()),
         }
     }
@@ -375,7 +875,7 @@ impl Connect {
         timeout: Duration,
     ) -> Result<tonic::Response<AppendEntriesResponse>, ProposeError> {
         let option_client = self.get().await;
-        let mut req = tonic::Request::new(request);
+        let mut req = self.request(request);
         req.set_timeout(timeout);
         match option_client {
             Ok(mut client) => Ok(client.append_entries(req).await?),
@@ -390,30 +890,66 @@ impl Connect {
         timeout: Duration,
     ) -> Result<tonic::Response<VoteResponse>, ProposeError> {
         let option_client = self.get().await;
-        let mut req = tonic::Request::new(request);
+        let mut req = self.request(request);
         req.set_timeout(timeout);
         match option_client {
             Ok(mut client) => Ok(client.vote(req).await?),
             Err(e) => Err(e.into()),
         }
     }
+
+    /// Send `Status` request
+    pub(crate) async fn status(
+        &self,
+        timeout: Duration,
+    ) -> Result<tonic::Response<StatusResponse>, ProposeError> {
+        let option_client = self.get().await;
+        let mut req = self.request(StatusRequest {});
+        req.set_timeout(timeout);
+        match option_client {
+            Ok(mut client) => Ok(client.status(req).await?),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Send `TimeoutNow` request
+    pub(crate) async fn timeout_now(
+        &self,
+        request: TimeoutNowRequest,
+        timeout: Duration,
+    ) -> Result<tonic::Response<TimeoutNowResponse>, ProposeError> {
+        let option_client = self.get().await;
+        let mut req = self.request(request);
+        req.set_timeout(timeout);
+        match option_client {
+            Ok(mut client) => Ok(client.timeout_now(req).await?),
+            Err(e) => Err(e.into()),
+        }
+    }
 }
 
-/// Convert a vec of addr string to a vec of `Connect`
-pub(crate) async fn try_connect(addrs: Vec<String>) -> Vec<Arc<Connect>> {
+/// Convert a vec of server ids and addr strings to a vec of `Connect` to the servers of `group`
+pub(crate) async fn try_connect(
+    addrs: Vec<(ServerId, String)>,
+    group: GroupId,
+) -> Vec<Arc<Connect>> {
     futures::future::join_all(
         addrs
             .iter()
-            .map(|addr| ProtocolClient::<_>::connect(addr.clone())),
+            .map(|&(_, ref addr)| ProtocolClient::<_>::connect(addr.clone())),
     )
     .await
     .into_iter()
     .zip(addrs.into_iter())
-    .map(|(conn, addr)| {
-        debug!("successfully establish connection with {addr}");
+    .map(|(conn, (id, addr))| {
+        debug!("successfully establish connection with {id}({addr})");
         Arc::new(Connect {
-            rpc_connect: RwLock::new(conn),
-            addr,
+            id,
+            rpc_connect: RwLock::new(conn.ok()),
+            addr: parking_lot::RwLock::new(addr),
+            propose_stream: Mutex::new(StreamState::Idle),
+            group,
+            transport: None,
         })
     })
     .collect()
@@ -1,18 +1,30 @@
 use std::{
     cmp::{min, Ordering},
-    collections::{HashMap, VecDeque},
+    collections::{HashMap, HashSet, VecDeque},
     fmt::Debug,
+    iter,
+    path::PathBuf,
     sync::Arc,
+    time::Duration,
     vec,
 };
 
 use clippy_utilities::NumericCast;
 use event_listener::Event;
+use futures::future;
 use opentelemetry::global;
-use parking_lot::{lock_api::RwLockUpgradableReadGuard, Mutex, RwLock};
-use tokio::{net::TcpListener, sync::broadcast, time::Instant};
-use tokio_stream::wrappers::TcpListenerStream;
-use tracing::{debug, error, info, instrument, Span};
+use parking_lot::{
+    lock_api::{RwLockUpgradableReadGuard, RwLockWriteGuard},
+    Mutex, RwLock,
+};
+use tokio::{
+    net::TcpListener,
+    sync::{broadcast, mpsc, watch},
+    task::JoinSet,
+    time::Instant,
+};
+use tokio_stream::wrappers::{TcpListenerStream, UnboundedReceiverStream};
+use tracing::{debug, error, info, info_span, instrument, Instrument, Span};
 use tracing_opentelemetry::OpenTelemetrySpanExt;
 
 use crate::{
@@ -21,21 +33,33 @@ use crate::{
     cmd::{Command, CommandExecutor, ProposeId},
     cmd_board::{CmdState, CommandBoard},
     cmd_execute_worker::{cmd_execute_channel, CmdExecuteSender},
-    error::{ProposeError, ServerError},
+    codec::WireFormat,
+    error::{CodecError, ProposeError, ServerError},
     gc::run_gc_tasks,
-    log::LogEntry,
-    message::TermNum,
+    group::{GroupId, GroupTransport, DEFAULT_GROUP},
+    log::{Log, LogEntry},
+    message::{ServerId, TermNum},
+    quorum::QuorumConfig,
     rpc::{
-        AppendEntriesRequest, AppendEntriesResponse, ProposeRequest, ProposeResponse,
-        ProtocolServer, VoteRequest, VoteResponse, WaitSyncedRequest, WaitSyncedResponse,
+        AppendEntriesRequest, AppendEntriesResponse, Connect, HeartbeatsRequest,
+        HeartbeatsResponse, ProposeRequest, ProposeResponse, ProposeStreamRequest,
+        ProposeStreamResponse, ProtocolServer, StatusRequest, StatusResponse, StreamError,
+        StreamRequest, StreamResponse, TimeoutNowRequest, TimeoutNowResponse, VoteRequest,
+        VoteResponse, WaitSyncedRequest, WaitSyncedResponse,
     },
     shutdown::Shutdown,
     util::{ExtractMap, RwLockMap},
+    wal::{self, Recovered, Wal},
+    LogIndex,
 };
 
 /// Default server serving port
 pub(crate) static DEFAULT_SERVER_PORT: u16 = 12345;
 
+/// Max number of requests handled at the same time on a propose stream, the stream stops
+/// reading new requests until some of them finish
+const MAX_STREAM_IN_FLIGHT: usize = 1024;
+
 /// The Rpc Server to handle rpc requests
 /// This Wrapper is introduced due to the `MadSim` rpc lib
 #[derive(Clone, Debug)]
@@ -72,49 +96,275 @@ impl<C: 'static + Command> crate::rpc::Protocol for Rpc<C> {
         &self,
         request: tonic::Request<AppendEntriesRequest>,
     ) -> Result<tonic::Response<AppendEntriesResponse>, tonic::Status> {
-        self.inner.append_entries(request)
+        self.inner.append_entries(request).await
+    }
+
+    async fn heartbeats(
+        &self,
+        _request: tonic::Request<HeartbeatsRequest>,
+    ) -> Result<tonic::Response<HeartbeatsResponse>, tonic::Status> {
+        Err(tonic::Status::unimplemented(
+            "the heartbeats of groups are only served by MultiGroupRpc",
+        ))
     }
 
     async fn vote(
         &self,
         request: tonic::Request<VoteRequest>,
     ) -> Result<tonic::Response<VoteResponse>, tonic::Status> {
-        self.inner.vote(request)
+        self.inner.vote(request).await
+    }
+
+    async fn timeout_now(
+        &self,
+        request: tonic::Request<TimeoutNowRequest>,
+    ) -> Result<tonic::Response<TimeoutNowResponse>, tonic::Status> {
+        self.inner.timeout_now(request)
+    }
+
+    async fn status(
+        &self,
+        _request: tonic::Request<StatusRequest>,
+    ) -> Result<tonic::Response<StatusResponse>, tonic::Status> {
+        Ok(tonic::Response::new(self.inner.status().into()))
+    }
+
+    type ProposeStreamStream =
+        UnboundedReceiverStream<Result<ProposeStreamResponse, tonic::Status>>;
+
+    async fn propose_stream(
+        &self,
+        request: tonic::Request<tonic::Streaming<ProposeStreamRequest>>,
+    ) -> Result<tonic::Response<Self::ProposeStreamStream>, tonic::Status> {
+        Ok(tonic::Response::new(
+            self.serve_propose_stream(request.into_inner()),
+        ))
     }
 }
 
 impl<C: Command + 'static> Rpc<C> {
-    /// New `Rpc`
+    /// Serve the requests on a propose stream, every request is handled in its own task and its
+    /// response is sent back as soon as it's ready, so responses may be out of order. At most
+    /// `MAX_STREAM_IN_FLIGHT` requests are handled at the same time, and the unfinished ones
+    /// are cancelled when the stream is closed.
+    fn serve_propose_stream(
+        &self,
+        mut inbound: tonic::Streaming<ProposeStreamRequest>,
+    ) -> UnboundedReceiverStream<Result<ProposeStreamResponse, tonic::Status>> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let inner = Arc::clone(&self.inner);
+        let _handle = tokio::spawn(async move {
+            // the tasks are aborted when the set is dropped
+            let mut in_flight = JoinSet::new();
+            loop {
+                #[allow(clippy::integer_arithmetic)] // Introduced by tokio::select!
+                let req = tokio::select! {
+                    req = inbound.message(), if in_flight.len() < MAX_STREAM_IN_FLIGHT => {
+                        match req {
+                            Ok(Some(req)) => req,
+                            Ok(None) | Err(_) => break,
+                        }
+                    }
+                    Some(_res) = in_flight.join_next() => continue,
+                    _closed = tx.closed() => break,
+                };
+                let inner = Arc::clone(&inner);
+                let tx = tx.clone();
+                let _abort_handle = in_flight.spawn(async move {
+                    let response =
+                        Self::serve_stream_request(&inner, req.request, &req.trace_context)
+                            .await
+                            .unwrap_or_else(|status| {
+                                #[allow(clippy::as_conversions)]
+                                // `tonic::Code` is a fieldless enum
+                                StreamResponse::Error(StreamError {
+                                    code: status.code() as i32,
+                                    message: status.message().to_owned(),
+                                })
+                            });
+                    // the stream is closed by the client if sending failed
+                    let _ignore = tx.send(Ok(ProposeStreamResponse {
+                        request_id: req.request_id,
+                        response: Some(response),
+                    }));
+                });
+            }
+        });
+        UnboundedReceiverStream::new(rx)
+    }
+
+    /// Serve a request on the propose stream in the span of the client
+    async fn serve_stream_request(
+        inner: &Protocol<C>,
+        request: Option<StreamRequest>,
+        trace_context: &HashMap<String, String>,
+    ) -> Result<StreamResponse, tonic::Status> {
+        let parent = global::get_text_map_propagator(|prop| prop.extract(trace_context));
+        match request {
+            Some(StreamRequest::Propose(propose)) => {
+                let span = info_span!("server propose");
+                span.set_parent(parent);
+                inner
+                    .propose(tonic::Request::new(propose))
+                    .instrument(span)
+                    .await
+                    .map(|resp| StreamResponse::Propose(resp.into_inner()))
+            }
+            Some(StreamRequest::WaitSynced(wait_synced)) => {
+                let span = info_span!("server wait_synced");
+                span.set_parent(parent);
+                inner
+                    .wait_synced(tonic::Request::new(wait_synced))
+                    .instrument(span)
+                    .await
+                    .map(|resp| StreamResponse::WaitSynced(resp.into_inner()))
+            }
+            None => Err(tonic::Status::invalid_argument("empty stream request")),
+        }
+    }
+
+    /// New `Rpc`, the values it sends are encoded with `wire_format`, see `Protocol::new`
+    ///
+    /// # Panics
+    ///   If the write-ahead log in `data_dir` can't be opened or it's corrupted
     #[inline]
+    #[allow(clippy::too_many_arguments)] // the server is configured in one place, it's ok
     pub fn new<CE: CommandExecutor<C> + 'static>(
-        id: &str,
+        id: ServerId,
         is_leader: bool,
         term: u64,
-        others: Vec<String>,
+        others: HashMap<ServerId, String>,
+        placements: HashMap<ServerId, Placement>,
+        quorum: QuorumConfig,
+        wire_format: WireFormat,
+        data_dir: Option<PathBuf>,
+        executor: CE,
+    ) -> Self {
+        Self::new_in_group(
+            DEFAULT_GROUP,
+            None,
+            id,
+            is_leader,
+            term,
+            others,
+            placements,
+            quorum,
+            wire_format,
+            data_dir,
+            executor,
+        )
+    }
+
+    /// New `Rpc` of the consensus `group`, it talks to the servers of the same group, see
+    /// `MultiGroupRpc`. The connections and heartbeats are shared with the other groups created
+    /// with the same `transport`, see `GroupTransport`.
+    ///
+    /// # Panics
+    ///   If the write-ahead log in `data_dir` can't be opened or it's corrupted
+    #[inline]
+    #[allow(clippy::too_many_arguments)] // the server is configured in one place, it's ok
+    pub fn new_in_group<CE: CommandExecutor<C> + 'static>(
+        group: GroupId,
+        transport: Option<Arc<GroupTransport>>,
+        id: ServerId,
+        is_leader: bool,
+        term: u64,
+        others: HashMap<ServerId, String>,
+        placements: HashMap<ServerId, Placement>,
+        quorum: QuorumConfig,
+        wire_format: WireFormat,
+        data_dir: Option<PathBuf>,
         executor: CE,
     ) -> Self {
         Self {
-            inner: Arc::new(Protocol::new(id, is_leader, term, others, executor)),
+            inner: Arc::new(Protocol::new_in_group(
+                group,
+                transport,
+                id,
+                is_leader,
+                term,
+                others,
+                placements,
+                quorum,
+                wire_format,
+                data_dir,
+                executor,
+            )),
         }
     }
 
+    /// Subscribe to the consensus progress of the server, see `Protocol::progress`
+    #[inline]
+    #[must_use]
+    pub fn progress(&self) -> watch::Receiver<ApplyProgress> {
+        self.inner.progress()
+    }
+
+    /// Get the consensus state of the server, see `Protocol::status`
+    #[inline]
+    #[must_use]
+    pub fn status(&self) -> ServerStatus {
+        self.inner.status()
+    }
+
+    /// Subscribe to the corruption alarms of the server, see `Protocol::alarm`
+    #[inline]
+    #[must_use]
+    pub fn alarm(&self) -> watch::Receiver<Option<CorruptionAlarm>> {
+        self.inner.alarm()
+    }
+
+    /// Get the addresses of other servers, see `Protocol::peer_addrs`
+    #[inline]
+    #[must_use]
+    pub fn peer_addrs(&self) -> HashMap<ServerId, String> {
+        self.inner.peer_addrs()
+    }
+
+    /// Get the placements of all servers, see `Protocol::placements`
+    #[inline]
+    #[must_use]
+    pub fn placements(&self) -> HashMap<ServerId, Placement> {
+        self.inner.placements()
+    }
+
+    /// Update the address of another server, see `Protocol::update_peer_addr`
+    #[inline]
+    pub async fn update_peer_addr(&self, id: ServerId, addr: &str) -> bool {
+        self.inner.update_peer_addr(id, addr).await
+    }
+
     /// Run a new rpc server
     ///
     /// # Errors
     ///   `ServerError::ParsingError` if parsing failed for the local server address
     ///   `ServerError::RpcError` if any rpc related error met
     #[inline]
+    #[allow(clippy::too_many_arguments)] // the server is configured in one place, it's ok
     pub async fn run<CE: CommandExecutor<C> + 'static>(
-        id: &str,
+        id: ServerId,
         is_leader: bool, // TODO: remove this option
         term: u64,
-        others: Vec<String>,
+        others: HashMap<ServerId, String>,
+        placements: HashMap<ServerId, Placement>,
+        quorum: QuorumConfig,
         server_port: Option<u16>,
+        data_dir: Option<PathBuf>,
         executor: CE,
     ) -> Result<(), ServerError> {
         let port = server_port.unwrap_or(DEFAULT_SERVER_PORT);
         info!("RPC server {id} started, listening on port {port}");
-        let server = Self::new(id, is_leader, term, others, executor);
+        let server = Self::new(
+            id,
+            is_leader,
+            term,
+            others,
+            placements,
+            quorum,
+            WireFormat::default(),
+            data_dir,
+            executor,
+        );
 
         tonic::transport::Server::builder()
             .add_service(ProtocolServer::new(server))
@@ -133,17 +383,29 @@ impl<C: Command + 'static> Rpc<C> {
     ///   `ServerError::ParsingError` if parsing failed for the local server address
     ///   `ServerError::RpcError` if any rpc related error met
     #[inline]
+    #[allow(clippy::too_many_arguments)] // the server is configured in one place, it's ok
     pub async fn run_from_listener<CE: CommandExecutor<C> + 'static>(
-        id: &str,
+        id: ServerId,
         is_leader: bool,
         term: u64,
-        others: Vec<String>,
+        others: HashMap<ServerId, String>,
+        placements: HashMap<ServerId, Placement>,
+        quorum: QuorumConfig,
         listener: TcpListener,
+        data_dir: Option<PathBuf>,
         executor: CE,
     ) -> Result<(), ServerError> {
-        let server = Self {
-            inner: Arc::new(Protocol::new(id, is_leader, term, others, executor)),
-        };
+        let server = Self::new(
+            id,
+            is_leader,
+            term,
+            others,
+            placements,
+            quorum,
+            WireFormat::default(),
+            data_dir,
+            executor,
+        );
         tonic::transport::Server::builder()
             .add_service(ProtocolServer::new(server))
             .serve_with_incoming(TcpListenerStream::new(listener))
@@ -207,11 +469,121 @@ impl<C: Command + 'static> SpeculativePool<C> {
     }
 }
 
+/// Default election priority of a server
+const DEFAULT_PRIORITY: u64 = 1;
+
+/// Where a server is placed and how much it's preferred to be the leader
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Placement {
+    /// Region label of the server, e.g. the data center it's located in
+    pub region: String,
+    /// Election priority, the healthy server with the highest priority is preferred to be the
+    /// leader. A server with priority 0 never starts an election.
+    pub priority: u64,
+}
+
+impl Placement {
+    /// New `Placement`
+    #[inline]
+    #[must_use]
+    pub fn new(region: String, priority: u64) -> Self {
+        Self { region, priority }
+    }
+}
+
+impl Default for Placement {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            region: String::new(),
+            priority: DEFAULT_PRIORITY,
+        }
+    }
+}
+
+/// The consensus progress of a server
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ApplyProgress {
+    /// Index of highest log entry known to be committed
+    pub commit_index: LogIndex,
+    /// Index of highest log entry whose cmds have all been executed and after synced
+    pub last_applied: LogIndex,
+    /// Current term
+    pub term: u64,
+    /// Id of the leader in current term, `None` if it's unknown
+    pub leader: Option<ServerId>,
+    /// Index of the first log entry of the server in current term if it's the leader, the
+    /// entries of previous terms are all applied once `last_applied` reaches it
+    pub ready_index: LogIndex,
+}
+
+/// The consensus state of a server
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ServerStatus {
+    /// Id of the server
+    pub id: ServerId,
+    /// Role of the server
+    pub role: ServerRole,
+    /// Current term
+    pub term: u64,
+    /// Id of the leader in current term, `None` if it's unknown
+    pub leader: Option<ServerId>,
+    /// Index of highest log entry known to be committed
+    pub commit_index: LogIndex,
+    /// Index of highest log entry whose cmds have all been executed and after synced, the
+    /// same as `ApplyProgress::last_applied`
+    pub last_applied: LogIndex,
+    /// Index of the last log entry
+    pub last_log_index: LogIndex,
+    /// Number of commands in the speculative pool
+    pub spec_pool_len: usize,
+    /// Replication progress of other servers, it's empty if the server is not the leader
+    pub peers: Vec<PeerStatus>,
+}
+
+/// Replication progress of another server, as seen by the leader
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct PeerStatus {
+    /// Id of the server
+    pub id: ServerId,
+    /// Index of the next log entry to send to the server
+    pub next_index: LogIndex,
+    /// Index of highest log entry known to be replicated on the server
+    pub match_index: LogIndex,
+    /// Time elapsed since the server last responded to the leader, `None` if it never did
+    pub last_ack: Option<Duration>,
+}
+
+/// An alarm raised when the log of the server may be corrupted
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CorruptionAlarm {
+    /// Entries sent by the leader don't match their checksums, they are rejected and the
+    /// leader sends them again
+    EntryChecksum {
+        /// Id of the leader that sent the entries
+        leader: ServerId,
+        /// The reason of the mismatch
+        reason: String,
+    },
+    /// The log of the server differs from the leader's one though the terms of the entries
+    /// match. The server stops accepting entries so that its state isn't corrupted further.
+    LogDivergence {
+        /// Id of the leader
+        leader: ServerId,
+        /// The logs differ at or before this index
+        index: LogIndex,
+    },
+}
+
 /// The server that handles client request and server consensus protocol
 pub struct Protocol<C: Command + 'static> {
     /// Current state
-    // TODO: apply fine-grain locking
-    state: Arc<RwLock<State<C>>>,
+    state: Arc<State<C>>,
     /// Last time a rpc is received
     last_rpc_time: Arc<RwLock<Instant>>,
     /// The speculative cmd pool, shared with executor
@@ -225,92 +597,272 @@ pub struct Protocol<C: Command + 'static> {
     stop_ch_tx: broadcast::Sender<()>,
     /// The channel to send cmds to background exe tasks
     cmd_exe_tx: CmdExecuteSender<C>,
+    /// Connections to other servers, in the same order as `State::others`. It's also the
+    /// id-to-address table of other servers.
+    connects: Vec<Arc<Connect>>,
 }
 
-/// State of the server
+/// A server with a higher priority is considered down if it has sent no request to this server
+/// within this duration, lower priority servers don't wait for it before starting an election
+const PRIORITY_ALIVE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// State of the server, it's split into several parts so that they can be locked separately.
+///
+/// To avoid deadlocks, the locks must be acquired in the order: `election`, `log`,
+/// `replication`, `commit`. `last_heard` and `timeout_now` are always acquired last.
 pub(crate) struct State<C: Command + 'static> {
     /// Id of the server
-    pub(crate) id: String,
+    pub(crate) id: ServerId,
+    /// Other server ids
+    pub(crate) others: Vec<ServerId>,
+    /// Placement of the server
+    pub(crate) placement: Placement,
+    /// Placements of other servers, in the same order as `others`
+    pub(crate) other_placements: Vec<Placement>,
+    /// Quorums of the fast path and the slow path
+    pub(crate) quorum: QuorumConfig,
+    /// The format used to encode the values sent by the server
+    pub(crate) wire_format: WireFormat,
+    /// Term, role and vote of the server
+    pub(crate) election: RwLock<ElectionState>,
+    /// Consensus log
+    pub(crate) log: RwLock<Log<C>>,
+    /// Write-ahead log of the term, the vote and the log. The records are submitted while the
+    /// state they describe is locked, and a server persists them before it tells others about
+    /// the state.
+    pub(crate) wal: Wal<C>,
+    /// Replication progress of other servers, only used by the leader
+    pub(crate) replication: Mutex<Replication>,
+    /// Commit and apply indices
+    pub(crate) commit: RwLock<CommitState>,
+    /// Trigger when a new leader needs to calibrate its followers
+    pub(crate) calibrate_trigger: Arc<Event>,
+    /// Trigger when the leader transfers its leadership to the server
+    pub(crate) timeout_now_trigger: Arc<Event>,
+    /// The term in which the leader transferred its leadership to the server, it's kept until
+    /// the election task takes it so that a transfer is never missed
+    timeout_now: Mutex<Option<TermNum>>,
+    /// For each other server, the last time it sent an `append_entries` or `vote` request to
+    /// this server
+    last_heard: Mutex<Vec<Option<Instant>>>,
+    /// Commands in the speculative pools of the servers that voted for this server in the
+    /// current election, with the total weight of the voters that hold each of them
+    pub(crate) spec_votes: Mutex<HashMap<ProposeId, (Arc<C>, u64)>>,
+    /// Publish the latest corruption alarm
+    alarm_tx: watch::Sender<Option<CorruptionAlarm>>,
+}
+
+/// Term, role and vote of the server
+#[derive(Debug)]
+pub(crate) struct ElectionState {
     /// Role of the server
     role: ServerRole,
     /// Current term
     pub(crate) term: TermNum,
-    /// Consensus log
-    pub(crate) log: Vec<LogEntry<C>>,
     /// Candidate id that received vote in current term
-    pub(crate) voted_for: Option<String>,
-    /// Votes received in the election
-    pub(crate) votes_received: usize,
+    pub(crate) voted_for: Option<ServerId>,
+    /// Leader id in current term
+    pub(crate) leader_id: Option<ServerId>,
+    /// Total weight of the votes received in the election
+    pub(crate) votes_received: u64,
+    /// Index of the last log entry when the server became the leader. The leader doesn't
+    /// execute commands speculatively until it applies the entries up to it, so that the
+    /// commands are not executed before the ones of previous terms.
+    pub(crate) ready_index: usize,
+    /// Trigger when server role changes
+    role_trigger: Arc<Event>,
+    /// Publish term and leader to the progress subscribers
+    progress_tx: Arc<watch::Sender<ApplyProgress>>,
+}
+
+/// Replication progress of other servers, indexed by the position of the server in
+/// `State::others`
+#[derive(Debug)]
+pub(crate) struct Replication {
+    /// For each other server, index of the next log entry to send to that server
+    pub(crate) next_index: Vec<usize>,
+    /// For each other server, index of highest log entry known to be replicated on server
+    pub(crate) match_index: Vec<usize>,
+    /// For each other server, the last time it responded to the leader
+    pub(crate) last_ack: Vec<Option<Instant>>,
+    /// For each other server, whether the leader is sending it the entries it misses
+    pub(crate) calibrating: Vec<bool>,
+}
+
+/// Commit and apply indices of the server
+#[derive(Debug)]
+pub(crate) struct CommitState {
     /// Index of highest log entry known to be committed
     pub(crate) commit_index: usize,
-    /// Index of highest log entry applied to state machine
+    /// Index of highest log entry whose cmds have been sent to after sync, they may not be
+    /// after synced yet
     pub(crate) last_applied: usize,
-    /// For each server, index of the next log entry to send to that server
-    // TODO: this should be indexed by server id and changed into a vec for efficiency
-    pub(crate) next_index: HashMap<String, usize>,
-    /// For each server, index of highest log entry known to be replicated on server
-    pub(crate) match_index: HashMap<String, usize>,
-    /// Other server ids
-    pub(crate) others: Vec<String>,
-    /// Trigger when server role changes
-    pub(crate) role_trigger: Arc<Event>,
     /// Trigger when there might be some logs to commit
-    pub(crate) commit_trigger: Arc<Event>,
-    /// Trigger when a new leader needs to calibrate its followers
-    pub(crate) calibrate_trigger: Arc<Event>,
+    commit_trigger: Arc<Event>,
+    /// Publish the commit index to the progress subscribers
+    progress_tx: Arc<watch::Sender<ApplyProgress>>,
 }
 
 impl<C: Command + 'static> State<C> {
-    /// Init server state
-    pub(crate) fn new(id: &str, role: ServerRole, term: TermNum, others: Vec<String>) -> Self {
-        let mut next_index = HashMap::new();
-        let mut match_index = HashMap::new();
-        let others: Vec<String> = others
-            .into_iter()
-            .map(|other| format!("http://{}", other))
+    /// Init server state. A server that has persisted nothing starts in `term` as the leader
+    /// if `is_leader`, otherwise it starts as a follower with the `recovered` state.
+    #[allow(clippy::too_many_arguments)] // the state is created in one place, it's ok
+    pub(crate) fn new(
+        id: ServerId,
+        is_leader: bool,
+        term: TermNum,
+        others: Vec<ServerId>,
+        mut placements: HashMap<ServerId, Placement>,
+        quorum: QuorumConfig,
+        wire_format: WireFormat,
+        wal: Wal<C>,
+        recovered: Recovered<C>,
+    ) -> Self {
+        let (role, term, voted_for) = if recovered.is_empty() {
+            let (role, voted_for) = if is_leader {
+                (ServerRole::Leader, Some(id))
+            } else {
+                (ServerRole::Follower, None)
+            };
+            let _seq = wal.submit_vote(term, voted_for);
+            (role, term, voted_for)
+        } else {
+            (ServerRole::Follower, recovered.term, recovered.voted_for)
+        };
+        let quorum = quorum.for_members(iter::once(id).chain(others.iter().copied()));
+        let leader_id = (role == ServerRole::Leader).then_some(id);
+        let placement = placements.remove(&id).unwrap_or_default();
+        let other_placements = others
+            .iter()
+            .map(|other| placements.remove(other).unwrap_or_default())
             .collect();
-        for other in &others {
-            assert!(next_index.insert(other.clone(), 1).is_none());
-            assert!(match_index.insert(other.clone(), 0).is_none());
-        }
-        Self {
-            id: format!("http://{}", id),
-            role,
+        let (progress_tx, _progress_rx) = watch::channel(ApplyProgress {
             term,
-            log: vec![LogEntry::new(0, &[])], // a fake log[0] will simplify the boundary check significantly
-            voted_for: None,
-            votes_received: 0,
-            commit_index: 0,
-            last_applied: 0,
-            next_index, // TODO: next_index should be initialized upon becoming a leader
-            match_index,
+            leader: leader_id,
+            ..ApplyProgress::default()
+        });
+        let progress_tx = Arc::new(progress_tx);
+        Self {
+            id,
+            election: RwLock::new(ElectionState {
+                role,
+                term,
+                voted_for,
+                leader_id,
+                votes_received: 0,
+                ready_index: 0,
+                role_trigger: Arc::new(Event::new()),
+                progress_tx: Arc::clone(&progress_tx),
+            }),
+            log: RwLock::new(recovered.log),
+            wal,
+            replication: Mutex::new(Replication {
+                next_index: vec![1; others.len()], // TODO: next_index should be initialized upon becoming a leader
+                match_index: vec![0; others.len()],
+                last_ack: vec![None; others.len()],
+                calibrating: vec![false; others.len()],
+            }),
+            last_heard: Mutex::new(vec![None; others.len()]),
+            spec_votes: Mutex::new(HashMap::new()),
+            commit: RwLock::new(CommitState {
+                commit_index: 0,
+                last_applied: 0,
+                commit_trigger: Arc::new(Event::new()),
+                progress_tx,
+            }),
             others,
-            role_trigger: Arc::new(Event::new()),
-            commit_trigger: Arc::new(Event::new()),
+            placement,
+            other_placements,
+            quorum,
+            wire_format,
             calibrate_trigger: Arc::new(Event::new()),
+            timeout_now_trigger: Arc::new(Event::new()),
+            timeout_now: Mutex::new(None),
+            alarm_tx: watch::channel(None).0,
+        }
+    }
+
+    /// How many distinct priorities of the other servers that are alive are higher than the
+    /// priority of this server, a server waits longer before starting an election if there are
+    /// more servers preferred
+    pub(crate) fn priority_rank(&self) -> u32 {
+        let last_heard = self.last_heard.lock();
+        self.other_placements
+            .iter()
+            .zip(last_heard.iter())
+            .filter(|&(placement, heard)| {
+                placement.priority > self.placement.priority
+                    && heard.map_or(false, |heard| heard.elapsed() < PRIORITY_ALIVE_TIMEOUT)
+            })
+            .map(|(placement, _)| placement.priority)
+            .collect::<HashSet<_>>()
+            .len()
+            .numeric_cast()
+    }
+
+    /// Record that server `id` has sent a request to this server
+    pub(crate) fn heard_from(&self, id: ServerId) {
+        if let Some(peer) = self.others.iter().position(|&other| other == id) {
+            if let Some(heard) = self.last_heard.lock().get_mut(peer) {
+                *heard = Some(Instant::now());
+            }
         }
     }
 
+    /// Latch the leadership transfer of the leader in `term` and wake up the election task
+    pub(crate) fn transfer_leadership(&self, term: TermNum) {
+        *self.timeout_now.lock() = Some(term);
+        self.timeout_now_trigger.notify(1);
+    }
+
+    /// Take the latched leadership transfer, return `true` if it's made in the current term
+    pub(crate) fn take_timeout_now(&self) -> bool {
+        let term = self.timeout_now.lock().take();
+        term.map_or(false, |term| term == self.election.read().term)
+    }
+
     /// Is leader?
     pub(crate) fn is_leader(&self) -> bool {
-        matches!(self.role, ServerRole::Leader)
+        self.election.read().is_leader()
     }
 
-    /// Last log index
-    #[allow(clippy::integer_arithmetic)] // log.len() >= 1 because we have a fake log[0]
-    pub(crate) fn last_log_index(&self) -> usize {
-        self.log.len() - 1
+    /// Get role trigger
+    pub(crate) fn role_trigger(&self) -> Arc<Event> {
+        Arc::clone(&self.election.read().role_trigger)
     }
 
-    /// Last log term
-    #[allow(dead_code, clippy::integer_arithmetic, clippy::indexing_slicing)] // log.len() >= 1 because we have a fake log[0]
-    pub(crate) fn last_log_term(&self) -> TermNum {
-        self.log[self.log.len() - 1].term()
+    /// Get commit trigger
+    pub(crate) fn commit_trigger(&self) -> Arc<Event> {
+        Arc::clone(&self.commit.read().commit_trigger)
     }
 
-    /// Need to commit
-    pub(crate) fn need_commit(&self) -> bool {
-        self.last_applied < self.commit_index
+    /// Get the sender that publishes the consensus progress
+    pub(crate) fn progress_tx(&self) -> Arc<watch::Sender<ApplyProgress>> {
+        Arc::clone(&self.commit.read().progress_tx)
+    }
+
+    /// Publish a corruption alarm to the alarm subscribers
+    pub(crate) fn raise_alarm(&self, alarm: CorruptionAlarm) {
+        let _prev = self.alarm_tx.send_replace(Some(alarm));
+    }
+
+    /// Submit the term and the vote of the server to the WAL, it must be called whenever they
+    /// change with the election lock held
+    pub(crate) fn persist_election(&self, election: &ElectionState) -> u64 {
+        self.wal.submit_vote(election.term, election.voted_for)
+    }
+
+    /// Submit the entries `log[index..]` to the WAL, it must be called whenever they change
+    /// with the log lock held
+    pub(crate) fn persist_entries(&self, log: &Log<C>, index: usize) -> u64 {
+        self.wal.submit(wal::entry_records(log, index))
+    }
+}
+
+impl ElectionState {
+    /// Is leader?
+    pub(crate) fn is_leader(&self) -> bool {
+        matches!(self.role, ServerRole::Leader)
     }
 
     /// Update to `term`
@@ -320,9 +872,34 @@ impl<C: Command + 'static> State<C> {
         self.set_role(ServerRole::Follower);
         self.voted_for = None;
         self.votes_received = 0;
+        self.leader_id = None;
+        self.publish_progress();
         debug!("updated to term {term}");
     }
 
+    /// Set the leader of current term
+    pub(crate) fn set_leader(&mut self, leader_id: ServerId) {
+        if self.leader_id != Some(leader_id) {
+            debug!("leader of term {} is {leader_id}", self.term);
+            self.leader_id = Some(leader_id);
+            self.publish_progress();
+        }
+    }
+
+    /// Publish term and leader to the progress subscribers
+    fn publish_progress(&self) {
+        let _ignore = self.progress_tx.send_if_modified(|progress| {
+            let ready_index = self.ready_index.numeric_cast();
+            let modified = progress.term != self.term
+                || progress.leader != self.leader_id
+                || progress.ready_index != ready_index;
+            progress.term = self.term;
+            progress.leader = self.leader_id;
+            progress.ready_index = ready_index;
+            modified
+        });
+    }
+
     /// Set server role
     pub(crate) fn set_role(&mut self, role: ServerRole) {
         let prev_role = self.role;
@@ -336,35 +913,47 @@ impl<C: Command + 'static> State<C> {
     pub(crate) fn role(&self) -> ServerRole {
         self.role
     }
+}
 
-    /// Get role trigger
-    pub(crate) fn role_trigger(&self) -> Arc<Event> {
-        Arc::clone(&self.role_trigger)
+impl CommitState {
+    /// Need to commit
+    pub(crate) fn need_commit(&self) -> bool {
+        self.last_applied < self.commit_index
     }
 
-    /// Get commit trigger
-    pub(crate) fn commit_trigger(&self) -> Arc<Event> {
-        Arc::clone(&self.commit_trigger)
+    /// Update commit index and notify the apply task
+    pub(crate) fn update_commit_index(&mut self, commit_index: usize) {
+        if self.commit_index != commit_index {
+            self.commit_index = commit_index;
+            debug!("commit_index updated to {commit_index}");
+            self.commit_trigger.notify(1);
+            let _ignore = self.progress_tx.send_if_modified(|progress| {
+                let commit_index = commit_index.numeric_cast();
+                let modified = progress.commit_index != commit_index;
+                progress.commit_index = commit_index;
+                modified
+            });
+        }
     }
 }
 
 impl<C: Command> Debug for Protocol<C> {
     #[inline]
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.state.map_read(|state| {
-            f.debug_struct("Server")
-                .field("role", &state.role)
-                .field("term", &state.term)
-                .field("spec", &self.spec)
-                .field("log", &state.log)
-                .finish()
-        })
+        let election = self.state.election.read();
+        f.debug_struct("Server")
+            .field("role", &election.role)
+            .field("term", &election.term)
+            .field("spec", &self.spec)
+            .field("log", &*self.state.log.read())
+            .finish()
     }
 }
 
 /// The server role same as Raft
-#[derive(Debug, Clone, Copy, PartialEq)]
-pub(crate) enum ServerRole {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ServerRole {
     /// A follower
     Follower,
     /// A candidate
@@ -374,14 +963,63 @@ pub(crate) enum ServerRole {
 }
 
 impl<C: 'static + Command> Protocol<C> {
-    /// Create a new server instance
+    /// Create a new server instance, the values it sends are encoded with `wire_format`.
+    ///
+    /// The term, the vote and the log are persisted in `data_dir` and recovered from it when
+    /// the server restarts, nothing is persisted if it's `None`. `is_leader` and `term` only
+    /// take effect when the server starts for the first time, a recovered server starts as a
+    /// follower, and it starts an election at once if `is_leader`.
+    ///
+    /// # Panics
+    ///   If the write-ahead log in `data_dir` can't be opened or it's corrupted
     #[must_use]
     #[inline]
+    #[allow(clippy::too_many_arguments)] // the server is configured in one place, it's ok
     pub fn new<CE: CommandExecutor<C> + 'static>(
-        id: &str,
+        id: ServerId,
+        is_leader: bool,
+        term: u64,
+        others: HashMap<ServerId, String>,
+        placements: HashMap<ServerId, Placement>,
+        quorum: QuorumConfig,
+        wire_format: WireFormat,
+        data_dir: Option<PathBuf>,
+        cmd_executor: CE,
+    ) -> Self {
+        Self::new_in_group(
+            DEFAULT_GROUP,
+            None,
+            id,
+            is_leader,
+            term,
+            others,
+            placements,
+            quorum,
+            wire_format,
+            data_dir,
+            cmd_executor,
+        )
+    }
+
+    /// Create a new server instance of the consensus `group` that shares `transport` with the
+    /// other groups in the process, see `Protocol::new`
+    ///
+    /// # Panics
+    ///   If the write-ahead log in `data_dir` can't be opened or it's corrupted
+    #[must_use]
+    #[inline]
+    #[allow(clippy::too_many_arguments)] // the server is configured in one place, it's ok
+    pub fn new_in_group<CE: CommandExecutor<C> + 'static>(
+        group: GroupId,
+        transport: Option<Arc<GroupTransport>>,
+        id: ServerId,
         is_leader: bool,
         term: u64,
-        others: Vec<String>,
+        others: HashMap<ServerId, String>,
+        placements: HashMap<ServerId, Placement>,
+        quorum: QuorumConfig,
+        wire_format: WireFormat,
+        data_dir: Option<PathBuf>,
         cmd_executor: CE,
     ) -> Self {
         let (sync_tx, sync_rx) = key_mpsc::channel();
@@ -391,16 +1029,49 @@ impl<C: 'static + Command> Protocol<C> {
         let (stop_ch_tx, stop_ch_rx) = broadcast::channel(1);
         let (exe_tx, exe_rx) = cmd_execute_channel();
 
-        let state = Arc::new(RwLock::new(State::new(
+        let mut others: Vec<_> = others.into_iter().collect();
+        others.sort_unstable_by_key(|&(other_id, _)| other_id);
+        let connects: Vec<_> = others
+            .iter()
+            .map(|&(other_id, ref addr)| {
+                Arc::new(Connect::new(
+                    other_id,
+                    Self::http_addr(addr),
+                    group,
+                    transport.clone(),
+                ))
+            })
+            .collect();
+
+        #[allow(clippy::panic)] // the server can't start without its persisted state
+        let (wal, recovered) = match data_dir {
+            Some(dir) => Wal::open(&dir)
+                .unwrap_or_else(|e| panic!("failed to open the WAL in {}, {e}", dir.display())),
+            None => Wal::memory(),
+        };
+        let restarted = !recovered.is_empty();
+        let state = Arc::new(State::new(
             id,
-            if is_leader {
-                ServerRole::Leader
-            } else {
-                ServerRole::Follower
-            },
+            is_leader,
             term,
-            others,
-        )));
+            others.into_iter().map(|(other_id, _)| other_id).collect(),
+            placements,
+            quorum,
+            wire_format,
+            wal,
+            recovered,
+        ));
+        if restarted {
+            let recovered_term = state.election.read().term;
+            info!(
+                "server {id} recovers term {recovered_term} and {} log entries",
+                state.log.read().last_log_index()
+            );
+            // the preferred leader takes over the leadership again
+            if is_leader {
+                state.transfer_leadership(recovered_term);
+            }
+        }
 
         // run background tasks
         let _bg_handle = tokio::spawn(run_bg_tasks(
@@ -412,6 +1083,8 @@ impl<C: 'static + Command> Protocol<C> {
             exe_tx.clone(),
             exe_rx,
             Arc::clone(&cmd_board),
+            connects.clone(),
+            transport,
             Shutdown::new(stop_ch_rx.resubscribe()),
         ));
 
@@ -425,21 +1098,129 @@ impl<C: 'static + Command> Protocol<C> {
             cmd_board,
             stop_ch_tx,
             cmd_exe_tx: exe_tx,
+            connects,
         }
     }
 
+    /// Addresses must start with "http" to communicate with other servers
+    fn http_addr(addr: &str) -> String {
+        if addr.starts_with("http") {
+            addr.to_owned()
+        } else {
+            format!("http://{addr}")
+        }
+    }
+
+    /// Get the addresses of other servers
+    #[inline]
+    #[must_use]
+    pub fn peer_addrs(&self) -> HashMap<ServerId, String> {
+        self.connects
+            .iter()
+            .map(|connect| (connect.id, connect.addr()))
+            .collect()
+    }
+
+    /// Get the placements of all servers, including this one
+    #[inline]
+    #[must_use]
+    pub fn placements(&self) -> HashMap<ServerId, Placement> {
+        self.state
+            .others
+            .iter()
+            .copied()
+            .zip(self.state.other_placements.iter().cloned())
+            .chain(iter::once((self.state.id, self.state.placement.clone())))
+            .collect()
+    }
+
+    /// Update the address of another server at runtime, the server keeps its id so its
+    /// replication progress and vote are preserved. Return `false` if `id` is not a peer.
+    #[inline]
+    pub async fn update_peer_addr(&self, id: ServerId, addr: &str) -> bool {
+        if let Some(connect) = self.connects.iter().find(|connect| connect.id == id) {
+            connect.update_addr(Self::http_addr(addr)).await;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Subscribe to the consensus progress of the server. The receiver is notified whenever the
+    /// commit index, the last applied index, the term or the leader changes.
+    #[inline]
+    #[must_use]
+    pub fn progress(&self) -> watch::Receiver<ApplyProgress> {
+        self.state.progress_tx().subscribe()
+    }
+
+    /// Get the consensus state of the server
+    #[inline]
+    #[must_use]
+    pub fn status(&self) -> ServerStatus {
+        let spec_pool_len = self.spec.lock().pool.len();
+        let election = self.state.election.read();
+        let last_log_index = self.state.log.read().last_log_index();
+        let peers = if election.is_leader() {
+            let replication = self.state.replication.lock();
+            self.state
+                .others
+                .iter()
+                .zip(replication.next_index.iter())
+                .zip(replication.match_index.iter())
+                .zip(replication.last_ack.iter())
+                .map(
+                    |(((&id, &next_index), &match_index), last_ack)| PeerStatus {
+                        id,
+                        next_index: next_index.numeric_cast(),
+                        match_index: match_index.numeric_cast(),
+                        last_ack: last_ack.map(|instant| instant.elapsed()),
+                    },
+                )
+                .collect()
+        } else {
+            vec![]
+        };
+        let commit_index = self.state.commit.read().commit_index;
+        // `CommitState::last_applied` moves before the cmds are after synced
+        let last_applied = self.state.progress_tx().borrow().last_applied;
+        ServerStatus {
+            id: self.state.id,
+            role: election.role(),
+            term: election.term,
+            leader: election.leader_id,
+            commit_index: commit_index.numeric_cast(),
+            last_applied,
+            last_log_index: last_log_index.numeric_cast(),
+            spec_pool_len,
+            peers,
+        }
+    }
+
+    /// Subscribe to the corruption alarms of the server. The receiver holds the latest alarm,
+    /// `None` if the log has never been found corrupted.
+    #[inline]
+    #[must_use]
+    pub fn alarm(&self) -> watch::Receiver<Option<CorruptionAlarm>> {
+        self.state.alarm_tx.subscribe()
+    }
+
     /// Send sync event to the background sync task, it's not a blocking function
     #[instrument(skip(self))]
     fn sync_to_others(&self, term: TermNum, cmd: &C, need_execute: bool) {
         let mut cmd_board = self.cmd_board.lock();
-        let _ignore = cmd_board.cmd_states.insert(
-            cmd.id().clone(),
-            if need_execute {
+        let state = cmd_board
+            .cmd_states
+            .entry(cmd.id().clone())
+            .or_insert(CmdState::EarlyArrive);
+        // a cmd proposed again is not after synced again
+        if !matches!(*state, CmdState::Applying | CmdState::FinalResponse(_)) {
+            *state = if need_execute {
                 CmdState::Execute
             } else {
                 CmdState::AfterSync
-            },
-        );
+            };
+        }
         let ready_notify = self
             .sync_chan
             .send(cmd.keys(), SyncMessage::new(term, Arc::new(cmd.clone())));
@@ -449,7 +1230,8 @@ impl<C: 'static + Command> Protocol<C> {
         }
     }
 
-    /// Handle "propose" requests
+    /// Handle "propose" requests, the batched commands in the request are handled one by one in
+    /// order, as if each of them is proposed on its own
     // TODO: dedup proposed commands
     async fn propose(
         &self,
@@ -457,78 +1239,106 @@ impl<C: 'static + Command> Protocol<C> {
     ) -> Result<tonic::Response<ProposeResponse>, tonic::Status> {
         let p = request.into_inner();
 
-        let cmd: C = p.cmd().map_err(|e| {
+        let cmds: Vec<C> = p.cmds().map_err(|e| {
             tonic::Status::invalid_argument(format!("propose cmd decode failed: {}", e))
         })?;
 
-        (|| async {
-            let (is_leader, term) = self.state.map_read(|state| (state.is_leader(), state.term));
-            let er_rx = {
-                let mut spec = self.spec.lock();
+        // `join_all` polls the futures in order, so the commands enter the speculative pool in
+        // the order they are batched
+        let mut resps = future::join_all(cmds.into_iter().map(|cmd| self.propose_cmd(cmd)))
+            .await
+            .into_iter();
+        let first = resps
+            .next()
+            .unwrap_or_else(|| unreachable!("a propose request contains at least one command"));
+        first
+            .and_then(|resp| Ok(resp.with_batch(resps.collect::<Result<_, _>>()?)))
+            .map_or_else(
+                |err| {
+                    Err(tonic::Status::internal(format!(
+                        "encode or decode error, {}",
+                        err
+                    )))
+                },
+                |resp| Ok(tonic::Response::new(resp)),
+            )
+    }
 
-                // check if the command is ready
-                if spec.ready.contains_key(cmd.id()) {
-                    return ProposeResponse::new_empty(false, term);
-                }
+    /// Handle a proposed command
+    async fn propose_cmd(&self, cmd: C) -> Result<ProposeResponse, CodecError> {
+        let (is_leader, term, ready_index) = self
+            .state
+            .election
+            .map_read(|election| (election.is_leader(), election.term, election.ready_index));
+        let er_rx = {
+            let mut spec = self.spec.lock();
+
+            // check if the command is ready
+            if spec.ready.contains_key(cmd.id()) {
+                return ProposeResponse::new_empty(false, term);
+            }
 
-                let has_conflict = spec.has_conflict_with(&cmd);
-                if !has_conflict {
-                    spec.push(cmd.clone());
-                }
+            let has_conflict = spec.has_conflict_with(&cmd);
+            if !has_conflict {
+                spec.push(cmd.clone());
+            }
 
-                // non-leader should return immediately
-                if !is_leader {
-                    return if has_conflict {
-                        ProposeResponse::new_error(is_leader, term, &ProposeError::KeyConflict)
-                    } else {
-                        ProposeResponse::new_empty(false, term)
-                    };
-                }
+            // non-leader should return immediately
+            if !is_leader {
+                return if has_conflict {
+                    ProposeResponse::new_error(
+                        is_leader,
+                        term,
+                        &ProposeError::KeyConflict,
+                        self.state.wire_format,
+                    )
+                } else {
+                    ProposeResponse::new_empty(false, term)
+                };
+            }
 
-                // leader should sync the cmd to others
-                let cmd = Arc::new(cmd);
+            // leader should sync the cmd to others
+            let cmd = Arc::new(cmd);
 
-                if has_conflict {
-                    // no spec execute, just sync
-                    self.sync_to_others(term, cmd.as_ref(), true);
-                    return ProposeResponse::new_error(is_leader, term, &ProposeError::KeyConflict);
-                }
+            // a new leader executes the cmd after the entries of previous terms are applied
+            let is_ready = self.state.progress_tx().borrow().last_applied
+                >= ready_index.numeric_cast::<LogIndex>();
+            if has_conflict || !is_ready {
+                // no spec execute, just sync
+                self.sync_to_others(term, cmd.as_ref(), true);
+                return ProposeResponse::new_error(
+                    is_leader,
+                    term,
+                    &ProposeError::KeyConflict,
+                    self.state.wire_format,
+                );
+            }
 
-                // spec execute and sync
-                // execute the command before sync so that the order of cmd is preserved
-                let er_rx = self.cmd_exe_tx.send_exe(Arc::clone(&cmd));
-                self.sync_to_others(term, cmd.as_ref(), false);
+            // spec execute and sync
+            // execute the command before sync so that the order of cmd is preserved
+            let er_rx = self.cmd_exe_tx.send_exe(Arc::clone(&cmd));
+            self.sync_to_others(term, cmd.as_ref(), false);
 
-                // now we can release the lock and wait for the execution result
-                er_rx
-            };
+            // now we can release the lock and wait for the execution result
+            er_rx
+        };
 
-            // wait for the speculative execution
-            let er = er_rx.await;
-            match er {
-                Ok(Ok(er)) => ProposeResponse::new_result::<C>(is_leader, term, &er),
-                Ok(Err(err)) => ProposeResponse::new_error(
-                    is_leader,
-                    term,
-                    &ProposeError::ExecutionError(err.to_string()),
-                ),
-                Err(err) => ProposeResponse::new_error(
-                    is_leader,
-                    term,
-                    &ProposeError::ProtocolError(err.to_string()),
-                ),
+        // wait for the speculative execution
+        let er = er_rx.await;
+        match er {
+            Ok(Ok(er)) => {
+                ProposeResponse::new_result::<C>(is_leader, term, &er, self.state.wire_format)
             }
-        })()
-        .await
-        .map_or_else(
-            |err| {
-                Err(tonic::Status::internal(format!(
-                    "encode or decode error, {}",
-                    err
-                )))
-            },
-            |resp| Ok(tonic::Response::new(resp)),
-        )
+            Ok(Err(err)) => {
+                ProposeResponse::new_error(is_leader, term, &err.into(), self.state.wire_format)
+            }
+            Err(err) => ProposeResponse::new_error(
+                is_leader,
+                term,
+                &ProposeError::ProtocolError(err.to_string()),
+                self.state.wire_format,
+            ),
+        }
     }
 
     /// handle "wait synced" request
@@ -570,114 +1380,281 @@ impl<C: 'static + Command> Protocol<C> {
         }
     }
 
-    /// Handle `AppendEntries` requests
-    fn append_entries(
+    /// Handle `AppendEntries` requests, the server responds after it persists the new entries
+    async fn append_entries(
         &self,
         request: tonic::Request<AppendEntriesRequest>,
     ) -> Result<tonic::Response<AppendEntriesResponse>, tonic::Status> {
-        let req = request.into_inner();
+        let resp = self.handle_append_entries(&request.into_inner())?;
+        self.wait_persisted().await?;
+        Ok(tonic::Response::new(resp))
+    }
+
+    /// Wait until the state changes submitted to the WAL are persisted
+    async fn wait_persisted(&self) -> Result<(), tonic::Status> {
+        self.state
+            .wal
+            .wait_all()
+            .await
+            .map_err(|e| tonic::Status::internal(format!("failed to persist the state, {e}")))
+    }
+
+    /// Update the log with an `AppendEntries` request
+    #[allow(clippy::too_many_lines)] // FIXME: split to smaller functions
+    fn handle_append_entries(
+        &self,
+        req: &AppendEntriesRequest,
+    ) -> Result<AppendEntriesResponse, tonic::Status> {
         debug!("append_entries received: term({}), commit({}), prev_log_index({}), prev_log_term({}), {} entries", 
             req.term, req.leader_commit, req.prev_log_index, req.prev_log_term, req.entries.len());
 
-        let state = self.state.upgradable_read();
+        // decode and verify the entries before any lock is held
+        let entries = match req.entries::<C>() {
+            Ok(entries) => entries,
+            Err(CodecError::Checksum(reason)) => {
+                error!("entries from {} are corrupted: {reason}", req.leader_id);
+                let status = tonic::Status::data_loss(format!("entries are corrupted, {reason}"));
+                self.state.raise_alarm(CorruptionAlarm::EntryChecksum {
+                    leader: req.leader_id,
+                    reason,
+                });
+                return Err(status);
+            }
+            Err(e) => {
+                return Err(tonic::Status::internal(format!(
+                    "encode or decode error, {}",
+                    e
+                )))
+            }
+        };
+        // hash the entries before the log is locked
+        let crcs = entries
+            .iter()
+            .map(LogEntry::crc)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| tonic::Status::internal(format!("encode or decode error, {}", e)))?;
+
+        let election = self.state.election.upgradable_read();
 
         // calibrate term
-        if req.term < state.term {
-            return Ok(tonic::Response::new(AppendEntriesResponse::new_reject(
-                state.term,
-                state.commit_index,
-            )));
+        if req.term < election.term {
+            return Ok(AppendEntriesResponse::new_reject(
+                election.term,
+                self.state.commit.read().commit_index,
+            ));
         }
 
-        let mut state = RwLockUpgradableReadGuard::upgrade(state);
-        if req.term > state.term {
-            state.update_to_term(req.term);
-        }
+        // the election lock is held until the log is updated so that the term stays the same
+        let election = if req.term > election.term || election.leader_id != Some(req.leader_id) {
+            let mut election = RwLockUpgradableReadGuard::upgrade(election);
+            if req.term > election.term {
+                election.update_to_term(req.term);
+                let _seq = self.state.persist_election(&election);
+            }
+            election.set_leader(req.leader_id);
+            RwLockWriteGuard::downgrade(election)
+        } else {
+            RwLockUpgradableReadGuard::downgrade(election)
+        };
 
         *self.last_rpc_time.write() = Instant::now();
+        self.state.heard_from(req.leader_id);
 
-        // remove inconsistencies
-        #[allow(clippy::integer_arithmetic)] // TODO: overflow of log index should be prevented
-        state.log.truncate((req.prev_log_index + 1).numeric_cast());
+        let mut log = self.state.log.write();
 
         // check if previous log index match leader's one
-        if state
-            .log
-            .get(req.prev_log_index.numeric_cast::<usize>())
-            .map_or(false, |entry| entry.term() != req.prev_log_term)
+        let prev_log_index = req.prev_log_index.numeric_cast::<usize>();
+        if log
+            .get(prev_log_index)
+            .map_or(true, |entry| entry.term() != req.prev_log_term)
         {
-            return Ok(tonic::Response::new(AppendEntriesResponse::new_reject(
-                state.term,
-                state.commit_index,
-            )));
+            return Ok(AppendEntriesResponse::new_reject(
+                election.term,
+                self.state.commit.read().commit_index,
+            ));
         }
 
-        // append new logs
-        let entries = req
-            .entries()
-            .map_err(|e| tonic::Status::internal(format!("encode or decode error, {}", e)))?;
-        state.log.extend(entries.into_iter());
+        // the terms match, so the logs must be identical up to the previous log index
+        let mut hash = log.hash(prev_log_index).unwrap_or_default();
+        if let Some(leader_hash) = req.prev_log_hash {
+            if hash != leader_hash {
+                error!(
+                    "log diverges from leader {} at or before log[{prev_log_index}], hash {hash:#010x}, leader's hash {leader_hash:#010x}",
+                    req.leader_id
+                );
+                self.state.raise_alarm(CorruptionAlarm::LogDivergence {
+                    leader: req.leader_id,
+                    index: req.prev_log_index,
+                });
+                let commit_index = self.state.commit.read().commit_index;
+                // committed entries can't be repaired, the uncommitted ones are overwritten
+                // once the leader sends the entries after the follower's commit index
+                if prev_log_index <= commit_index {
+                    return Err(tonic::Status::data_loss(format!(
+                        "log diverges from the leader at log[{prev_log_index}]"
+                    )));
+                }
+                return Ok(AppendEntriesResponse::new_reject(
+                    election.term,
+                    commit_index,
+                ));
+            }
+        }
+
+        // append new logs, entries that are already in the log are skipped so that a delayed
+        // request never removes the entries after it
+        #[allow(clippy::integer_arithmetic)] // TODO: overflow of log index should be prevented
+        let last_new_index = prev_log_index + entries.len();
+        let commit_index = self.state.commit.read().commit_index;
+        let mut first_new_index = None;
+        #[allow(clippy::integer_arithmetic)] // TODO: overflow of log index should be prevented
+        for (i, (entry, crc)) in entries.into_iter().zip(crcs).enumerate() {
+            let index = prev_log_index + 1 + i;
+            hash = Log::<C>::chain_hash(hash, crc);
+            if log.hash(index) == Some(hash) {
+                continue;
+            }
+            if index <= commit_index {
+                error!(
+                    "committed log[{index}] differs from the one of leader {}",
+                    req.leader_id
+                );
+                self.state.raise_alarm(CorruptionAlarm::LogDivergence {
+                    leader: req.leader_id,
+                    index: index.numeric_cast(),
+                });
+                return Err(tonic::Status::data_loss(format!(
+                    "log diverges from the leader at log[{index}]"
+                )));
+            }
+            // remove inconsistencies
+            log.truncate(index);
+            log.push(entry, crc);
+            let _first = first_new_index.get_or_insert(index);
+        }
+        if let Some(index) = first_new_index {
+            let _seq = self.state.persist_entries(&log, index);
+        }
+
+        // the log is the same as the leader's one up to the last new entry
+        let _repaired = self.state.alarm_tx.send_if_modified(|alarm| {
+            let repaired = matches!(
+                *alarm,
+                Some(CorruptionAlarm::LogDivergence { index, .. })
+                    if index.numeric_cast::<usize>() <= last_new_index
+            );
+            if repaired {
+                info!("log is repaired up to log[{last_new_index}]");
+                *alarm = None;
+            }
+            repaired
+        });
 
         // update commit index
-        let prev_commit_index = state.commit_index;
-        state.commit_index = min(req.leader_commit.numeric_cast(), state.last_log_index());
-        if prev_commit_index != state.commit_index {
-            debug!("commit_index updated to {}", state.commit_index);
-            state.commit_trigger.notify(1);
+        let mut commit = self.state.commit.write();
+        let leader_commit = min(req.leader_commit.numeric_cast(), last_new_index);
+        if leader_commit > commit.commit_index {
+            commit.update_commit_index(leader_commit);
         }
 
-        Ok(tonic::Response::new(AppendEntriesResponse::new_accept(
-            state.term,
-        )))
+        Ok(AppendEntriesResponse::new_accept(election.term))
     }
 
-    /// Handle `Vote` requests
-    #[allow(clippy::pedantic)] // need not return result, but to keep it consistent with rpc handler functions, we keep it this way
-    fn vote(
+    /// Handle `Vote` requests, the server responds after it persists its vote
+    async fn vote(
         &self,
         request: tonic::Request<VoteRequest>,
     ) -> Result<tonic::Response<VoteResponse>, tonic::Status> {
-        let req = request.into_inner();
+        let resp = self.handle_vote(&request.into_inner())?;
+        self.wait_persisted().await?;
+        Ok(tonic::Response::new(resp))
+    }
+
+    /// Decide whether to grant the vote of a `Vote` request
+    fn handle_vote(&self, req: &VoteRequest) -> Result<VoteResponse, tonic::Status> {
         debug!(
             "vote received: term({}), last_log_index({}), last_log_term({}), id({})",
             req.term, req.last_log_index, req.last_log_term, req.candidate_id
         );
 
         // just grab a write lock because it's highly likely that term is updated and a vote is granted
-        let mut state = self.state.write();
+        let mut election = self.state.election.write();
 
         // calibrate term
-        match req.term.cmp(&state.term) {
+        match req.term.cmp(&election.term) {
             Ordering::Less => {
-                return Ok(tonic::Response::new(VoteResponse::new_reject(state.term)));
+                return Ok(VoteResponse::new_reject(election.term));
             }
             Ordering::Equal => {}
             Ordering::Greater => {
-                state.update_to_term(req.term);
+                election.update_to_term(req.term);
+                let _seq = self.state.persist_election(&election);
             }
         }
 
-        if let Some(id) = state.voted_for.as_ref() {
-            if id != &req.candidate_id {
-                return Ok(tonic::Response::new(VoteResponse::new_reject(state.term)));
-            }
-        }
+        self.state.heard_from(req.candidate_id);
 
-        // If a follower receives votes from a candidate, it should update last_rpc_time to prevent itself from starting election
-        if state.role() == ServerRole::Follower {
-            *self.last_rpc_time.write() = Instant::now();
+        if let Some(id) = election.voted_for {
+            if id != req.candidate_id {
+                return Ok(VoteResponse::new_reject(election.term));
+            }
         }
 
-        if req.last_log_term > state.last_log_term()
-            || (req.last_log_term == state.last_log_term()
-                && req.last_log_index.numeric_cast::<usize>() >= state.last_log_index())
+        let (last_log_index, last_log_term) = self
+            .state
+            .log
+            .map_read(|log| (log.last_log_index(), log.last_log_term()));
+        if req.last_log_term > last_log_term
+            || (req.last_log_term == last_log_term
+                && req.last_log_index.numeric_cast::<usize>() >= last_log_index)
         {
             debug!("vote for server {}", req.candidate_id);
-            state.voted_for = Some(req.candidate_id);
-            Ok(tonic::Response::new(VoteResponse::new_accept(state.term)))
+            // the candidate recovers the commands that may have been accepted by a fast quorum
+            let resp = VoteResponse::new_accept(
+                election.term,
+                self.spec.lock().pool.iter(),
+                self.state.wire_format,
+            )
+            .map_err(|e| tonic::Status::internal(format!("encode spec pool failed, {e}")))?;
+            election.voted_for = Some(req.candidate_id);
+            let _seq = self.state.persist_election(&election);
+            // If a follower grants its vote, it should update last_rpc_time to prevent itself from starting election.
+            // A rejected candidate doesn't, or a candidate with a stale log would keep the up-to-date servers from
+            // starting an election forever.
+            if election.role() == ServerRole::Follower {
+                *self.last_rpc_time.write() = Instant::now();
+            }
+            Ok(resp)
         } else {
-            Ok(tonic::Response::new(VoteResponse::new_reject(state.term)))
+            Ok(VoteResponse::new_reject(election.term))
+        }
+    }
+
+    /// Handle `TimeoutNow` requests
+    #[allow(clippy::pedantic)] // need not return result, but to keep it consistent with rpc handler functions, we keep it this way
+    fn timeout_now(
+        &self,
+        request: tonic::Request<TimeoutNowRequest>,
+    ) -> Result<tonic::Response<TimeoutNowResponse>, tonic::Status> {
+        let req = request.into_inner();
+        debug!(
+            "timeout_now received: term({}), leader_id({})",
+            req.term, req.leader_id
+        );
+
+        let election = self.state.election.read();
+        // only the follower of the same term takes over the leadership
+        if req.term == election.term
+            && election.role() == ServerRole::Follower
+            && self.state.placement.priority > 0
+        {
+            info!(
+                "leader {} transfers its leadership to this server",
+                req.leader_id
+            );
+            self.state.transfer_leadership(election.term);
         }
+        Ok(tonic::Response::new(TimeoutNowResponse::new(election.term)))
     }
 }
 
@@ -688,3 +1665,257 @@ impl<C: 'static + Command> Drop for Protocol<C> {
         let _ = self.stop_ch_tx.send(()).ok();
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::test_cmd::{TestCommand, TestCommandType, TestExecutor};
+
+    /// Leader of the follower under test, it never runs
+    const LEADER: ServerId = 0;
+
+    fn new_follower() -> Protocol<TestCommand> {
+        Protocol::new(
+            1,
+            false,
+            1,
+            HashMap::from([(LEADER, "127.0.0.1:1".to_owned())]),
+            HashMap::new(),
+            QuorumConfig::classic([0, 1]),
+            WireFormat::default(),
+            None,
+            executor(),
+        )
+    }
+
+    /// An executor whose reports are dropped
+    fn executor() -> TestExecutor {
+        TestExecutor::new(mpsc::channel(1).0, mpsc::channel(1).0)
+    }
+
+    #[tokio::test]
+    async fn priority_rank_only_counts_servers_alive() {
+        let placements = HashMap::from([
+            (0, Placement::new("dc1".to_owned(), 3)),
+            (1, Placement::new("dc1".to_owned(), 1)),
+            (2, Placement::new("dc1".to_owned(), 2)),
+            (3, Placement::new("dc1".to_owned(), 2)),
+        ]);
+        let server = Protocol::<TestCommand>::new(
+            1,
+            false,
+            1,
+            [0, 2, 3]
+                .into_iter()
+                .map(|id| (id, "127.0.0.1:1".to_owned()))
+                .collect(),
+            placements,
+            QuorumConfig::classic(0..4),
+            WireFormat::default(),
+            None,
+            executor(),
+        );
+        assert_eq!(server.state.priority_rank(), 0);
+        server.state.heard_from(2);
+        server.state.heard_from(3);
+        assert_eq!(server.state.priority_rank(), 1);
+        server.state.heard_from(0);
+        assert_eq!(server.state.priority_rank(), 2);
+    }
+
+    #[tokio::test]
+    async fn leadership_transfer_is_latched() {
+        let follower = new_follower();
+        // the election task is not listening when the transfer happens
+        follower.state.transfer_leadership(1);
+        assert!(follower.state.take_timeout_now());
+        assert!(!follower.state.take_timeout_now());
+
+        // a transfer made in a previous term is stale
+        follower.state.transfer_leadership(0);
+        assert!(!follower.state.take_timeout_now());
+    }
+
+    fn entry(key: &str) -> LogEntry<TestCommand> {
+        let cmd = TestCommand::new(
+            ProposeId::new(key.to_owned()),
+            TestCommandType::Put,
+            vec![key.to_owned()],
+            Some(key.to_owned()),
+        );
+        LogEntry::new(1, &[Arc::new(cmd)])
+    }
+
+    /// Log of the leader
+    #[allow(clippy::unwrap_used)]
+    fn leader_log(keys: &[&str]) -> Log<TestCommand> {
+        let mut log = Log::new();
+        for key in keys {
+            let e = entry(key);
+            let crc = e.crc().unwrap();
+            log.push(e, crc);
+        }
+        log
+    }
+
+    /// Request that carries `log[prev_log_index + 1..]` of the leader
+    #[allow(
+        clippy::unwrap_used,
+        clippy::integer_arithmetic,
+        clippy::indexing_slicing
+    )]
+    fn append_entries(
+        log: &Log<TestCommand>,
+        prev_log_index: usize,
+        leader_commit: usize,
+    ) -> tonic::Request<AppendEntriesRequest> {
+        tonic::Request::new(
+            AppendEntriesRequest::new(
+                1,
+                LEADER,
+                prev_log_index,
+                log[prev_log_index].term(),
+                log.hash(prev_log_index).unwrap(),
+                log[prev_log_index + 1..].to_vec(),
+                leader_commit,
+                WireFormat::default(),
+            )
+            .unwrap(),
+        )
+    }
+
+    #[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+    #[tokio::test]
+    async fn corrupted_append_entries_raises_an_alarm() {
+        let follower = new_follower();
+        let log = leader_log(&["a"]);
+
+        let mut req = append_entries(&log, 0, 0);
+        req.get_mut().entries[0][2] ^= 0xff;
+        let status = follower.append_entries(req).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::DataLoss);
+        assert!(matches!(
+            *follower.state.alarm_tx.borrow(),
+            Some(CorruptionAlarm::EntryChecksum { leader: LEADER, .. })
+        ));
+        assert_eq!(follower.state.log.read().last_log_index(), 0);
+
+        // the leader sends the entries again
+        let resp = follower
+            .append_entries(append_entries(&log, 0, 0))
+            .await
+            .unwrap();
+        assert!(resp.into_inner().success);
+        assert_eq!(follower.state.log.read().hash(1), log.hash(1));
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[tokio::test]
+    async fn diverged_log_is_repaired_by_the_leader() {
+        let follower = new_follower();
+        let stale = leader_log(&["a", "b"]);
+        let log = leader_log(&["a", "c", "d"]);
+        assert!(
+            follower
+                .append_entries(append_entries(&stale, 0, 1))
+                .await
+                .unwrap()
+                .into_inner()
+                .success
+        );
+
+        // log[2] has the same term as the leader's one but a different command
+        let resp = follower
+            .append_entries(append_entries(&log, 2, 1))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(!resp.success);
+        assert_eq!(resp.commit_index, 1);
+        assert!(matches!(
+            *follower.state.alarm_tx.borrow(),
+            Some(CorruptionAlarm::LogDivergence {
+                leader: LEADER,
+                index: 2
+            })
+        ));
+
+        // the leader sends the entries after the commit index of the follower
+        let repaired = follower
+            .append_entries(append_entries(&log, 1, 1))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(repaired.success);
+        assert!(follower.state.alarm_tx.borrow().is_none());
+        let follower_log = follower.state.log.read();
+        assert_eq!(follower_log.last_log_index(), 3);
+        assert_eq!(follower_log.hash(3), log.hash(3));
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[tokio::test]
+    async fn diverged_committed_log_is_not_repaired() {
+        let follower = new_follower();
+        let stale = leader_log(&["a", "b"]);
+        let log = leader_log(&["a", "c"]);
+        assert!(
+            follower
+                .append_entries(append_entries(&stale, 0, 2))
+                .await
+                .unwrap()
+                .into_inner()
+                .success
+        );
+
+        let status = follower
+            .append_entries(append_entries(&log, 2, 2))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::DataLoss);
+        let calibrated = follower
+            .append_entries(append_entries(&log, 1, 2))
+            .await
+            .unwrap_err();
+        assert_eq!(calibrated.code(), tonic::Code::DataLoss);
+        assert!(follower.state.alarm_tx.borrow().is_some());
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[tokio::test]
+    async fn delayed_append_entries_keeps_later_entries() {
+        let follower = new_follower();
+        let log = leader_log(&["a", "b"]);
+
+        // log[2] arrives before log[1]
+        assert!(
+            !follower
+                .append_entries(append_entries(&log, 1, 0))
+                .await
+                .unwrap()
+                .into_inner()
+                .success
+        );
+        assert!(
+            follower
+                .append_entries(append_entries(&log, 0, 0))
+                .await
+                .unwrap()
+                .into_inner()
+                .success
+        );
+        // the request that only carries log[1] is delayed
+        let delayed = append_entries(&leader_log(&["a"]), 0, 0);
+        assert!(
+            follower
+                .append_entries(delayed)
+                .await
+                .unwrap()
+                .into_inner()
+                .success
+        );
+        let follower_log = follower.state.log.read();
+        assert_eq!(follower_log.last_log_index(), 2);
+        assert_eq!(follower_log.hash(2), log.hash(2));
+    }
+}
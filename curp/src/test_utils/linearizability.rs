@@ -0,0 +1,217 @@
+//! A history recorder for client operations and a linearizability checker for a KV model.
+//!
+//! The checker is the Wing & Gong search with the memoization of Lowe, as used by Porcupine.
+//! Operations on different keys are checked separately, since a history of a KV store is
+//! linearizable iff the history of every key is.
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::Instant,
+};
+
+use parking_lot::Mutex;
+
+/// Input of an operation on the KV store
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum KvInput {
+    /// Put `value` to `key`
+    Put {
+        /// Key to put
+        key: String,
+        /// Value to put
+        value: String,
+    },
+    /// Get the value of `key`
+    Get {
+        /// Key to get
+        key: String,
+    },
+}
+
+impl KvInput {
+    /// Key operated on
+    fn key(&self) -> &str {
+        match *self {
+            KvInput::Put { ref key, .. } | KvInput::Get { ref key } => key,
+        }
+    }
+}
+
+/// Output of an operation on the KV store
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum KvOutput {
+    /// The put is done
+    Put,
+    /// The value got, `None` if the key doesn't exist
+    Get(Option<String>),
+}
+
+/// An operation in the history
+#[derive(Debug, Clone)]
+struct Operation {
+    /// Input of the operation
+    input: KvInput,
+    /// When the operation is invoked
+    call: Instant,
+    /// When the operation completes and its output, `None` if it failed. A failed operation may
+    /// or may not take effect, so it's considered to complete at an infinite time.
+    ret: Option<(Instant, KvOutput)>,
+}
+
+/// History of the operations issued by all clients, it can be shared between clients
+#[derive(Debug, Clone, Default)]
+pub struct History {
+    /// Recorded operations, the id of an operation is its position
+    ops: Arc<Mutex<Vec<Operation>>>,
+}
+
+impl History {
+    /// New empty `History`
+    #[inline]
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the invocation of an operation, returns the id used to complete it
+    #[inline]
+    #[must_use]
+    pub fn invoke(&self, input: KvInput) -> usize {
+        let mut ops = self.ops.lock();
+        let id = ops.len();
+        ops.push(Operation {
+            input,
+            call: Instant::now(),
+            ret: None,
+        });
+        id
+    }
+
+    /// Record the completion of an operation, an unknown `id` is ignored
+    #[inline]
+    pub fn complete(&self, id: usize, output: KvOutput) {
+        let ret = Instant::now();
+        if let Some(op) = self.ops.lock().get_mut(id) {
+            op.ret = Some((ret, output));
+        }
+    }
+
+    /// Number of the recorded operations
+    #[inline]
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.ops.lock().len()
+    }
+
+    /// Whether no operation is recorded
+    #[inline]
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.ops.lock().is_empty()
+    }
+
+    /// Check whether the history is linearizable
+    #[inline]
+    #[must_use]
+    pub fn is_linearizable(&self) -> bool {
+        let ops = self.ops.lock().clone();
+        let mut by_key: HashMap<String, Vec<Operation>> = HashMap::new();
+        for op in ops {
+            // a failed get has no effect on the store
+            if op.ret.is_none() && matches!(op.input, KvInput::Get { .. }) {
+                continue;
+            }
+            by_key
+                .entry(op.input.key().to_owned())
+                .or_default()
+                .push(op);
+        }
+        by_key.values().all(|key_ops| Search::new(key_ops).run())
+    }
+}
+
+/// Apply an operation to the value of a key, `None` if the output is impossible
+#[allow(clippy::option_option)] // the value of a key is optional itself
+fn step(value: Option<&str>, op: &Operation) -> Option<Option<String>> {
+    match (&op.input, op.ret.as_ref().map(|ret| &ret.1)) {
+        (&KvInput::Put { value: ref new, .. }, _) => Some(Some(new.clone())),
+        (&KvInput::Get { .. }, Some(&KvOutput::Get(ref got))) if got.as_deref() == value => {
+            Some(value.map(ToOwned::to_owned))
+        }
+        (&KvInput::Get { .. }, _) => None,
+    }
+}
+
+/// The search of a linearization of the operations on one key
+struct Search<'a> {
+    /// Operations on the key
+    ops: &'a [Operation],
+    /// Whether each operation is linearized
+    linearized: Vec<bool>,
+    /// Visited configurations
+    cache: HashSet<(Vec<bool>, Option<String>)>,
+}
+
+impl<'a> Search<'a> {
+    /// New `Search` of `ops`
+    fn new(ops: &'a [Operation]) -> Self {
+        Self {
+            ops,
+            linearized: vec![false; ops.len()],
+            cache: HashSet::new(),
+        }
+    }
+
+    /// Whether a linearization is found, the key doesn't exist at first
+    fn run(&mut self) -> bool {
+        self.search(None)
+    }
+
+    /// Whether the pending operations can be linearized from `value`
+    fn search(&mut self, value: Option<&str>) -> bool {
+        // an operation can be linearized next only if it's invoked before every pending
+        // operation completes
+        let ops = self.ops;
+        let pending: Vec<_> = ops
+            .iter()
+            .zip(&self.linearized)
+            .enumerate()
+            .filter(|&(_, (_, &linearized))| !linearized)
+            .map(|(i, (op, _))| (i, op))
+            .collect();
+        let min_ret = pending
+            .iter()
+            .filter_map(|&(_, op)| op.ret.as_ref().map(|ret| ret.0))
+            .min();
+        let candidates: Vec<_> = pending
+            .into_iter()
+            .filter(|&(_, op)| min_ret.map_or(true, |min_ret| op.call <= min_ret))
+            .collect();
+        // there's always a candidate until all operations are linearized
+        if candidates.is_empty() {
+            return true;
+        }
+        for (i, op) in candidates {
+            if let Some(next) = step(value, op) {
+                self.set_linearized(i, true);
+                if self.cache.insert((self.linearized.clone(), next.clone()))
+                    && self.search(next.as_deref())
+                {
+                    return true;
+                }
+                self.set_linearized(i, false);
+            }
+        }
+        false
+    }
+
+    /// Mark whether the `i`th operation is linearized
+    fn set_linearized(&mut self, i: usize, linearized: bool) {
+        if let Some(l) = self.linearized.get_mut(i) {
+            *l = linearized;
+        }
+    }
+}
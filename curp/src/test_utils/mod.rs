@@ -0,0 +1,8 @@
+/// A history recorder and a linearizability checker of a KV store
+pub mod linearizability;
+
+/// A network between the servers and the clients that can be partitioned
+pub mod network;
+
+/// A KV store command and its executor
+pub mod test_cmd;
@@ -0,0 +1,173 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    net::SocketAddr,
+    sync::Arc,
+};
+
+use parking_lot::{Mutex, RwLock};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::watch,
+};
+
+use crate::message::ServerId;
+
+/// A TCP proxy that forwards the connections it accepts to a target address. The proxy can be
+/// cut, which closes the forwarded connections and refuses the new ones.
+#[derive(Debug, Clone)]
+struct Proxy {
+    /// Address the proxy listens on
+    addr: SocketAddr,
+    /// Address the connections are forwarded to
+    target: Arc<RwLock<SocketAddr>>,
+    /// Whether the proxy forwards connections
+    up: Arc<watch::Sender<bool>>,
+}
+
+impl Proxy {
+    /// Bind a new `Proxy` to `target` on a random local port
+    async fn bind(target: SocketAddr) -> io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let (up, _) = watch::channel(true);
+        let proxy = Self {
+            addr: listener.local_addr()?,
+            target: Arc::new(RwLock::new(target)),
+            up: Arc::new(up),
+        };
+        let _handle = tokio::spawn(proxy.clone().accept(listener));
+        Ok(proxy)
+    }
+
+    /// Accept connections and forward them until the proxy is cut
+    async fn accept(self, listener: TcpListener) {
+        while let Ok((mut inbound, _)) = listener.accept().await {
+            let mut up = self.up.subscribe();
+            // the connection is closed when it's dropped
+            if !*up.borrow_and_update() {
+                continue;
+            }
+            let target = *self.target.read();
+            let _handle = tokio::spawn(async move {
+                if let Ok(mut outbound) = TcpStream::connect(target).await {
+                    tokio::select! {
+                        _res = tokio::io::copy_bidirectional(&mut inbound, &mut outbound) => {}
+                        () = Self::cut(&mut up) => {}
+                    }
+                }
+            });
+        }
+    }
+
+    /// Wait until the proxy is cut
+    async fn cut(up: &mut watch::Receiver<bool>) {
+        while *up.borrow_and_update() {
+            if up.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Set whether the proxy forwards connections
+    fn set_up(&self, up: bool) {
+        let _prev = self.up.send_replace(up);
+    }
+}
+
+/// A network of proxies between the servers of a cluster and their clients, a server reaches
+/// each of the others, and the clients reach each server, through a proxy of its own. So that
+/// a server can be isolated from the others and the clients without touching the server.
+#[derive(Debug)]
+pub struct Network {
+    /// Proxies between the servers, keyed by the ids of the source and the destination
+    links: HashMap<(ServerId, ServerId), Proxy>,
+    /// Proxies from the clients to each server
+    client_links: HashMap<ServerId, Proxy>,
+    /// Servers that are isolated
+    isolated: Mutex<HashSet<ServerId>>,
+}
+
+impl Network {
+    /// New `Network` of the servers listening on `addrs`
+    ///
+    /// # Errors
+    ///   `io::Error` if a proxy cannot be bound
+    #[inline]
+    pub async fn new(addrs: &HashMap<ServerId, SocketAddr>) -> io::Result<Self> {
+        let mut links = HashMap::new();
+        let mut client_links = HashMap::new();
+        for (&to, &addr) in addrs {
+            for &from in addrs.keys().filter(|&&from| from != to) {
+                let _prev = links.insert((from, to), Proxy::bind(addr).await?);
+            }
+            let _prev = client_links.insert(to, Proxy::bind(addr).await?);
+        }
+        Ok(Self {
+            links,
+            client_links,
+            isolated: Mutex::new(HashSet::new()),
+        })
+    }
+
+    /// Addresses that the server `id` reaches the other servers at
+    #[inline]
+    #[must_use]
+    pub fn peer_addrs(&self, id: ServerId) -> HashMap<ServerId, SocketAddr> {
+        self.links
+            .iter()
+            .filter(|&(&(from, _), _)| from == id)
+            .map(|(&(_, to), proxy)| (to, proxy.addr))
+            .collect()
+    }
+
+    /// Addresses that the clients reach the servers at
+    #[inline]
+    #[must_use]
+    pub fn client_addrs(&self) -> HashMap<ServerId, SocketAddr> {
+        self.client_links
+            .iter()
+            .map(|(&id, proxy)| (id, proxy.addr))
+            .collect()
+    }
+
+    /// Isolate the server `id` from the other servers and the clients, its connections are
+    /// closed and the new ones are refused
+    #[inline]
+    pub fn isolate(&self, id: ServerId) {
+        let _new = self.isolated.lock().insert(id);
+        self.update();
+    }
+
+    /// Reconnect the server `id` to the servers that are not isolated and the clients
+    #[inline]
+    pub fn heal(&self, id: ServerId) {
+        let _removed = self.isolated.lock().remove(&id);
+        self.update();
+    }
+
+    /// Forward the connections to the server `id` to `addr`, e.g. after it restarts on
+    /// another address. Existing connections are not affected.
+    #[inline]
+    pub fn redirect(&self, id: ServerId, addr: SocketAddr) {
+        for proxy in self
+            .links
+            .iter()
+            .filter(|&(&(_, to), _)| to == id)
+            .map(|(_, proxy)| proxy)
+            .chain(self.client_links.get(&id))
+        {
+            *proxy.target.write() = addr;
+        }
+    }
+
+    /// Cut the proxies from or to the isolated servers and bring up the others
+    fn update(&self) {
+        let isolated = self.isolated.lock();
+        for (&(from, to), proxy) in &self.links {
+            proxy.set_up(!isolated.contains(&from) && !isolated.contains(&to));
+        }
+        for (to, proxy) in &self.client_links {
+            proxy.set_up(!isolated.contains(to));
+        }
+    }
+}
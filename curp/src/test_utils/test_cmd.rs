@@ -0,0 +1,163 @@
+//! A command of a KV store and its executor, shared by the unit tests and the integration
+//! tests of the protocol.
+
+use std::{collections::HashMap, sync::Arc};
+
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+use crate::{
+    cmd::{Command, CommandExecutor, ConflictCheck, ProposeId},
+    error::ExecuteError,
+    LogIndex,
+};
+
+/// Type of a `TestCommand`
+#[allow(clippy::exhaustive_enums)] // the tests match all the types
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TestCommandType {
+    /// Get the value of the first key
+    Get,
+    /// Put the value to the first key
+    Put,
+}
+
+/// A command of a KV store, it operates on its first key
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TestCommand {
+    /// Propose id
+    id: ProposeId,
+    /// Type of the command
+    t: TestCommandType,
+    /// Keys of the command, they are used to tell conflicts
+    keys: Vec<String>,
+    /// Value to put
+    value: Option<String>,
+}
+
+impl TestCommand {
+    /// New `TestCommand`
+    #[inline]
+    #[must_use]
+    pub fn new(
+        id: ProposeId,
+        t: TestCommandType,
+        keys: Vec<String>,
+        value: Option<String>,
+    ) -> Self {
+        Self { id, t, keys, value }
+    }
+
+    /// The key operated on, it's empty if the command has no keys
+    fn key(&self) -> String {
+        self.keys.first().cloned().unwrap_or_default()
+    }
+}
+
+/// Execution result of a `TestCommand`
+#[allow(clippy::exhaustive_enums)] // the tests match all the results
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum TestCommandResult {
+    /// The value put
+    PutResult(String),
+    /// The value got, it's empty if the key doesn't exist
+    GetResult(String),
+}
+
+impl Command for TestCommand {
+    type K = String;
+
+    type ER = TestCommandResult;
+
+    type ASR = LogIndex;
+
+    #[inline]
+    fn keys(&self) -> &[Self::K] {
+        &self.keys
+    }
+
+    #[inline]
+    fn id(&self) -> &ProposeId {
+        &self.id
+    }
+
+    #[inline]
+    fn is_read_only(&self) -> bool {
+        self.t == TestCommandType::Get
+    }
+}
+
+impl ConflictCheck for TestCommand {
+    #[inline]
+    fn is_conflict(&self, other: &Self) -> bool {
+        self.keys.iter().any(|key| {
+            other
+                .keys
+                .iter()
+                .any(|other_key| key.is_conflict(other_key))
+        })
+    }
+}
+
+/// Executor of `TestCommand`s, it reports the type and the key of every command it executes or
+/// after syncs to the channels it's created with. The reports are dropped once the channels are
+/// closed.
+#[derive(Debug, Clone)]
+pub struct TestExecutor {
+    /// Report the executed commands
+    exe_sender: mpsc::Sender<(TestCommandType, String)>,
+    /// Report the after synced commands
+    after_sync_sender: mpsc::Sender<(TestCommandType, String)>,
+    /// The KV store
+    store: Arc<Mutex<HashMap<String, String>>>,
+}
+
+#[async_trait]
+impl CommandExecutor<TestCommand> for TestExecutor {
+    #[inline]
+    async fn execute(&self, cmd: &TestCommand) -> Result<TestCommandResult, ExecuteError> {
+        let _ignore = self.exe_sender.send((cmd.t, cmd.key())).await;
+        match cmd.t {
+            TestCommandType::Get => Ok(TestCommandResult::GetResult(
+                self.store
+                    .lock()
+                    .get(&cmd.key())
+                    .cloned()
+                    .unwrap_or_default(),
+            )),
+            TestCommandType::Put => {
+                let value = cmd.value.clone().unwrap_or_default();
+                let _prev = self.store.lock().insert(cmd.key(), value.clone());
+                Ok(TestCommandResult::PutResult(value))
+            }
+        }
+    }
+
+    #[inline]
+    async fn after_sync(
+        &self,
+        cmd: &TestCommand,
+        index: LogIndex,
+    ) -> Result<LogIndex, ExecuteError> {
+        let _ignore = self.after_sync_sender.send((cmd.t, cmd.key())).await;
+        Ok(index)
+    }
+}
+
+impl TestExecutor {
+    /// New `TestExecutor` with an empty store
+    #[inline]
+    #[must_use]
+    pub fn new(
+        exe_sender: mpsc::Sender<(TestCommandType, String)>,
+        after_sync_sender: mpsc::Sender<(TestCommandType, String)>,
+    ) -> Self {
+        Self {
+            exe_sender,
+            after_sync_sender,
+            store: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+}
@@ -0,0 +1,471 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    sync::mpsc as std_mpsc,
+    thread,
+};
+
+use clippy_utilities::NumericCast;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+use tracing::{error, warn};
+
+use crate::{
+    codec::{Bincode, Codec},
+    log::{Log, LogEntry},
+    message::{ServerId, TermNum},
+};
+
+/// Name of the WAL file in the data dir
+const WAL_FILE: &str = "curp.wal";
+
+/// Length of the header of a frame, the length and the crc32 of the record
+const FRAME_HEADER_LEN: usize = 8;
+
+/// A record in the WAL, it's framed as `[len: u32, crc32: u32, record..]` in little endian
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) enum Record<C> {
+    /// The term and the vote of the server, the last one wins
+    Vote {
+        /// Current term
+        term: TermNum,
+        /// Candidate that received the vote in the term
+        voted_for: Option<ServerId>,
+    },
+    /// A log entry at `index`, it replaces the entries at and after `index`
+    Entry {
+        /// Index of the entry
+        index: usize,
+        /// Running hash of the log up to the entry, it's checked when the log is replayed
+        hash: u32,
+        /// The entry
+        entry: LogEntry<C>,
+    },
+}
+
+/// The consensus state recovered from the WAL
+#[derive(Debug)]
+pub(crate) struct Recovered<C> {
+    /// The last persisted term
+    pub(crate) term: TermNum,
+    /// Candidate that received the vote in `term`
+    pub(crate) voted_for: Option<ServerId>,
+    /// The persisted log
+    pub(crate) log: Log<C>,
+}
+
+impl<C> Recovered<C> {
+    /// The state of a server that starts for the first time
+    fn new() -> Self {
+        Self {
+            term: 0,
+            voted_for: None,
+            log: Log::new(),
+        }
+    }
+
+    /// Nothing has been persisted, the server starts for the first time
+    pub(crate) fn is_empty(&self) -> bool {
+        self.term == 0 && self.voted_for.is_none() && self.log.last_log_index() == 0
+    }
+}
+
+/// Records submitted to the writer, `seq` increases with every submission
+struct Submission<C> {
+    /// Sequence number of the submission
+    seq: u64,
+    /// Records to write
+    records: Vec<Record<C>>,
+}
+
+/// Write-ahead log of the term, the vote and the log entries of a server.
+///
+/// Records are submitted while the state they describe is locked, so they are written in the
+/// same order as the state changes. A dedicated thread writes the submitted records and syncs
+/// them with one `fsync` per batch, the async tasks wait for their submissions without
+/// blocking the runtime. If the WAL fails to write, every later wait fails and the server stops
+/// acknowledging anything it can't persist. A WAL without a data dir keeps nothing.
+///
+/// The log is never compacted, it's kept in memory and replayed from the beginning when the
+/// server restarts.
+pub(crate) struct Wal<C> {
+    /// Sequence number of the last submission and the channel to the writer, `None` if
+    /// nothing is persisted
+    writer: Option<Mutex<(u64, std_mpsc::Sender<Submission<C>>)>>,
+    /// Sequence number of the last synced submission, it's closed once the writer fails
+    synced: watch::Receiver<u64>,
+}
+
+impl<C> std::fmt::Debug for Wal<C> {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Wal")
+            .field("persistent", &self.writer.is_some())
+            .field("synced", &*self.synced.borrow())
+            .finish()
+    }
+}
+
+impl<C> Wal<C>
+where
+    C: Serialize + for<'de> Deserialize<'de> + Send + Sync + 'static,
+{
+    /// A WAL that keeps nothing, the server always starts with an empty state
+    pub(crate) fn memory() -> (Self, Recovered<C>) {
+        let (_synced_tx, synced) = watch::channel(0);
+        (
+            Self {
+                writer: None,
+                synced,
+            },
+            Recovered::new(),
+        )
+    }
+
+    /// Open the WAL in `dir` and replay it, a record torn by a crash at the end of the WAL is
+    /// dropped.
+    ///
+    /// # Errors
+    ///   `io::Error` if the WAL can't be read or written, or a record is corrupted
+    pub(crate) fn open(dir: &Path) -> io::Result<(Self, Recovered<C>)> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(WAL_FILE);
+        let file = OpenOptions::new().append(true).create(true).open(&path)?;
+        let buf = fs::read(&path)?;
+        let (recovered, valid_len) = Self::replay(&buf)?;
+        if valid_len < buf.len() {
+            warn!(
+                "drop the torn record at the end of {}, {} bytes",
+                path.display(),
+                buf.len().wrapping_sub(valid_len)
+            );
+            file.set_len(valid_len.numeric_cast())?;
+            file.sync_all()?;
+        }
+
+        let (tx, rx) = std_mpsc::channel();
+        let (synced_tx, synced) = watch::channel(0);
+        let _handle = thread::Builder::new()
+            .name("curp-wal".to_owned())
+            .spawn(move || Self::write_loop(file, &path, &rx, &synced_tx))?;
+        Ok((
+            Self {
+                writer: Some(Mutex::new((0, tx))),
+                synced,
+            },
+            recovered,
+        ))
+    }
+
+    /// Replay the records in `buf`, return the recovered state and the length of the valid
+    /// records
+    fn replay(buf: &[u8]) -> io::Result<(Recovered<C>, usize)> {
+        let mut recovered = Recovered::new();
+        let mut pos = 0;
+        while pos < buf.len() {
+            let header = buf.get(pos..pos.saturating_add(FRAME_HEADER_LEN));
+            let frame = header.and_then(|header| {
+                let (len, crc) = header.split_at(4);
+                let len: usize = u32::from_le_bytes(len.try_into().ok()?).try_into().ok()?;
+                let crc = u32::from_le_bytes(crc.try_into().ok()?);
+                let start = pos.saturating_add(FRAME_HEADER_LEN);
+                buf.get(start..start.saturating_add(len))
+                    .map(|record| (record, crc))
+            });
+            let Some((record, crc)) = frame else {
+                // the last record is torn by a crash
+                break;
+            };
+            let end = pos
+                .saturating_add(FRAME_HEADER_LEN)
+                .saturating_add(record.len());
+            if crc32fast::hash(record) != crc {
+                if end == buf.len() {
+                    break;
+                }
+                return Err(corrupted(format!(
+                    "record at offset {pos} mismatches its crc"
+                )));
+            }
+            let record = Bincode::decode(record).map_err(corrupted)?;
+            Self::apply(&mut recovered, record)?;
+            pos = end;
+        }
+        Ok((recovered, pos))
+    }
+
+    /// Apply a record to the recovered state, the crcs of the entries are computed again and
+    /// the running hash of the log must be the same as the one recorded
+    fn apply(recovered: &mut Recovered<C>, record: Record<C>) -> io::Result<()> {
+        match record {
+            Record::Vote { term, voted_for } => {
+                recovered.term = term;
+                recovered.voted_for = voted_for;
+            }
+            Record::Entry { index, hash, entry } => {
+                if index == 0 || index > recovered.log.len() {
+                    return Err(corrupted(format!(
+                        "log[{index}] is recorded after log[{}]",
+                        recovered.log.last_log_index()
+                    )));
+                }
+                let crc = entry.crc().map_err(corrupted)?;
+                recovered.log.truncate(index);
+                recovered.log.push(entry, crc);
+                if recovered.log.hash(index) != Some(hash) {
+                    return Err(corrupted(format!(
+                        "running hash of log[{index}] mismatches the recorded one"
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Write and sync the submitted records in batches until the WAL is dropped or it fails
+    fn write_loop(
+        mut file: File,
+        path: &Path,
+        rx: &std_mpsc::Receiver<Submission<C>>,
+        synced_tx: &watch::Sender<u64>,
+    ) {
+        let mut buf = vec![];
+        while let Ok(first) = rx.recv() {
+            buf.clear();
+            let mut last_seq = first.seq;
+            for submission in std::iter::once(first).chain(rx.try_iter()) {
+                last_seq = submission.seq;
+                for record in &submission.records {
+                    if let Err(e) = Self::encode(record, &mut buf) {
+                        error!("failed to encode a record of {}: {e}", path.display());
+                        return;
+                    }
+                }
+            }
+            if let Err(e) = file.write_all(&buf).and_then(|()| file.sync_data()) {
+                error!("failed to write {}: {e}", path.display());
+                return;
+            }
+            let _ignore = synced_tx.send_replace(last_seq);
+        }
+    }
+
+    /// Append the frame of `record` to `buf`
+    fn encode(record: &Record<C>, buf: &mut Vec<u8>) -> io::Result<()> {
+        let bytes = Bincode::encode(record).map_err(corrupted)?;
+        let len: u32 = bytes
+            .len()
+            .try_into()
+            .map_err(|_e| corrupted(format!("record of {} bytes is too large", bytes.len())))?;
+        buf.extend_from_slice(&len.to_le_bytes());
+        buf.extend_from_slice(&crc32fast::hash(&bytes).to_le_bytes());
+        buf.extend_from_slice(&bytes);
+        Ok(())
+    }
+
+    /// Submit `records` to be persisted, it doesn't block. Return the sequence number of the
+    /// submission to wait for.
+    pub(crate) fn submit(&self, records: Vec<Record<C>>) -> u64 {
+        let Some(ref writer) = self.writer else {
+            return 0;
+        };
+        let mut writer = writer.lock();
+        writer.0 = writer.0.wrapping_add(1);
+        let seq = writer.0;
+        if writer.1.send(Submission { seq, records }).is_err() {
+            error!("the WAL writer has stopped, record {seq} is not persisted");
+        }
+        seq
+    }
+
+    /// Submit the vote of the server in `term`
+    pub(crate) fn submit_vote(&self, term: TermNum, voted_for: Option<ServerId>) -> u64 {
+        self.submit(vec![Record::Vote { term, voted_for }])
+    }
+
+    /// Wait until the submission `seq` and all the ones before it are persisted
+    ///
+    /// # Errors
+    ///   `io::Error` if the WAL fails to write
+    pub(crate) async fn wait(&self, seq: u64) -> io::Result<()> {
+        if self.writer.is_none() {
+            return Ok(());
+        }
+        let mut synced = self.synced.clone();
+        let _synced = synced
+            .wait_for(|&synced| synced >= seq)
+            .await
+            .map_err(|_e| {
+                io::Error::new(io::ErrorKind::BrokenPipe, "the WAL writer has stopped")
+            })?;
+        Ok(())
+    }
+
+    /// Wait until everything submitted so far is persisted
+    ///
+    /// # Errors
+    ///   `io::Error` if the WAL fails to write
+    pub(crate) async fn wait_all(&self) -> io::Result<()> {
+        let seq = self.writer.as_ref().map_or(0, |writer| writer.lock().0);
+        self.wait(seq).await
+    }
+}
+
+/// The error of a corrupted WAL
+fn corrupted(reason: impl std::fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("WAL is corrupted, {reason}"),
+    )
+}
+
+/// Records of the entries `log[index..]`
+pub(crate) fn entry_records<C: Clone>(log: &Log<C>, index: usize) -> Vec<Record<C>> {
+    log.get(index..)
+        .into_iter()
+        .flatten()
+        .zip(index..)
+        .map(|(entry, i)| Record::Entry {
+            index: i,
+            hash: log.hash(i).unwrap_or_default(),
+            entry: entry.clone(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::{path::PathBuf, sync::Arc};
+
+    use super::*;
+    use crate::{
+        cmd::ProposeId,
+        test_utils::test_cmd::{TestCommand, TestCommandType},
+    };
+
+    /// A new dir for a WAL
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("curp-wal-{}", rand::random::<u64>()))
+    }
+
+    /// A log entry of `term` that puts `key`
+    fn entry(term: TermNum, key: &str) -> LogEntry<TestCommand> {
+        let cmd = TestCommand::new(
+            ProposeId::new(key.to_owned()),
+            TestCommandType::Put,
+            vec![key.to_owned()],
+            Some(key.to_owned()),
+        );
+        LogEntry::new(term, &[Arc::new(cmd)])
+    }
+
+    /// A log of the entries of `term` that put `keys`
+    #[allow(clippy::unwrap_used)]
+    fn log(term: TermNum, keys: &[&str]) -> Log<TestCommand> {
+        let mut log = Log::new();
+        for key in keys {
+            let entry = entry(term, key);
+            let crc = entry.crc().unwrap();
+            log.push(entry, crc);
+        }
+        log
+    }
+
+    /// Append `records` to the WAL in `dir` and wait until they are persisted
+    #[allow(clippy::unwrap_used)]
+    async fn write(dir: &Path, records: Vec<Record<TestCommand>>) {
+        let (wal, _recovered) = Wal::<TestCommand>::open(dir).unwrap();
+        let _seq = wal.submit(records);
+        wal.wait_all().await.unwrap();
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[tokio::test]
+    async fn state_is_recovered_after_restart() {
+        let dir = temp_dir();
+        let stale = log(1, &["a", "b"]);
+        // log[2] is overwritten by the leader of term 2
+        let mut log = log(1, &["a"]);
+        let entry = entry(2, "c");
+        let crc = entry.crc().unwrap();
+        log.push(entry, crc);
+
+        let (_empty_wal, empty) = Wal::<TestCommand>::open(&dir).unwrap();
+        assert!(empty.is_empty());
+        let mut records = vec![Record::Vote {
+            term: 1,
+            voted_for: Some(0),
+        }];
+        records.extend(entry_records(&stale, 1));
+        records.push(Record::Vote {
+            term: 2,
+            voted_for: Some(1),
+        });
+        records.extend(entry_records(&log, 2));
+        write(&dir, records).await;
+
+        let (_wal, recovered) = Wal::<TestCommand>::open(&dir).unwrap();
+        assert_eq!(recovered.term, 2);
+        assert_eq!(recovered.voted_for, Some(1));
+        assert_eq!(recovered.log.last_log_index(), 2);
+        assert_eq!(recovered.log.last_log_term(), 2);
+        assert_eq!(recovered.log.hash(2), log.hash(2));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[tokio::test]
+    async fn torn_record_is_dropped() {
+        let dir = temp_dir();
+        let log = log(1, &["a", "b"]);
+        write(&dir, entry_records(&log, 1)).await;
+
+        // the crash happens while log[2] is written
+        let path = dir.join(WAL_FILE);
+        let len = fs::metadata(&path).unwrap().len();
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(len.wrapping_sub(3)).unwrap();
+        drop(file);
+
+        let (_torn_wal, torn) = Wal::<TestCommand>::open(&dir).unwrap();
+        assert_eq!(torn.log.last_log_index(), 1);
+        // the records written after the torn one are recovered
+        write(&dir, entry_records(&log, 2)).await;
+        let (_wal, recovered) = Wal::<TestCommand>::open(&dir).unwrap();
+        assert_eq!(recovered.log.hash(2), log.hash(2));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[allow(clippy::unwrap_used, clippy::indexing_slicing)]
+    #[tokio::test]
+    async fn corrupted_record_is_rejected() {
+        let dir = temp_dir();
+        write(&dir, entry_records(&log(1, &["a", "b"]), 1)).await;
+
+        // a bit of log[1] flips on the disk
+        let path = dir.join(WAL_FILE);
+        let mut buf = fs::read(&path).unwrap();
+        buf[FRAME_HEADER_LEN] ^= 0x01;
+        fs::write(&path, buf).unwrap();
+
+        let err = Wal::<TestCommand>::open(&dir).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[tokio::test]
+    async fn mismatched_running_hash_is_rejected() {
+        let dir = temp_dir();
+        // log[2] is recorded with the hash of a log that has a different log[1]
+        let mut records = entry_records(&log(1, &["a"]), 1);
+        records.extend(entry_records(&log(1, &["c", "b"]), 2));
+        write(&dir, records).await;
+
+        let err = Wal::<TestCommand>::open(&dir).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,36 @@
+use std::time::Duration;
+
+use crate::common::{put, spawn_cluster, TestCommandResult};
+
+mod common;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 10)]
+async fn apply_progress() {
+    tracing_subscriber::fmt::init();
+    let cluster = spawn_cluster(3).await;
+    let servers = &cluster.servers;
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    let progress = servers[0].progress();
+    assert_eq!(progress.borrow().last_applied, 0);
+    assert_eq!(progress.borrow().leader, Some(0));
+
+    let client = cluster.client().await;
+    let (er, index) = client.propose_indexed(put("id1", "A")).await.unwrap();
+    assert_eq!(er, TestCommandResult::PutResult("A".to_owned()));
+
+    assert!(progress.borrow().commit_index >= index);
+
+    // every server learns the leader and applies the cmd eventually
+    for server in servers {
+        let mut progress = server.progress();
+        tokio::time::timeout(Duration::from_secs(2), async {
+            while progress.borrow_and_update().last_applied < index {
+                progress.changed().await.unwrap();
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(progress.borrow().leader, Some(0));
+    }
+}
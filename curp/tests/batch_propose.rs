@@ -0,0 +1,35 @@
+use std::{sync::Arc, time::Duration};
+
+use curp::client::BatchConfig;
+
+use crate::common::{create_servers_client, put, TestCommandResult};
+
+mod common;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 10)]
+async fn batched_proposals_are_fanned_out() {
+    let (mut exe_rx, mut after_sync_rx, mut client) = create_servers_client().await;
+    // drain the executor channels so that the executors never block
+    tokio::spawn(async move { while exe_rx.recv().await.is_some() {} });
+    tokio::spawn(async move { while after_sync_rx.recv().await.is_some() {} });
+    client.enable_batching(BatchConfig::new(Duration::from_millis(10), 16));
+    let client = Arc::new(client);
+
+    // the last two commands conflict with each other, they still get their own results
+    let keys = (0..20)
+        .map(|i| format!("key{i}"))
+        .chain(["dup".to_owned(), "dup".to_owned()]);
+    let handles: Vec<_> = keys
+        .enumerate()
+        .map(|(i, key)| {
+            let client = Arc::clone(&client);
+            tokio::spawn(async move {
+                let er = client.propose(put(&format!("id{i}"), &key)).await.unwrap();
+                assert_eq!(er, TestCommandResult::PutResult(key));
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.await.unwrap();
+    }
+}
@@ -1,126 +1,149 @@
-use std::{net::SocketAddr, thread, time::Duration};
+use std::{collections::HashMap, net::SocketAddr, path::PathBuf, time::Duration};
 
-use async_trait::async_trait;
 use curp::{
     client::Client,
-    cmd::{Command, CommandExecutor, ConflictCheck, ProposeId},
-    error::ExecuteError,
-    server::Rpc,
-    LogIndex,
+    cmd::ProposeId,
+    codec::WireFormat,
+    quorum::QuorumConfig,
+    server::{Placement, Rpc},
+    test_utils::network::Network,
+    ProtocolServer, ServerId,
 };
-use itertools::Itertools;
-use serde::{Deserialize, Serialize};
-use tokio::sync::mpsc::{self, Receiver};
-
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
-pub enum TestCommandType {
-    Get,
-    Put,
-}
+use tokio::{
+    net::TcpListener,
+    sync::mpsc::{self, Receiver, Sender},
+    task::JoinHandle,
+};
+use tokio_stream::wrappers::TcpListenerStream;
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
-pub struct TestCommand {
-    id: ProposeId,
-    t: TestCommandType,
-    keys: Vec<String>,
-    value: Option<String>,
-}
+#[allow(unused_imports)] // not every test uses all of them
+pub use curp::test_utils::test_cmd::{
+    TestCommand, TestCommandResult, TestCommandType, TestExecutor,
+};
 
-impl TestCommand {
-    pub fn new(
-        id: ProposeId,
-        t: TestCommandType,
-        keys: Vec<String>,
-        value: Option<String>,
-    ) -> Self {
-        Self { id, t, keys, value }
-    }
+/// A put of `key` whose value is `key`
+#[allow(dead_code)]
+pub fn put(id: &str, key: &str) -> TestCommand {
+    TestCommand::new(
+        ProposeId::new(id.to_owned()),
+        TestCommandType::Put,
+        vec![key.to_owned()],
+        Some(key.to_owned()),
+    )
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
-pub enum TestCommandResult {
-    PutResult(String),
-    GetResult(String),
+/// A cluster of curp servers listening on random local ports, the id of a server is its
+/// position in `servers`
+#[allow(dead_code)]
+pub struct TestCluster {
+    pub servers: Vec<Rpc<TestCommand>>,
+    pub addrs: Vec<SocketAddr>,
+    pub exe_rx: Receiver<(TestCommandType, String)>,
+    pub after_sync_rx: Receiver<(TestCommandType, String)>,
 }
 
-impl Command for TestCommand {
-    type K = String;
-
-    type ER = TestCommandResult;
-
-    type ASR = LogIndex;
-
-    fn keys(&self) -> &[Self::K] {
-        &self.keys
+#[allow(dead_code)]
+impl TestCluster {
+    /// Create a client of the cluster, server 0 is the initial leader
+    pub async fn client(&self) -> Client<TestCommand> {
+        Client::<TestCommand>::new(0, member_addrs(&self.addrs), QuorumConfig::default()).await
     }
+}
 
-    fn id(&self) -> &curp::cmd::ProposeId {
-        &self.id
+/// Bind `n` listeners on random local ports
+#[allow(dead_code)]
+pub async fn bind_listeners(n: usize) -> Vec<TcpListener> {
+    let mut listeners = vec![];
+    for _ in 0..n {
+        listeners.push(TcpListener::bind("127.0.0.1:0").await.unwrap());
     }
+    listeners
 }
 
-impl ConflictCheck for TestCommand {
-    fn is_conflict(&self, other: &Self) -> bool {
-        let this_keys = self.keys();
-        let other_keys = other.keys();
-
-        this_keys
-            .iter()
-            .cartesian_product(other_keys.iter())
-            .map(|(k1, k2)| k1.is_conflict(k2))
-            .any(|conflict| conflict)
-    }
+/// Addresses of all the servers by their ids
+#[allow(dead_code)]
+pub fn member_addrs(addrs: &[SocketAddr]) -> HashMap<ServerId, SocketAddr> {
+    addrs
+        .iter()
+        .enumerate()
+        .map(|(id, &addr)| (id as ServerId, addr))
+        .collect()
 }
 
-#[derive(Debug, Clone)]
-pub struct TestExecutor {
-    exe_sender: mpsc::Sender<(TestCommandType, String)>,
-    after_sync_sender: mpsc::Sender<(TestCommandType, String)>,
+/// Addresses of the servers other than `id`
+#[allow(dead_code)]
+pub fn other_addrs(addrs: &[SocketAddr], id: ServerId) -> HashMap<ServerId, String> {
+    addrs
+        .iter()
+        .enumerate()
+        .map(|(other, addr)| (other as ServerId, addr.to_string()))
+        .filter(|&(other, _)| other != id)
+        .collect()
 }
 
-#[async_trait]
-impl CommandExecutor<TestCommand> for TestExecutor {
-    async fn execute(&self, cmd: &TestCommand) -> Result<TestCommandResult, ExecuteError> {
-        let _ = self
-            .exe_sender
-            .send((cmd.t.clone(), cmd.keys()[0].clone()))
-            .await;
-        match cmd.t {
-            TestCommandType::Get => Ok(TestCommandResult::GetResult("".to_owned())),
-            TestCommandType::Put => Ok(TestCommandResult::PutResult(
-                cmd.value
-                    .as_ref()
-                    .expect("push command should contain value")
-                    .clone(),
-            )),
-        }
-    }
+/// Serve the curp protocol of `server` on `listener`
+#[allow(dead_code)]
+pub fn serve(
+    server: Rpc<TestCommand>,
+    listener: TcpListener,
+) -> JoinHandle<Result<(), tonic::transport::Error>> {
+    tokio::spawn(async move {
+        tonic::transport::Server::builder()
+            .add_service(ProtocolServer::new(server))
+            .serve_with_incoming(TcpListenerStream::new(listener))
+            .await
+    })
+}
 
-    async fn after_sync(
-        &self,
-        cmd: &TestCommand,
-        index: LogIndex,
-    ) -> Result<LogIndex, ExecuteError> {
-        let _ = self
-            .after_sync_sender
-            .send((cmd.t.clone(), cmd.keys()[0].clone()))
-            .await;
-        match cmd.t {
-            TestCommandType::Get => Ok(index),
-            TestCommandType::Put => Ok(index),
-        }
-    }
+/// Spawn a cluster of `n` servers, server 0 is the initial leader
+#[allow(dead_code)]
+pub async fn spawn_cluster(n: usize) -> TestCluster {
+    spawn_cluster_with_placements(n, HashMap::new()).await
 }
 
-impl TestExecutor {
-    pub fn new(
-        exe_sender: mpsc::Sender<(TestCommandType, String)>,
-        after_sync_sender: mpsc::Sender<(TestCommandType, String)>,
-    ) -> Self {
-        Self {
-            exe_sender,
-            after_sync_sender,
-        }
+/// Spawn a cluster of `n` servers with `placements`, server 0 is the initial leader
+#[allow(dead_code)]
+pub async fn spawn_cluster_with_placements(
+    n: usize,
+    placements: HashMap<ServerId, Placement>,
+) -> TestCluster {
+    let listeners = bind_listeners(n).await;
+    let addrs: Vec<_> = listeners
+        .iter()
+        .map(|listener| listener.local_addr().unwrap())
+        .collect();
+
+    let (exe_tx, exe_rx) = mpsc::channel(100);
+    let (after_sync_tx, after_sync_rx) = mpsc::channel(100);
+
+    let mut servers = vec![];
+    for (i, listener) in listeners.into_iter().enumerate() {
+        let id = i as ServerId;
+        let exe = TestExecutor::new(exe_tx.clone(), after_sync_tx.clone());
+        let server = Rpc::<TestCommand>::new(
+            id,
+            i == 0,
+            0,
+            other_addrs(&addrs, id),
+            placements.clone(),
+            QuorumConfig::default(),
+            // servers with different formats must be able to talk to each other
+            if id % 2 == 0 {
+                WireFormat::Bincode
+            } else {
+                WireFormat::Json
+            },
+            None,
+            exe,
+        );
+        servers.push(server.clone());
+        let _handle = serve(server, listener);
+    }
+    TestCluster {
+        servers,
+        addrs,
+        exe_rx,
+        after_sync_rx,
     }
 }
 
@@ -130,50 +153,163 @@ pub async fn create_servers_client() -> (
     Receiver<(TestCommandType, String)>,
     Client<TestCommand>,
 ) {
-    let addrs: Vec<String> = vec![
-        "127.0.0.1:8765".to_owned(),
-        "127.0.0.1:8766".to_owned(),
-        "127.0.0.1:8767".to_owned(),
-    ];
+    let cluster = spawn_cluster(3).await;
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    let client = cluster.client().await;
+    (cluster.exe_rx, cluster.after_sync_rx, client)
+}
 
-    let (exe_tx, exe_rx) = mpsc::channel(100);
-    let (after_sync_tx, after_sync_rx) = mpsc::channel(100);
+/// A cluster whose servers talk to each other and to the clients through a `Network`, so that
+/// the servers can be partitioned, killed and restarted. The id of a server is its position in
+/// the listeners it's spawned on. The servers of a persistent cluster keep their state in
+/// `data_dir` across restarts.
+#[allow(dead_code)]
+pub struct FaultyCluster {
+    pub network: Network,
+    /// Running servers and the tasks serving them
+    servers: HashMap<
+        ServerId,
+        (
+            Rpc<TestCommand>,
+            JoinHandle<Result<(), tonic::transport::Error>>,
+        ),
+    >,
+    exe_tx: Sender<(TestCommandType, String)>,
+    after_sync_tx: Sender<(TestCommandType, String)>,
+    data_dir: Option<PathBuf>,
+}
 
-    let exe_tx1 = exe_tx.clone();
-    let after_sync_tx1 = after_sync_tx.clone();
-    let addr0 = addrs[0].clone();
-    let addr1 = vec![addrs[1].clone(), addrs[2].clone()];
-    tokio::spawn(async move {
-        let exe = TestExecutor::new(exe_tx1, after_sync_tx1);
-        Rpc::<TestCommand>::run(addr0.as_str(), true, 0, addr1, Some(8765), exe).await
-    });
-    let exe_tx2 = exe_tx.clone();
-    let after_sync_tx2 = after_sync_tx.clone();
-    let addr1 = addrs[1].clone();
-    let addr2 = vec![addrs[0].clone(), addrs[2].clone()];
-    tokio::spawn(async move {
-        let exe = TestExecutor::new(exe_tx2, after_sync_tx2);
-        Rpc::<TestCommand>::run(addr1.as_str(), false, 0, addr2, Some(8766), exe).await
-    });
-    let exe_tx3 = exe_tx.clone();
-    let after_sync_tx3 = after_sync_tx.clone();
-    let addr2 = addrs[2].clone();
-    let addr3 = vec![addrs[0].clone(), addrs[1].clone()];
-    tokio::spawn(async move {
-        let exe = TestExecutor::new(exe_tx3, after_sync_tx3);
-        let _ = Rpc::<TestCommand>::run(addr2.as_str(), false, 0, addr3, Some(8767), exe).await;
-    });
+#[allow(dead_code)]
+impl FaultyCluster {
+    /// Spawn a cluster of `n` servers, server 0 is the initial leader
+    pub async fn spawn(n: usize) -> Self {
+        Self::spawn_with_data_dir(n, None).await
+    }
+
+    /// Spawn a cluster of `n` servers that persist their state in a temp dir, server 0 is the
+    /// initial leader
+    pub async fn spawn_persistent(n: usize) -> Self {
+        let data_dir = std::env::temp_dir().join(format!("curp-cluster-{}", rand::random::<u64>()));
+        Self::spawn_with_data_dir(n, Some(data_dir)).await
+    }
+
+    /// Spawn a cluster of `n` servers, server 0 is the initial leader
+    async fn spawn_with_data_dir(n: usize, data_dir: Option<PathBuf>) -> Self {
+        let listeners = bind_listeners(n).await;
+        let addrs: Vec<_> = listeners
+            .iter()
+            .map(|listener| listener.local_addr().unwrap())
+            .collect();
+        let network = Network::new(&member_addrs(&addrs)).await.unwrap();
 
-    thread::sleep(Duration::from_secs(1));
+        let (exe_tx, mut exe_rx) = mpsc::channel(100);
+        let (after_sync_tx, mut after_sync_rx) = mpsc::channel(100);
+        // drain the executor channels so that the executors never block
+        tokio::spawn(async move { while exe_rx.recv().await.is_some() {} });
+        tokio::spawn(async move { while after_sync_rx.recv().await.is_some() {} });
 
-    let client = Client::<TestCommand>::new(
-        0,
-        addrs
+        let mut cluster = Self {
+            network,
+            servers: HashMap::new(),
+            exe_tx,
+            after_sync_tx,
+            data_dir,
+        };
+        for (i, listener) in listeners.into_iter().enumerate() {
+            cluster.start(i as ServerId, i == 0, listener);
+        }
+        cluster
+    }
+
+    /// Start the server `id` on `listener`
+    fn start(&mut self, id: ServerId, is_leader: bool, listener: TcpListener) {
+        let exe = TestExecutor::new(self.exe_tx.clone(), self.after_sync_tx.clone());
+        let others = self
+            .network
+            .peer_addrs(id)
             .into_iter()
-            .map(|a| a.parse())
-            .collect::<Result<Vec<SocketAddr>, _>>()
-            .unwrap(),
-    )
-    .await;
-    (exe_rx, after_sync_rx, client)
+            .map(|(other, addr)| (other, addr.to_string()))
+            .collect();
+        let server = Rpc::<TestCommand>::new(
+            id,
+            is_leader,
+            0,
+            others,
+            HashMap::new(),
+            QuorumConfig::default(),
+            WireFormat::default(),
+            self.data_dir.as_ref().map(|dir| dir.join(id.to_string())),
+            exe,
+        );
+        let handle = serve(server.clone(), listener);
+        self.servers.insert(id, (server, handle));
+    }
+
+    /// Kill the server `id`, its state is lost
+    pub fn kill(&mut self, id: ServerId) {
+        self.network.isolate(id);
+        if let Some((_server, handle)) = self.servers.remove(&id) {
+            handle.abort();
+        }
+    }
+
+    /// Restart the killed server `id` as a follower, its state is recovered if the cluster is
+    /// persistent, otherwise it's empty
+    pub async fn restart(&mut self, id: ServerId) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        self.network.redirect(id, listener.local_addr().unwrap());
+        self.start(id, false, listener);
+        self.network.heal(id);
+    }
+
+    /// The running server `id`
+    pub fn server(&self, id: ServerId) -> &Rpc<TestCommand> {
+        &self.servers[&id].0
+    }
+
+    /// Create a client of the cluster whose leader is `leader`
+    pub async fn client(&self, leader: ServerId) -> Client<TestCommand> {
+        Client::<TestCommand>::new(leader, self.network.client_addrs(), QuorumConfig::default())
+            .await
+    }
+
+    /// Wait until the running servers other than `old` agree on a leader other than `old`
+    pub async fn wait_for_new_leader(&self, old: ServerId) -> ServerId {
+        self.wait_for_leader_other_than(Some(old)).await
+    }
+
+    /// Wait until the running servers agree on a leader
+    pub async fn wait_for_leader(&self) -> ServerId {
+        self.wait_for_leader_other_than(None).await
+    }
+
+    /// Wait until the running servers other than `old` agree on a leader other than `old`
+    async fn wait_for_leader_other_than(&self, old: Option<ServerId>) -> ServerId {
+        tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                let leaders: Vec<_> = self
+                    .servers
+                    .iter()
+                    .filter(|&(&id, _)| Some(id) != old)
+                    .map(|(_, (server, _))| server.status().leader)
+                    .collect();
+                if let Some(&Some(leader)) = leaders.first() {
+                    if Some(leader) != old && leaders.iter().all(|&l| l == Some(leader)) {
+                        return leader;
+                    }
+                }
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        })
+        .await
+        .expect("a new leader should be elected")
+    }
+}
+
+impl Drop for FaultyCluster {
+    fn drop(&mut self) {
+        if let Some(ref dir) = self.data_dir {
+            let _ignore = std::fs::remove_dir_all(dir);
+        }
+    }
 }
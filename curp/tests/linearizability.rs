@@ -0,0 +1,245 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock,
+    },
+    time::Duration,
+};
+
+use curp::{
+    client::Client,
+    cmd::ProposeId,
+    test_utils::linearizability::{History, KvInput, KvOutput},
+};
+use rand::Rng;
+
+use crate::common::{
+    create_servers_client, FaultyCluster, TestCommand, TestCommandResult, TestCommandType,
+};
+
+mod common;
+
+/// Number of concurrent clients
+const N_CLIENTS: usize = 8;
+/// Number of operations issued by each client
+const N_OPS: usize = 50;
+/// Keys operated on, few of them so that operations conflict often
+const KEYS: [&str; 3] = ["A", "B", "C"];
+/// Number of concurrent clients when failures are injected
+const N_FAULTY_CLIENTS: usize = 4;
+/// Pause between two operations of a client when failures are injected, so that the workload
+/// lasts until the cluster recovers
+const OP_INTERVAL: Duration = Duration::from_millis(20);
+/// Pause of a client after its operation fails. An uncompleted put may take effect at any time
+/// after it's invoked, so the clients back off to keep the history small enough to check.
+const FAILURE_BACKOFF: Duration = Duration::from_millis(500);
+
+/// The client used by the workload, it's replaced when the leader changes
+type SharedClient = Arc<RwLock<Arc<Client<TestCommand>>>>;
+
+#[test]
+fn checker_accepts_linearizable_history() {
+    let history = History::new();
+    let put = history.invoke(KvInput::Put {
+        key: "A".to_owned(),
+        value: "1".to_owned(),
+    });
+    // a get concurrent with the put may see either value
+    let get0 = history.invoke(KvInput::Get {
+        key: "A".to_owned(),
+    });
+    history.complete(get0, KvOutput::Get(None));
+    let get1 = history.invoke(KvInput::Get {
+        key: "A".to_owned(),
+    });
+    history.complete(get1, KvOutput::Get(Some("1".to_owned())));
+    history.complete(put, KvOutput::Put);
+    // a failed put may take effect at any time
+    let _failed = history.invoke(KvInput::Put {
+        key: "B".to_owned(),
+        value: "2".to_owned(),
+    });
+    let get2 = history.invoke(KvInput::Get {
+        key: "B".to_owned(),
+    });
+    history.complete(get2, KvOutput::Get(Some("2".to_owned())));
+    assert!(history.is_linearizable());
+}
+
+#[test]
+fn checker_rejects_stale_read() {
+    let history = History::new();
+    let put = history.invoke(KvInput::Put {
+        key: "A".to_owned(),
+        value: "1".to_owned(),
+    });
+    history.complete(put, KvOutput::Put);
+    let get = history.invoke(KvInput::Get {
+        key: "A".to_owned(),
+    });
+    history.complete(get, KvOutput::Get(None));
+    assert!(!history.is_linearizable());
+}
+
+/// Issue a random operation with `client` and record it in `history`, an operation that fails
+/// or times out is left uncompleted. Returns whether the operation completes.
+async fn random_op(client: &Client<TestCommand>, history: &History, id: String) -> bool {
+    let (key, is_put) = {
+        let mut rng = rand::thread_rng();
+        (KEYS[rng.gen_range(0..KEYS.len())].to_owned(), rng.gen())
+    };
+    let (cmd, op) = if is_put {
+        let value = id.clone();
+        (
+            TestCommand::new(
+                ProposeId::new(id),
+                TestCommandType::Put,
+                vec![key.clone()],
+                Some(value.clone()),
+            ),
+            history.invoke(KvInput::Put { key, value }),
+        )
+    } else {
+        (
+            TestCommand::new(
+                ProposeId::new(id),
+                TestCommandType::Get,
+                vec![key.clone()],
+                None,
+            ),
+            history.invoke(KvInput::Get { key }),
+        )
+    };
+    match tokio::time::timeout(Duration::from_secs(5), client.propose(cmd)).await {
+        Ok(Ok(TestCommandResult::PutResult(_))) => {
+            history.complete(op, KvOutput::Put);
+            true
+        }
+        Ok(Ok(TestCommandResult::GetResult(value))) => {
+            // the test executor returns an empty value for a missing key
+            let value = (!value.is_empty()).then_some(value);
+            history.complete(op, KvOutput::Get(value));
+            true
+        }
+        Ok(Err(_)) | Err(_) => false,
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 10)]
+async fn concurrent_workload_is_linearizable() {
+    tracing_subscriber::fmt::init();
+    let (mut exe_rx, mut after_sync_rx, client) = create_servers_client().await;
+    // drain the executor channels so that the executors never block
+    tokio::spawn(async move { while exe_rx.recv().await.is_some() {} });
+    tokio::spawn(async move { while after_sync_rx.recv().await.is_some() {} });
+    let client = Arc::new(client);
+    let history = History::new();
+
+    let handles: Vec<_> = (0..N_CLIENTS)
+        .map(|c| {
+            let client = Arc::clone(&client);
+            let history = history.clone();
+            tokio::spawn(async move {
+                for i in 0..N_OPS {
+                    let _completed = random_op(&client, &history, format!("{c}-{i}")).await;
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.await.unwrap();
+    }
+
+    assert_eq!(history.len(), N_CLIENTS * N_OPS);
+    assert!(history.is_linearizable());
+}
+
+/// Workload of `N_FAULTY_CLIENTS` clients that keep issuing operations with the shared client until
+/// the returned flag is set
+fn spawn_workload(
+    client: &SharedClient,
+    history: &History,
+) -> (Arc<AtomicBool>, Vec<tokio::task::JoinHandle<()>>) {
+    let stop = Arc::new(AtomicBool::new(false));
+    let handles = (0..N_FAULTY_CLIENTS)
+        .map(|c| {
+            let client = Arc::clone(client);
+            let history = history.clone();
+            let stop = Arc::clone(&stop);
+            tokio::spawn(async move {
+                let mut i = 0;
+                while !stop.load(Ordering::Relaxed) {
+                    let current = Arc::clone(&client.read().unwrap());
+                    let completed = random_op(&current, &history, format!("{c}-{i}")).await;
+                    i += 1;
+                    tokio::time::sleep(if completed {
+                        OP_INTERVAL
+                    } else {
+                        FAILURE_BACKOFF
+                    })
+                    .await;
+                }
+            })
+        })
+        .collect();
+    (stop, handles)
+}
+
+/// Stop the workload and wait for it
+async fn stop_workload(stop: &AtomicBool, handles: Vec<tokio::task::JoinHandle<()>>) {
+    stop.store(true, Ordering::Relaxed);
+    for handle in handles {
+        handle.await.unwrap();
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 10)]
+async fn workload_with_partitioned_leader_is_linearizable() {
+    let cluster = FaultyCluster::spawn(3).await;
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    let client: SharedClient = Arc::new(RwLock::new(Arc::new(cluster.client(0).await)));
+    let history = History::new();
+    let (stop, handles) = spawn_workload(&client, &history);
+
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    cluster.network.isolate(0);
+    let leader = cluster.wait_for_new_leader(0).await;
+    *client.write().unwrap() = Arc::new(cluster.client(leader).await);
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    // the old leader rejoins as a follower
+    cluster.network.heal(0);
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    stop_workload(&stop, handles).await;
+
+    assert!(!history.is_empty());
+    assert!(history.is_linearizable());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 10)]
+async fn workload_with_restarted_leader_is_linearizable() {
+    let mut cluster = FaultyCluster::spawn(3).await;
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    let client: SharedClient = Arc::new(RwLock::new(Arc::new(cluster.client(0).await)));
+    let history = History::new();
+    let (stop, handles) = spawn_workload(&client, &history);
+
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    cluster.kill(0);
+    let leader = cluster.wait_for_new_leader(0).await;
+    *client.write().unwrap() = Arc::new(cluster.client(leader).await);
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    // the log is not persisted, the restarted server catches up from the new leader
+    cluster.restart(0).await;
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while cluster.server(0).status().leader != Some(leader) {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .unwrap();
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    stop_workload(&stop, handles).await;
+
+    assert!(!history.is_empty());
+    assert!(history.is_linearizable());
+}
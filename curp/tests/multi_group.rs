@@ -0,0 +1,117 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use curp::{
+    client::Client,
+    cmd::ProposeId,
+    codec::WireFormat,
+    group::{GroupTransport, MultiGroupClient, MultiGroupRpc, RangeRouter},
+    quorum::QuorumConfig,
+    server::Rpc,
+    ELECTION_TIMEOUT,
+};
+use tokio::sync::mpsc;
+
+use crate::common::{
+    bind_listeners, member_addrs, other_addrs, put, TestCommand, TestCommandResult,
+    TestCommandType, TestExecutor,
+};
+
+mod common;
+
+/// Number of groups hosted by every server
+const N_GROUPS: u64 = 2;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 10)]
+async fn multi_group() {
+    tracing_subscriber::fmt::init();
+    let listeners = bind_listeners(3).await;
+    let addrs: Vec<_> = listeners
+        .iter()
+        .map(|listener| listener.local_addr().unwrap())
+        .collect();
+
+    let (exe_tx, _exe_rx) = mpsc::channel(100);
+    let (after_sync_tx, _after_sync_rx) = mpsc::channel(100);
+
+    // every server hosts all the groups on one address, group `g` is led by server `g`, the groups
+    // on a server share the connections and heartbeats to the other servers
+    let mut hosts = vec![];
+    for (i, listener) in listeners.into_iter().enumerate() {
+        let transport = GroupTransport::new();
+        let groups = (0..N_GROUPS)
+            .map(|group| {
+                let exe = TestExecutor::new(exe_tx.clone(), after_sync_tx.clone());
+                let server = Rpc::<TestCommand>::new_in_group(
+                    group,
+                    Some(Arc::clone(&transport)),
+                    i as u64,
+                    i as u64 == group,
+                    0,
+                    other_addrs(&addrs, i as u64),
+                    HashMap::new(),
+                    QuorumConfig::default(),
+                    WireFormat::default(),
+                    None,
+                    exe,
+                );
+                (group, server)
+            })
+            .collect();
+        let host = MultiGroupRpc::new(groups);
+        hosts.push(host.clone());
+        tokio::spawn(host.run_from_listener(listener));
+    }
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    let router = RangeRouter::new()
+        .with_range("".to_owned(), 0)
+        .with_range("M".to_owned(), 1);
+    let mut client = MultiGroupClient::new(router);
+    for group in 0..N_GROUPS {
+        client = client.with_group(
+            group,
+            Client::<TestCommand>::new_in_group(
+                group,
+                group,
+                member_addrs(&addrs),
+                QuorumConfig::default(),
+            )
+            .await,
+        );
+    }
+
+    for key in ["A", "Z"] {
+        let er = client.propose(put(key, key)).await.unwrap();
+        assert_eq!(er, TestCommandResult::PutResult(key.to_owned()));
+    }
+    // a command is not split across groups
+    assert!(client
+        .propose(TestCommand::new(
+            ProposeId::new("AZ".to_owned()),
+            TestCommandType::Get,
+            vec!["A".to_owned(), "Z".to_owned()],
+            None,
+        ))
+        .await
+        .is_err());
+
+    // every group has its own leader and log
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let mut terms = vec![];
+    for group in 0..N_GROUPS {
+        let status = hosts[0].group(group).unwrap().status();
+        assert_eq!(status.leader, Some(group));
+        assert_eq!(status.last_log_index, 1);
+        terms.push(status.term);
+    }
+
+    // the batched heartbeats keep the leaders of all groups, no follower starts an election
+    tokio::time::sleep(ELECTION_TIMEOUT * 2).await;
+    for host in &hosts {
+        for group in 0..N_GROUPS {
+            let status = host.group(group).unwrap().status();
+            assert_eq!(status.leader, Some(group));
+            assert_eq!(status.term, terms[group as usize]);
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use std::{collections::HashMap, time::Duration};
+
+use curp::{client::Client, codec::WireFormat, quorum::QuorumConfig, server::Rpc};
+use tokio::sync::mpsc;
+
+use crate::common::{
+    bind_listeners, member_addrs, other_addrs, put, serve, TestCommand, TestCommandResult,
+    TestExecutor,
+};
+
+mod common;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 10)]
+async fn update_peer_addr() {
+    tracing_subscriber::fmt::init();
+    // the last listener is dropped, server 2 listens on the third one, but other servers
+    // think it's on the address of the dropped one
+    let mut listeners = bind_listeners(4).await;
+    let stale_listener = listeners.pop().unwrap();
+    let stale_addr = stale_listener.local_addr().unwrap();
+    drop(stale_listener);
+    let listen_addrs: Vec<_> = listeners
+        .iter()
+        .map(|listener| listener.local_addr().unwrap())
+        .collect();
+    let mut stale_addrs = listen_addrs.clone();
+    stale_addrs[2] = stale_addr;
+
+    let (exe_tx, _exe_rx) = mpsc::channel(100);
+    let (after_sync_tx, _after_sync_rx) = mpsc::channel(100);
+
+    let mut servers = vec![];
+    for (i, listener) in listeners.into_iter().enumerate() {
+        let id = i as u64;
+        let exe = TestExecutor::new(exe_tx.clone(), after_sync_tx.clone());
+        let server = Rpc::<TestCommand>::new(
+            id,
+            i == 0,
+            0,
+            other_addrs(&stale_addrs, id),
+            HashMap::new(),
+            QuorumConfig::default(),
+            WireFormat::default(),
+            None,
+            exe,
+        );
+        servers.push(server.clone());
+        serve(server, listener);
+    }
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    let client =
+        Client::<TestCommand>::new(0, member_addrs(&listen_addrs), QuorumConfig::default()).await;
+    let (er, index) = client.propose_indexed(put("id1", "A")).await.unwrap();
+    assert_eq!(er, TestCommandResult::PutResult("A".to_owned()));
+    assert_eq!(servers[2].progress().borrow().commit_index, 0);
+
+    // the leader reaches server 2 once its address is updated, and keeps replicating to it
+    let new_addr = listen_addrs[2].to_string();
+    assert!(!servers[0].update_peer_addr(0, &new_addr).await);
+    assert!(servers[0].update_peer_addr(2, &new_addr).await);
+    assert_eq!(servers[0].peer_addrs()[&2], format!("http://{new_addr}"));
+
+    let mut progress = servers[2].progress();
+    tokio::time::timeout(Duration::from_secs(3), async {
+        while progress.borrow_and_update().last_applied < index {
+            progress.changed().await.unwrap();
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(progress.borrow().leader, Some(0));
+}
@@ -0,0 +1,33 @@
+use std::{collections::HashMap, time::Duration};
+
+use curp::server::Placement;
+
+use crate::common::spawn_cluster_with_placements;
+
+mod common;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 10)]
+async fn leadership_is_transferred_to_preferred_server() {
+    tracing_subscriber::fmt::init();
+    // server 2 is preferred, but server 0 is the initial leader
+    let placements: HashMap<u64, Placement> = HashMap::from([
+        (0, Placement::new("dc1".to_owned(), 1)),
+        (1, Placement::new("dc1".to_owned(), 1)),
+        (2, Placement::new("dc2".to_owned(), 2)),
+    ]);
+
+    let cluster = spawn_cluster_with_placements(3, placements.clone()).await;
+    let servers = &cluster.servers;
+    assert_eq!(servers[1].placements(), placements);
+
+    let mut progress = servers[0].progress();
+    assert_eq!(progress.borrow().leader, Some(0));
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while progress.borrow_and_update().leader != Some(2) {
+            progress.changed().await.unwrap();
+        }
+    })
+    .await
+    .unwrap();
+    assert!(progress.borrow().term > 0);
+}
@@ -0,0 +1,65 @@
+use std::time::Duration;
+
+use curp::{
+    client::{Client, ProposeMode, ProposeOptions},
+    error::ProposeError,
+    quorum::QuorumConfig,
+};
+
+use crate::common::{
+    bind_listeners, create_servers_client, member_addrs, put, TestCommand, TestCommandResult,
+};
+
+mod common;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 10)]
+async fn propose_with_options() {
+    let (_exe_rx, _after_sync_rx, client) = create_servers_client().await;
+
+    // the speculative result is returned first, and the commit can be waited later
+    let proposal = client
+        .propose_with(put("id1", "A"), ProposeOptions::default())
+        .await
+        .unwrap();
+    assert_eq!(proposal.er(), &TestCommandResult::PutResult("A".to_owned()));
+    assert_eq!(proposal.committed().await.unwrap(), 1); // log[0] is a fake one
+
+    let proposal = client
+        .propose_with(put("id2", "B"), ProposeOptions::new(ProposeMode::Synced))
+        .await
+        .unwrap();
+    assert_eq!(proposal.er(), &TestCommandResult::PutResult("B".to_owned()));
+    assert_eq!(proposal.committed().await.unwrap(), 2);
+
+    let proposal = client
+        .propose_with(put("id3", "C"), ProposeOptions::new(ProposeMode::FastOnly))
+        .await
+        .unwrap();
+    let (er, commit) = proposal.into_parts();
+    assert_eq!(er, TestCommandResult::PutResult("C".to_owned()));
+    assert_eq!(commit.await.unwrap(), 3);
+
+    let result = client
+        .propose_with(
+            put("id4", "D"),
+            ProposeOptions::new(ProposeMode::Synced).with_timeout(Duration::from_nanos(1)),
+        )
+        .await;
+    assert!(matches!(result, Err(ProposeError::Timeout)));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 10)]
+async fn fast_only_reports_errors_other_than_conflicts() {
+    // nobody listens on the addresses
+    let addrs: Vec<_> = bind_listeners(3)
+        .await
+        .into_iter()
+        .map(|listener| listener.local_addr().unwrap())
+        .collect();
+    let client = Client::<TestCommand>::new(0, member_addrs(&addrs), QuorumConfig::default()).await;
+
+    let result = client
+        .propose_with(put("id1", "A"), ProposeOptions::new(ProposeMode::FastOnly))
+        .await;
+    assert!(matches!(result, Err(ProposeError::RpcError(_))));
+}
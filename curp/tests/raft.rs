@@ -1,6 +1,8 @@
 use curp::client::Client;
 use curp::cmd::ProposeId;
+use curp::quorum::QuorumConfig;
 use curp::server::Rpc;
+use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::time::Duration;
 use tokio::sync::mpsc;
@@ -27,27 +29,57 @@ pub async fn create_raft_group() -> (
 
     let exe_tx1 = exe_tx.clone();
     let after_sync_tx1 = after_sync_tx.clone();
-    let addr0 = addrs[0].clone();
-    let addr1 = vec![addrs[1].clone(), addrs[2].clone()];
+    let addr1 = HashMap::from([(1, addrs[1].clone()), (2, addrs[2].clone())]);
     tokio::spawn(async move {
         let exe = TestExecutor::new(exe_tx1, after_sync_tx1);
-        Rpc::<TestCommand>::run(addr0.as_str(), false, 0, addr1, Some(8765), exe).await
+        Rpc::<TestCommand>::run(
+            0,
+            false,
+            0,
+            addr1,
+            HashMap::new(),
+            QuorumConfig::default(),
+            Some(8765),
+            None,
+            exe,
+        )
+        .await
     });
     let exe_tx2 = exe_tx.clone();
     let after_sync_tx2 = after_sync_tx.clone();
-    let addr1 = addrs[1].clone();
-    let addr2 = vec![addrs[0].clone(), addrs[2].clone()];
+    let addr2 = HashMap::from([(0, addrs[0].clone()), (2, addrs[2].clone())]);
     tokio::spawn(async move {
         let exe = TestExecutor::new(exe_tx2, after_sync_tx2);
-        Rpc::<TestCommand>::run(addr1.as_str(), false, 0, addr2, Some(8766), exe).await
+        Rpc::<TestCommand>::run(
+            1,
+            false,
+            0,
+            addr2,
+            HashMap::new(),
+            QuorumConfig::default(),
+            Some(8766),
+            None,
+            exe,
+        )
+        .await
     });
     let exe_tx3 = exe_tx.clone();
     let after_sync_tx3 = after_sync_tx.clone();
-    let addr2 = addrs[2].clone();
-    let addr3 = vec![addrs[0].clone(), addrs[1].clone()];
+    let addr3 = HashMap::from([(0, addrs[0].clone()), (1, addrs[1].clone())]);
     tokio::spawn(async move {
         let exe = TestExecutor::new(exe_tx3, after_sync_tx3);
-        let _ = Rpc::<TestCommand>::run(addr2.as_str(), false, 0, addr3, Some(8767), exe).await;
+        let _ = Rpc::<TestCommand>::run(
+            2,
+            false,
+            0,
+            addr3,
+            HashMap::new(),
+            QuorumConfig::default(),
+            Some(8767),
+            None,
+            exe,
+        )
+        .await;
     });
 
     tokio::time::sleep(Duration::from_secs(3)).await;
@@ -56,9 +88,11 @@ pub async fn create_raft_group() -> (
         0,
         addrs
             .into_iter()
-            .map(|a| a.parse())
-            .collect::<Result<Vec<SocketAddr>, _>>()
+            .enumerate()
+            .map(|(id, a)| a.parse().map(|addr| (id as u64, addr)))
+            .collect::<Result<HashMap<u64, SocketAddr>, _>>()
             .unwrap(),
+        QuorumConfig::default(),
     )
     .await;
     (exe_rx, after_sync_rx, client)
@@ -0,0 +1,22 @@
+use crate::common::{create_servers_client, put, TestCommandResult};
+
+mod common;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 10)]
+async fn rtts_are_measured_and_fast_quorum_is_reported() {
+    let (_exe_rx, _after_sync_rx, client) = create_servers_client().await;
+    assert!(client.replica_rtts().iter().all(|rtt| rtt.srtt.is_none()));
+
+    let (er, report) = client.propose_with_report(put("id1", "A")).await.unwrap();
+    assert_eq!(er, TestCommandResult::PutResult("A".to_owned()));
+
+    // the fast quorum of 3 servers consists of all of them
+    let mut fast_quorum = report.fast_quorum.unwrap();
+    fast_quorum.sort_unstable();
+    assert_eq!(fast_quorum, vec![0, 1, 2]);
+
+    let rtts = client.replica_rtts();
+    assert_eq!(rtts.len(), 3);
+    assert!(rtts.iter().all(|rtt| rtt.srtt.is_some()));
+    assert!(rtts.windows(2).all(|w| w[0].srtt <= w[1].srtt));
+}
@@ -0,0 +1,52 @@
+use std::time::Duration;
+
+use curp::cmd::ProposeId;
+
+use crate::common::{put, FaultyCluster, TestCommand, TestCommandResult, TestCommandType};
+
+mod common;
+
+/// A get of `key`
+fn get(id: &str, key: &str) -> TestCommand {
+    TestCommand::new(
+        ProposeId::new(id.to_owned()),
+        TestCommandType::Get,
+        vec![key.to_owned()],
+        None,
+    )
+}
+
+#[tokio::test]
+async fn committed_commands_survive_a_full_restart() {
+    let mut cluster = FaultyCluster::spawn_persistent(3).await;
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    let client = cluster.client(0).await;
+    assert_eq!(
+        client.propose(put("put-a", "A")).await.unwrap(),
+        TestCommandResult::PutResult("A".to_owned())
+    );
+    // the put is replicated to every server before they are killed
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    for id in 0..3 {
+        cluster.kill(id);
+    }
+    for id in 0..3 {
+        cluster.restart(id).await;
+    }
+    let leader = cluster.wait_for_leader().await;
+    let client = cluster.client(leader).await;
+    assert_eq!(
+        client.propose(put("put-b", "B")).await.unwrap(),
+        TestCommandResult::PutResult("B".to_owned())
+    );
+    // the commands committed before the restart are applied again from the recovered log
+    assert_eq!(
+        client.propose(get("get-a", "A")).await.unwrap(),
+        TestCommandResult::GetResult("A".to_owned())
+    );
+    assert_eq!(
+        client.propose(get("get-b", "B")).await.unwrap(),
+        TestCommandResult::GetResult("B".to_owned())
+    );
+}
@@ -0,0 +1,50 @@
+use std::time::Duration;
+
+use curp::server::ServerRole;
+
+use crate::common::{put, spawn_cluster};
+
+mod common;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 10)]
+async fn server_status() {
+    tracing_subscriber::fmt::init();
+    let cluster = spawn_cluster(3).await;
+    let servers = &cluster.servers;
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    let client = cluster.client().await;
+    let (_er, index) = client.propose_indexed(put("id1", "A")).await.unwrap();
+
+    // the cmd is reported applied once it's after synced
+    let mut progress = servers[0].progress();
+    tokio::time::timeout(Duration::from_secs(2), async {
+        while progress.borrow_and_update().last_applied < index {
+            progress.changed().await.unwrap();
+        }
+    })
+    .await
+    .unwrap();
+
+    let status = servers[0].status();
+    assert_eq!(status.id, 0);
+    assert_eq!(status.role, ServerRole::Leader);
+    assert_eq!(status.leader, Some(0));
+    assert!(status.commit_index >= index);
+    assert_eq!(status.last_applied, progress.borrow().last_applied);
+    assert!(status.last_log_index >= index);
+    let mut peers: Vec<_> = status.peers.iter().map(|peer| peer.id).collect();
+    peers.sort_unstable();
+    assert_eq!(peers, vec![1, 2]);
+
+    // the status of a follower can be inspected remotely
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let status = client.status(1).await.unwrap();
+    assert_eq!(status.id, 1);
+    assert_eq!(status.role, ServerRole::Follower);
+    assert_eq!(status.leader, Some(0));
+    assert!(status.last_log_index >= index);
+    assert!(status.peers.is_empty());
+
+    assert!(client.status(3).await.is_err());
+}
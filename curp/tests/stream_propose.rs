@@ -0,0 +1,32 @@
+use std::sync::Arc;
+
+use crate::common::{create_servers_client, put, TestCommandResult};
+
+mod common;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 10)]
+async fn concurrent_proposals_are_multiplexed() {
+    let (mut exe_rx, mut after_sync_rx, client) = create_servers_client().await;
+    // drain the executor channels so that the executors never block
+    tokio::spawn(async move { while exe_rx.recv().await.is_some() {} });
+    tokio::spawn(async move { while after_sync_rx.recv().await.is_some() {} });
+    let client = Arc::new(client);
+
+    // every response must be matched to its own request
+    let handles: Vec<_> = (0..50)
+        .map(|i| {
+            let client = Arc::clone(&client);
+            tokio::spawn(async move {
+                let key = format!("key{i}");
+                let (er, _index) = client
+                    .propose_indexed(put(&format!("id{i}"), &key))
+                    .await
+                    .unwrap();
+                assert_eq!(er, TestCommandResult::PutResult(key));
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.await.unwrap();
+    }
+}
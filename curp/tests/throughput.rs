@@ -0,0 +1,55 @@
+//! Multi-client throughput benchmark, run it with
+//! `cargo test -p curp --release --test throughput -- --ignored --nocapture`
+
+use std::{sync::Arc, time::Instant};
+
+use crate::common::{create_servers_client, put, TestCommandResult};
+
+mod common;
+
+/// Number of concurrent clients
+const N_CLIENTS: usize = 64;
+/// Number of proposals sent by each client
+const N_PROPOSALS: usize = 200;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 10)]
+#[ignore] // it's a benchmark, not a correctness test
+async fn multi_client_throughput() {
+    let (mut exe_rx, mut after_sync_rx, client) = create_servers_client().await;
+    // drain the executor channels so that the executors never block
+    tokio::spawn(async move { while exe_rx.recv().await.is_some() {} });
+    tokio::spawn(async move { while after_sync_rx.recv().await.is_some() {} });
+    let client = Arc::new(client);
+
+    let start = Instant::now();
+    let handles: Vec<_> = (0..N_CLIENTS)
+        .map(|c| {
+            let client = Arc::clone(&client);
+            tokio::spawn(async move {
+                let mut failed = 0;
+                for i in 0..N_PROPOSALS {
+                    // every proposal has its own key so that there is no conflict
+                    let key = format!("{c}-{i}");
+                    // a proposal may time out when the machine is overloaded, it's counted
+                    // instead of failing the whole benchmark
+                    match client.propose(put(&key, &key)).await {
+                        Ok(er) => assert_eq!(er, TestCommandResult::PutResult(key)),
+                        Err(_) => failed += 1,
+                    }
+                }
+                failed
+            })
+        })
+        .collect();
+    let mut failed = 0;
+    for handle in handles {
+        failed += handle.await.unwrap();
+    }
+    let elapsed = start.elapsed();
+
+    let total = N_CLIENTS * N_PROPOSALS;
+    println!(
+        "{total} proposals from {N_CLIENTS} clients in {elapsed:?}, {:.0} proposals/s, {failed} failed",
+        (total - failed) as f64 / elapsed.as_secs_f64()
+    );
+}
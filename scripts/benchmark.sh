@@ -4,21 +4,21 @@ OUTPUT_DIR="${WORKDIR}/out"
 SERVERS=("172.20.0.2" "172.20.0.3" "172.20.0.4" "172.20.0.5")
 CLUSTER_PEERS=(
     ""
-    "${SERVERS[2]}:2379 ${SERVERS[3]}:2379"
-    "${SERVERS[1]}:2379 ${SERVERS[3]}:2379"
-    "${SERVERS[1]}:2379 ${SERVERS[2]}:2379"
+    "2=${SERVERS[2]}:2379 3=${SERVERS[3]}:2379"
+    "1=${SERVERS[1]}:2379 3=${SERVERS[3]}:2379"
+    "1=${SERVERS[1]}:2379 2=${SERVERS[2]}:2379"
 )
-# container use_curp endpoints
+# container use_curp endpoints, the first endpoint is used as the leader
 XLINE_TESTCASE=(
-    "node1  false ${SERVERS[1]}:2379"
-    "node2  false ${SERVERS[2]}:2379"
-    "client false ${SERVERS[3]}:2379"
-    "client true  ${SERVERS[1]}:2379 ${SERVERS[2]}:2379 ${SERVERS[3]}:2379"
+    "node1  false 1=${SERVERS[1]}:2379"
+    "node2  false 2=${SERVERS[2]}:2379"
+    "client false 3=${SERVERS[3]}:2379"
+    "client true  1=${SERVERS[1]}:2379 2=${SERVERS[2]}:2379 3=${SERVERS[3]}:2379"
 )
 ETCD_TESTCASE=(
-    "node1  false ${SERVERS[1]}:2379"
-    "node2  false ${SERVERS[2]}:2379"
-    "client false ${SERVERS[3]}:2379"
+    "node1  false 1=${SERVERS[1]}:2379"
+    "node2  false 2=${SERVERS[2]}:2379"
+    "client false 3=${SERVERS[3]}:2379"
 )
 KEY_SPACE_SIZE=("1" "100000")
 CLIENTS_TOTAL=("1 50" "10 300" "50 1000" "100 3000" "200 5000")
@@ -39,7 +39,7 @@ benchmark_cmd() {
     clients=${4}
     total=${5}
     key_space_size=${6}
-    echo "docker exec ${container_name} /usr/local/bin/benchmark --endpoints ${endpoints} --leader-index=0 ${use_curp} --clients=${clients} --stdout put --key-size=8 --val-size=256 --total=${total} --key-space-size=${key_space_size}"
+    echo "docker exec ${container_name} /usr/local/bin/benchmark --endpoints ${endpoints} --leader-id=${endpoints%%=*} ${use_curp} --clients=${clients} --stdout put --key-size=8 --val-size=256 --total=${total} --key-space-size=${key_space_size}"
 }
 
 # run xline node by index
@@ -48,9 +48,10 @@ benchmark_cmd() {
 run_xline() {
     cmd="/usr/local/bin/xline \
     --name node${1} \
+    --member-id ${1} \
     --cluster-peers ${CLUSTER_PEERS[$1]} \
     --self-ip-port ${SERVERS[$1]}:2379 \
-    --leader-ip-port ${SERVERS[1]}:2379"
+    --leader-id 1"
 
     if [ ${1} -eq 1 ]; then
         cmd="${cmd} --is-leader"
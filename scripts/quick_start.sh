@@ -3,9 +3,9 @@ WORKDIR=$(pwd)
 SERVERS=("172.20.0.2" "172.20.0.3" "172.20.0.4" "172.20.0.5")
 CLUSTER_PEERS=(
     ""
-    "${SERVERS[2]}:2379 ${SERVERS[3]}:2379"
-    "${SERVERS[1]}:2379 ${SERVERS[3]}:2379"
-    "${SERVERS[1]}:2379 ${SERVERS[2]}:2379"
+    "2=${SERVERS[2]}:2379 3=${SERVERS[3]}:2379"
+    "1=${SERVERS[1]}:2379 3=${SERVERS[3]}:2379"
+    "1=${SERVERS[1]}:2379 2=${SERVERS[2]}:2379"
 )
 
 # run xline node by index
@@ -14,9 +14,10 @@ CLUSTER_PEERS=(
 run_xline() {
     cmd="/usr/local/bin/xline \
     --name node${1} \
+    --member-id ${1} \
     --cluster-peers ${CLUSTER_PEERS[$1]} \
     --self-ip-port ${SERVERS[$1]}:2379 \
-    --leader-ip-port ${SERVERS[1]}:2379"
+    --leader-id 1"
 
     if [ ${1} -eq 1 ]; then
         cmd="${cmd} --is-leader"
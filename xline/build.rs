@@ -7,7 +7,9 @@ fn main() {
                 "proto/rpc.proto",
                 "proto/auth.proto",
                 "proto/v3lock.proto",
+                "proto/v3election.proto",
                 "proto/lease.proto",
+                "proto/membership.proto",
             ],
             &["proto"],
         )
@@ -1,8 +1,11 @@
-use std::net::SocketAddr;
+use std::{collections::HashMap, net::SocketAddr};
 
 // use anyhow::{anyhow, Result};
-use curp::{client::Client as CurpClient, cmd::ProposeId};
-use etcd_client::{AuthClient, Client as EtcdClient};
+use curp::{client::Client as CurpClient, cmd::ProposeId, quorum::QuorumConfig, ServerId};
+use etcd_client::{
+    AuthClient, Client as EtcdClient, ClusterClient, ElectionClient, KvClient, LeaseClient,
+    LockClient, MaintenanceClient, WatchClient,
+};
 use uuid::Uuid;
 
 use crate::{
@@ -35,26 +38,31 @@ pub struct Client {
 }
 
 impl Client {
-    /// New `Client`
+    /// New `Client`, `all_members` are the ids and addresses of the members, the ids must be
+    /// the same as the ones the members are configured with
     ///
     /// # Errors
     ///
     /// If `EtcdClient::connect` fails.
+    ///
+    /// # Panics
+    ///
+    /// If `leader_id` is not in `all_members`
     #[inline]
     pub async fn new(
-        leader_index: usize,
-        all_members: Vec<SocketAddr>,
+        leader_id: ServerId,
+        all_members: HashMap<ServerId, SocketAddr>,
         use_curp_client: bool,
     ) -> Result<Self, ClientError> {
         let etcd_client = EtcdClient::connect(
             all_members
-                .iter()
+                .values()
                 .map(ToString::to_string)
                 .collect::<Vec<_>>(),
             None,
         )
         .await?;
-        let curp_client = CurpClient::new(leader_index, all_members).await;
+        let curp_client = CurpClient::new(leader_id, all_members, QuorumConfig::default()).await;
         Ok(Self {
             name: String::from("client"),
             curp_client,
@@ -157,4 +165,46 @@ impl Client {
     pub fn auth_client(&mut self) -> AuthClient {
         self.etcd_client.auth_client()
     }
+
+    /// Gets a kv client.
+    #[inline]
+    pub fn kv_client(&mut self) -> KvClient {
+        self.etcd_client.kv_client()
+    }
+
+    /// Gets a watch client.
+    #[inline]
+    pub fn watch_client(&mut self) -> WatchClient {
+        self.etcd_client.watch_client()
+    }
+
+    /// Gets a lease client.
+    #[inline]
+    pub fn lease_client(&mut self) -> LeaseClient {
+        self.etcd_client.lease_client()
+    }
+
+    /// Gets a cluster client.
+    #[inline]
+    pub fn cluster_client(&mut self) -> ClusterClient {
+        self.etcd_client.cluster_client()
+    }
+
+    /// Gets a maintenance client.
+    #[inline]
+    pub fn maintenance_client(&mut self) -> MaintenanceClient {
+        self.etcd_client.maintenance_client()
+    }
+
+    /// Gets an election client.
+    #[inline]
+    pub fn election_client(&mut self) -> ElectionClient {
+        self.etcd_client.election_client()
+    }
+
+    /// Gets a lock client.
+    #[inline]
+    pub fn lock_client(&mut self) -> LockClient {
+        self.etcd_client.lock_client()
+    }
 }
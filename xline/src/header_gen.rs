@@ -50,7 +50,6 @@ impl HeaderGenerator {
     }
 
     /// Set term
-    #[allow(dead_code)] // Will be used in the future
     pub(crate) fn set_term(&self, term: u64) {
         *self.term.lock() = term;
     }
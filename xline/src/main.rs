@@ -110,10 +110,11 @@
     clippy::multiple_crate_versions, // caused by the dependency, can't be fixed
 )]
 
-use std::{net::SocketAddr, path::PathBuf};
+use std::{collections::HashMap, net::SocketAddr, path::PathBuf, str::FromStr, time::Duration};
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use clap::Parser;
+use curp::{codec::WireFormat, quorum::QuorumConfig, server::Placement, ServerId};
 use jsonwebtoken::{DecodingKey, EncodingKey};
 use log::debug;
 use opentelemetry::{global, runtime::Tokio, sdk::propagation::TraceContextPropagator};
@@ -121,7 +122,7 @@ use opentelemetry_contrib::trace::exporter::jaeger_json::JaegerJsonExporter;
 use tokio::fs;
 use tracing::{error, metadata::LevelFilter};
 use tracing_subscriber::prelude::*;
-use xline::server::XlineServer;
+use xline::server::{AutoCompactionConfig, StorageConfig, XlineServer, REVISION_CHECK_INTERVAL};
 
 /// Command line arguments
 #[derive(Parser)]
@@ -130,18 +131,38 @@ struct ServerArgs {
     /// Node name
     #[clap(long)]
     name: String,
-    /// Cluster peers. eg: 192.168.x.x:8080 192.168.x.x:8080
+    /// Id of the node, it must be unique in the cluster and must not change after the node
+    /// joins the cluster
+    #[clap(long)]
+    member_id: ServerId,
+    /// Ids and addresses of cluster peers. eg: 1=192.168.x.x:8080 2=192.168.x.x:8080
     #[clap(long, multiple = true, required = true)]
-    cluster_peers: Vec<SocketAddr>,
+    cluster_peers: Vec<ClusterPeer>,
     /// If node is leader
     #[clap(long)]
     is_leader: bool,
-    /// Leader's ip and port. eg: 192.168.x.x:8080
+    /// Id of the leader when the cluster starts
     #[clap(long)]
-    leader_ip_port: SocketAddr,
+    leader_id: ServerId,
     /// Current node ip and port. eg: 192.168.x.x:8080
     #[clap(long)]
     self_ip_port: SocketAddr,
+    /// Region labels and election priorities of cluster members by their ids. eg: 1=dc1:2.
+    /// The healthy member with the highest priority is preferred to be the leader, a member with
+    /// priority 0 never becomes the leader. Members not listed have priority 1.
+    #[clap(long, multiple = true)]
+    cluster_placements: Vec<MemberPlacement>,
+    /// Total weight of the members required by the fast path of curp. The classic quorums of
+    /// curp are used if the quorums are not given
+    #[clap(long, requires = "slow-quorum")]
+    fast_quorum: Option<u64>,
+    /// Total weight of the members required by the slow path of curp
+    #[clap(long, requires = "fast-quorum")]
+    slow_quorum: Option<u64>,
+    /// Weights of the regions in the quorums. eg: dc1=2. A member weighs 1 if its region is not
+    /// listed
+    #[clap(long, multiple = true, requires = "fast-quorum")]
+    region_weights: Vec<RegionWeight>,
     /// Private key uesd to sign the token
     #[clap(long)]
     auth_private_key: Option<PathBuf>,
@@ -160,6 +181,197 @@ struct ServerArgs {
     /// Trace level of jaeger
     #[clap(long)]
     jaeger_level: Option<LevelFilter>,
+    /// Format used to encode curp messages, bincode or json.
+    /// Every server can decode both, so it can be switched by a rolling restart
+    #[clap(long, default_value = "bincode")]
+    curp_wire_format: WireFormat,
+    /// Directory to persist data, data is only kept in memory if it's not given
+    #[clap(long)]
+    data_dir: Option<PathBuf>,
+    /// Auto compaction mode, periodic or revision. Auto compaction is disabled if it's not given
+    #[clap(long, requires = "auto-compaction-retention")]
+    auto_compaction_mode: Option<AutoCompactionMode>,
+    /// History kept by auto compaction. A duration such as 1h, 30m or 10s in periodic mode (a
+    /// bare number means hours), or the number of revisions in revision mode
+    #[clap(long, requires = "auto-compaction-mode")]
+    auto_compaction_retention: Option<String>,
+}
+
+/// Mode of auto compaction
+#[derive(Debug, Clone, Copy)]
+enum AutoCompactionMode {
+    /// Keep the history of a period
+    Periodic,
+    /// Keep a number of revisions
+    Revision,
+}
+
+impl FromStr for AutoCompactionMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "periodic" => Ok(Self::Periodic),
+            "revision" => Ok(Self::Revision),
+            _ => Err(format!(
+                "invalid auto compaction mode: {s}, expected periodic or revision"
+            )),
+        }
+    }
+}
+
+impl AutoCompactionMode {
+    /// Build the auto compaction config with the `retention` of this mode
+    fn config(self, retention: &str) -> Result<AutoCompactionConfig, String> {
+        match self {
+            Self::Periodic => {
+                let (num, unit_secs) = if let Some(num) = retention.strip_suffix('h') {
+                    (num, 3600)
+                } else if let Some(num) = retention.strip_suffix('m') {
+                    (num, 60)
+                } else if let Some(num) = retention.strip_suffix('s') {
+                    (num, 1)
+                } else {
+                    (retention, 3600)
+                };
+                let secs = num
+                    .parse::<u64>()
+                    .ok()
+                    .and_then(|n| n.checked_mul(unit_secs))
+                    .filter(|&secs| secs > 0)
+                    .ok_or_else(|| {
+                        format!(
+                            "invalid periodic retention: {retention}, expected eg. 1h, 30m or 10s"
+                        )
+                    })?;
+                Ok(AutoCompactionConfig::Periodic(Duration::from_secs(secs)))
+            }
+            Self::Revision => {
+                let revisions = retention
+                    .parse::<i64>()
+                    .ok()
+                    .filter(|&revisions| revisions > 0)
+                    .ok_or_else(|| {
+                        format!(
+                            "invalid revision retention: {retention}, expected a positive integer"
+                        )
+                    })?;
+                Ok(AutoCompactionConfig::Revision(
+                    revisions,
+                    REVISION_CHECK_INTERVAL,
+                ))
+            }
+        }
+    }
+}
+
+/// Peer of the cluster, in the format of `<id>=<ip:port>`
+#[derive(Debug, Clone, Copy)]
+struct ClusterPeer {
+    /// Id of the peer
+    id: ServerId,
+    /// Address of the peer
+    addr: SocketAddr,
+}
+
+impl FromStr for ClusterPeer {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (id, addr) = s
+            .split_once('=')
+            .ok_or_else(|| format!("invalid peer: {s}, expected <id>=<ip:port>"))?;
+        Ok(Self {
+            id: id
+                .parse()
+                .map_err(|e| format!("invalid id in peer {s}: {e}"))?,
+            addr: addr
+                .parse()
+                .map_err(|e| format!("invalid address in peer {s}: {e}"))?,
+        })
+    }
+}
+
+/// Placement of a cluster member, in the format of `<id>=<region>:<priority>`
+#[derive(Debug, Clone)]
+struct MemberPlacement {
+    /// Id of the member
+    id: ServerId,
+    /// Region label and election priority of the member
+    placement: Placement,
+}
+
+impl FromStr for MemberPlacement {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (id, placement) = s
+            .split_once('=')
+            .ok_or_else(|| format!("invalid placement: {s}, expected <id>=<region>:<priority>"))?;
+        let (region, priority) = placement
+            .rsplit_once(':')
+            .ok_or_else(|| format!("invalid placement: {s}, expected <id>=<region>:<priority>"))?;
+        Ok(Self {
+            id: id
+                .parse()
+                .map_err(|e| format!("invalid id in placement {s}: {e}"))?,
+            placement: Placement::new(
+                region.to_owned(),
+                priority
+                    .parse()
+                    .map_err(|e| format!("invalid priority in placement {s}: {e}"))?,
+            ),
+        })
+    }
+}
+
+/// Weight of a region in the quorums, in the format of `<region>=<weight>`
+#[derive(Debug, Clone)]
+struct RegionWeight {
+    /// Region label
+    region: String,
+    /// Weight of every member in the region
+    weight: u64,
+}
+
+impl FromStr for RegionWeight {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (region, weight) = s
+            .split_once('=')
+            .ok_or_else(|| format!("invalid region weight: {s}, expected <region>=<weight>"))?;
+        Ok(Self {
+            region: region.to_owned(),
+            weight: weight
+                .parse()
+                .map_err(|e| format!("invalid weight in region weight {s}: {e}"))?,
+        })
+    }
+}
+
+/// Build the quorum config of curp shared by the server and the client, the classic quorums
+/// are used if `quorums` is `None`
+fn quorum_config(
+    members: impl IntoIterator<Item = ServerId>,
+    placements: &HashMap<ServerId, Placement>,
+    region_weights: Vec<RegionWeight>,
+    quorums: Option<(u64, u64)>,
+) -> Result<QuorumConfig> {
+    let Some((fast, slow)) = quorums else {
+        return Ok(QuorumConfig::default());
+    };
+    // members not placed are in the default region
+    let placements = members
+        .into_iter()
+        .map(|id| (id, placements.get(&id).cloned().unwrap_or_default()))
+        .collect();
+    let region_weights = region_weights
+        .into_iter()
+        .map(|rw| (rw.region, rw.weight))
+        .collect();
+    QuorumConfig::weighted_by_region(&placements, &region_weights, fast, slow)
+        .map_err(|e| anyhow!("invalid quorums: {e}"))
 }
 
 /// init tracing subscriber
@@ -251,16 +463,60 @@ async fn main() -> Result<()> {
         server_args.jaeger_level,
     )?;
     debug!("name = {:?}", server_args.name);
+    debug!("member_id = {:?}", server_args.member_id);
+    debug!("leader_id = {:?}", server_args.leader_id);
     debug!("server_addr = {:?}", server_args.self_ip_port);
     debug!("cluster_peers = {:?}", server_args.cluster_peers);
+    debug!("cluster_placements = {:?}", server_args.cluster_placements);
+    debug!(
+        "quorums = {:?} {:?} {:?}",
+        server_args.fast_quorum, server_args.slow_quorum, server_args.region_weights
+    );
+    debug!("curp_wire_format = {:?}", server_args.curp_wire_format);
+    debug!("data_dir = {:?}", server_args.data_dir);
+    debug!(
+        "auto_compaction = {:?} {:?}",
+        server_args.auto_compaction_mode, server_args.auto_compaction_retention
+    );
+    let auto_compaction = match (
+        server_args.auto_compaction_mode,
+        server_args.auto_compaction_retention,
+    ) {
+        (Some(mode), Some(retention)) => Some(mode.config(&retention).map_err(|e| anyhow!(e))?),
+        (Some(_) | None, _) => None,
+    };
+    let peers: HashMap<_, _> = server_args
+        .cluster_peers
+        .into_iter()
+        .map(|peer| (peer.id, peer.addr))
+        .collect();
+    let placements: HashMap<_, _> = server_args
+        .cluster_placements
+        .into_iter()
+        .map(|member| (member.id, member.placement))
+        .collect();
+    let quorum = quorum_config(
+        peers.keys().copied().chain([server_args.member_id]),
+        &placements,
+        server_args.region_weights,
+        server_args.fast_quorum.zip(server_args.slow_quorum),
+    )?;
     let key_pair = read_key_pair(server_args.auth_private_key, server_args.auth_public_key).await;
     let server = XlineServer::new(
         server_args.name,
-        server_args.cluster_peers,
+        server_args.member_id,
+        peers,
         server_args.is_leader,
-        server_args.leader_ip_port,
+        server_args.leader_id,
         server_args.self_ip_port,
+        placements,
+        quorum,
         key_pair,
+        server_args
+            .data_dir
+            .map_or(StorageConfig::Memory, StorageConfig::Disk),
+        auto_compaction,
+        server_args.curp_wire_format,
     )
     .await;
     debug!("{:?}", server);
@@ -268,3 +524,95 @@ async fn main() -> Result<()> {
     global::shutdown_tracer_provider();
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn periodic_retention_should_be_parsed() {
+        let periodic = |retention| AutoCompactionMode::Periodic.config(retention);
+        assert_eq!(
+            periodic("1h"),
+            Ok(AutoCompactionConfig::Periodic(Duration::from_secs(3600)))
+        );
+        assert_eq!(
+            periodic("30m"),
+            Ok(AutoCompactionConfig::Periodic(Duration::from_secs(1800)))
+        );
+        assert_eq!(
+            periodic("10s"),
+            Ok(AutoCompactionConfig::Periodic(Duration::from_secs(10)))
+        );
+        assert_eq!(
+            periodic("2"),
+            Ok(AutoCompactionConfig::Periodic(Duration::from_secs(7200)))
+        );
+        for invalid in ["", "0", "0s", "-1h", "1d", "h", "1.5h"] {
+            assert!(periodic(invalid).is_err(), "{invalid} should be invalid");
+        }
+    }
+
+    #[test]
+    fn revision_retention_should_be_parsed() {
+        let revision = |retention| AutoCompactionMode::Revision.config(retention);
+        assert_eq!(
+            revision("1000"),
+            Ok(AutoCompactionConfig::Revision(
+                1000,
+                REVISION_CHECK_INTERVAL
+            ))
+        );
+        for invalid in ["", "0", "-1", "1h", "1.5"] {
+            assert!(revision(invalid).is_err(), "{invalid} should be invalid");
+        }
+    }
+
+    #[test]
+    fn auto_compaction_mode_should_be_parsed() {
+        assert!(matches!(
+            "periodic".parse(),
+            Ok(AutoCompactionMode::Periodic)
+        ));
+        assert!(matches!(
+            "revision".parse(),
+            Ok(AutoCompactionMode::Revision)
+        ));
+        assert!("Periodic".parse::<AutoCompactionMode>().is_err());
+    }
+
+    #[test]
+    fn member_placement_should_be_parsed() {
+        let member: MemberPlacement = "2=dc1:3".parse().unwrap();
+        assert_eq!(member.id, 2);
+        assert_eq!(member.placement.region, "dc1");
+        assert_eq!(member.placement.priority, 3);
+        for invalid in ["", "2", "2=dc1", "127.0.0.1:2379=dc1:3", "2=dc1:-1"] {
+            assert!(
+                invalid.parse::<MemberPlacement>().is_err(),
+                "{invalid} should be invalid"
+            );
+        }
+    }
+
+    #[test]
+    fn quorum_config_should_be_built_from_regions() {
+        assert_eq!(
+            quorum_config([1, 2, 3], &HashMap::new(), vec![], None).unwrap(),
+            QuorumConfig::default()
+        );
+
+        let placements = HashMap::from([(1, Placement::new("dc1".to_owned(), 1))]);
+        let weights = vec!["dc1=3".parse().unwrap()];
+        let config = quorum_config([1, 2, 3], &placements, weights, Some((5, 3))).unwrap();
+        assert_eq!(config.weight(1), 3);
+        assert_eq!(config.weight(2), 1);
+        assert_eq!(config.fast_quorum(), 5);
+        assert_eq!(config.slow_quorum(), 3);
+
+        // two slow quorums of weight 2 may not intersect
+        assert!(quorum_config([1, 2, 3], &HashMap::new(), vec![], Some((3, 1))).is_err());
+        assert!("dc1".parse::<RegionWeight>().is_err());
+        assert!("dc1=-1".parse::<RegionWeight>().is_err());
+    }
+}
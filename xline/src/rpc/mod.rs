@@ -55,6 +55,20 @@ mod v3lockpb {
     tonic::include_proto!("v3lockpb");
 }
 
+#[allow(
+    clippy::all,
+    clippy::restriction,
+    clippy::pedantic,
+    clippy::nursery,
+    clippy::cargo,
+    unused_qualifications,
+    unreachable_pub,
+    variant_size_differences
+)]
+mod v3electionpb {
+    tonic::include_proto!("v3electionpb");
+}
+
 #[allow(
     clippy::all,
     clippy::restriction,
@@ -69,20 +83,37 @@ mod leasepb {
     tonic::include_proto!("leasepb");
 }
 
+#[allow(
+    clippy::all,
+    clippy::restriction,
+    clippy::pedantic,
+    clippy::nursery,
+    clippy::cargo,
+    unused_qualifications,
+    unreachable_pub,
+    variant_size_differences
+)]
+mod membershippb {
+    tonic::include_proto!("membershippb");
+}
+
 use serde::{Deserialize, Serialize};
 
 pub(crate) use self::authpb::{permission::Type, Permission, Role, User};
 pub(crate) use self::etcdserverpb::{
     auth_server::{Auth, AuthServer},
+    cluster_server::{Cluster, ClusterServer},
     compare::{CompareResult, CompareTarget, TargetUnion},
     kv_server::{Kv, KvServer},
+    lease_client::LeaseClient,
     lease_server::{Lease, LeaseServer},
+    maintenance_server::{Maintenance, MaintenanceServer},
     request_op::Request,
     response_op::Response,
     watch_request::RequestUnion,
     watch_server::{Watch, WatchServer},
     AuthDisableRequest, AuthDisableResponse, AuthEnableRequest, AuthEnableResponse,
-    AuthRoleAddRequest, AuthRoleAddResponse, AuthRoleDeleteRequest, AuthRoleDeleteResponse,
+    AlarmRequest, AlarmResponse, AuthRoleAddRequest, AuthRoleAddResponse, AuthRoleDeleteRequest, AuthRoleDeleteResponse,
     AuthRoleGetRequest, AuthRoleGetResponse, AuthRoleGrantPermissionRequest,
     AuthRoleGrantPermissionResponse, AuthRoleListRequest, AuthRoleListResponse,
     AuthRoleRevokePermissionRequest, AuthRoleRevokePermissionResponse, AuthStatusRequest,
@@ -91,14 +122,30 @@ pub(crate) use self::etcdserverpb::{
     AuthUserGetRequest, AuthUserGetResponse, AuthUserGrantRoleRequest, AuthUserGrantRoleResponse,
     AuthUserListRequest, AuthUserListResponse, AuthUserRevokeRoleRequest,
     AuthUserRevokeRoleResponse, AuthenticateRequest, AuthenticateResponse, CompactionRequest,
-    CompactionResponse, Compare, DeleteRangeRequest, DeleteRangeResponse, LeaseGrantRequest,
-    LeaseGrantResponse, LeaseKeepAliveRequest, LeaseKeepAliveResponse, LeaseLeasesRequest,
-    LeaseLeasesResponse, LeaseRevokeRequest, LeaseRevokeResponse, LeaseTimeToLiveRequest,
-    LeaseTimeToLiveResponse, PutRequest, PutResponse, RangeRequest, RangeResponse, RequestOp,
-    ResponseHeader, ResponseOp, TxnRequest, TxnResponse, WatchCancelRequest, WatchCreateRequest,
-    WatchRequest, WatchResponse,
+    CompactionResponse, Compare, DefragmentRequest, DefragmentResponse, DeleteRangeRequest,
+    DeleteRangeResponse, DowngradeRequest, DowngradeResponse, HashKvRequest, HashKvResponse,
+    HashRequest, HashResponse, LeaseCheckpoint,
+    LeaseCheckpointRequest, LeaseCheckpointResponse, LeaseGrantRequest, LeaseGrantResponse,
+    LeaseKeepAliveRequest, LeaseKeepAliveResponse, LeaseLeasesRequest, LeaseLeasesResponse,
+    LeaseRevokeRequest, LeaseRevokeResponse, LeaseStatus, LeaseTimeToLiveRequest,
+    LeaseTimeToLiveResponse, Member, MemberAddRequest, MemberAddResponse, MemberListRequest,
+    MemberListResponse, MemberPromoteRequest, MemberPromoteResponse, MemberRemoveRequest,
+    MemberRemoveResponse, MemberUpdateRequest, MemberUpdateResponse, MoveLeaderRequest,
+    MoveLeaderResponse, PutRequest, PutResponse, RangeRequest, RangeResponse, RequestOp,
+    ResponseHeader, ResponseOp, SnapshotRequest, SnapshotResponse, StatusRequest, StatusResponse,
+    TxnRequest, TxnResponse,
+    WatchCancelRequest, WatchCreateRequest, WatchRequest, WatchResponse,
+};
+pub(crate) use self::leasepb::Lease as PbLease;
+pub(crate) use self::membershippb::{
+    Attributes, ClusterMemberAttrSetRequest, ClusterMemberAttrSetResponse,
 };
 pub(crate) use self::mvccpb::{event::EventType, Event, KeyValue};
+pub(crate) use self::v3electionpb::{
+    election_server::{Election, ElectionServer},
+    CampaignRequest, CampaignResponse, LeaderKey, LeaderRequest, LeaderResponse, ProclaimRequest,
+    ProclaimResponse, ResignRequest, ResignResponse,
+};
 pub(crate) use self::v3lockpb::{
     lock_server::{Lock, LockServer},
     LockRequest, LockResponse, UnlockRequest, UnlockResponse,
@@ -136,6 +183,12 @@ pub(crate) enum RequestWrapper {
     TxnRequest(TxnRequest),
     /// `CompactionRequest`
     CompactionRequest(CompactionRequest),
+    /// `LeaseGrantRequest`
+    LeaseGrantRequest(LeaseGrantRequest),
+    /// `LeaseRevokeRequest`
+    LeaseRevokeRequest(LeaseRevokeRequest),
+    /// `LeaseCheckpointRequest`
+    LeaseCheckpointRequest(LeaseCheckpointRequest),
     /// `AuthEnableRequest`
     AuthEnableRequest(AuthEnableRequest),
     /// `AuthDisableRequest`
@@ -170,6 +223,16 @@ pub(crate) enum RequestWrapper {
     AuthUserRevokeRoleRequest(AuthUserRevokeRoleRequest),
     /// `AuthenticateRequest`
     AuthenticateRequest(AuthenticateRequest),
+    /// `ClusterMemberAttrSetRequest`
+    ClusterMemberAttrSetRequest(ClusterMemberAttrSetRequest),
+    /// `MemberAddRequest`
+    MemberAddRequest(MemberAddRequest),
+    /// `MemberRemoveRequest`
+    MemberRemoveRequest(MemberRemoveRequest),
+    /// `MemberUpdateRequest`
+    MemberUpdateRequest(MemberUpdateRequest),
+    /// `MemberPromoteRequest`
+    MemberPromoteRequest(MemberPromoteRequest),
 }
 
 /// Wrapper for responses
@@ -186,6 +249,12 @@ pub(crate) enum ResponseWrapper {
     TxnResponse(TxnResponse),
     /// `CompactionResponse`
     CompactionResponse(CompactionResponse),
+    /// `LeaseGrantResponse`
+    LeaseGrantResponse(LeaseGrantResponse),
+    /// `LeaseRevokeResponse`
+    LeaseRevokeResponse(LeaseRevokeResponse),
+    /// `LeaseCheckpointResponse`
+    LeaseCheckpointResponse(LeaseCheckpointResponse),
     /// `AuthEnableResponse`
     AuthEnableResponse(AuthEnableResponse),
     /// `AuthDisableResponse`
@@ -220,6 +289,16 @@ pub(crate) enum ResponseWrapper {
     AuthUserRevokeRoleResponse(AuthUserRevokeRoleResponse),
     /// `AuthenticateResponse`
     AuthenticateResponse(AuthenticateResponse),
+    /// `ClusterMemberAttrSetResponse`
+    ClusterMemberAttrSetResponse(ClusterMemberAttrSetResponse),
+    /// `MemberAddResponse`
+    MemberAddResponse(MemberAddResponse),
+    /// `MemberRemoveResponse`
+    MemberRemoveResponse(MemberRemoveResponse),
+    /// `MemberUpdateResponse`
+    MemberUpdateResponse(MemberUpdateResponse),
+    /// `MemberPromoteResponse`
+    MemberPromoteResponse(MemberPromoteResponse),
 }
 
 impl ResponseWrapper {
@@ -231,6 +310,9 @@ impl ResponseWrapper {
             ResponseWrapper::DeleteRangeResponse(ref mut resp) => &mut resp.header,
             ResponseWrapper::TxnResponse(ref mut resp) => &mut resp.header,
             ResponseWrapper::CompactionResponse(ref mut resp) => &mut resp.header,
+            ResponseWrapper::LeaseGrantResponse(ref mut resp) => &mut resp.header,
+            ResponseWrapper::LeaseRevokeResponse(ref mut resp) => &mut resp.header,
+            ResponseWrapper::LeaseCheckpointResponse(ref mut resp) => &mut resp.header,
             ResponseWrapper::AuthEnableResponse(ref mut resp) => &mut resp.header,
             ResponseWrapper::AuthDisableResponse(ref mut resp) => &mut resp.header,
             ResponseWrapper::AuthStatusResponse(ref mut resp) => &mut resp.header,
@@ -248,6 +330,11 @@ impl ResponseWrapper {
             ResponseWrapper::AuthUserListResponse(ref mut resp) => &mut resp.header,
             ResponseWrapper::AuthUserRevokeRoleResponse(ref mut resp) => &mut resp.header,
             ResponseWrapper::AuthenticateResponse(ref mut resp) => &mut resp.header,
+            ResponseWrapper::ClusterMemberAttrSetResponse(ref mut resp) => &mut resp.header,
+            ResponseWrapper::MemberAddResponse(ref mut resp) => &mut resp.header,
+            ResponseWrapper::MemberRemoveResponse(ref mut resp) => &mut resp.header,
+            ResponseWrapper::MemberUpdateResponse(ref mut resp) => &mut resp.header,
+            ResponseWrapper::MemberPromoteResponse(ref mut resp) => &mut resp.header,
         };
         if let Some(ref mut header) = *header {
             header.revision = revision;
@@ -272,7 +359,15 @@ impl RequestWrapper {
             | RequestWrapper::RangeRequest(_)
             | RequestWrapper::DeleteRangeRequest(_)
             | RequestWrapper::TxnRequest(_)
-            | RequestWrapper::CompactionRequest(_) => RequestBackend::Kv,
+            | RequestWrapper::CompactionRequest(_)
+            | RequestWrapper::LeaseGrantRequest(_)
+            | RequestWrapper::LeaseRevokeRequest(_)
+            | RequestWrapper::LeaseCheckpointRequest(_)
+            | RequestWrapper::ClusterMemberAttrSetRequest(_)
+            | RequestWrapper::MemberAddRequest(_)
+            | RequestWrapper::MemberRemoveRequest(_)
+            | RequestWrapper::MemberUpdateRequest(_)
+            | RequestWrapper::MemberPromoteRequest(_) => RequestBackend::Kv,
             RequestWrapper::AuthEnableRequest(_)
             | RequestWrapper::AuthDisableRequest(_)
             | RequestWrapper::AuthStatusRequest(_)
@@ -293,6 +388,11 @@ impl RequestWrapper {
         }
     }
 
+    /// Check if this request only reads data
+    pub(crate) fn is_read_only(&self) -> bool {
+        matches!(*self, RequestWrapper::RangeRequest(_)) || self.is_auth_read_request()
+    }
+
     /// Check if this request is a auth read request
     pub(crate) fn is_auth_read_request(&self) -> bool {
         matches!(
@@ -314,6 +414,9 @@ impl RequestWrapper {
                 | RequestWrapper::DeleteRangeRequest(_)
                 | RequestWrapper::TxnRequest(_)
                 | RequestWrapper::CompactionRequest(_)
+                | RequestWrapper::LeaseGrantRequest(_)
+                | RequestWrapper::LeaseRevokeRequest(_)
+                | RequestWrapper::LeaseCheckpointRequest(_)
         )
     }
 }
@@ -368,6 +471,9 @@ impl_from_requests!(
     DeleteRangeRequest,
     TxnRequest,
     CompactionRequest,
+    LeaseGrantRequest,
+    LeaseRevokeRequest,
+    LeaseCheckpointRequest,
     AuthEnableRequest,
     AuthDisableRequest,
     AuthStatusRequest,
@@ -384,7 +490,12 @@ impl_from_requests!(
     AuthUserGrantRoleRequest,
     AuthUserListRequest,
     AuthUserRevokeRoleRequest,
-    AuthenticateRequest
+    AuthenticateRequest,
+    ClusterMemberAttrSetRequest,
+    MemberAddRequest,
+    MemberRemoveRequest,
+    MemberUpdateRequest,
+    MemberPromoteRequest
 );
 
 impl_from_responses!(
@@ -393,6 +504,9 @@ impl_from_responses!(
     DeleteRangeResponse,
     TxnResponse,
     CompactionResponse,
+    LeaseGrantResponse,
+    LeaseRevokeResponse,
+    LeaseCheckpointResponse,
     AuthEnableResponse,
     AuthDisableResponse,
     AuthStatusResponse,
@@ -409,7 +523,12 @@ impl_from_responses!(
     AuthUserGrantRoleResponse,
     AuthUserListResponse,
     AuthUserRevokeRoleResponse,
-    AuthenticateResponse
+    AuthenticateResponse,
+    ClusterMemberAttrSetResponse,
+    MemberAddResponse,
+    MemberRemoveResponse,
+    MemberUpdateResponse,
+    MemberPromoteResponse
 );
 
 impl From<RequestOp> for RequestWrapper {
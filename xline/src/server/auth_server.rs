@@ -1,6 +1,6 @@
 use std::sync::Arc;
 
-use curp::{client::Client, cmd::ProposeId, error::ProposeError};
+use curp::{client::Client, cmd::ProposeId};
 use log::debug;
 use pbkdf2::{
     password_hash::{rand_core::OsRng, PasswordHasher, SaltString},
@@ -23,7 +23,7 @@ use crate::{
         AuthUserRevokeRoleRequest, AuthUserRevokeRoleResponse, AuthenticateRequest,
         AuthenticateResponse, RequestWithToken, RequestWrapper, ResponseWrapper,
     },
-    storage::AuthStore,
+    storage::{error::propose_error_status, AuthStore},
 };
 
 use super::command::{Command, CommandResponse, SyncResponse};
@@ -84,22 +84,18 @@ impl AuthServer {
         let propose_id = self.generate_propose_id();
         let cmd = Self::command_from_request_wrapper(propose_id, wrapper);
         if use_fast_path {
-            let cmd_res = self.client.propose(cmd).await.map_err(|err| {
-                if let ProposeError::ExecutionError(e) = err {
-                    tonic::Status::invalid_argument(e)
-                } else {
-                    panic!("propose err {err:?}")
-                }
-            })?;
+            let cmd_res = self
+                .client
+                .propose(cmd)
+                .await
+                .map_err(propose_error_status)?;
             Ok((cmd_res, None))
         } else {
-            let (cmd_res, sync_res) = self.client.propose_indexed(cmd).await.map_err(|err| {
-                if let ProposeError::ExecutionError(e) = err {
-                    tonic::Status::invalid_argument(e)
-                } else {
-                    panic!("propose err {err:?}")
-                }
-            })?;
+            let (cmd_res, sync_res) = self
+                .client
+                .propose_indexed(cmd)
+                .await
+                .map_err(propose_error_status)?;
             Ok((cmd_res, Some(sync_res)))
         }
     }
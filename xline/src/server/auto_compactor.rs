@@ -0,0 +1,268 @@
+use std::{
+    collections::VecDeque,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use clippy_utilities::OverflowArithmetic;
+use curp::{client::Client, cmd::ProposeId, server::ApplyProgress, ServerId};
+use log::{debug, info, warn};
+use opentelemetry::{
+    global,
+    metrics::{Counter, Histogram},
+    Context, KeyValue,
+};
+use tokio::sync::watch;
+use uuid::Uuid;
+
+use super::command::{Command, KeyRange};
+use crate::{
+    rpc::{CompactionRequest, RequestWithToken},
+    storage::{AuthStore, KvStore},
+};
+
+/// Default interval to check the revision in revision mode, the same as etcd
+pub const REVISION_CHECK_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Number of revisions sampled in the retention of periodic mode
+const PERIODIC_SAMPLES: u32 = 10;
+
+/// Minimum interval between two checks
+const MIN_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Auto compaction of the kv store
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum AutoCompactionConfig {
+    /// Keep the history of the last period
+    Periodic(Duration),
+    /// Keep the last revisions, the revision is checked at the interval
+    Revision(i64, Duration),
+}
+
+/// Revisions sampled in periodic mode, to find the revision of the start of the retention
+#[derive(Debug)]
+struct PeriodicSamples {
+    /// Period of the history to keep
+    retention: Duration,
+    /// Sampled revisions, the oldest one is at the front
+    samples: VecDeque<(Instant, i64)>,
+}
+
+impl PeriodicSamples {
+    /// New `PeriodicSamples` of `retention`
+    fn new(retention: Duration) -> Self {
+        Self {
+            retention,
+            samples: VecDeque::new(),
+        }
+    }
+
+    /// Sample `revision` at `now`, returns the latest revision sampled at least `retention`
+    /// ago, which is the revision to compact to. The samples older than it are dropped.
+    fn sample(&mut self, now: Instant, revision: i64) -> Option<i64> {
+        self.samples.push_back((now, revision));
+        let mut target = None;
+        while let Some(&(at, sampled)) = self.samples.front() {
+            if now.saturating_duration_since(at) < self.retention {
+                break;
+            }
+            target = Some(sampled);
+            let _expired = self.samples.pop_front();
+        }
+        target
+    }
+
+    /// Drop all samples
+    fn clear(&mut self) {
+        self.samples.clear();
+    }
+}
+
+/// Metrics of auto compaction
+#[derive(Debug)]
+struct Metrics {
+    /// Number of the proposed compactions, by result
+    compactions: Counter<u64>,
+    /// Duration of the proposed compactions in seconds
+    duration: Histogram<f64>,
+}
+
+impl Metrics {
+    /// New `Metrics` from the global meter provider
+    fn new() -> Self {
+        let meter = global::meter("xline");
+        Self {
+            compactions: meter
+                .u64_counter("auto_compaction_total")
+                .with_description("Number of the compactions proposed by the auto compactor")
+                .init(),
+            duration: meter
+                .f64_histogram("auto_compaction_duration_seconds")
+                .with_description("Duration of the compactions proposed by the auto compactor")
+                .init(),
+        }
+    }
+
+    /// Record a compaction that took `duration`
+    fn record(&self, duration: Duration, success: bool) {
+        let cx = Context::current();
+        let result = [KeyValue::new(
+            "result",
+            if success { "success" } else { "failure" },
+        )];
+        self.compactions.add(&cx, 1, &result);
+        self.duration.record(&cx, duration.as_secs_f64(), &result);
+    }
+}
+
+/// Propose compactions of the kv store periodically when the server is the leader
+#[derive(Debug)]
+pub(crate) struct AutoCompactor {
+    /// Auto compaction config
+    config: AutoCompactionConfig,
+    /// Id of the server
+    id: ServerId,
+    /// Name of the server
+    name: String,
+    /// Kv storage
+    kv_storage: Arc<KvStore>,
+    /// Auth storage
+    auth_storage: Arc<AuthStore>,
+    /// Consensus client
+    client: Arc<Client<Command>>,
+    /// Progress of the curp server, used to find the leader
+    progress: watch::Receiver<ApplyProgress>,
+    /// Metrics of the compactions
+    metrics: Metrics,
+}
+
+impl AutoCompactor {
+    /// New `AutoCompactor`
+    #[allow(clippy::too_many_arguments)] // the compactor needs all the parts of the server
+    pub(crate) fn new(
+        config: AutoCompactionConfig,
+        id: ServerId,
+        name: String,
+        kv_storage: Arc<KvStore>,
+        auth_storage: Arc<AuthStore>,
+        client: Arc<Client<Command>>,
+        progress: watch::Receiver<ApplyProgress>,
+    ) -> Self {
+        Self {
+            config,
+            id,
+            name,
+            kv_storage,
+            auth_storage,
+            client,
+            progress,
+            metrics: Metrics::new(),
+        }
+    }
+
+    /// Check if the server is the leader
+    fn is_leader(&self) -> bool {
+        self.progress.borrow().leader == Some(self.id)
+    }
+
+    /// Run the compactor forever
+    pub(crate) async fn run(self) {
+        let (interval, mut samples) = match self.config {
+            AutoCompactionConfig::Periodic(retention) => (
+                retention / PERIODIC_SAMPLES,
+                Some(PeriodicSamples::new(retention)),
+            ),
+            AutoCompactionConfig::Revision(_, interval) => (interval, None),
+        };
+        info!("auto compactor started with {:?}", self.config);
+        let mut ticker = tokio::time::interval(interval.max(MIN_CHECK_INTERVAL));
+        loop {
+            let _now = ticker.tick().await;
+            if !self.is_leader() {
+                // the history kept by the new leader starts from the time it's elected
+                if let Some(samples) = samples.as_mut() {
+                    samples.clear();
+                }
+                continue;
+            }
+            let revision = self.kv_storage.revision();
+            let target = match self.config {
+                AutoCompactionConfig::Periodic(_) => samples
+                    .as_mut()
+                    .and_then(|samples| samples.sample(Instant::now(), revision)),
+                AutoCompactionConfig::Revision(retention, _) => {
+                    Some(revision.overflow_sub(retention))
+                }
+            };
+            match target {
+                Some(target) if target > self.kv_storage.compacted_revision() => {
+                    self.compact(target).await;
+                }
+                Some(_) | None => debug!("auto compactor skips at revision {revision}"),
+            }
+        }
+    }
+
+    /// Propose a compaction to `revision`
+    async fn compact(&self, revision: i64) {
+        let start = Instant::now();
+        let token = match self.auth_storage.assign_root_token() {
+            Ok(token) => token,
+            Err(e) => {
+                warn!("auto compaction to revision {revision} failed to get a token: {e}");
+                self.metrics.record(start.elapsed(), false);
+                return;
+            }
+        };
+        let request = CompactionRequest {
+            revision,
+            physical: false,
+        };
+        let wrapper = match token {
+            Some(token) => RequestWithToken::new_with_token(request.into(), token),
+            None => RequestWithToken::new(request.into()),
+        };
+        let propose_id = ProposeId::new(format!("{}-{}", self.name, Uuid::new_v4()));
+        let cmd = Command::new(vec![KeyRange::new_all_keys()], wrapper, propose_id);
+        let res = self.client.propose_indexed(cmd).await;
+        let elapsed = start.elapsed();
+        self.metrics.record(elapsed, res.is_ok());
+        match res {
+            Ok(_res) => info!("auto compaction compacted to revision {revision} in {elapsed:?}"),
+            Err(e) => warn!("auto compaction to revision {revision} failed: {e:?}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn periodic_samples_should_keep_the_retention() {
+        let retention = Duration::from_secs(10);
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut samples = PeriodicSamples::new(retention);
+        assert_eq!(samples.sample(at(0), 1), None);
+        assert_eq!(samples.sample(at(5), 3), None);
+        // the sample at 0 is exactly one retention old
+        assert_eq!(samples.sample(at(10), 6), Some(1));
+        assert_eq!(samples.sample(at(14), 8), None);
+        // both samples at 5 and 10 are out of the retention, the later one is the target
+        assert_eq!(samples.sample(at(21), 9), Some(6));
+        assert_eq!(samples.samples.len(), 2);
+    }
+
+    #[test]
+    fn periodic_samples_should_restart_after_clear() {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut samples = PeriodicSamples::new(Duration::from_secs(10));
+        assert_eq!(samples.sample(at(0), 1), None);
+        samples.clear();
+        assert_eq!(samples.sample(at(10), 5), None);
+        assert_eq!(samples.sample(at(20), 7), Some(5));
+    }
+}
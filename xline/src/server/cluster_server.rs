@@ -0,0 +1,209 @@
+use std::{collections::HashMap, net::SocketAddr, sync::Arc, time::Duration};
+
+use curp::{client::Client, cmd::ProposeId, ServerId};
+use log::{debug, info, warn};
+use uuid::Uuid;
+
+use super::{
+    auth_server::get_token,
+    command::{Command, CommandResponse, SyncResponse},
+};
+use crate::{
+    header_gen::HeaderGenerator,
+    rpc::{
+        Attributes, Cluster, ClusterMemberAttrSetRequest, Member, MemberAddRequest,
+        MemberAddResponse, MemberListRequest, MemberListResponse, MemberPromoteRequest,
+        MemberPromoteResponse, MemberRemoveRequest, MemberRemoveResponse, MemberUpdateRequest,
+        MemberUpdateResponse, RequestWithToken, RequestWrapper, ResponseWrapper,
+    },
+    storage::{error::propose_error_status, membertable::fnv_hash, AuthStore, MemberTable},
+};
+
+/// Interval to retry publishing the attributes of the member
+const PUBLISH_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Generate the cluster id from the ids of all members like etcd does, it's the same on every
+/// member and doesn't change when the address of a member changes
+pub(crate) fn cluster_id(member_ids: impl IntoIterator<Item = ServerId>) -> u64 {
+    let mut member_ids: Vec<_> = member_ids.into_iter().collect();
+    member_ids.sort_unstable();
+    fnv_hash(member_ids.into_iter().flat_map(u64::to_be_bytes))
+}
+
+/// Members the cluster starts with, their names and client URLs are unknown until they
+/// publish themselves
+pub(crate) fn initial_members(members: &HashMap<ServerId, SocketAddr>) -> Vec<Member> {
+    members
+        .iter()
+        .map(|(&id, addr)| Member {
+            id,
+            name: String::new(),
+            peer_ur_ls: vec![format!("http://{addr}")],
+            client_ur_ls: vec![],
+            is_learner: false,
+        })
+        .collect()
+}
+
+/// Cluster Server, the members are added, removed, updated and promoted in the member table
+/// through consensus, while the peers of the curp server are the ones it starts with
+#[derive(Debug)]
+pub(crate) struct ClusterServer {
+    /// Members of the cluster
+    member_table: Arc<MemberTable>,
+    /// Header generator
+    header_gen: Arc<HeaderGenerator>,
+    /// Consensus client
+    client: Arc<Client<Command>>,
+    /// Server name
+    name: String,
+}
+
+impl ClusterServer {
+    /// New `ClusterServer`
+    pub(crate) fn new(
+        member_table: Arc<MemberTable>,
+        header_gen: Arc<HeaderGenerator>,
+        client: Arc<Client<Command>>,
+        name: String,
+    ) -> Self {
+        Self {
+            member_table,
+            header_gen,
+            client,
+            name,
+        }
+    }
+
+    /// Generate propose id
+    fn generate_propose_id(&self) -> ProposeId {
+        ProposeId::new(format!("{}-{}", self.name, Uuid::new_v4()))
+    }
+
+    /// Propose a member change request, the member table is not touched by other requests
+    async fn propose<T>(
+        &self,
+        request: T,
+        token: Option<String>,
+    ) -> Result<(CommandResponse, SyncResponse), tonic::Status>
+    where
+        T: Into<RequestWrapper>,
+    {
+        let wrapper = match token {
+            Some(token) => RequestWithToken::new_with_token(request.into(), token),
+            None => RequestWithToken::new(request.into()),
+        };
+        let cmd = Command::new(vec![], wrapper, self.generate_propose_id());
+        self.client
+            .propose_indexed(cmd)
+            .await
+            .map_err(propose_error_status)
+    }
+
+    /// Propose a member change request and return its response
+    async fn change_member<Req, Res>(
+        &self,
+        request: tonic::Request<Req>,
+    ) -> Result<tonic::Response<Res>, tonic::Status>
+    where
+        Req: Into<RequestWrapper>,
+        Res: From<ResponseWrapper>,
+    {
+        let token = get_token(request.metadata());
+        let (cmd_res, sync_res) = self.propose(request.into_inner(), token).await?;
+        let mut res = cmd_res.decode();
+        res.update_revision(sync_res.revision());
+        Ok(tonic::Response::new(res.into()))
+    }
+
+    /// Publish the name and the client URLs of the member through consensus, it's retried
+    /// until it succeeds
+    pub(crate) async fn publish(
+        auth_storage: Arc<AuthStore>,
+        client: Arc<Client<Command>>,
+        id: ServerId,
+        name: String,
+        self_addr: SocketAddr,
+    ) {
+        let request = ClusterMemberAttrSetRequest {
+            member_id: id,
+            member_attributes: Some(Attributes {
+                name: name.clone(),
+                client_urls: vec![format!("http://{self_addr}")],
+            }),
+        };
+        loop {
+            let wrapper = match auth_storage.assign_root_token() {
+                Ok(Some(token)) => RequestWithToken::new_with_token(request.clone().into(), token),
+                Ok(None) => RequestWithToken::new(request.clone().into()),
+                Err(e) => {
+                    warn!("failed to get the root token to publish member {id}: {e}");
+                    tokio::time::sleep(PUBLISH_RETRY_INTERVAL).await;
+                    continue;
+                }
+            };
+            let propose_id = ProposeId::new(format!("{name}-{}", Uuid::new_v4()));
+            // the member table is not touched by other requests
+            let cmd = Command::new(vec![], wrapper, propose_id);
+            match client.propose(cmd).await {
+                Ok(_res) => {
+                    info!("published member {id} with name {name}");
+                    return;
+                }
+                Err(e) => warn!("failed to publish member {id}: {e:?}"),
+            }
+            tokio::time::sleep(PUBLISH_RETRY_INTERVAL).await;
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Cluster for ClusterServer {
+    /// MemberAdd adds a member into the cluster.
+    async fn member_add(
+        &self,
+        request: tonic::Request<MemberAddRequest>,
+    ) -> Result<tonic::Response<MemberAddResponse>, tonic::Status> {
+        debug!("Receive MemberAddRequest {:?}", request);
+        self.change_member(request).await
+    }
+
+    /// MemberRemove removes an existing member from the cluster.
+    async fn member_remove(
+        &self,
+        request: tonic::Request<MemberRemoveRequest>,
+    ) -> Result<tonic::Response<MemberRemoveResponse>, tonic::Status> {
+        debug!("Receive MemberRemoveRequest {:?}", request);
+        self.change_member(request).await
+    }
+
+    /// MemberUpdate updates the member configuration.
+    async fn member_update(
+        &self,
+        request: tonic::Request<MemberUpdateRequest>,
+    ) -> Result<tonic::Response<MemberUpdateResponse>, tonic::Status> {
+        debug!("Receive MemberUpdateRequest {:?}", request);
+        self.change_member(request).await
+    }
+
+    /// MemberList lists all the members in the cluster.
+    async fn member_list(
+        &self,
+        request: tonic::Request<MemberListRequest>,
+    ) -> Result<tonic::Response<MemberListResponse>, tonic::Status> {
+        debug!("Receive MemberListRequest {:?}", request);
+        Ok(tonic::Response::new(MemberListResponse {
+            header: Some(self.header_gen.gen_header()),
+            members: self.member_table.members(),
+        }))
+    }
+
+    /// MemberPromote promotes a member from raft learner (non-voting) to raft voting member.
+    async fn member_promote(
+        &self,
+        request: tonic::Request<MemberPromoteRequest>,
+    ) -> Result<tonic::Response<MemberPromoteResponse>, tonic::Status> {
+        debug!("Receive MemberPromoteRequest {:?}", request);
+        self.change_member(request).await
+    }
+}
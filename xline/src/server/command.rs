@@ -65,6 +65,11 @@ impl KeyRange {
         }
     }
 
+    /// New `KeyRange` that contains all keys
+    pub(crate) fn new_all_keys() -> Self {
+        Self::new(UNBOUNDED, UNBOUNDED)
+    }
+
     /// Return if `KeyRange` is conflicted with another
     pub(crate) fn is_conflicted(&self, other: &Self) -> bool {
         // s1 < s2 ?
@@ -181,6 +186,24 @@ impl CommandExecutor {
             auth_storage,
         }
     }
+
+    /// Sync the commands in `backend` in one batch, return a response for each command
+    async fn sync(
+        &self,
+        backend: RequestBackend,
+        cmds: Vec<(ProposeId, LogIndex)>,
+    ) -> Vec<SyncResponse> {
+        if cmds.is_empty() {
+            return vec![];
+        }
+        let receiver = match backend {
+            RequestBackend::Kv => self.kv_storage.send_sync(cmds).await,
+            RequestBackend::Auth => self.auth_storage.send_sync(cmds).await,
+        };
+        receiver
+            .await
+            .unwrap_or_else(|_| panic!("Failed to receive response from storage"))
+    }
 }
 
 #[async_trait::async_trait]
@@ -200,17 +223,51 @@ impl CurpCommandExecutor<Command> for CommandExecutor {
     async fn after_sync(
         &self,
         cmd: &Command,
-        _index: LogIndex,
+        index: LogIndex,
     ) -> Result<SyncResponse, ExecuteError> {
         let (_, wrapper, id) = cmd.clone().unpack();
         self.auth_storage.check_permission(&wrapper)?;
-        let receiver = match wrapper.request.backend() {
-            RequestBackend::Kv => self.kv_storage.send_sync(id).await,
-            RequestBackend::Auth => self.auth_storage.send_sync(id).await,
-        };
-        receiver
-            .await
-            .or_else(|_| panic!("Failed to receive response from storage"))
+        let mut responses = self
+            .sync(wrapper.request.backend(), vec![(id, index)])
+            .await;
+        Ok(responses
+            .pop()
+            .unwrap_or_else(|| panic!("Failed to receive response from storage")))
+    }
+
+    async fn after_sync_batch(
+        &self,
+        cmds: &[(&Command, LogIndex)],
+    ) -> Vec<Result<SyncResponse, ExecuteError>> {
+        let mut asrs = Vec::with_capacity(cmds.len());
+        // the kv commands in a row are synced in one batch, while an auth command is synced on
+        // its own because it may change the permissions of the commands after it
+        let mut kv_cmds = vec![];
+        for &(cmd, index) in cmds {
+            let (_, wrapper, id) = cmd.clone().unpack();
+            let checked = self.auth_storage.check_permission(&wrapper);
+            let backend = wrapper.request.backend();
+            if checked.is_ok() && matches!(backend, RequestBackend::Kv) {
+                kv_cmds.push((id, index));
+                continue;
+            }
+            let kv_responses = self
+                .sync(RequestBackend::Kv, std::mem::take(&mut kv_cmds))
+                .await;
+            asrs.extend(kv_responses.into_iter().map(Ok));
+            match checked {
+                Ok(()) => asrs.extend(
+                    self.sync(backend, vec![(id, index)])
+                        .await
+                        .into_iter()
+                        .map(Ok),
+                ),
+                Err(e) => asrs.push(Err(e)),
+            }
+        }
+        let kv_responses = self.sync(RequestBackend::Kv, kv_cmds).await;
+        asrs.extend(kv_responses.into_iter().map(Ok));
+        asrs
     }
 }
 
@@ -288,35 +345,39 @@ impl CommandResponse {
     }
 }
 
-/// Sync Request
+/// Sync Request, the commands are synced in order and committed to the storage in one batch
 #[derive(Debug)]
 pub(crate) struct SyncRequest {
-    /// Propose id to sync
-    propose_id: ProposeId,
-    /// Command response sender
-    res_sender: oneshot::Sender<SyncResponse>,
+    /// Propose ids of the commands to sync with their log indexes
+    cmds: Vec<(ProposeId, LogIndex)>,
+    /// Command responses sender, a response for each command
+    res_sender: oneshot::Sender<Vec<SyncResponse>>,
 }
 
 impl SyncRequest {
     /// New `SyncRequest`
-    pub(crate) fn new(propose_id: ProposeId) -> (Self, oneshot::Receiver<SyncResponse>) {
+    pub(crate) fn new(
+        cmds: Vec<(ProposeId, LogIndex)>,
+    ) -> (Self, oneshot::Receiver<Vec<SyncResponse>>) {
         let (tx, rx) = oneshot::channel();
         (
             Self {
-                propose_id,
+                cmds,
                 res_sender: tx,
             },
             rx,
         )
     }
 
-    /// Consume `ExecutionRequest` and get ownership of each field
-    pub(crate) fn unpack(self) -> (ProposeId, oneshot::Sender<SyncResponse>) {
-        let Self {
-            propose_id,
-            res_sender,
-        } = self;
-        (propose_id, res_sender)
+    /// Consume `SyncRequest` and get ownership of each field
+    pub(crate) fn unpack(
+        self,
+    ) -> (
+        Vec<(ProposeId, LogIndex)>,
+        oneshot::Sender<Vec<SyncResponse>>,
+    ) {
+        let Self { cmds, res_sender } = self;
+        (cmds, res_sender)
     }
 }
 /// Sync Response
@@ -398,4 +459,8 @@ impl CurpCommand for Command {
     fn id(&self) -> &ProposeId {
         &self.id
     }
+
+    fn is_read_only(&self) -> bool {
+        self.request.request.is_read_only()
+    }
 }
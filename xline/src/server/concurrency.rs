@@ -0,0 +1,420 @@
+use std::{sync::Arc, time::Duration};
+
+use clippy_utilities::OverflowArithmetic;
+use curp::{client::Client, cmd::ProposeId};
+use log::warn;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use super::{
+    command::{Command, KeyRange},
+    lease_server::LeaseServer,
+};
+use crate::{
+    rpc::{
+        Compare, CompareResult, CompareTarget, DeleteRangeRequest, DeleteRangeResponse, Event,
+        EventType, KeyValue, LeaseGrantRequest, LeaseRevokeRequest, PutRequest, RangeRequest,
+        RangeResponse, Request, RequestOp, RequestWithToken, RequestWrapper, Response,
+        ResponseHeader, ResponseWrapper, SortOrder, SortTarget, TargetUnion, TxnRequest,
+    },
+    storage::{
+        error::propose_error_status,
+        kvwatcher::{KvWatcher, WatchEvent, Watcher},
+        KvStore,
+    },
+};
+
+/// Default channel size
+const CHANNEL_SIZE: usize = 128;
+
+/// Ttl in seconds of the lease granted for a request without a lease, the same as the
+/// default session ttl of etcd
+const DEFAULT_SESSION_TTL: i64 = 60;
+
+/// Interval to keep the session lease alive, a third of the ttl like the keep alive of the
+/// etcd client
+const SESSION_KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(20);
+
+/// Error message of a waiter whose key is deleted while waiting, e.g. its lease has expired
+pub(crate) const SESSION_EXPIRED_ERROR: &str = "session is expired";
+
+/// Client of the kv store for the servers of the concurrency apis, the requests are proposed
+/// through consensus and the keys are watched through the local watcher
+#[derive(Debug, Clone)]
+pub(crate) struct ConcurrencyClient {
+    /// KV storage
+    storage: Arc<KvStore>,
+    /// Consensus client
+    client: Arc<Client<Command>>,
+    /// Server name
+    name: String,
+    /// Lease server, used to keep the session leases alive
+    lease_server: LeaseServer,
+}
+
+/// Guard of the key of a waiter, the key is deleted if the guard is dropped before it's
+/// disarmed, e.g. the request is canceled while waiting, so the waiters after it are not
+/// blocked forever
+#[derive(Debug)]
+pub(crate) struct WaiterGuard {
+    /// Client of the kv store
+    client: ConcurrencyClient,
+    /// The key to delete, it's taken when the guard is disarmed
+    key: Option<Vec<u8>>,
+    /// Token of the waiter
+    token: Option<String>,
+}
+
+impl WaiterGuard {
+    /// Keep the key when the guard is dropped
+    pub(crate) fn disarm(mut self) {
+        self.key = None;
+    }
+}
+
+impl Drop for WaiterGuard {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            let client = self.client.clone();
+            let token = self.token.take();
+            let _handle = tokio::spawn(async move {
+                if let Err(e) = client.delete_key(key, token).await {
+                    warn!("failed to delete the key of a canceled waiter: {e}");
+                }
+            });
+        }
+    }
+}
+
+/// Watch of a key range, the watcher is canceled when it's dropped because the request
+/// waiting on it may be canceled
+#[derive(Debug)]
+pub(crate) struct KeyWatch {
+    /// KV watcher
+    kv_watcher: Arc<KvWatcher>,
+    /// The watcher to cancel
+    watcher: Watcher,
+    /// Revision and events that happened before the watcher is created
+    history: Option<(i64, Vec<Event>)>,
+    /// Event receiver
+    event_rx: mpsc::Receiver<WatchEvent>,
+}
+
+impl KeyWatch {
+    /// Get the revision and events of the next update
+    pub(crate) async fn next(&mut self) -> Option<(i64, Vec<Event>)> {
+        if let Some(history) = self.history.take() {
+            if !history.1.is_empty() {
+                return Some(history);
+            }
+        }
+        self.event_rx
+            .recv()
+            .await
+            .map(|mut watch_event| (watch_event.revision(), watch_event.take_events()))
+    }
+}
+
+impl Drop for KeyWatch {
+    fn drop(&mut self) {
+        let _revision = self.kv_watcher.cancel(&self.watcher);
+    }
+}
+
+impl ConcurrencyClient {
+    /// New `ConcurrencyClient`
+    pub(crate) fn new(
+        storage: Arc<KvStore>,
+        client: Arc<Client<Command>>,
+        name: String,
+        lease_server: LeaseServer,
+    ) -> Self {
+        Self {
+            storage,
+            client,
+            name,
+            lease_server,
+        }
+    }
+
+    /// Generate propose id
+    fn generate_propose_id(&self) -> ProposeId {
+        ProposeId::new(format!("{}-{}", self.name, Uuid::new_v4()))
+    }
+
+    /// Get the prefix of the waiters on `name` and the key of the waiter holding `lease`, the
+    /// same layout as etcd
+    pub(crate) fn session_key(name: &[u8], lease: i64) -> (Vec<u8>, Vec<u8>) {
+        let mut prefix = name.to_vec();
+        prefix.push(b'/');
+        let mut key = prefix.clone();
+        key.extend_from_slice(format!("{lease:x}").as_bytes());
+        (prefix, key)
+    }
+
+    /// Propose request and wait until it's synced, the revision of the response is updated
+    pub(crate) async fn propose<T>(
+        &self,
+        request: T,
+        token: Option<String>,
+        keys: Vec<KeyRange>,
+    ) -> Result<ResponseWrapper, tonic::Status>
+    where
+        T: Into<RequestWrapper>,
+    {
+        let wrapper = match token {
+            Some(token) => RequestWithToken::new_with_token(request.into(), token),
+            None => RequestWithToken::new(request.into()),
+        };
+        let cmd = Command::new(keys, wrapper, self.generate_propose_id());
+        let (cmd_res, sync_res) = self
+            .client
+            .propose_indexed(cmd)
+            .await
+            .map_err(propose_error_status)?;
+        let mut res = cmd_res.decode();
+        res.update_revision(sync_res.revision());
+        Ok(res)
+    }
+
+    /// Range through consensus so that the result is linearizable
+    pub(crate) async fn range(
+        &self,
+        request: RangeRequest,
+        token: Option<String>,
+    ) -> Result<RangeResponse, tonic::Status> {
+        let keys = vec![KeyRange::new(
+            request.key.as_slice(),
+            request.range_end.as_slice(),
+        )];
+        let res = self.propose(request, token, keys).await?;
+        if let ResponseWrapper::RangeResponse(response) = res {
+            Ok(response)
+        } else {
+            panic!("Receive wrong response {res:?} for RangeRequest");
+        }
+    }
+
+    /// Get the key with the smallest create revision under `prefix`
+    pub(crate) async fn first_created(
+        &self,
+        prefix: &[u8],
+        token: Option<String>,
+    ) -> Result<(Option<ResponseHeader>, Option<KeyValue>), tonic::Status> {
+        #[allow(clippy::as_conversions)] // Converting Enum to i32 is safe.
+        let request = RangeRequest {
+            key: prefix.to_vec(),
+            range_end: KeyRange::get_prefix(prefix),
+            limit: 1,
+            sort_order: SortOrder::Ascend as i32,
+            sort_target: SortTarget::Create as i32,
+            ..RangeRequest::default()
+        };
+        let res = self.range(request, token).await?;
+        Ok((res.header, res.kvs.into_iter().next()))
+    }
+
+    /// Grant a lease for a request without a lease
+    pub(crate) async fn grant_session_lease(
+        &self,
+        token: Option<String>,
+    ) -> Result<i64, tonic::Status> {
+        let request = LeaseGrantRequest {
+            ttl: DEFAULT_SESSION_TTL,
+            id: LeaseServer::generate_lease_id(),
+        };
+        let res = self.propose(request, token, vec![]).await?;
+        if let ResponseWrapper::LeaseGrantResponse(response) = res {
+            Ok(response.id)
+        } else {
+            panic!("Receive wrong response {res:?} for LeaseGrantRequest");
+        }
+    }
+
+    /// Keep the session lease granted by the server alive until the waiter `key` created at
+    /// `revision` is deleted, then the lease is revoked so that it doesn't outlive the session
+    pub(crate) fn keep_session_alive(
+        &self,
+        lease: i64,
+        key: Vec<u8>,
+        revision: i64,
+        token: Option<String>,
+    ) {
+        let client = self.clone();
+        let _handle = tokio::spawn(async move {
+            let deleted = client.wait_for_delete(key, revision);
+            tokio::pin!(deleted);
+            let mut ticker = tokio::time::interval(SESSION_KEEP_ALIVE_INTERVAL);
+            loop {
+                tokio::select! {
+                    () = &mut deleted => break,
+                    _now = ticker.tick() => match client.lease_server.renew(lease).await {
+                        // the keys of an expired lease are deleted when it's revoked
+                        Ok(ttl) if ttl <= 0 => return,
+                        Ok(_ttl) => {}
+                        Err(e) => warn!("failed to keep session lease {lease} alive: {e}"),
+                    },
+                }
+            }
+            let request = LeaseRevokeRequest { id: lease };
+            let keys = vec![KeyRange::new_all_keys()];
+            if let Err(e) = client.propose(request, token, keys).await {
+                warn!("failed to revoke session lease {lease}: {e}");
+            }
+        });
+    }
+
+    /// Guard the key of a waiter so that it's deleted if the waiter is canceled
+    pub(crate) fn guard_waiter(&self, key: Vec<u8>, token: Option<String>) -> WaiterGuard {
+        WaiterGuard {
+            client: self.clone(),
+            key: Some(key),
+            token,
+        }
+    }
+
+    /// Delete `key`
+    pub(crate) async fn delete_key(
+        &self,
+        key: Vec<u8>,
+        token: Option<String>,
+    ) -> Result<DeleteRangeResponse, tonic::Status> {
+        let keys = vec![KeyRange::new(key.as_slice(), vec![])];
+        let request = DeleteRangeRequest {
+            key,
+            ..DeleteRangeRequest::default()
+        };
+        let res = self.propose(request, token, keys).await?;
+        if let ResponseWrapper::DeleteRangeResponse(response) = res {
+            Ok(response)
+        } else {
+            panic!("Receive wrong response {res:?} for DeleteRangeRequest");
+        }
+    }
+
+    /// Create `key` bound to `lease` if it doesn't exist, return its create revision and the
+    /// existing key value if it's already created
+    pub(crate) async fn create_key(
+        &self,
+        key: &[u8],
+        value: Vec<u8>,
+        lease: i64,
+        token: Option<String>,
+    ) -> Result<(i64, Option<KeyValue>), tonic::Status> {
+        #[allow(clippy::as_conversions)] // Converting Enum to i32 is safe.
+        let compare = Compare {
+            result: CompareResult::Equal as i32,
+            target: CompareTarget::Create as i32,
+            key: key.to_vec(),
+            range_end: vec![],
+            target_union: Some(TargetUnion::CreateRevision(0)),
+        };
+        let put = RequestOp {
+            request: Some(Request::RequestPut(PutRequest {
+                key: key.to_vec(),
+                value,
+                lease,
+                ..PutRequest::default()
+            })),
+        };
+        let get = RequestOp {
+            request: Some(Request::RequestRange(RangeRequest {
+                key: key.to_vec(),
+                ..RangeRequest::default()
+            })),
+        };
+        let txn_request = TxnRequest {
+            compare: vec![compare],
+            success: vec![put],
+            failure: vec![get],
+        };
+        let keys = vec![KeyRange::new(key, vec![])];
+        let res = self.propose(txn_request, token, keys).await?;
+        let txn_res = if let ResponseWrapper::TxnResponse(response) = res {
+            response
+        } else {
+            panic!("Receive wrong response {res:?} for TxnRequest");
+        };
+        if txn_res.succeeded {
+            return Ok((txn_res.header.map_or(0, |header| header.revision), None));
+        }
+        let range_res = txn_res
+            .responses
+            .into_iter()
+            .next()
+            .and_then(|op| op.response);
+        if let Some(Response::ResponseRange(response)) = range_res {
+            let kv = response
+                .kvs
+                .into_iter()
+                .next()
+                .unwrap_or_else(|| panic!("Key must exist when the compare fails"));
+            Ok((kv.create_revision, Some(kv)))
+        } else {
+            panic!("Receive wrong response {range_res:?} for RangeRequest in TxnRequest");
+        }
+    }
+
+    /// Wait until all the keys under `prefix` created before `revision` are deleted, so
+    /// the waiters are served in the order of their create revisions
+    pub(crate) async fn wait_for_predecessors(
+        &self,
+        prefix: &[u8],
+        revision: i64,
+        token: Option<String>,
+    ) -> Result<(), tonic::Status> {
+        // nothing is created before revision 1, and a zero max create revision means no limit
+        if revision <= 1 {
+            return Ok(());
+        }
+        loop {
+            #[allow(clippy::as_conversions)] // Converting Enum to i32 is safe.
+            let request = RangeRequest {
+                key: prefix.to_vec(),
+                range_end: KeyRange::get_prefix(prefix),
+                limit: 1,
+                sort_order: SortOrder::Descend as i32,
+                sort_target: SortTarget::Create as i32,
+                max_create_revision: revision.overflow_sub(1),
+                ..RangeRequest::default()
+            };
+            let res = self.range(request, token.clone()).await?;
+            // only wait for the closest predecessor, the others may be deleted meanwhile
+            match res.kvs.into_iter().next() {
+                Some(kv) => {
+                    let header_revision = res.header.map_or(0, |header| header.revision);
+                    self.wait_for_delete(kv.key, header_revision).await;
+                }
+                None => return Ok(()),
+            }
+        }
+    }
+
+    /// Watch `key_range` for the updates after `revision`
+    pub(crate) async fn watch(&self, key_range: KeyRange, revision: i64) -> KeyWatch {
+        let (event_tx, event_rx) = mpsc::channel(CHANNEL_SIZE);
+        let kv_watcher = self.storage.kv_watcher();
+        let (watcher, events, current_revision) = kv_watcher
+            .watch(0, key_range, revision.overflow_add(1), vec![], event_tx)
+            .await;
+        KeyWatch {
+            kv_watcher,
+            watcher,
+            history: Some((current_revision, events)),
+            event_rx,
+        }
+    }
+
+    /// Wait until `key` is deleted after `revision`
+    pub(crate) async fn wait_for_delete(&self, key: Vec<u8>, revision: i64) {
+        let mut key_watch = self.watch(KeyRange::new(key, vec![]), revision).await;
+        while let Some((_revision, events)) = key_watch.next().await {
+            if events
+                .iter()
+                .any(|event| event.r#type() == EventType::Delete)
+            {
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,327 @@
+use std::sync::Arc;
+
+use curp::client::Client;
+use log::debug;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+use super::{
+    auth_server::get_token,
+    command::{Command, KeyRange},
+    concurrency::{ConcurrencyClient, SESSION_EXPIRED_ERROR},
+    lease_server::LeaseServer,
+};
+use crate::{
+    rpc::{
+        CampaignRequest, CampaignResponse, Compare, CompareResult, CompareTarget,
+        DeleteRangeRequest, Election, EventType, LeaderKey, LeaderRequest, LeaderResponse,
+        ProclaimRequest, ProclaimResponse, PutRequest, RangeRequest, Request, RequestOp,
+        ResignRequest, ResignResponse, ResponseHeader, ResponseWrapper, TargetUnion, TxnRequest,
+        TxnResponse,
+    },
+    storage::KvStore,
+};
+
+/// Default channel size
+const CHANNEL_SIZE: usize = 128;
+
+/// Error message of a request without the leader key
+const MISSING_LEADER_KEY_ERROR: &str = "election: missing leader key";
+
+/// Error message of a request on an election without leader
+const NO_LEADER_ERROR: &str = "election: no leader";
+
+/// Error message of a request from a campaigner that isn't the leader
+const NOT_LEADER_ERROR: &str = "election: not leader";
+
+/// Election Server
+#[derive(Debug)]
+pub(crate) struct ElectionServer {
+    /// Client of the kv store
+    client: ConcurrencyClient,
+}
+
+impl ElectionServer {
+    /// New `ElectionServer`
+    pub(crate) fn new(
+        storage: Arc<KvStore>,
+        client: Arc<Client<Command>>,
+        name: String,
+        lease_server: LeaseServer,
+    ) -> Self {
+        Self {
+            client: ConcurrencyClient::new(storage, client, name, lease_server),
+        }
+    }
+
+    /// Run `request` if `leader` still holds the leadership
+    async fn txn_if_leader(
+        &self,
+        leader: &LeaderKey,
+        request: Request,
+        token: Option<String>,
+    ) -> Result<TxnResponse, tonic::Status> {
+        #[allow(clippy::as_conversions)] // Converting Enum to i32 is safe.
+        let compare = Compare {
+            result: CompareResult::Equal as i32,
+            target: CompareTarget::Create as i32,
+            key: leader.key.clone(),
+            range_end: vec![],
+            target_union: Some(TargetUnion::CreateRevision(leader.rev)),
+        };
+        let txn_request = TxnRequest {
+            compare: vec![compare],
+            success: vec![RequestOp {
+                request: Some(request),
+            }],
+            failure: vec![],
+        };
+        let keys = vec![KeyRange::new(leader.key.as_slice(), vec![])];
+        let res = self.client.propose(txn_request, token, keys).await?;
+        if let ResponseWrapper::TxnResponse(response) = res {
+            Ok(response)
+        } else {
+            panic!("Receive wrong response {res:?} for TxnRequest");
+        }
+    }
+
+    /// Update the value of `leader`
+    async fn proclaim_value(
+        &self,
+        leader: &LeaderKey,
+        value: Vec<u8>,
+        token: Option<String>,
+    ) -> Result<TxnResponse, tonic::Status> {
+        let put = Request::RequestPut(PutRequest {
+            key: leader.key.clone(),
+            value,
+            lease: leader.lease,
+            ..PutRequest::default()
+        });
+        let res = self.txn_if_leader(leader, put, token).await?;
+        if res.succeeded {
+            Ok(res)
+        } else {
+            Err(tonic::Status::failed_precondition(NOT_LEADER_ERROR))
+        }
+    }
+
+    /// Send the leaders of the election under `prefix` and their updates to `tx` until it's
+    /// closed
+    async fn observe_leaders(
+        client: &ConcurrencyClient,
+        prefix: &[u8],
+        token: Option<String>,
+        tx: &mpsc::Sender<Result<LeaderResponse, tonic::Status>>,
+    ) -> Result<(), tonic::Status> {
+        let new_header = |revision: i64| {
+            Some(ResponseHeader {
+                revision,
+                ..ResponseHeader::default()
+            })
+        };
+        loop {
+            let (range_header, leader) = client.first_created(prefix, token.clone()).await?;
+            let revision = range_header.as_ref().map_or(0, |h| h.revision);
+            let (header, leader_kv) = match leader {
+                Some(kv) => (range_header, kv),
+                None => {
+                    // wait for the first campaigner, it's the leader
+                    let key_range = KeyRange::new(prefix, KeyRange::get_prefix(prefix));
+                    let mut key_watch = client.watch(key_range, revision).await;
+                    loop {
+                        let (update_revision, events) = match key_watch.next().await {
+                            Some(update) => update,
+                            None => return Ok(()),
+                        };
+                        let created = events
+                            .into_iter()
+                            .find(|event| event.r#type() == EventType::Put)
+                            .and_then(|event| event.kv);
+                        if let Some(kv) = created {
+                            break (new_header(update_revision), kv);
+                        }
+                    }
+                }
+            };
+            let leader_revision = leader_kv.mod_revision;
+            let leader_key = leader_kv.key.clone();
+            let res = LeaderResponse {
+                header,
+                kv: Some(leader_kv),
+            };
+            if tx.send(Ok(res)).await.is_err() {
+                return Ok(());
+            }
+            // follow the proclamations of the leader until it's deleted
+            let mut key_watch = client
+                .watch(KeyRange::new(leader_key, vec![]), leader_revision)
+                .await;
+            'follow: while let Some((update_revision, events)) = key_watch.next().await {
+                for event in events {
+                    if event.r#type() == EventType::Delete {
+                        break 'follow;
+                    }
+                    let res = LeaderResponse {
+                        header: new_header(update_revision),
+                        kv: event.kv,
+                    };
+                    if tx.send(Ok(res)).await.is_err() {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Election for ElectionServer {
+    /// Campaign waits to acquire leadership in an election, returning a LeaderKey
+    /// representing the leadership if successful. The LeaderKey can then be used
+    /// to issue new values on the election, transactionally guard API requests on
+    /// leadership still being held, and resign from the election.
+    async fn campaign(
+        &self,
+        request: tonic::Request<CampaignRequest>,
+    ) -> Result<tonic::Response<CampaignResponse>, tonic::Status> {
+        debug!("Receive CampaignRequest {:?}", request);
+        let token = get_token(request.metadata());
+        let req = request.into_inner();
+        let is_session = req.lease == 0;
+        let lease = if is_session {
+            self.client.grant_session_lease(token.clone()).await?
+        } else {
+            req.lease
+        };
+        // every campaigner puts a key under the prefix, the key with the smallest create
+        // revision is the leader
+        let (prefix, key) = ConcurrencyClient::session_key(&req.name, lease);
+        let (revision, existing) = self
+            .client
+            .create_key(&key, req.value.clone(), lease, token.clone())
+            .await?;
+        if is_session {
+            // the session lasts until the campaigner resigns
+            self.client
+                .keep_session_alive(lease, key.clone(), revision, token.clone());
+        }
+        // the key created by another campaign with the same lease is kept
+        let guard = existing
+            .is_none()
+            .then(|| self.client.guard_waiter(key.clone(), token.clone()));
+        let leader = LeaderKey {
+            name: req.name,
+            key,
+            rev: revision,
+            lease,
+        };
+        // campaign again with the same lease but a new value
+        if existing.map_or(false, |kv| kv.value != req.value) {
+            let _res = self
+                .proclaim_value(&leader, req.value, token.clone())
+                .await?;
+        }
+        self.client
+            .wait_for_predecessors(&prefix, revision, token.clone())
+            .await?;
+        // the key is deleted if the lease has expired while waiting
+        let res = self
+            .client
+            .range(
+                RangeRequest {
+                    key: leader.key.clone(),
+                    ..RangeRequest::default()
+                },
+                token,
+            )
+            .await?;
+        if res.kvs.is_empty() {
+            return Err(tonic::Status::failed_precondition(SESSION_EXPIRED_ERROR));
+        }
+        if let Some(guard) = guard {
+            guard.disarm();
+        }
+        Ok(tonic::Response::new(CampaignResponse {
+            header: res.header,
+            leader: Some(leader),
+        }))
+    }
+
+    /// Proclaim updates the leader's posted value with a new value.
+    async fn proclaim(
+        &self,
+        request: tonic::Request<ProclaimRequest>,
+    ) -> Result<tonic::Response<ProclaimResponse>, tonic::Status> {
+        debug!("Receive ProclaimRequest {:?}", request);
+        let token = get_token(request.metadata());
+        let req = request.into_inner();
+        let leader = req
+            .leader
+            .ok_or_else(|| tonic::Status::invalid_argument(MISSING_LEADER_KEY_ERROR))?;
+        let res = self.proclaim_value(&leader, req.value, token).await?;
+        Ok(tonic::Response::new(ProclaimResponse {
+            header: res.header,
+        }))
+    }
+
+    /// Leader returns the current election proclamation, if any.
+    async fn leader(
+        &self,
+        request: tonic::Request<LeaderRequest>,
+    ) -> Result<tonic::Response<LeaderResponse>, tonic::Status> {
+        debug!("Receive LeaderRequest {:?}", request);
+        let token = get_token(request.metadata());
+        let (prefix, _key) = ConcurrencyClient::session_key(&request.into_inner().name, 0);
+        match self.client.first_created(&prefix, token).await? {
+            (header, Some(kv)) => Ok(tonic::Response::new(LeaderResponse {
+                header,
+                kv: Some(kv),
+            })),
+            (_header, None) => Err(tonic::Status::failed_precondition(NO_LEADER_ERROR)),
+        }
+    }
+
+    ///Server streaming response type for the Observe method.
+    type ObserveStream = ReceiverStream<Result<LeaderResponse, tonic::Status>>;
+
+    /// Observe streams election proclamations in-order as made by the election's
+    /// elected leaders.
+    async fn observe(
+        &self,
+        request: tonic::Request<LeaderRequest>,
+    ) -> Result<tonic::Response<Self::ObserveStream>, tonic::Status> {
+        debug!("Receive ObserveRequest {:?}", request);
+        let token = get_token(request.metadata());
+        let (prefix, _key) = ConcurrencyClient::session_key(&request.into_inner().name, 0);
+        let client = self.client.clone();
+        let (tx, rx) = mpsc::channel(CHANNEL_SIZE);
+        let _hd = tokio::spawn(async move {
+            if let Err(e) = Self::observe_leaders(&client, &prefix, token, &tx).await {
+                let _ignore = tx.send(Err(e)).await;
+            }
+        });
+        Ok(tonic::Response::new(ReceiverStream::new(rx)))
+    }
+
+    /// Resign releases election leadership so other campaigners may acquire
+    /// leadership on the election.
+    async fn resign(
+        &self,
+        request: tonic::Request<ResignRequest>,
+    ) -> Result<tonic::Response<ResignResponse>, tonic::Status> {
+        debug!("Receive ResignRequest {:?}", request);
+        let token = get_token(request.metadata());
+        let leader = request
+            .into_inner()
+            .leader
+            .ok_or_else(|| tonic::Status::invalid_argument(MISSING_LEADER_KEY_ERROR))?;
+        let delete = Request::RequestDeleteRange(DeleteRangeRequest {
+            key: leader.key.clone(),
+            ..DeleteRangeRequest::default()
+        });
+        // resigning an election that is not held is a no-op, the same as etcd
+        let res = self.txn_if_leader(&leader, delete, token).await?;
+        Ok(tonic::Response::new(ResignResponse { header: res.header }))
+    }
+}
@@ -1,6 +1,6 @@
 use std::{collections::HashSet, fmt::Debug, sync::Arc};
 
-use curp::{client::Client, cmd::ProposeId, error::ProposeError};
+use curp::{client::Client, cmd::ProposeId};
 use log::debug;
 use tracing::instrument;
 use uuid::Uuid;
@@ -15,7 +15,11 @@ use crate::{
         PutRequest, PutResponse, RangeRequest, RangeResponse, Request, RequestOp, RequestWithToken,
         RequestWrapper, Response, ResponseOp, SortOrder, SortTarget, TxnRequest, TxnResponse,
     },
-    storage::KvStore,
+    storage::{
+        authstore::AuthStore,
+        error::{execute_error_status, propose_error_status},
+        KvStore,
+    },
 };
 
 /// Default max txn ops
@@ -27,6 +31,8 @@ const DEFAULT_MAX_TXN_OPS: usize = 128;
 pub(crate) struct KvServer {
     /// KV storage
     storage: Arc<KvStore>,
+    /// Auth storage
+    auth_storage: Arc<AuthStore>,
     /// Consensus client
     client: Arc<Client<Command>>,
     /// Server name
@@ -35,9 +41,15 @@ pub(crate) struct KvServer {
 
 impl KvServer {
     /// New `KvServer`
-    pub(crate) fn new(storage: Arc<KvStore>, client: Arc<Client<Command>>, name: String) -> Self {
+    pub(crate) fn new(
+        storage: Arc<KvStore>,
+        auth_storage: Arc<AuthStore>,
+        client: Arc<Client<Command>>,
+        name: String,
+    ) -> Self {
         Self {
             storage,
+            auth_storage,
             client,
             name,
         }
@@ -76,6 +88,8 @@ impl KvServer {
                     end: cmp.range_end.clone(),
                 })
                 .collect(),
+            // compaction removes the history of all keys
+            RequestWrapper::CompactionRequest(_) => vec![KeyRange::new_all_keys()],
             _ => unreachable!("Other request should not be sent to this store"),
         };
         Command::new(key_ranges, wrapper, propose_id)
@@ -98,22 +112,18 @@ impl KvServer {
         let propose_id = self.generate_propose_id();
         let cmd = Self::command_from_request_wrapper(propose_id, wrapper);
         if use_fast_path {
-            let cmd_res = self.client.propose(cmd).await.map_err(|err| {
-                if let ProposeError::ExecutionError(e) = err {
-                    tonic::Status::invalid_argument(e)
-                } else {
-                    panic!("propose err {err:?}")
-                }
-            })?;
+            let cmd_res = self
+                .client
+                .propose(cmd)
+                .await
+                .map_err(propose_error_status)?;
             Ok((cmd_res, None))
         } else {
-            let (cmd_res, sync_res) = self.client.propose_indexed(cmd).await.map_err(|err| {
-                if let ProposeError::ExecutionError(e) = err {
-                    tonic::Status::invalid_argument(e)
-                } else {
-                    panic!("propose err {err:?}")
-                }
-            })?;
+            let (cmd_res, sync_res) = self
+                .client
+                .propose_indexed(cmd)
+                .await
+                .map_err(propose_error_status)?;
             Ok((cmd_res, Some(sync_res)))
         }
     }
@@ -149,6 +159,26 @@ impl KvServer {
         };
     }
 
+    /// Handle a serializable `RangeRequest` with the data of this member, it may be stale but
+    /// doesn't go through consensus
+    fn serializable_range(
+        &self,
+        request: tonic::Request<RangeRequest>,
+    ) -> Result<RangeResponse, tonic::Status> {
+        let token = get_token(request.metadata());
+        let req = request.into_inner();
+        let wrapper = match token {
+            Some(token) => RequestWithToken::new_with_token(req.clone().into(), token),
+            None => RequestWithToken::new(req.clone().into()),
+        };
+        self.auth_storage
+            .check_permission(&wrapper)
+            .map_err(|e| tonic::Status::invalid_argument(e.to_string()))?;
+        self.storage
+            .serializable_range(&req)
+            .map_err(execute_error_status)
+    }
+
     /// Generate propose id
     fn generate_propose_id(&self) -> ProposeId {
         ProposeId::new(format!("{}-{}", self.name, Uuid::new_v4()))
@@ -156,25 +186,6 @@ impl KvServer {
 
     /// Validate range request before handle
     fn check_range_request(req: &RangeRequest) -> Result<(), tonic::Status> {
-        if req.serializable {
-            return Err(tonic::Status::unimplemented(
-                "serializable is unimplemented",
-            ));
-        }
-        if req.keys_only {
-            return Err(tonic::Status::unimplemented("keys_only is unimplemented"));
-        }
-        if req.min_mod_revision != 0
-            || req.max_mod_revision != 0
-            || req.min_create_revision != 0
-            || req.max_create_revision != 0
-        {
-            return Err(tonic::Status::unimplemented(
-                "min/max mod/create revision is unimplemented",
-            ));
-        }
-        // TODO: Remove the above errors after implementation
-
         if req.key.is_empty() {
             return Err(tonic::Status::invalid_argument("key is not provided"));
         }
@@ -187,10 +198,6 @@ impl KvServer {
 
     /// Validate put request before handle
     fn check_put_request(req: &PutRequest) -> Result<(), tonic::Status> {
-        if req.lease != 0 {
-            return Err(tonic::Status::unimplemented("lease is unimplemented"));
-        }
-        // TODO: Remove the above errors after implementation
         if req.key.is_empty() {
             return Err(tonic::Status::invalid_argument("key is not provided"));
         }
@@ -335,6 +342,9 @@ impl Kv for KvServer {
     ) -> Result<tonic::Response<RangeResponse>, tonic::Status> {
         debug!("Receive RangeRequest {:?}", request);
         Self::check_range_request(request.get_ref())?;
+        if request.get_ref().serializable {
+            return self.serializable_range(request).map(tonic::Response::new);
+        }
         let is_fast_path = true;
         let (cmd_res, sync_res) = self.propose(request, is_fast_path).await?;
 
@@ -436,10 +446,19 @@ impl Kv for KvServer {
         request: tonic::Request<CompactionRequest>,
     ) -> Result<tonic::Response<CompactionResponse>, tonic::Status> {
         debug!("Receive CompactionRequest {:?}", request);
-        Err(tonic::Status::new(
-            tonic::Code::Unimplemented,
-            "Not Implemented".to_owned(),
-        ))
+        // the history is removed after the compaction is synced
+        let is_fast_path = false;
+        let (cmd_res, sync_res) = self.propose(request, is_fast_path).await?;
+
+        let mut res: CompactionResponse = cmd_res.decode().into();
+        if let Some(sync_res) = sync_res {
+            let revision = sync_res.revision();
+            debug!("Get revision {:?} for CompactionRequest", revision);
+            if let Some(header) = res.header.as_mut() {
+                header.revision = revision;
+            }
+        }
+        Ok(tonic::Response::new(res))
     }
 }
 
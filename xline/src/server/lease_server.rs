@@ -1,42 +1,248 @@
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use curp::client::Client;
-use log::{debug, warn};
-use tokio::sync::mpsc;
+use clippy_utilities::Cast;
+use curp::{client::Client, cmd::ProposeId, server::ApplyProgress, ServerId, ELECTION_TIMEOUT};
+use log::{debug, info, warn};
+use tokio::sync::{mpsc, watch};
 use tokio_stream::{wrappers::ReceiverStream, StreamExt};
+use tonic::transport::Channel;
+use uuid::Uuid;
 
-use super::command::Command;
+use super::{
+    auth_server::get_token,
+    command::{Command, CommandResponse, KeyRange, SyncResponse},
+};
 use crate::{
+    header_gen::HeaderGenerator,
     rpc::{
-        Lease, LeaseGrantRequest, LeaseGrantResponse, LeaseKeepAliveRequest,
-        LeaseKeepAliveResponse, LeaseLeasesRequest, LeaseLeasesResponse, LeaseRevokeRequest,
-        LeaseRevokeResponse, LeaseTimeToLiveRequest, LeaseTimeToLiveResponse,
+        Lease, LeaseCheckpointRequest, LeaseClient, LeaseGrantRequest, LeaseGrantResponse,
+        LeaseKeepAliveRequest, LeaseKeepAliveResponse, LeaseLeasesRequest, LeaseLeasesResponse,
+        LeaseRevokeRequest, LeaseRevokeResponse, LeaseStatus, LeaseTimeToLiveRequest,
+        LeaseTimeToLiveResponse, RequestWithToken, RequestWrapper,
     },
-    storage::KvStore,
+    storage::{error::propose_error_status, leasestore::MIN_LEASE_TTL, AuthStore, LeaseCollection},
 };
 
 /// Default channel size
 const CHANNEL_SIZE: usize = 128;
 
+/// Interval for the leader to check the expired leases
+const EXPIRY_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Interval for the leader to checkpoint the remaining ttl of all the leases, the same as etcd
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Max number of leases checkpointed in one proposal, the same as etcd
+const MAX_CHECKPOINT_BATCH_SIZE: usize = 1000;
+
+/// Extension of the leases when the server becomes the leader, it's the longest election
+/// timeout like etcd, so that clients have time to find the new leader and keep alive again
+const ELECTION_GRACE_PERIOD: Duration = ELECTION_TIMEOUT;
+
+/// Error message of a keep alive request received by a server that is not the leader
+const NOT_LEADER_ERROR: &str = "lease: server is not the leader";
+
 /// Lease Server
-//#[derive(Debug)]
-#[allow(dead_code)] // Remove this after feature is completed
+#[derive(Debug, Clone)]
 pub(crate) struct LeaseServer {
-    /// KV storage
-    storage: Arc<KvStore>,
+    /// Leases of the kv store
+    lease_collection: Arc<LeaseCollection>,
+    /// Header generator
+    header_gen: Arc<HeaderGenerator>,
     /// Consensus client
     client: Arc<Client<Command>>,
     /// Server name
     name: String,
+    /// Id of the server
+    id: ServerId,
+    /// Addresses of the peers, used to forward requests to the leader
+    peers: HashMap<ServerId, SocketAddr>,
+    /// Progress of the curp server, used to find the leader
+    progress: watch::Receiver<ApplyProgress>,
 }
 
 impl LeaseServer {
     /// New `LeaseServer`
-    pub(crate) fn new(storage: Arc<KvStore>, client: Arc<Client<Command>>, name: String) -> Self {
+    pub(crate) fn new(
+        lease_collection: Arc<LeaseCollection>,
+        header_gen: Arc<HeaderGenerator>,
+        client: Arc<Client<Command>>,
+        name: String,
+        id: ServerId,
+        peers: HashMap<ServerId, SocketAddr>,
+        progress: watch::Receiver<ApplyProgress>,
+    ) -> Self {
         Self {
-            storage,
+            lease_collection,
+            header_gen,
             client,
             name,
+            id,
+            peers,
+            progress,
+        }
+    }
+
+    /// Generate propose id
+    fn generate_propose_id(&self) -> ProposeId {
+        ProposeId::new(format!("{}-{}", self.name, Uuid::new_v4()))
+    }
+
+    /// Generate a random positive lease id
+    pub(crate) fn generate_lease_id() -> i64 {
+        let (high, _low) = Uuid::new_v4().as_u64_pair();
+        let id: i64 = (high & 0x7fff_ffff_ffff_ffff).cast();
+        id.max(1)
+    }
+
+    /// Check if the server is the leader
+    fn is_leader(&self) -> bool {
+        self.progress.borrow().leader == Some(self.id)
+    }
+
+    /// Connect to the lease service of the leader
+    async fn leader_client(&self) -> Result<LeaseClient<Channel>, tonic::Status> {
+        let leader = self.progress.borrow().leader;
+        let addr = leader
+            .and_then(|id| self.peers.get(&id))
+            .ok_or_else(|| tonic::Status::unavailable("leader is unknown"))?;
+        LeaseClient::connect(format!("http://{addr}"))
+            .await
+            .map_err(|e| tonic::Status::unavailable(format!("failed to connect to leader: {e}")))
+    }
+
+    /// Renew the lease on the leader and return its ttl, the ttl is 0 if the lease is not
+    /// found or has expired
+    pub(crate) async fn renew(&self, id: i64) -> Result<i64, tonic::Status> {
+        if self.is_leader() {
+            return Ok(self.lease_collection.renew(id).unwrap_or(0));
+        }
+        let mut leader = self.leader_client().await?;
+        let mut res_stream = leader
+            .lease_keep_alive(tokio_stream::once(LeaseKeepAliveRequest { id }))
+            .await?
+            .into_inner();
+        match res_stream.next().await {
+            Some(res) => res.map(|response| response.ttl),
+            None => Err(tonic::Status::unavailable("keep alive stream is closed")),
+        }
+    }
+
+    /// Propose request and wait until it's synced, the lease requests take effect after sync
+    async fn propose<T>(
+        &self,
+        request: T,
+        token: Option<String>,
+        keys: Vec<KeyRange>,
+    ) -> Result<(CommandResponse, SyncResponse), tonic::Status>
+    where
+        T: Into<RequestWrapper>,
+    {
+        let wrapper = match token {
+            Some(token) => RequestWithToken::new_with_token(request.into(), token),
+            None => RequestWithToken::new(request.into()),
+        };
+        let cmd = Command::new(keys, wrapper, self.generate_propose_id());
+        self.client
+            .propose_indexed(cmd)
+            .await
+            .map_err(propose_error_status)
+    }
+
+    /// Propose an internal request with the root token, the result is only logged
+    async fn propose_as_root<T>(
+        auth_storage: &AuthStore,
+        client: &Client<Command>,
+        name: &str,
+        request: T,
+        keys: Vec<KeyRange>,
+    ) -> Result<(), String>
+    where
+        T: Into<RequestWrapper>,
+    {
+        let wrapper = match auth_storage.assign_root_token() {
+            Ok(Some(token)) => RequestWithToken::new_with_token(request.into(), token),
+            Ok(None) => RequestWithToken::new(request.into()),
+            Err(e) => return Err(format!("failed to get the root token: {e}")),
+        };
+        let propose_id = ProposeId::new(format!("{name}-{}", Uuid::new_v4()));
+        let cmd = Command::new(keys, wrapper, propose_id);
+        client
+            .propose_indexed(cmd)
+            .await
+            .map(|_res| ())
+            .map_err(|e| format!("{e:?}"))
+    }
+
+    /// Maintain the leases when the server is the leader: revoke the expired leases and
+    /// checkpoint the remaining ttl of the leases through consensus
+    pub(crate) async fn maintain_leases(
+        lease_collection: Arc<LeaseCollection>,
+        auth_storage: Arc<AuthStore>,
+        client: Arc<Client<Command>>,
+        name: String,
+        id: ServerId,
+        progress: watch::Receiver<ApplyProgress>,
+    ) {
+        let mut ticker = tokio::time::interval(EXPIRY_CHECK_INTERVAL);
+        // the term the server is the leader in, `None` if it's not the leader
+        let mut leader_term = None;
+        let mut last_checkpoint = Instant::now();
+        loop {
+            let _now = ticker.tick().await;
+            let (leader, term, is_ready) = {
+                let progress = progress.borrow();
+                (
+                    progress.leader,
+                    progress.term,
+                    progress.last_applied >= progress.ready_index,
+                )
+            };
+            if leader != Some(id) {
+                leader_term = None;
+                continue;
+            }
+            // the leases granted in previous terms are applied before they are extended
+            if !is_ready {
+                continue;
+            }
+            // the server may lose and regain the leadership between two ticks
+            if leader_term.replace(term) != Some(term) {
+                // the keep alive requests were sent to the previous leader
+                lease_collection.promote(ELECTION_GRACE_PERIOD);
+                // resets recorded while being the leader last time are stale
+                let _stale = lease_collection.take_pending_resets();
+                last_checkpoint = Instant::now();
+                continue;
+            }
+            for lease_id in lease_collection.expired() {
+                let request = LeaseRevokeRequest { id: lease_id };
+                let keys = vec![KeyRange::new_all_keys()];
+                match Self::propose_as_root(&auth_storage, &client, &name, request, keys).await {
+                    Ok(()) => info!("revoked expired lease {lease_id}"),
+                    Err(e) => warn!("failed to revoke expired lease {lease_id}: {e}"),
+                }
+            }
+            let mut checkpoints = lease_collection.take_pending_resets();
+            if last_checkpoint.elapsed() >= CHECKPOINT_INTERVAL {
+                checkpoints.extend(lease_collection.checkpoints());
+                last_checkpoint = Instant::now();
+            }
+            for batch in checkpoints.chunks(MAX_CHECKPOINT_BATCH_SIZE) {
+                let request = LeaseCheckpointRequest {
+                    checkpoints: batch.to_vec(),
+                };
+                if let Err(e) =
+                    Self::propose_as_root(&auth_storage, &client, &name, request, vec![]).await
+                {
+                    warn!("failed to checkpoint {} leases: {e}", batch.len());
+                }
+            }
         }
     }
 }
@@ -51,8 +257,17 @@ impl Lease for LeaseServer {
         request: tonic::Request<LeaseGrantRequest>,
     ) -> Result<tonic::Response<LeaseGrantResponse>, tonic::Status> {
         debug!("Receive LeaseGrantRequest {:?}", request);
-        // TODO add real implementation
-        Ok(tonic::Response::new(LeaseGrantResponse::default()))
+        let token = get_token(request.metadata());
+        let mut req = request.into_inner();
+        // the id and ttl are chosen before proposing so that every member grants the same lease
+        if req.id == 0 {
+            req.id = Self::generate_lease_id();
+        }
+        req.ttl = req.ttl.max(MIN_LEASE_TTL);
+        let (cmd_res, sync_res) = self.propose(req, token, vec![]).await?;
+        let mut res = cmd_res.decode();
+        res.update_revision(sync_res.revision());
+        Ok(tonic::Response::new(res.into()))
     }
 
     /// LeaseRevoke revokes a lease. All keys attached to the lease will expire and be deleted.
@@ -61,8 +276,13 @@ impl Lease for LeaseServer {
         request: tonic::Request<LeaseRevokeRequest>,
     ) -> Result<tonic::Response<LeaseRevokeResponse>, tonic::Status> {
         debug!("Receive LeaseRevokeRequest {:?}", request);
-        // TODO add real implementation
-        Ok(tonic::Response::new(LeaseRevokeResponse::default()))
+        let token = get_token(request.metadata());
+        // the attached keys can be any keys
+        let keys = vec![KeyRange::new_all_keys()];
+        let (cmd_res, sync_res) = self.propose(request.into_inner(), token, keys).await?;
+        let mut res = cmd_res.decode();
+        res.update_revision(sync_res.revision());
+        Ok(tonic::Response::new(res.into()))
     }
 
     ///Server streaming response type for the LeaseKeepAlive method.
@@ -75,33 +295,63 @@ impl Lease for LeaseServer {
         request: tonic::Request<tonic::Streaming<LeaseKeepAliveRequest>>,
     ) -> Result<tonic::Response<Self::LeaseKeepAliveStream>, tonic::Status> {
         debug!("Receive LeaseKeepAliveRequest {:?}", request);
-        // TODO: Temporary solution
+        let metadata = request.metadata().clone();
         let mut req_stream = request.into_inner();
         let (tx, rx) = mpsc::channel(CHANNEL_SIZE);
-        let _hd = tokio::spawn(async move {
-            while let Some(req_result) = req_stream.next().await {
-                match req_result {
-                    Ok(req) => {
-                        debug!("Receive LeaseKeepAliveRequest {:?}", req);
-                        assert!(
-                            tx.send(Ok(LeaseKeepAliveResponse {
+        if self.is_leader() {
+            let lease_collection = Arc::clone(&self.lease_collection);
+            let header_gen = Arc::clone(&self.header_gen);
+            let progress = self.progress.clone();
+            let id = self.id;
+            let _hd = tokio::spawn(async move {
+                while let Some(req_result) = req_stream.next().await {
+                    match req_result {
+                        Ok(req) => {
+                            debug!("Receive LeaseKeepAliveRequest {:?}", req);
+                            // the client should keep alive on the new leader, the expiry of
+                            // leases is only kept by the leader
+                            if progress.borrow().leader != Some(id) {
+                                let _ignore = tx
+                                    .send(Err(tonic::Status::unavailable(NOT_LEADER_ERROR)))
+                                    .await;
+                                break;
+                            }
+                            // ttl is 0 if the lease is not found or has expired
+                            let res = LeaseKeepAliveResponse {
+                                header: Some(header_gen.gen_header()),
                                 id: req.id,
-                                ttl: 1,
-                                ..LeaseKeepAliveResponse::default()
-                            }))
-                            .await
-                            .is_ok(),
-                            "Command receiver dropped"
-                        );
+                                ttl: lease_collection.renew(req.id).unwrap_or(0),
+                            };
+                            if tx.send(Ok(res)).await.is_err() {
+                                break;
+                            }
+                        }
+                        Err(e) => {
+                            warn!("Receive LeaseKeepAliveRequest error {:?}", e);
+                            break;
+                        }
                     }
-                    Err(e) => {
-                        warn!("Receive LeaseKeepAliveRequest error {:?}", e);
+                }
+            });
+        } else {
+            // only the leader keeps the expiry of leases
+            let mut leader = self.leader_client().await?;
+            let mut forward_request = tonic::Request::new(req_stream.filter_map(Result::ok));
+            // the leader authenticates the forwarded requests with the same token
+            for key in ["token", "authorization"] {
+                if let Some(value) = metadata.get(key) {
+                    let _prev = forward_request.metadata_mut().insert(key, value.clone());
+                }
+            }
+            let mut res_stream = leader.lease_keep_alive(forward_request).await?.into_inner();
+            let _hd = tokio::spawn(async move {
+                while let Some(res) = res_stream.next().await {
+                    if tx.send(res).await.is_err() {
                         break;
-                        //panic!("Failed to receive LeaseKeepAliveRequest {:?}", e),
                     }
                 }
-            }
-        });
+            });
+        }
         Ok(tonic::Response::new(ReceiverStream::new(rx)))
     }
 
@@ -111,11 +361,33 @@ impl Lease for LeaseServer {
         request: tonic::Request<LeaseTimeToLiveRequest>,
     ) -> Result<tonic::Response<LeaseTimeToLiveResponse>, tonic::Status> {
         debug!("Receive LeaseTimeToLiveRequest {:?}", request);
-        // TODO add real implementation
-        Err(tonic::Status::new(
-            tonic::Code::Unimplemented,
-            "Not Implemented".to_owned(),
-        ))
+        if !self.is_leader() {
+            // only the leader keeps the expiry of leases
+            return self
+                .leader_client()
+                .await?
+                .lease_time_to_live(request.into_inner())
+                .await;
+        }
+        let req = request.into_inner();
+        let header = Some(self.header_gen.gen_header());
+        let res = match self.lease_collection.look_up(req.id) {
+            Some(lease) => LeaseTimeToLiveResponse {
+                header,
+                id: req.id,
+                ttl: lease.remaining_ttl(),
+                granted_ttl: lease.ttl(),
+                keys: if req.keys { lease.keys() } else { vec![] },
+            },
+            // ttl is -1 if the lease is not found, the same as etcd
+            None => LeaseTimeToLiveResponse {
+                header,
+                id: req.id,
+                ttl: -1,
+                ..LeaseTimeToLiveResponse::default()
+            },
+        };
+        Ok(tonic::Response::new(res))
     }
 
     /// LeaseLeases lists all existing leases.
@@ -124,10 +396,15 @@ impl Lease for LeaseServer {
         request: tonic::Request<LeaseLeasesRequest>,
     ) -> Result<tonic::Response<LeaseLeasesResponse>, tonic::Status> {
         debug!("Receive LeaseLeasesRequest {:?}", request);
-        // TODO add real implementation
-        Err(tonic::Status::new(
-            tonic::Code::Unimplemented,
-            "Not Implemented".to_owned(),
-        ))
+        let leases = self
+            .lease_collection
+            .ids()
+            .into_iter()
+            .map(|id| LeaseStatus { id })
+            .collect();
+        Ok(tonic::Response::new(LeaseLeasesResponse {
+            header: Some(self.header_gen.gen_header()),
+            leases,
+        }))
     }
 }
@@ -0,0 +1,136 @@
+use std::sync::Arc;
+
+use curp::server::Rpc;
+use log::debug;
+use tokio_stream::wrappers::ReceiverStream;
+
+use super::command::Command;
+use crate::{
+    header_gen::HeaderGenerator,
+    rpc::{
+        AlarmRequest, AlarmResponse, DefragmentRequest, DefragmentResponse, DowngradeRequest,
+        DowngradeResponse, HashKvRequest, HashKvResponse, HashRequest, HashResponse, Maintenance,
+        MoveLeaderRequest, MoveLeaderResponse, SnapshotRequest, SnapshotResponse, StatusRequest,
+        StatusResponse,
+    },
+};
+
+/// Error message of the maintenance operations that are not supported yet
+const MAINTENANCE_ERROR: &str = "maintenance operation is not supported yet";
+
+/// Maintenance Server
+#[derive(Debug)]
+pub(crate) struct MaintenanceServer {
+    /// Consensus server of the member
+    curp_server: Rpc<Command>,
+    /// Header generator
+    header_gen: Arc<HeaderGenerator>,
+}
+
+impl MaintenanceServer {
+    /// New `MaintenanceServer`
+    pub(crate) fn new(curp_server: Rpc<Command>, header_gen: Arc<HeaderGenerator>) -> Self {
+        Self {
+            curp_server,
+            header_gen,
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Maintenance for MaintenanceServer {
+    /// Alarm activates, deactivates, and queries alarms regarding cluster health.
+    async fn alarm(
+        &self,
+        request: tonic::Request<AlarmRequest>,
+    ) -> Result<tonic::Response<AlarmResponse>, tonic::Status> {
+        debug!("Receive AlarmRequest {:?}", request);
+        Err(tonic::Status::unimplemented(MAINTENANCE_ERROR))
+    }
+
+    /// Status gets the status of the member.
+    async fn status(
+        &self,
+        request: tonic::Request<StatusRequest>,
+    ) -> Result<tonic::Response<StatusResponse>, tonic::Status> {
+        debug!("Receive StatusRequest {:?}", request);
+        let status = self.curp_server.status();
+        let errors = self
+            .curp_server
+            .alarm()
+            .borrow()
+            .iter()
+            .map(|alarm| format!("{alarm:?}"))
+            .collect();
+        Ok(tonic::Response::new(StatusResponse {
+            header: Some(self.header_gen.gen_header()),
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            db_size: 0,
+            leader: status.leader.unwrap_or(0),
+            raft_index: status.commit_index,
+            raft_term: status.term,
+            raft_applied_index: status.last_applied,
+            errors,
+            db_size_in_use: 0,
+            is_learner: false,
+        }))
+    }
+
+    /// Defragment defragments a member's backend database to recover storage space.
+    async fn defragment(
+        &self,
+        request: tonic::Request<DefragmentRequest>,
+    ) -> Result<tonic::Response<DefragmentResponse>, tonic::Status> {
+        debug!("Receive DefragmentRequest {:?}", request);
+        Err(tonic::Status::unimplemented(MAINTENANCE_ERROR))
+    }
+
+    /// Hash computes the hash of whole backend keyspace.
+    async fn hash(
+        &self,
+        request: tonic::Request<HashRequest>,
+    ) -> Result<tonic::Response<HashResponse>, tonic::Status> {
+        debug!("Receive HashRequest {:?}", request);
+        Err(tonic::Status::unimplemented(MAINTENANCE_ERROR))
+    }
+
+    /// HashKV computes the hash of all MVCC keys up to a given revision.
+    async fn hash_kv(
+        &self,
+        request: tonic::Request<HashKvRequest>,
+    ) -> Result<tonic::Response<HashKvResponse>, tonic::Status> {
+        debug!("Receive HashKvRequest {:?}", request);
+        Err(tonic::Status::unimplemented(MAINTENANCE_ERROR))
+    }
+
+    ///Server streaming response type for the Snapshot method.
+    type SnapshotStream = ReceiverStream<Result<SnapshotResponse, tonic::Status>>;
+
+    /// Snapshot sends a snapshot of the entire backend from a member over a stream to a client.
+    async fn snapshot(
+        &self,
+        request: tonic::Request<SnapshotRequest>,
+    ) -> Result<tonic::Response<Self::SnapshotStream>, tonic::Status> {
+        debug!("Receive SnapshotRequest {:?}", request);
+        Err(tonic::Status::unimplemented(MAINTENANCE_ERROR))
+    }
+
+    /// MoveLeader requests current leader node to transfer its leadership to transferee.
+    async fn move_leader(
+        &self,
+        request: tonic::Request<MoveLeaderRequest>,
+    ) -> Result<tonic::Response<MoveLeaderResponse>, tonic::Status> {
+        debug!("Receive MoveLeaderRequest {:?}", request);
+        Err(tonic::Status::unimplemented(MAINTENANCE_ERROR))
+    }
+
+    /// Downgrade requests downgrades, verifies feasibility or cancels downgrade
+    /// on the cluster version.
+    async fn downgrade(
+        &self,
+        request: tonic::Request<DowngradeRequest>,
+    ) -> Result<tonic::Response<DowngradeResponse>, tonic::Status> {
+        debug!("Receive DowngradeRequest {:?}", request);
+        Err(tonic::Status::unimplemented(MAINTENANCE_ERROR))
+    }
+}
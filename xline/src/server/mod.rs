@@ -1,16 +1,30 @@
 /// Xline auth server
 mod auth_server;
+/// Automatic compaction of the kv store
+mod auto_compactor;
+/// Xline cluster server
+mod cluster_server;
 /// Command to be executed
 pub(crate) mod command;
+/// Client of the kv store for the concurrency servers
+mod concurrency;
+/// Xline election server
+mod election_server;
 /// Xline kv server
 mod kv_server;
 /// Xline lease server
 mod lease_server;
 /// Xline lock server
 mod lock_server;
+/// Xline maintenance server
+mod maintenance_server;
 /// Xline watch server
 mod watch_server;
 /// Xline server
 pub(crate) mod xline_server;
 
-pub use self::xline_server::XlineServer;
+pub use self::{
+    auto_compactor::{AutoCompactionConfig, REVISION_CHECK_INTERVAL},
+    xline_server::XlineServer,
+};
+pub use crate::storage::engine::StorageConfig;
@@ -11,7 +11,10 @@ use crate::{
         RequestUnion, ResponseHeader, Watch, WatchCancelRequest, WatchCreateRequest, WatchRequest,
         WatchResponse,
     },
-    storage::kvwatcher::{KvWatcher, WatchEvent, WatchId, Watcher},
+    storage::{
+        kvstore::COMPACTED_ERROR,
+        kvwatcher::{KvWatcher, WatchEvent, WatchId, Watcher},
+    },
 };
 
 /// Default channel size
@@ -88,10 +91,48 @@ impl WatchHandle {
         }
     }
 
+    /// Handle `WatchCreateRequest` whose start revision has been compacted, the watcher is
+    /// created and canceled at once with the compacted revision
+    async fn handle_compacted_watch_create(&mut self, watch_id: WatchId, compact_revision: i64) {
+        let header = Some(ResponseHeader {
+            revision: self.kv_watcher.revision(),
+            ..ResponseHeader::default()
+        });
+        let responses = [
+            WatchResponse {
+                header: header.clone(),
+                watch_id,
+                created: true,
+                ..WatchResponse::default()
+            },
+            WatchResponse {
+                header,
+                watch_id,
+                canceled: true,
+                compact_revision,
+                cancel_reason: COMPACTED_ERROR.to_owned(),
+                ..WatchResponse::default()
+            },
+        ];
+        for response in responses {
+            // TODO: handle client closes connection
+            assert!(
+                self.response_tx.send(Ok(response)).await.is_ok(),
+                "Watch client closes connection"
+            );
+        }
+    }
+
     /// Handle `WatchCreateRequest`
     async fn handle_watch_create(&mut self, req: WatchCreateRequest) {
         let watch_id = self.get_next_watch_id(req.watch_id);
         if let Some(watch_id) = watch_id {
+            let compacted_revision = self.kv_watcher.compacted_revision();
+            if req.start_revision > 0 && req.start_revision < compacted_revision {
+                self.handle_compacted_watch_create(watch_id, compacted_revision)
+                    .await;
+                return;
+            }
             let key_range = KeyRange {
                 start: req.key,
                 end: req.range_end,
@@ -1,44 +1,76 @@
-use std::{future::Future, net::SocketAddr, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
 
 use anyhow::Result;
-use curp::{client::Client, server::Rpc, ProtocolServer};
+use curp::{
+    client::Client,
+    codec::WireFormat,
+    quorum::QuorumConfig,
+    server::{ApplyProgress, Placement, Rpc},
+    ProtocolServer, ServerId,
+};
 use jsonwebtoken::{DecodingKey, EncodingKey};
-use tokio::net::TcpListener;
+use tokio::{net::TcpListener, sync::watch};
 use tokio_stream::wrappers::TcpListenerStream;
 use tonic::transport::Server;
 
 use super::{
     auth_server::AuthServer,
+    auto_compactor::{AutoCompactionConfig, AutoCompactor},
+    cluster_server::{cluster_id, initial_members, ClusterServer},
     command::{Command, CommandExecutor},
+    election_server::ElectionServer,
     kv_server::KvServer,
     lease_server::LeaseServer,
     lock_server::LockServer,
+    maintenance_server::MaintenanceServer,
     watch_server::WatchServer,
 };
 use crate::{
     header_gen::HeaderGenerator,
     rpc::{
-        AuthServer as RpcAuthServer, KvServer as RpcKvServer, LeaseServer as RpcLeaseServer,
-        LockServer as RpcLockServer, WatchServer as RpcWatchServer,
+        AuthServer as RpcAuthServer, ClusterServer as RpcClusterServer,
+        ElectionServer as RpcElectionServer, KvServer as RpcKvServer,
+        LeaseServer as RpcLeaseServer, LockServer as RpcLockServer,
+        MaintenanceServer as RpcMaintenanceServer, WatchServer as RpcWatchServer,
     },
-    storage::{authstore::AuthStore, KvStore},
+    storage::{authstore::AuthStore, engine::StorageConfig, KvStore, LeaseCollection, MemberTable},
 };
 
 /// Rpc Server of curp protocol
 type CurpServer = Rpc<Command>;
 
+/// Interval to persist the applied index of curp
+const APPLIED_INDEX_SYNC_INTERVAL: Duration = Duration::from_secs(1);
+
 /// Xline server
 #[allow(dead_code)] // Remove this after feature is completed
 #[derive(Debug)]
 pub struct XlineServer {
     /// Server name
     name: String,
-    /// Address of peers
-    peers: Vec<SocketAddr>,
+    /// Id of self node in curp
+    id: ServerId,
+    /// Ids and addresses of peers
+    peers: HashMap<ServerId, SocketAddr>,
+    /// Region labels and election priorities of cluster members
+    placements: HashMap<ServerId, Placement>,
+    /// Quorums of curp, shared by the server and the client
+    quorum: QuorumConfig,
     /// Kv storage
     kv_storage: Arc<KvStore>,
     /// Auth storage
     auth_storage: Arc<AuthStore>,
+    /// Leases of the kv storage
+    lease_collection: Arc<LeaseCollection>,
+    /// Members of the cluster
+    member_table: Arc<MemberTable>,
     /// Consensus Server
     //node: Arc<DefaultServer<Command, CommandExecutor>>,
     /// Consensus client
@@ -46,53 +78,105 @@ pub struct XlineServer {
     /// If current node is leader when it starts
     /// TODO: remove this when leader selection is supported
     is_leader: bool,
-    /// Leader index
-    leader_index: usize,
+    /// Id of the leader when the cluster starts
+    leader_id: ServerId,
     /// Address of self node
     self_addr: SocketAddr,
     /// Header generator
     header_gen: Arc<HeaderGenerator>,
+    /// Auto compaction of the kv store, disabled if it's `None`
+    auto_compaction: Option<AutoCompactionConfig>,
+    /// The format used to encode the values sent by curp
+    wire_format: WireFormat,
+    /// Dir where curp persists its state, nothing is persisted if it's `None`
+    curp_dir: Option<PathBuf>,
 }
 
 impl XlineServer {
-    /// New `XlineServer`
+    /// New `XlineServer`, the ids of the members are assigned by the operator and must be the
+    /// same on every member
     ///
     /// # Panics
     ///
-    /// panic when peers do not contain leader address
+    /// panic when the leader or a placed member is not a member, the ids or addresses of the
+    /// members are not unique, or the storage cannot be opened
     #[inline]
+    #[allow(clippy::too_many_arguments)] // the server is configured by the command line arguments
     pub async fn new(
         name: String,
-        peers: Vec<SocketAddr>,
+        id: ServerId,
+        peers: HashMap<ServerId, SocketAddr>,
         is_leader: bool,
-        leader_addr: SocketAddr,
+        leader_id: ServerId,
         self_addr: SocketAddr,
+        placements: HashMap<ServerId, Placement>,
+        quorum: QuorumConfig,
         key_pair: Option<(EncodingKey, DecodingKey)>,
+        storage: StorageConfig,
+        auto_compaction: Option<AutoCompactionConfig>,
+        wire_format: WireFormat,
     ) -> Self {
-        let header_gen = Arc::new(HeaderGenerator::new(0, 0));
-        let kv_storage = Arc::new(KvStore::new(Arc::clone(&header_gen)));
-        let auth_storage = Arc::new(AuthStore::new(key_pair, Arc::clone(&header_gen)));
+        let engine = storage
+            .open()
+            .unwrap_or_else(|e| panic!("failed to open storage {storage:?}: {e}"));
 
         let mut all_members = peers.clone();
-        all_members.push(self_addr);
-        all_members.sort();
+        if all_members.insert(id, self_addr).is_some() {
+            panic!("member id {id} is used by both a peer and self");
+        }
+        let addrs: HashSet<_> = all_members.values().collect();
+        if addrs.len() != all_members.len() {
+            panic!("members should have different addresses, but got {all_members:?}");
+        }
+        if !all_members.contains_key(&leader_id) {
+            panic!("leader {leader_id} should be one of the peers and self");
+        }
+        if let Some(placed) = placements
+            .keys()
+            .find(|&placed| !all_members.contains_key(placed))
+        {
+            panic!("placed member {placed} should be one of the peers and self");
+        }
 
-        let leader_index = all_members.binary_search(&leader_addr).unwrap_or_else(|_| {
-            panic!("leader address should be in the collection of peers and self address")
-        });
+        let header_gen = Arc::new(HeaderGenerator::new(
+            cluster_id(all_members.keys().copied()),
+            id,
+        ));
+        let lease_collection = Arc::new(LeaseCollection::new(engine.as_ref()));
+        let member_table = Arc::new(MemberTable::new(
+            engine.as_ref(),
+            initial_members(&all_members),
+        ));
+        let kv_storage = Arc::new(KvStore::new(
+            Arc::clone(&header_gen),
+            Arc::clone(&engine),
+            Arc::clone(&lease_collection),
+            Arc::clone(&member_table),
+        ));
+        let auth_storage = Arc::new(AuthStore::new(key_pair, Arc::clone(&header_gen), engine));
 
-        let client = Arc::new(Client::<Command>::new(leader_index, all_members).await);
+        let mut client = Client::<Command>::new(leader_id, all_members, quorum.clone()).await;
+        client.set_wire_format(wire_format);
+        let client = Arc::new(client);
 
         Self {
             name,
+            id,
             peers,
+            placements,
+            quorum,
             kv_storage,
             auth_storage,
+            lease_collection,
+            member_table,
             client,
             is_leader,
-            leader_index,
+            leader_id,
             self_addr,
             header_gen,
+            auto_compaction,
+            wire_format,
+            curp_dir: storage.curp_dir(),
         }
     }
 
@@ -103,14 +187,26 @@ impl XlineServer {
     /// Will return `Err` when `tonic::Server` serve return an error
     #[inline]
     pub async fn start(&self, addr: SocketAddr) -> Result<()> {
-        let (kv_server, lock_server, lease_server, auth_server, watch_server, curp_server) =
-            self.init_servers();
+        let (
+            kv_server,
+            lock_server,
+            election_server,
+            lease_server,
+            auth_server,
+            watch_server,
+            cluster_server,
+            maintenance_server,
+            curp_server,
+        ) = self.init_servers();
         Ok(Server::builder()
             .add_service(RpcLockServer::new(lock_server))
+            .add_service(RpcElectionServer::new(election_server))
             .add_service(RpcKvServer::new(kv_server))
             .add_service(RpcLeaseServer::new(lease_server))
             .add_service(RpcAuthServer::new(auth_server))
             .add_service(RpcWatchServer::new(watch_server))
+            .add_service(RpcClusterServer::new(cluster_server))
+            .add_service(RpcMaintenanceServer::new(maintenance_server))
             .add_service(ProtocolServer::new(curp_server))
             .serve(addr)
             .await?)
@@ -130,34 +226,108 @@ impl XlineServer {
     where
         F: Future<Output = ()>,
     {
-        let (kv_server, lock_server, lease_server, auth_server, watch_server, curp_server) =
-            self.init_servers();
+        let (
+            kv_server,
+            lock_server,
+            election_server,
+            lease_server,
+            auth_server,
+            watch_server,
+            cluster_server,
+            maintenance_server,
+            curp_server,
+        ) = self.init_servers();
         Ok(Server::builder()
             .add_service(RpcLockServer::new(lock_server))
+            .add_service(RpcElectionServer::new(election_server))
             .add_service(RpcKvServer::new(kv_server))
             .add_service(RpcLeaseServer::new(lease_server))
             .add_service(RpcAuthServer::new(auth_server))
             .add_service(RpcWatchServer::new(watch_server))
+            .add_service(RpcClusterServer::new(cluster_server))
+            .add_service(RpcMaintenanceServer::new(maintenance_server))
             .add_service(ProtocolServer::new(curp_server))
             .serve_with_incoming_shutdown(TcpListenerStream::new(xline_listener), signal)
             .await?)
     }
 
-    /// Init `KvServer`, `LockServer`, `LeaseServer`, `WatchServer` and `CurpServer`
-    /// for the Xline Server.
+    /// Init `KvServer`, `LockServer`, `ElectionServer`, `LeaseServer`, `WatchServer`,
+    /// `ClusterServer`, `MaintenanceServer` and `CurpServer` for the Xline Server.
     fn init_servers(
         &self,
     ) -> (
         KvServer,
         LockServer,
+        ElectionServer,
         LeaseServer,
         AuthServer,
         WatchServer,
+        ClusterServer,
+        MaintenanceServer,
         CurpServer,
     ) {
+        let curp_server = CurpServer::new(
+            self.id,
+            self.is_leader,
+            0,
+            self.peers
+                .iter()
+                .map(|(&id, addr)| (id, addr.to_string()))
+                .collect(),
+            self.placements.clone(),
+            self.quorum.clone(),
+            self.wire_format,
+            self.curp_dir.clone(),
+            CommandExecutor::new(Arc::clone(&self.kv_storage), Arc::clone(&self.auth_storage)),
+        );
+        let _handle = tokio::spawn(Self::sync_term(
+            curp_server.progress(),
+            Arc::clone(&self.header_gen),
+        ));
+        let _applied_handle = tokio::spawn(Self::sync_applied_index(
+            curp_server.progress(),
+            Arc::clone(&self.kv_storage),
+        ));
+        if let Some(config) = self.auto_compaction {
+            let compactor = AutoCompactor::new(
+                config,
+                self.id,
+                self.name.clone(),
+                Arc::clone(&self.kv_storage),
+                Arc::clone(&self.auth_storage),
+                Arc::clone(&self.client),
+                curp_server.progress(),
+            );
+            let _compactor_handle = tokio::spawn(compactor.run());
+        }
+        let _publish_handle = tokio::spawn(ClusterServer::publish(
+            Arc::clone(&self.auth_storage),
+            Arc::clone(&self.client),
+            self.id,
+            self.name.clone(),
+            self.self_addr,
+        ));
+        let _lease_handle = tokio::spawn(LeaseServer::maintain_leases(
+            Arc::clone(&self.lease_collection),
+            Arc::clone(&self.auth_storage),
+            Arc::clone(&self.client),
+            self.name.clone(),
+            self.id,
+            curp_server.progress(),
+        ));
+        let lease_server = LeaseServer::new(
+            Arc::clone(&self.lease_collection),
+            Arc::clone(&self.header_gen),
+            Arc::clone(&self.client),
+            self.name.clone(),
+            self.id,
+            self.peers.clone(),
+            curp_server.progress(),
+        );
         (
             KvServer::new(
                 Arc::clone(&self.kv_storage),
+                Arc::clone(&self.auth_storage),
                 Arc::clone(&self.client),
                 self.name.clone(),
             ),
@@ -165,25 +335,64 @@ impl XlineServer {
                 Arc::clone(&self.kv_storage),
                 Arc::clone(&self.client),
                 self.name.clone(),
+                lease_server.clone(),
             ),
-            LeaseServer::new(
+            ElectionServer::new(
                 Arc::clone(&self.kv_storage),
                 Arc::clone(&self.client),
                 self.name.clone(),
+                lease_server.clone(),
             ),
+            lease_server,
             AuthServer::new(
                 Arc::clone(&self.auth_storage),
                 Arc::clone(&self.client),
                 self.name.clone(),
             ),
             WatchServer::new(self.kv_storage.kv_watcher()),
-            CurpServer::new(
-                self.self_addr.to_string().as_str(),
-                self.is_leader,
-                0,
-                self.peers.iter().map(ToString::to_string).collect(),
-                CommandExecutor::new(Arc::clone(&self.kv_storage), Arc::clone(&self.auth_storage)),
+            ClusterServer::new(
+                Arc::clone(&self.member_table),
+                Arc::clone(&self.header_gen),
+                Arc::clone(&self.client),
+                self.name.clone(),
             ),
+            MaintenanceServer::new(curp_server.clone(), Arc::clone(&self.header_gen)),
+            curp_server,
         )
     }
+
+    /// Keep the term in `ResponseHeader` consistent with the term of curp
+    async fn sync_term(
+        mut progress: watch::Receiver<ApplyProgress>,
+        header_gen: Arc<HeaderGenerator>,
+    ) {
+        loop {
+            let term = progress.borrow_and_update().term;
+            header_gen.set_term(term);
+            if progress.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Persist the applied index of curp periodically, so that the storage only records the
+    /// synced commands after it
+    async fn sync_applied_index(
+        progress: watch::Receiver<ApplyProgress>,
+        kv_storage: Arc<KvStore>,
+    ) {
+        let mut ticker = tokio::time::interval(APPLIED_INDEX_SYNC_INTERVAL);
+        loop {
+            let _now = ticker.tick().await;
+            if progress.has_changed().is_err() {
+                return;
+            }
+            let last_applied = progress.borrow().last_applied;
+            // the applied index is written with a sync, so it's not done on the runtime
+            let kv_storage = Arc::clone(&kv_storage);
+            tokio::task::spawn_blocking(move || kv_storage.update_applied_index(last_applied))
+                .await
+                .unwrap_or_else(|e| panic!("Failed to update the applied index: {e}"));
+        }
+    }
 }
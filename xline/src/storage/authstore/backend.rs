@@ -2,7 +2,7 @@ use std::{cmp::Ordering, collections::HashMap, fmt, sync::Arc};
 
 use anyhow::Result;
 use clippy_utilities::{Cast, OverflowArithmetic};
-use curp::{cmd::ProposeId, error::ExecuteError};
+use curp::{cmd::ProposeId, error::ExecuteError, LogIndex};
 use itertools::Itertools;
 use jsonwebtoken::{DecodingKey, EncodingKey};
 use log::debug;
@@ -15,7 +15,12 @@ use prost::Message;
 
 use crate::{
     header_gen::HeaderGenerator,
-    storage::{db::DB, index::Index},
+    storage::{
+        db::{AUTH_TABLE, DB},
+        engine::StorageEngine,
+        index::Index,
+        revision::KeyRevision,
+    },
 };
 use crate::{
     rpc::{
@@ -85,14 +90,16 @@ impl fmt::Debug for AuthStoreBackend {
 }
 
 impl AuthStoreBackend {
-    /// New `AuthStoreBackend`
+    /// New `AuthStoreBackend`, the index, revision and auth status are rebuilt from the data
+    /// in `engine`
     pub(crate) fn new(
         key_pair: Option<(EncodingKey, DecodingKey)>,
         header_gen: Arc<HeaderGenerator>,
+        engine: Arc<dyn StorageEngine>,
     ) -> Self {
-        Self {
+        let backend = Self {
             index: Index::new(),
-            db: DB::new(),
+            db: DB::new(engine, AUTH_TABLE),
             revision: Mutex::new(1),
             sp_exec_pool: Mutex::new(HashMap::new()),
             enabled: Mutex::new(false),
@@ -101,6 +108,39 @@ impl AuthStoreBackend {
             }),
             permission_cache: RwLock::new(PermissionCache::new()),
             header_gen,
+        };
+        backend.recover();
+        backend
+    }
+
+    /// Rebuild the index, revision and auth status from the `KeyValue`s in the db
+    fn recover(&self) {
+        let mut enabled = false;
+        let mut last_revision = None;
+        for (revision, kv) in self.db.get_all() {
+            let key_rev = if kv.version == 0 {
+                KeyRevision::new_deletion(revision.revision(), revision.sub_revision())
+            } else {
+                KeyRevision::new(
+                    kv.create_revision,
+                    kv.version,
+                    revision.revision(),
+                    revision.sub_revision(),
+                )
+            };
+            if kv.key == AUTH_ENABLE_KEY {
+                enabled = kv.value == [1];
+            }
+            last_revision = Some(revision.revision());
+            self.index.restore(kv.key, key_rev);
+        }
+        if let Some(revision) = last_revision {
+            let mut current = self.revision.lock();
+            *current = (*current).max(revision);
+        }
+        if enabled {
+            *self.enabled.lock() = true;
+            self.create_permission_cache();
         }
     }
 
@@ -144,7 +184,7 @@ impl AuthStoreBackend {
     }
 
     /// Assign token
-    fn assign(&self, username: &str) -> Result<String, ExecuteError> {
+    pub(crate) fn assign(&self, username: &str) -> Result<String, ExecuteError> {
         match self.token_manager {
             Some(ref token_manager) => token_manager
                 .assign(username, self.revision())
@@ -716,28 +756,49 @@ impl AuthStoreBackend {
         })
     }
 
-    /// Sync a Command to storage and generate revision for Command.
-    pub(crate) async fn sync_cmd(&self, sync_req: SyncRequest) {
+    /// Sync a batch of Commands to storage and generate revisions for them.
+    pub(crate) async fn sync_cmd(self: &Arc<Self>, sync_req: SyncRequest) {
         debug!("Receive SyncRequest {:?}", sync_req);
-        let (propose_id, res_sender) = sync_req.unpack();
-        let requests = self
-            .sp_exec_pool
-            .lock()
-            .remove(&propose_id)
-            .unwrap_or_else(|| {
-                panic!(
-                    "Failed to get speculative execution propose id {:?}",
-                    propose_id
-                );
-            });
-        self.sync_request(requests);
+        let (cmds, res_sender) = sync_req.unpack();
+        let n_cmds = cmds.len();
+        // the batch is committed to the storage with a sync, so it's not done on the runtime
+        let backend = Arc::clone(self);
+        tokio::task::spawn_blocking(move || backend.sync_batch(cmds))
+            .await
+            .unwrap_or_else(|e| panic!("Failed to sync the commands: {e}"));
         let kv_revision = self.header_gen.revision();
         assert!(
-            res_sender.send(SyncResponse::new(kv_revision)).is_ok(),
+            res_sender
+                .send(vec![SyncResponse::new(kv_revision); n_cmds])
+                .is_ok(),
             "Failed to send response"
         );
     }
 
+    /// Sync the commands, each with its log index, in order and commit all their writes in
+    /// one batch
+    fn sync_batch(&self, cmds: Vec<(ProposeId, LogIndex)>) {
+        for (propose_id, index) in cmds {
+            let requests = self
+                .sp_exec_pool
+                .lock()
+                .remove(&propose_id)
+                .unwrap_or_else(|| {
+                    panic!(
+                        "Failed to get speculative execution propose id {:?}",
+                        propose_id
+                    );
+                });
+            // the command synced before the server restarted is skipped when the leader
+            // replays it
+            if !self.db.is_applied(index, &propose_id) {
+                self.sync_request(requests);
+                self.db.record_applied(index, &propose_id);
+            }
+        }
+        self.db.flush();
+    }
+
     /// Sync `RequestWrapper`
     fn sync_request(&self, wrapper: RequestWithToken) {
         let revision = *self.revision.lock();
@@ -1,7 +1,7 @@
 use std::sync::Arc;
 
 use anyhow::Result;
-use curp::{cmd::ProposeId, error::ExecuteError};
+use curp::{cmd::ProposeId, error::ExecuteError, LogIndex};
 use jsonwebtoken::{DecodingKey, EncodingKey};
 use tokio::sync::{mpsc, oneshot};
 
@@ -14,8 +14,9 @@ use crate::server::command::{
     CommandResponse, ExecutionRequest, KeyRange, SyncRequest, SyncResponse,
 };
 use crate::storage::authstore::backend::AuthStoreBackend;
+use crate::storage::engine::StorageEngine;
 
-use super::backend::ROOT_ROLE;
+use super::backend::{ROOT_ROLE, ROOT_USER};
 
 /// Default channel size
 const CHANNEL_SIZE: usize = 128;
@@ -39,10 +40,11 @@ impl AuthStore {
     pub(crate) fn new(
         key_pair: Option<(EncodingKey, DecodingKey)>,
         header_gen: Arc<HeaderGenerator>,
+        engine: Arc<dyn StorageEngine>,
     ) -> Self {
         let (exec_tx, mut exec_rx) = mpsc::channel(CHANNEL_SIZE);
         let (sync_tx, mut sync_rx) = mpsc::channel(CHANNEL_SIZE);
-        let inner = Arc::new(AuthStoreBackend::new(key_pair, header_gen));
+        let inner = Arc::new(AuthStoreBackend::new(key_pair, header_gen, engine));
 
         let inner_clone = Arc::clone(&inner);
         let _handle = tokio::spawn(async move {
@@ -83,9 +85,12 @@ impl AuthStore {
         receiver
     }
 
-    /// Send sync request to Auth store
-    pub(crate) async fn send_sync(&self, propose_id: ProposeId) -> oneshot::Receiver<SyncResponse> {
-        let (req, receiver) = SyncRequest::new(propose_id);
+    /// Send sync request of a batch of commands to Auth store
+    pub(crate) async fn send_sync(
+        &self,
+        cmds: Vec<(ProposeId, LogIndex)>,
+    ) -> oneshot::Receiver<Vec<SyncResponse>> {
+        let (req, receiver) = SyncRequest::new(cmds);
         assert!(
             self.sync_tx.send(req).await.is_ok(),
             "Command receiver dropped"
@@ -98,6 +103,15 @@ impl AuthStore {
         self.inner.revision()
     }
 
+    /// Assign a token of the root user to the requests proposed by the server itself, `None`
+    /// if auth is not enabled
+    pub(crate) fn assign_root_token(&self) -> Result<Option<String>, ExecuteError> {
+        if !self.inner.is_enabled() {
+            return Ok(None);
+        }
+        self.inner.assign(ROOT_USER).map(Some)
+    }
+
     /// Check password
     pub(crate) fn check_password(
         &self,
@@ -143,6 +157,8 @@ impl AuthStore {
             RequestWrapper::TxnRequest(ref txn_req) => {
                 self.check_txn_permission(&username, txn_req)?;
             }
+            // any authenticated user can grant and revoke leases
+            RequestWrapper::LeaseGrantRequest(_) | RequestWrapper::LeaseRevokeRequest(_) => {}
             RequestWrapper::AuthUserGetRequest(ref user_get_req) => {
                 self.check_admin_permission(&username).map_or_else(
                     |e| {
@@ -286,7 +302,11 @@ impl AuthStore {
 
 #[cfg(test)]
 mod test {
-    use std::{collections::HashMap, error::Error};
+    use std::{
+        collections::HashMap,
+        error::Error,
+        sync::atomic::{AtomicU64, Ordering},
+    };
 
     use crate::{
         rpc::{
@@ -294,7 +314,10 @@ mod test {
             AuthRoleRevokePermissionRequest, AuthUserAddRequest, AuthUserDeleteRequest,
             AuthUserGrantRoleRequest, Permission,
         },
-        storage::authstore::perms::{PermissionCache, UserPermissions},
+        storage::{
+            authstore::perms::{PermissionCache, UserPermissions},
+            engine::MemoryEngine,
+        },
     };
 
     use super::*;
@@ -396,7 +419,7 @@ mod test {
     async fn init_auth_store() -> AuthStore {
         let key_pair = test_key_pair();
         let header_gen = Arc::new(HeaderGenerator::new(0, 0));
-        let store = AuthStore::new(key_pair, header_gen);
+        let store = AuthStore::new(key_pair, header_gen, Arc::new(MemoryEngine::new()));
 
         let req1 = RequestWithToken::new(
             AuthRoleAddRequest {
@@ -456,11 +479,14 @@ mod test {
         store: &AuthStore,
         req: RequestWithToken,
     ) -> Result<(CommandResponse, SyncResponse), Box<dyn Error>> {
+        // every request is synced at a new log index
+        static INDEX: AtomicU64 = AtomicU64::new(1);
         let id = ProposeId::new("test-id".to_owned());
         let exe_receiver = store.send_req(id.clone(), req).await;
         let cmd_res = exe_receiver.await??;
-        let sync_receiver = store.send_sync(id).await;
-        let sync_res = sync_receiver.await?;
+        let index = INDEX.fetch_add(1, Ordering::Relaxed);
+        let sync_receiver = store.send_sync(vec![(id, index)]).await;
+        let sync_res = sync_receiver.await?.remove(0);
         Ok((cmd_res, sync_res))
     }
 
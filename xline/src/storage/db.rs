@@ -1,50 +1,282 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Arc};
 
-//use clippy_utilities::OverflowArithmetic;
+use clippy_utilities::{Cast, OverflowArithmetic};
+use curp::{cmd::ProposeId, LogIndex};
 use parking_lot::Mutex;
+use prost::Message;
 
-use super::revision::Revision;
+use super::{
+    engine::{StorageEngine, WriteOp},
+    revision::Revision,
+};
 use crate::rpc::KeyValue;
 
-/// Database to store revision to kv mapping
+/// Table of the kv store
+pub(crate) const KV_TABLE: &str = "kv";
+
+/// Table of the auth store
+pub(crate) const AUTH_TABLE: &str = "auth";
+
+/// Table of the leases
+pub(crate) const LEASE_TABLE: &str = "lease";
+
+/// Table of the cluster members
+pub(crate) const MEMBER_TABLE: &str = "member";
+
+/// Table of the metadata of the stores, the compacted revisions are keyed by the tables of
+/// the stores
+pub(crate) const META_TABLE: &str = "meta";
+
+/// Key of the applied index in `META_TABLE`, all commands at or before it have been synced
+const APPLIED_INDEX_KEY: &[u8] = b"applied_index";
+
+/// Prefix of the keys of the commands synced after the applied index in `META_TABLE`, the
+/// commands that don't conflict are synced out of order, so each of them is recorded until
+/// the applied index passes it
+const APPLIED_CMD_PREFIX: &[u8] = b"applied_cmd/";
+
+/// Writes of the command being synced that are not committed to the engine yet
+#[derive(Debug, Default)]
+struct PendingWrites {
+    /// Write operations in the order they are made
+    ops: Vec<WriteOp>,
+    /// Values put by the operations, they are read before the ones in the engine
+    values: HashMap<Vec<u8>, Vec<u8>>,
+}
+
+/// Database to store revision to kv mapping. The writes are buffered and committed to the
+/// engine in one batch by `flush`, so the commands synced together are persisted atomically
+/// with a single sync.
 #[derive(Debug)]
 pub(crate) struct DB {
-    /// internal storage of `DB`
-    storage: Mutex<HashMap<Revision, KeyValue>>,
+    /// Storage engine of `DB`
+    engine: Arc<dyn StorageEngine>,
+    /// Table of `DB` in the engine
+    table: &'static str,
+    /// Writes that are not flushed yet. The values are kept until the engine has applied
+    /// them, so the reads always see the writes either here or in the engine. The writes are
+    /// made by one task, so nothing is buffered while flushing.
+    pending: Mutex<PendingWrites>,
 }
 
 impl DB {
-    /// New `DB`
-    pub(crate) fn new() -> Self {
+    /// New `DB` that stores data in `table` of `engine`
+    pub(crate) fn new(engine: Arc<dyn StorageEngine>, table: &'static str) -> Self {
         Self {
-            storage: Mutex::new(HashMap::new()),
+            engine,
+            table,
+            pending: Mutex::new(PendingWrites::default()),
         }
     }
 
+    /// Buffer a record of the command `id` at log `index`, so that it's not synced again when
+    /// it's replayed once the record is flushed along with its writes
+    pub(crate) fn record_applied(&self, index: LogIndex, id: &ProposeId) {
+        self.pending.lock().ops.push(WriteOp::Put {
+            table: META_TABLE,
+            key: Self::applied_cmd_key(index, id),
+            value: vec![],
+        });
+    }
+
+    /// Commit the pending writes to the engine in one synced batch
+    pub(crate) fn flush(&self) {
+        let ops = std::mem::take(&mut self.pending.lock().ops);
+        self.engine
+            .write_batch(ops, true)
+            .unwrap_or_else(|e| panic!("Failed to write to storage: {e}"));
+        self.pending.lock().values.clear();
+    }
+
+    /// Buffer the write operations of the command being synced on the other tables, such as
+    /// the leases and the members, so that they are committed in the same batch by `flush`
+    pub(crate) fn buffer(&self, ops: impl IntoIterator<Item = WriteOp>) {
+        self.pending.lock().ops.extend(ops);
+    }
+
+    /// Get the values of `keys`, the pending ones are read first
+    fn get_multi(&self, keys: &[Vec<u8>]) -> Vec<Option<Vec<u8>>> {
+        let pending = self.pending.lock();
+        let values = self
+            .engine
+            .get_multi(self.table, keys)
+            .unwrap_or_else(|e| panic!("Failed to read from storage: {e}"));
+        keys.iter()
+            .zip(values)
+            .map(|(key, value)| pending.values.get(key).cloned().or(value))
+            .collect()
+    }
+
+    /// Buffer a put operation
+    fn put(&self, key: Vec<u8>, value: Vec<u8>) {
+        let mut pending = self.pending.lock();
+        let _prev = pending.values.insert(key.clone(), value.clone());
+        pending.ops.push(WriteOp::Put {
+            table: self.table,
+            key,
+            value,
+        });
+    }
+
+    /// Encode a `Revision` as a key in the engine, the keys are ordered by revision
+    fn encode_key(revision: Revision) -> Vec<u8> {
+        let mut key = Vec::with_capacity(16);
+        key.extend_from_slice(&revision.revision().cast::<u64>().to_be_bytes());
+        key.extend_from_slice(&revision.sub_revision().cast::<u64>().to_be_bytes());
+        key
+    }
+
+    /// Decode a key in the engine as a `Revision`
+    fn decode_key(key: &[u8]) -> Revision {
+        let part = |range: std::ops::Range<usize>| -> i64 {
+            key.get(range)
+                .and_then(|bytes| bytes.try_into().ok())
+                .map(u64::from_be_bytes)
+                .unwrap_or_else(|| panic!("Invalid revision key {key:?}"))
+                .cast()
+        };
+        Revision::new(part(0..8), part(8..16))
+    }
+
+    /// Decode a `KeyValue` stored in the engine
+    fn decode_value(value: &[u8]) -> KeyValue {
+        KeyValue::decode(value).unwrap_or_else(|e| panic!("Failed to decode KeyValue: {e}"))
+    }
+
     /// Insert a `KeyValue`
     pub(crate) fn insert(&self, revision: Revision, kv: KeyValue) -> Option<KeyValue> {
-        self.storage.lock().insert(revision, kv)
+        let key = Self::encode_key(revision);
+        let prev = self.get_multi(&[key.clone()]).pop().flatten();
+        self.put(key, kv.encode_to_vec());
+        prev.map(|v| Self::decode_value(&v))
     }
 
     /// Get a list of `KeyValue`
     pub(crate) fn get_values(&self, revisions: &[Revision]) -> Vec<KeyValue> {
-        let storage = self.storage.lock();
-        revisions
-            .iter()
-            .filter_map(|revision| storage.get(revision).cloned())
+        let keys: Vec<_> = revisions.iter().copied().map(Self::encode_key).collect();
+        self.get_multi(&keys)
+            .into_iter()
+            .flatten()
+            .map(|v| Self::decode_value(&v))
             .collect()
     }
 
+    /// Get all the committed `KeyValue`s with their `Revision`s in the order of revision
+    pub(crate) fn get_all(&self) -> Vec<(Revision, KeyValue)> {
+        self.engine
+            .range(self.table, &[], &[])
+            .unwrap_or_else(|e| panic!("Failed to read from storage: {e}"))
+            .into_iter()
+            .map(|(k, v)| (Self::decode_key(&k), Self::decode_value(&v)))
+            .collect()
+    }
+
+    /// Key of the record of the command `id` at log `index`, the records are ordered by index
+    fn applied_cmd_key(index: LogIndex, id: &ProposeId) -> Vec<u8> {
+        let mut key = APPLIED_CMD_PREFIX.to_vec();
+        key.extend_from_slice(&index.to_be_bytes());
+        key.extend_from_slice(id.as_str().as_bytes());
+        key
+    }
+
+    /// Get the applied index, 0 if it's never updated
+    fn applied_index(&self) -> LogIndex {
+        self.engine
+            .get(META_TABLE, APPLIED_INDEX_KEY)
+            .unwrap_or_else(|e| panic!("Failed to read from storage: {e}"))
+            .map_or(0, |value| {
+                value
+                    .try_into()
+                    .map(LogIndex::from_be_bytes)
+                    .unwrap_or_else(|v| panic!("Invalid applied index {v:?}"))
+            })
+    }
+
+    /// Check if the command `id` at log `index` has been synced, curp applies its persisted
+    /// log again from the beginning when the server restarts
+    pub(crate) fn is_applied(&self, index: LogIndex, id: &ProposeId) -> bool {
+        index <= self.applied_index()
+            || self
+                .engine
+                .get(META_TABLE, &Self::applied_cmd_key(index, id))
+                .unwrap_or_else(|e| panic!("Failed to read from storage: {e}"))
+                .is_some()
+    }
+
+    /// Advance the applied index to `index` when all the commands at or before it have been
+    /// synced, and drop the records of those commands. The records are shared by all `DB`s, so
+    /// it's only called on one of them.
+    pub(crate) fn update_applied_index(&self, index: LogIndex) {
+        if index <= self.applied_index() {
+            return;
+        }
+        let mut end = APPLIED_CMD_PREFIX.to_vec();
+        end.extend_from_slice(&index.overflow_add(1).to_be_bytes());
+        let mut ops: Vec<_> = self
+            .engine
+            .range(META_TABLE, APPLIED_CMD_PREFIX, &end)
+            .unwrap_or_else(|e| panic!("Failed to read from storage: {e}"))
+            .into_iter()
+            .map(|(key, _value)| WriteOp::Delete {
+                table: META_TABLE,
+                key,
+            })
+            .collect();
+        ops.push(WriteOp::Put {
+            table: META_TABLE,
+            key: APPLIED_INDEX_KEY.to_vec(),
+            value: index.to_be_bytes().to_vec(),
+        });
+        self.engine
+            .write_batch(ops, true)
+            .unwrap_or_else(|e| panic!("Failed to write to storage: {e}"));
+    }
+
+    /// Get the revision that `DB` has been compacted to, 0 if it's never compacted
+    pub(crate) fn compacted_revision(&self) -> i64 {
+        self.engine
+            .get(META_TABLE, self.table.as_bytes())
+            .unwrap_or_else(|e| panic!("Failed to read from storage: {e}"))
+            .map_or(0, |value| {
+                value
+                    .try_into()
+                    .map(i64::from_be_bytes)
+                    .unwrap_or_else(|v| panic!("Invalid compacted revision {v:?}"))
+            })
+    }
+
+    /// Remove the compacted `revisions` and record that `DB` has been compacted to `revision`
+    pub(crate) fn compact(&self, revisions: &[Revision], revision: i64) {
+        let mut pending = self.pending.lock();
+        for &rev in revisions {
+            let key = Self::encode_key(rev);
+            let _prev = pending.values.remove(&key);
+            pending.ops.push(WriteOp::Delete {
+                table: self.table,
+                key,
+            });
+        }
+        pending.ops.push(WriteOp::Put {
+            table: META_TABLE,
+            key: self.table.as_bytes().to_vec(),
+            value: revision.to_be_bytes().to_vec(),
+        });
+    }
+
     /// Mark deletion for keys
     /// TODO support don't return `prev_kvs`
     pub(crate) fn mark_deletions(&self, revisions: &[(Revision, Revision)]) -> Vec<KeyValue> {
-        let mut storage = self.storage.lock();
-        let prev_kvs: Vec<KeyValue> = revisions
+        let keys: Vec<_> = revisions
             .iter()
-            .map(|&(ref prev_rev, _)| {
-                storage
-                    .get(prev_rev)
-                    .cloned()
+            .map(|&(prev_rev, _)| Self::encode_key(prev_rev))
+            .collect();
+        let prev_kvs: Vec<KeyValue> = self
+            .get_multi(&keys)
+            .into_iter()
+            .zip(revisions.iter())
+            .map(|(value, &(ref prev_rev, _))| {
+                value
+                    .map(|v| Self::decode_value(&v))
                     .unwrap_or_else(|| panic!("Failed to get revision {:?} from DB", prev_rev))
             })
             .collect();
@@ -52,18 +284,108 @@ impl DB {
             prev_kvs.len() == revisions.len(),
             "Index doesn't match with DB"
         );
-        prev_kvs
-            .iter()
-            .zip(revisions.iter())
-            .for_each(|(kv, &(_, new_rev))| {
-                let del_kv = KeyValue {
-                    key: kv.key.clone(),
-                    mod_revision: new_rev.revision(),
-                    ..KeyValue::default()
-                };
-                let _prev_val = storage.insert(new_rev, del_kv);
-            });
+        for (kv, &(_, new_rev)) in prev_kvs.iter().zip(revisions.iter()) {
+            let del_kv = KeyValue {
+                key: kv.key.clone(),
+                mod_revision: new_rev.revision(),
+                ..KeyValue::default()
+            };
+            self.put(Self::encode_key(new_rev), del_kv.encode_to_vec());
+        }
 
         prev_kvs
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::storage::engine::MemoryEngine;
+
+    #[test]
+    fn values_should_be_ordered_by_revision() {
+        let db = DB::new(Arc::new(MemoryEngine::new()), KV_TABLE);
+        for (rev, sub) in [(10, 0), (2, 1), (2, 0)] {
+            let kv = KeyValue {
+                key: format!("{rev}-{sub}").into_bytes(),
+                mod_revision: rev,
+                ..KeyValue::default()
+            };
+            assert!(db.insert(Revision::new(rev, sub), kv).is_none());
+        }
+        db.flush();
+        let (revs, keys): (Vec<_>, Vec<_>) = db
+            .get_all()
+            .into_iter()
+            .map(|(rev, kv)| (rev, kv.key))
+            .unzip();
+        assert_eq!(
+            revs,
+            vec![
+                Revision::new(2, 0),
+                Revision::new(2, 1),
+                Revision::new(10, 0)
+            ]
+        );
+        assert_eq!(
+            keys,
+            vec![b"2-0".to_vec(), b"2-1".to_vec(), b"10-0".to_vec()]
+        );
+    }
+
+    #[test]
+    fn writes_should_be_visible_before_flush() -> Result<(), Box<dyn std::error::Error>> {
+        let engine: Arc<dyn StorageEngine> = Arc::new(MemoryEngine::new());
+        let db = DB::new(Arc::clone(&engine), KV_TABLE);
+        let kv = KeyValue {
+            key: b"foo".to_vec(),
+            mod_revision: 2,
+            version: 1,
+            ..KeyValue::default()
+        };
+        assert!(db.insert(Revision::new(2, 0), kv.clone()).is_none());
+        let prev = db.mark_deletions(&[(Revision::new(2, 0), Revision::new(3, 0))]);
+        assert_eq!(prev, vec![kv]);
+        assert!(engine.range(KV_TABLE, &[], &[])?.is_empty());
+        assert_eq!(db.get_values(&[Revision::new(3, 0)]).len(), 1);
+
+        db.record_applied(1, &ProposeId::new("id".to_owned()));
+        db.flush();
+        assert_eq!(engine.range(KV_TABLE, &[], &[])?.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn commands_synced_out_of_order_should_be_recorded() -> Result<(), Box<dyn std::error::Error>> {
+        let engine: Arc<dyn StorageEngine> = Arc::new(MemoryEngine::new());
+        let db = DB::new(Arc::clone(&engine), KV_TABLE);
+        let id = |index: u64| ProposeId::new(format!("id-{index}"));
+        db.record_applied(3, &id(3));
+        assert!(!db.is_applied(3, &id(3)));
+        db.flush();
+        assert!(db.is_applied(3, &id(3)));
+        assert!(!db.is_applied(2, &id(2)));
+        // the commands synced in a batch are recorded with one flush
+        db.record_applied(2, &id(2));
+        db.record_applied(5, &id(5));
+        db.flush();
+        assert!(db.is_applied(2, &id(2)));
+        assert!(!db.is_applied(4, &id(4)));
+
+        db.update_applied_index(4);
+        assert!(db.is_applied(1, &id(1)));
+        assert!(db.is_applied(4, &id(4)));
+        assert!(db.is_applied(5, &id(5)));
+        assert!(!db.is_applied(6, &id(6)));
+        // only the record after the applied index is kept
+        assert_eq!(
+            engine
+                .range(META_TABLE, APPLIED_CMD_PREFIX, b"applied_cmd0")?
+                .len(),
+            1
+        );
+        db.update_applied_index(1);
+        assert!(db.is_applied(4, &id(4)));
+        Ok(())
+    }
+}
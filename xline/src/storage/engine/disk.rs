@@ -0,0 +1,508 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread,
+};
+
+use clippy_utilities::{Cast, OverflowArithmetic};
+use parking_lot::{Mutex, RwLock};
+use tracing::warn;
+
+use super::{apply, scan, EngineError, Snapshot, StorageEngine, Tables, WriteOp};
+use crate::storage::db::{AUTH_TABLE, KV_TABLE, LEASE_TABLE, MEMBER_TABLE, META_TABLE};
+
+/// Name of the data file in the data directory
+const DATA_FILE: &str = "data.log";
+
+/// Name of the temporary file used by compaction
+const COMPACT_FILE: &str = "data.log.compact";
+
+/// Tag of a put operation in the data file
+const PUT_TAG: u8 = 1;
+
+/// Tag of a delete operation in the data file
+const DELETE_TAG: u8 = 2;
+
+/// Size of the length prefix of a batch or a field
+const LEN_SIZE: usize = 4;
+
+/// The data file is not compacted until it's larger than this size
+const MIN_COMPACT_SIZE: u64 = 64 * 1024 * 1024;
+
+/// The data file is compacted when it grows to this many times of the size of the live data
+/// written by the last compaction, so the rewrites are amortized over the appended batches
+const COMPACT_RATIO: u64 = 2;
+
+/// Writer of the data file
+#[derive(Debug)]
+struct Writer {
+    /// The data file opened for appending
+    file: File,
+    /// Size of the data file
+    file_size: u64,
+    /// Size of the live data written by the last compaction
+    compacted_size: u64,
+    /// Number of the compactions started, a background compaction is dropped if another one
+    /// has started after it
+    compactions: u64,
+    /// Batches appended since the tables were copied by the running background compaction,
+    /// they are appended to the compacted file before it replaces the data file
+    tail: Option<Vec<u8>>,
+}
+
+/// Data files of a `DiskEngine`, they are shared with the background compactions
+#[derive(Debug)]
+struct Files {
+    /// Data directory
+    dir: PathBuf,
+    /// Writer of the data file, the lock serializes the writes to the file
+    writer: Mutex<Writer>,
+    /// Held by a compaction while it rewrites the data file
+    compaction: Mutex<()>,
+}
+
+impl Files {
+    /// Write all the live keys in `tables` to the temporary file, return the file and the
+    /// size written
+    fn write_tables(&self, tables: &Tables) -> Result<(File, u64), EngineError> {
+        let mut tmp = File::create(self.dir.join(COMPACT_FILE))?;
+        let mut size: u64 = 0;
+        for (table, kvs) in tables {
+            for (key, value) in kvs {
+                let batch = frame(encode_put(table, key, value));
+                tmp.write_all(&batch)?;
+                size = size.overflow_add(batch.len().cast());
+            }
+        }
+        Ok((tmp, size))
+    }
+
+    /// Replace the data file with the temporary file of `size` bytes of live data, `tail` is
+    /// appended to it first. The lock of `writer` is held.
+    fn install(
+        &self,
+        writer: &mut Writer,
+        mut tmp: File,
+        size: u64,
+        tail: &[u8],
+    ) -> Result<(), EngineError> {
+        tmp.write_all(tail)?;
+        tmp.sync_all()?;
+        fs::rename(self.dir.join(COMPACT_FILE), self.dir.join(DATA_FILE))?;
+        File::open(&self.dir)?.sync_all()?;
+        writer.file = OpenOptions::new()
+            .append(true)
+            .open(self.dir.join(DATA_FILE))?;
+        writer.file_size = size.overflow_add(tail.len().cast());
+        writer.compacted_size = size;
+        Ok(())
+    }
+
+    /// Rewrite the data file with the `tables` copied by the background compaction numbered
+    /// `compaction`, it's dropped if another compaction has started after it
+    fn compact_copied(&self, compaction: u64, tables: &Tables) -> Result<(), EngineError> {
+        let _compaction = self.compaction.lock();
+        if self.writer.lock().compactions != compaction {
+            return Ok(());
+        }
+        let written = self.write_tables(tables);
+        let mut writer = self.writer.lock();
+        if writer.compactions != compaction {
+            return Ok(());
+        }
+        let tail = writer.tail.take().unwrap_or_default();
+        let installed = written.and_then(|(tmp, size)| self.install(&mut writer, tmp, size, &tail));
+        if installed.is_err() {
+            // not compacted again until the data file doubles
+            writer.compacted_size = writer.file_size;
+        }
+        installed
+    }
+}
+
+/// A storage engine that appends every batch to a data file in a directory and keeps the
+/// tables in memory. The data file is replayed when the engine is opened, and it's rewritten
+/// with only the live keys in the background when it has grown too large, so its size is
+/// bounded by the live data rather than the history of writes.
+///
+/// All the tables are held in memory, so the engine is meant for data that fits in memory:
+/// the memory used grows with the live data, and a compaction takes another copy of the
+/// tables, plus the batches appended while it runs, until the data file is replaced.
+#[derive(Debug)]
+pub(crate) struct DiskEngine {
+    /// The data file is compacted when it's larger than this size
+    min_compact_size: u64,
+    /// Tables replayed from the data file, a batch is applied after it's appended to the file
+    /// so the reads are not blocked by the writes to the file
+    tables: RwLock<Tables>,
+    /// Data files
+    files: Arc<Files>,
+}
+
+impl DiskEngine {
+    /// Open the engine in `dir`, the directory is created if it doesn't exist
+    ///
+    /// # Errors
+    ///   `EngineError::IoError` if the data file cannot be read
+    ///   `EngineError::Corruption` if a complete batch in the data file cannot be decoded
+    pub(crate) fn open(dir: impl AsRef<Path>) -> Result<Self, EngineError> {
+        Self::open_with_compact_size(dir, MIN_COMPACT_SIZE)
+    }
+
+    /// Open the engine in `dir`, the data file is compacted when it's larger than
+    /// `min_compact_size`
+    fn open_with_compact_size(
+        dir: impl AsRef<Path>,
+        min_compact_size: u64,
+    ) -> Result<Self, EngineError> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let path = dir.join(DATA_FILE);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&path)?;
+        let mut data = vec![];
+        let _len = file.read_to_end(&mut data)?;
+
+        let mut tables = Tables::new();
+        let mut offset = 0;
+        while let Some(batch) = get_field(&data, offset) {
+            apply(&mut tables, decode_batch(batch)?);
+            offset = offset.overflow_add(LEN_SIZE).overflow_add(batch.len());
+        }
+        if offset < data.len() {
+            // the last batch was torn by a crash, it was never acknowledged
+            file.set_len(offset.cast())?;
+            file.sync_all()?;
+        }
+
+        let file_size = offset.cast();
+        Ok(Self {
+            min_compact_size,
+            tables: RwLock::new(tables),
+            files: Arc::new(Files {
+                dir,
+                writer: Mutex::new(Writer {
+                    file,
+                    file_size,
+                    compacted_size: file_size,
+                    compactions: 0,
+                    tail: None,
+                }),
+                compaction: Mutex::new(()),
+            }),
+        })
+    }
+
+    /// Rewrite the data file with only the live keys, the running background compaction is
+    /// waited for and dropped
+    fn compact(&self) -> Result<(), EngineError> {
+        let _compaction = self.files.compaction.lock();
+        let mut writer = self.files.writer.lock();
+        writer.compactions = writer.compactions.overflow_add(1);
+        writer.tail = None;
+        let (tmp, size) = self.files.write_tables(&self.tables.read())?;
+        self.files.install(&mut writer, tmp, size, &[])
+    }
+
+    /// Start compacting the data file in the background with a copy of the tables, the lock
+    /// of `writer` is held so that the batches after the copy are kept in the tail
+    fn compact_in_background(&self, writer: &mut Writer) {
+        writer.compactions = writer.compactions.overflow_add(1);
+        writer.tail = Some(vec![]);
+        let compaction = writer.compactions;
+        let tables = self.tables.read().clone();
+        let files = Arc::clone(&self.files);
+        let spawned = thread::Builder::new()
+            .name("xline-compact".to_owned())
+            .spawn(move || {
+                if let Err(e) = files.compact_copied(compaction, &tables) {
+                    warn!("failed to compact the data file: {e}");
+                }
+            });
+        if let Err(e) = spawned {
+            warn!("failed to start compacting the data file: {e}");
+            writer.tail = None;
+            writer.compacted_size = writer.file_size;
+        }
+    }
+
+    /// Check if the data file has grown large enough to be compacted
+    fn should_compact(&self, writer: &Writer) -> bool {
+        writer.tail.is_none()
+            && writer.file_size >= self.min_compact_size
+            && writer.file_size >= writer.compacted_size.overflow_mul(COMPACT_RATIO)
+    }
+}
+
+impl StorageEngine for DiskEngine {
+    fn get(&self, table: &str, key: &[u8]) -> Result<Option<Vec<u8>>, EngineError> {
+        Ok(self
+            .tables
+            .read()
+            .get(table)
+            .and_then(|t| t.get(key).cloned()))
+    }
+
+    fn get_multi(
+        &self,
+        table: &str,
+        keys: &[Vec<u8>],
+    ) -> Result<Vec<Option<Vec<u8>>>, EngineError> {
+        let tables = self.tables.read();
+        let t = tables.get(table);
+        Ok(keys
+            .iter()
+            .map(|key| t.and_then(|t| t.get(key).cloned()))
+            .collect())
+    }
+
+    fn range(
+        &self,
+        table: &str,
+        start: &[u8],
+        end: &[u8],
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, EngineError> {
+        Ok(self
+            .tables
+            .read()
+            .get(table)
+            .map(|t| scan(t, start, end))
+            .unwrap_or_default())
+    }
+
+    fn write_batch(&self, ops: Vec<WriteOp>, sync: bool) -> Result<(), EngineError> {
+        if ops.is_empty() {
+            return Ok(());
+        }
+        let mut batch = vec![];
+        for op in &ops {
+            match *op {
+                WriteOp::Put {
+                    table,
+                    ref key,
+                    ref value,
+                } => batch.extend(encode_put(table, key, value)),
+                WriteOp::Delete { table, ref key } => {
+                    batch.push(DELETE_TAG);
+                    put_field(&mut batch, table.as_bytes());
+                    put_field(&mut batch, key);
+                }
+            }
+        }
+        let batch = frame(batch);
+        let mut writer = self.files.writer.lock();
+        writer.file.write_all(&batch)?;
+        if sync {
+            writer.file.sync_data()?;
+        }
+        writer.file_size = writer.file_size.overflow_add(batch.len().cast());
+        if let Some(ref mut tail) = writer.tail {
+            tail.extend_from_slice(&batch);
+        }
+        // applied with the lock of `writer` held, so the tables change in the order of the file
+        apply(&mut self.tables.write(), ops);
+        if self.should_compact(&writer) {
+            self.compact_in_background(&mut writer);
+        }
+        Ok(())
+    }
+
+    fn snapshot(&self) -> Result<Snapshot, EngineError> {
+        Ok(Snapshot {
+            tables: self.tables.read().clone(),
+        })
+    }
+
+    fn apply_snapshot(&self, snapshot: Snapshot) -> Result<(), EngineError> {
+        {
+            let _writer = self.files.writer.lock();
+            *self.tables.write() = snapshot.tables;
+        }
+        self.compact()
+    }
+}
+
+/// Append a length-prefixed field to `buf`
+fn put_field(buf: &mut Vec<u8>, field: &[u8]) {
+    buf.extend_from_slice(&field.len().cast::<u32>().to_le_bytes());
+    buf.extend_from_slice(field);
+}
+
+/// Encode a put operation
+fn encode_put(table: &str, key: &[u8], value: &[u8]) -> Vec<u8> {
+    let mut buf = vec![PUT_TAG];
+    put_field(&mut buf, table.as_bytes());
+    put_field(&mut buf, key);
+    put_field(&mut buf, value);
+    buf
+}
+
+/// Prefix a batch with its length
+fn frame(batch: Vec<u8>) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(LEN_SIZE.overflow_add(batch.len()));
+    put_field(&mut buf, &batch);
+    buf
+}
+
+/// Read a length-prefixed field at `offset`, `None` if the data is too short
+fn get_field(data: &[u8], offset: usize) -> Option<&[u8]> {
+    let len_end = offset.checked_add(LEN_SIZE)?;
+    let len_bytes: [u8; LEN_SIZE] = data.get(offset..len_end)?.try_into().ok()?;
+    let end = len_end.checked_add(u32::from_le_bytes(len_bytes).cast())?;
+    data.get(len_end..end)
+}
+
+/// Decode the operations of a batch
+fn decode_batch(batch: &[u8]) -> Result<Vec<WriteOp>, EngineError> {
+    let corrupted = || EngineError::Corruption("failed to decode a write batch".to_owned());
+    let mut ops = vec![];
+    let mut offset = 0;
+    while offset < batch.len() {
+        let tag = batch.get(offset).copied().ok_or_else(corrupted)?;
+        offset = offset.overflow_add(1);
+        let table_bytes = get_field(batch, offset).ok_or_else(corrupted)?;
+        offset = offset
+            .overflow_add(LEN_SIZE)
+            .overflow_add(table_bytes.len());
+        let table = static_table(table_bytes).ok_or_else(corrupted)?;
+        let key = get_field(batch, offset).ok_or_else(corrupted)?.to_vec();
+        offset = offset.overflow_add(LEN_SIZE).overflow_add(key.len());
+        match tag {
+            PUT_TAG => {
+                let value = get_field(batch, offset).ok_or_else(corrupted)?.to_vec();
+                offset = offset.overflow_add(LEN_SIZE).overflow_add(value.len());
+                ops.push(WriteOp::Put { table, key, value });
+            }
+            DELETE_TAG => ops.push(WriteOp::Delete { table, key }),
+            _ => return Err(corrupted()),
+        }
+    }
+    Ok(ops)
+}
+
+/// Names of the tables that can be stored in a `DiskEngine`
+const TABLES: [&str; 5] = [KV_TABLE, AUTH_TABLE, LEASE_TABLE, MEMBER_TABLE, META_TABLE];
+
+/// Get the static name of a table read from the data file
+fn static_table(name: &[u8]) -> Option<&'static str> {
+    TABLES.into_iter().find(|table| table.as_bytes() == name)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("xline-engine-{}", uuid::Uuid::new_v4()))
+    }
+
+    fn wait_for_compaction(engine: &DiskEngine) {
+        while engine.files.writer.lock().tail.is_some() {
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn reopen_should_replay_batches() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = temp_dir();
+        {
+            let engine = DiskEngine::open(&dir)?;
+            engine.write_batch(
+                vec![
+                    WriteOp::Put {
+                        table: KV_TABLE,
+                        key: b"a".to_vec(),
+                        value: b"1".to_vec(),
+                    },
+                    WriteOp::Put {
+                        table: KV_TABLE,
+                        key: b"b".to_vec(),
+                        value: b"2".to_vec(),
+                    },
+                ],
+                true,
+            )?;
+            engine.write_batch(
+                vec![WriteOp::Delete {
+                    table: KV_TABLE,
+                    key: b"a".to_vec(),
+                }],
+                true,
+            )?;
+        }
+        // a torn batch left by a crash
+        let mut file = OpenOptions::new().append(true).open(dir.join(DATA_FILE))?;
+        file.write_all(&[100, 0, 0, 0, PUT_TAG])?;
+
+        let replayed = DiskEngine::open(&dir)?;
+        assert_eq!(replayed.get(KV_TABLE, b"a")?, None);
+        assert_eq!(replayed.get(KV_TABLE, b"b")?, Some(b"2".to_vec()));
+        replayed.compact()?;
+        replayed.write_batch(
+            vec![WriteOp::Put {
+                table: KV_TABLE,
+                key: b"c".to_vec(),
+                value: b"3".to_vec(),
+            }],
+            true,
+        )?;
+
+        let compacted = DiskEngine::open(&dir)?;
+        assert_eq!(
+            compacted.range(KV_TABLE, b"", b"")?,
+            vec![
+                (b"b".to_vec(), b"2".to_vec()),
+                (b"c".to_vec(), b"3".to_vec())
+            ]
+        );
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn data_file_should_be_compacted_when_it_grows() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = temp_dir();
+        let engine = DiskEngine::open_with_compact_size(&dir, 64 * 1024)?;
+        for i in 0..1000_u32 {
+            // the batches written while a compaction runs are kept, so it's waited for every
+            // 32 KiB to bound them
+            if i % 32 == 0 {
+                wait_for_compaction(&engine);
+            }
+            engine.write_batch(
+                vec![WriteOp::Put {
+                    table: KV_TABLE,
+                    key: b"a".to_vec(),
+                    value: vec![0; 1024],
+                }],
+                false,
+            )?;
+            engine.write_batch(
+                vec![WriteOp::Put {
+                    table: KV_TABLE,
+                    key: b"b".to_vec(),
+                    value: i.to_le_bytes().to_vec(),
+                }],
+                false,
+            )?;
+        }
+        wait_for_compaction(&engine);
+        assert!(engine.files.writer.lock().compactions > 0);
+        // 1000 overwrites of a 1 KiB value never stay in the data file
+        assert!(fs::metadata(dir.join(DATA_FILE))?.len() < 128 * 1024);
+
+        let reopened = DiskEngine::open(&dir)?;
+        assert_eq!(reopened.get(KV_TABLE, b"a")?, Some(vec![0; 1024]));
+        assert_eq!(
+            reopened.get(KV_TABLE, b"b")?,
+            Some(999_u32.to_le_bytes().to_vec())
+        );
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
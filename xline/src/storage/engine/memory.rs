@@ -0,0 +1,119 @@
+use parking_lot::RwLock;
+
+use super::{apply, scan, EngineError, Snapshot, StorageEngine, Tables, WriteOp};
+
+/// A storage engine that keeps all the tables in memory
+#[derive(Debug, Default)]
+pub(crate) struct MemoryEngine {
+    /// The tables
+    tables: RwLock<Tables>,
+}
+
+impl MemoryEngine {
+    /// New empty `MemoryEngine`
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl StorageEngine for MemoryEngine {
+    fn get(&self, table: &str, key: &[u8]) -> Result<Option<Vec<u8>>, EngineError> {
+        Ok(self
+            .tables
+            .read()
+            .get(table)
+            .and_then(|t| t.get(key).cloned()))
+    }
+
+    fn get_multi(
+        &self,
+        table: &str,
+        keys: &[Vec<u8>],
+    ) -> Result<Vec<Option<Vec<u8>>>, EngineError> {
+        let tables = self.tables.read();
+        let t = tables.get(table);
+        Ok(keys
+            .iter()
+            .map(|key| t.and_then(|t| t.get(key).cloned()))
+            .collect())
+    }
+
+    fn range(
+        &self,
+        table: &str,
+        start: &[u8],
+        end: &[u8],
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, EngineError> {
+        Ok(self
+            .tables
+            .read()
+            .get(table)
+            .map(|t| scan(t, start, end))
+            .unwrap_or_default())
+    }
+
+    fn write_batch(&self, ops: Vec<WriteOp>, _sync: bool) -> Result<(), EngineError> {
+        apply(&mut self.tables.write(), ops);
+        Ok(())
+    }
+
+    fn snapshot(&self) -> Result<Snapshot, EngineError> {
+        Ok(Snapshot {
+            tables: self.tables.read().clone(),
+        })
+    }
+
+    fn apply_snapshot(&self, snapshot: Snapshot) -> Result<(), EngineError> {
+        *self.tables.write() = snapshot.tables;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::storage::db::{AUTH_TABLE, KV_TABLE};
+
+    #[test]
+    fn snapshot_should_not_see_later_writes() -> Result<(), EngineError> {
+        let engine = MemoryEngine::new();
+        let put = |table, key: &[u8], value: &[u8]| WriteOp::Put {
+            table,
+            key: key.to_vec(),
+            value: value.to_vec(),
+        };
+        engine.write_batch(
+            vec![
+                put(KV_TABLE, b"a", b"1"),
+                put(KV_TABLE, b"b", b"2"),
+                put(AUTH_TABLE, b"a", b"3"),
+            ],
+            false,
+        )?;
+        let snapshot = engine.snapshot()?;
+        engine.write_batch(
+            vec![
+                put(KV_TABLE, b"c", b"4"),
+                WriteOp::Delete {
+                    table: KV_TABLE,
+                    key: b"a".to_vec(),
+                },
+            ],
+            false,
+        )?;
+        assert_eq!(snapshot.get(KV_TABLE, b"a"), Some(b"1".to_vec()));
+        assert_eq!(snapshot.range(KV_TABLE, b"b", b"").len(), 1);
+        assert_eq!(
+            engine.range(KV_TABLE, b"a", b"c")?,
+            vec![(b"b".to_vec(), b"2".to_vec())]
+        );
+
+        engine.apply_snapshot(snapshot)?;
+        assert_eq!(
+            engine.get_multi(KV_TABLE, &[b"a".to_vec(), b"c".to_vec()])?,
+            vec![Some(b"1".to_vec()), None]
+        );
+        assert_eq!(engine.get(AUTH_TABLE, b"a")?, Some(b"3".to_vec()));
+        Ok(())
+    }
+}
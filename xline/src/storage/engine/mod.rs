@@ -0,0 +1,193 @@
+use std::{collections::BTreeMap, fmt::Debug, io, ops::Bound, path::PathBuf, sync::Arc};
+
+use thiserror::Error;
+
+/// The in-memory engine
+mod memory;
+
+/// The embedded on-disk engine
+mod disk;
+
+pub(crate) use self::{disk::DiskEngine, memory::MemoryEngine};
+
+/// Error met by a storage engine
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub(crate) enum EngineError {
+    /// Met I/O error while reading or writing the data
+    #[error("storage io error: {0}")]
+    IoError(#[from] io::Error),
+    /// The data on disk is corrupted
+    #[error("storage is corrupted: {0}")]
+    Corruption(String),
+}
+
+/// A write operation in a batch
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum WriteOp {
+    /// Put `value` to `key` of `table`
+    Put {
+        /// Table of the key
+        table: &'static str,
+        /// Key to put
+        key: Vec<u8>,
+        /// Value to put
+        value: Vec<u8>,
+    },
+    /// Delete `key` of `table`
+    Delete {
+        /// Table of the key
+        table: &'static str,
+        /// Key to delete
+        key: Vec<u8>,
+    },
+}
+
+/// Tables of a storage engine, they are ordered by key so that they can be scanned by range
+pub(crate) type Tables = BTreeMap<String, BTreeMap<Vec<u8>, Vec<u8>>>;
+
+/// Scan the keys in `[start, end)` of a table, an empty `end` means no upper bound
+fn scan(table: &BTreeMap<Vec<u8>, Vec<u8>>, start: &[u8], end: &[u8]) -> Vec<(Vec<u8>, Vec<u8>)> {
+    let upper = if end.is_empty() {
+        Bound::Unbounded
+    } else {
+        Bound::Excluded(end)
+    };
+    if !end.is_empty() && start >= end {
+        return vec![];
+    }
+    table
+        .range::<[u8], _>((Bound::Included(start), upper))
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect()
+}
+
+/// Apply a batch of write operations to the tables
+fn apply(tables: &mut Tables, ops: Vec<WriteOp>) {
+    for op in ops {
+        match op {
+            WriteOp::Put { table, key, value } => {
+                let _prev = tables
+                    .entry(table.to_owned())
+                    .or_default()
+                    .insert(key, value);
+            }
+            WriteOp::Delete { table, key } => {
+                if let Some(t) = tables.get_mut(table) {
+                    let _prev = t.remove(&key);
+                }
+            }
+        }
+    }
+}
+
+/// A point-in-time view of all the tables of a storage engine
+#[allow(dead_code)] // Will be used to send snapshots to lagging members
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Snapshot {
+    /// Tables when the snapshot is taken
+    tables: Tables,
+}
+
+#[allow(dead_code)] // Will be used to send snapshots to lagging members
+impl Snapshot {
+    /// Get the value of `key` in `table`
+    pub(crate) fn get(&self, table: &str, key: &[u8]) -> Option<Vec<u8>> {
+        self.tables.get(table).and_then(|t| t.get(key).cloned())
+    }
+
+    /// Scan the keys in `[start, end)` of `table`, an empty `end` means no upper bound
+    pub(crate) fn range(&self, table: &str, start: &[u8], end: &[u8]) -> Vec<(Vec<u8>, Vec<u8>)> {
+        self.tables
+            .get(table)
+            .map(|t| scan(t, start, end))
+            .unwrap_or_default()
+    }
+}
+
+/// A storage engine that stores ordered key-value tables
+pub(crate) trait StorageEngine: Debug + Send + Sync {
+    /// Get the value of `key` in `table`
+    ///
+    /// # Errors
+    ///   `EngineError` if the value cannot be read
+    fn get(&self, table: &str, key: &[u8]) -> Result<Option<Vec<u8>>, EngineError>;
+
+    /// Get the values of `keys` in `table`, in the same order as `keys`
+    ///
+    /// # Errors
+    ///   `EngineError` if the values cannot be read
+    fn get_multi(&self, table: &str, keys: &[Vec<u8>])
+        -> Result<Vec<Option<Vec<u8>>>, EngineError>;
+
+    /// Scan the keys in `[start, end)` of `table` in order, an empty `end` means no upper bound
+    ///
+    /// # Errors
+    ///   `EngineError` if the values cannot be read
+    fn range(
+        &self,
+        table: &str,
+        start: &[u8],
+        end: &[u8],
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, EngineError>;
+
+    /// Apply a batch of write operations atomically, the batch is durable when it returns if
+    /// `sync` is true
+    ///
+    /// # Errors
+    ///   `EngineError` if the batch cannot be written
+    fn write_batch(&self, ops: Vec<WriteOp>, sync: bool) -> Result<(), EngineError>;
+
+    /// Take a snapshot of all the tables
+    ///
+    /// # Errors
+    ///   `EngineError` if the snapshot cannot be taken
+    #[allow(dead_code)] // Will be used to send snapshots to lagging members
+    fn snapshot(&self) -> Result<Snapshot, EngineError>;
+
+    /// Replace all the tables with the ones in `snapshot`
+    ///
+    /// # Errors
+    ///   `EngineError` if the snapshot cannot be applied
+    #[allow(dead_code)] // Will be used to install snapshots from the leader
+    fn apply_snapshot(&self, snapshot: Snapshot) -> Result<(), EngineError>;
+}
+
+/// Storage used by an xline server
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum StorageConfig {
+    /// Keep all data in memory, it's lost when the server stops
+    Memory,
+    /// Persist data in the directory
+    Disk(PathBuf),
+}
+
+impl Default for StorageConfig {
+    #[inline]
+    fn default() -> Self {
+        Self::Memory
+    }
+}
+
+impl StorageConfig {
+    /// Open the storage engine
+    ///
+    /// # Errors
+    ///   `EngineError` if the data on disk cannot be read
+    pub(crate) fn open(&self) -> Result<Arc<dyn StorageEngine>, EngineError> {
+        match *self {
+            StorageConfig::Memory => Ok(Arc::new(MemoryEngine::new())),
+            StorageConfig::Disk(ref dir) => Ok(Arc::new(DiskEngine::open(dir)?)),
+        }
+    }
+
+    /// Dir where curp persists its log, the term and the vote, nothing is persisted if the
+    /// data is kept in memory
+    pub(crate) fn curp_dir(&self) -> Option<PathBuf> {
+        match *self {
+            StorageConfig::Memory => None,
+            StorageConfig::Disk(ref dir) => Some(dir.join("curp")),
+        }
+    }
+}
@@ -0,0 +1,171 @@
+use curp::error::{ExecuteError, ProposeError};
+
+use super::{
+    kvstore::{COMPACTED_ERROR, FUTURE_REVISION_ERROR},
+    leasestore::{LEASE_EXIST_ERROR, LEASE_NOT_FOUND_ERROR, LEASE_TTL_TOO_LARGE_ERROR},
+    membertable::{
+        MEMBER_BAD_URLS_ERROR, MEMBER_EXIST_ERROR, MEMBER_NOT_FOUND_ERROR,
+        MEMBER_NOT_LEARNER_ERROR, PEER_URL_EXIST_ERROR,
+    },
+};
+
+/// Kinds of the errors that a command is rejected with. The kind is sent to the client along
+/// with the error, so the servers can map it to a status code without parsing the message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ExecuteErrorKind {
+    /// The required revision has been compacted
+    Compacted,
+    /// The required revision is greater than the current revision
+    FutureRevision,
+    /// The lease doesn't exist
+    LeaseNotFound,
+    /// The lease to grant already exists
+    LeaseExist,
+    /// The ttl of the lease to grant is too large
+    LeaseTtlTooLarge,
+    /// The member doesn't exist
+    MemberNotFound,
+    /// The id of the member to add already exists
+    MemberExist,
+    /// A peer URL of the member to add or update is used by another member
+    PeerUrlExist,
+    /// The member to promote is not a learner
+    MemberNotLearner,
+    /// The peer URLs of the member to add or update are invalid
+    MemberBadUrls,
+}
+
+impl ExecuteErrorKind {
+    /// The code of the kind sent on the wire, it must never change
+    fn code(self) -> u32 {
+        match self {
+            ExecuteErrorKind::Compacted => 1,
+            ExecuteErrorKind::FutureRevision => 2,
+            ExecuteErrorKind::LeaseNotFound => 3,
+            ExecuteErrorKind::LeaseExist => 4,
+            ExecuteErrorKind::LeaseTtlTooLarge => 5,
+            ExecuteErrorKind::MemberNotFound => 6,
+            ExecuteErrorKind::MemberExist => 7,
+            ExecuteErrorKind::PeerUrlExist => 8,
+            ExecuteErrorKind::MemberNotLearner => 9,
+            ExecuteErrorKind::MemberBadUrls => 10,
+        }
+    }
+
+    /// Get the kind from its code
+    fn from_code(code: u32) -> Option<Self> {
+        match code {
+            1 => Some(ExecuteErrorKind::Compacted),
+            2 => Some(ExecuteErrorKind::FutureRevision),
+            3 => Some(ExecuteErrorKind::LeaseNotFound),
+            4 => Some(ExecuteErrorKind::LeaseExist),
+            5 => Some(ExecuteErrorKind::LeaseTtlTooLarge),
+            6 => Some(ExecuteErrorKind::MemberNotFound),
+            7 => Some(ExecuteErrorKind::MemberExist),
+            8 => Some(ExecuteErrorKind::PeerUrlExist),
+            9 => Some(ExecuteErrorKind::MemberNotLearner),
+            10 => Some(ExecuteErrorKind::MemberBadUrls),
+            _ => None,
+        }
+    }
+
+    /// The message of the error, the same as etcd
+    pub(crate) fn message(self) -> &'static str {
+        match self {
+            ExecuteErrorKind::Compacted => COMPACTED_ERROR,
+            ExecuteErrorKind::FutureRevision => FUTURE_REVISION_ERROR,
+            ExecuteErrorKind::LeaseNotFound => LEASE_NOT_FOUND_ERROR,
+            ExecuteErrorKind::LeaseExist => LEASE_EXIST_ERROR,
+            ExecuteErrorKind::LeaseTtlTooLarge => LEASE_TTL_TOO_LARGE_ERROR,
+            ExecuteErrorKind::MemberNotFound => MEMBER_NOT_FOUND_ERROR,
+            ExecuteErrorKind::MemberExist => MEMBER_EXIST_ERROR,
+            ExecuteErrorKind::PeerUrlExist => PEER_URL_EXIST_ERROR,
+            ExecuteErrorKind::MemberNotLearner => MEMBER_NOT_LEARNER_ERROR,
+            ExecuteErrorKind::MemberBadUrls => MEMBER_BAD_URLS_ERROR,
+        }
+    }
+
+    /// The status code returned to the client, the same as etcd
+    fn status_code(self) -> tonic::Code {
+        match self {
+            ExecuteErrorKind::Compacted
+            | ExecuteErrorKind::FutureRevision
+            | ExecuteErrorKind::LeaseTtlTooLarge => tonic::Code::OutOfRange,
+            ExecuteErrorKind::LeaseNotFound | ExecuteErrorKind::MemberNotFound => {
+                tonic::Code::NotFound
+            }
+            ExecuteErrorKind::LeaseExist
+            | ExecuteErrorKind::MemberExist
+            | ExecuteErrorKind::PeerUrlExist
+            | ExecuteErrorKind::MemberNotLearner => tonic::Code::FailedPrecondition,
+            ExecuteErrorKind::MemberBadUrls => tonic::Code::InvalidArgument,
+        }
+    }
+}
+
+impl From<ExecuteErrorKind> for ExecuteError {
+    #[inline]
+    fn from(kind: ExecuteErrorKind) -> Self {
+        ExecuteError::Rejected {
+            code: kind.code(),
+            message: kind.message().to_owned(),
+        }
+    }
+}
+
+/// Convert the error met while proposing a command to `tonic::Status`. The errors other than
+/// the execution errors mean that the cluster is not available now, the client may retry.
+pub(crate) fn propose_error_status(err: ProposeError) -> tonic::Status {
+    if let ProposeError::Rejected { code, message } = err {
+        match ExecuteErrorKind::from_code(code) {
+            Some(kind) => tonic::Status::new(kind.status_code(), message),
+            None => tonic::Status::invalid_argument(message),
+        }
+    } else if let ProposeError::ExecutionError(message) = err {
+        tonic::Status::invalid_argument(message)
+    } else {
+        tonic::Status::unavailable(err.to_string())
+    }
+}
+
+/// Convert the error met while executing a command locally to `tonic::Status`
+pub(crate) fn execute_error_status(err: ExecuteError) -> tonic::Status {
+    propose_error_status(err.into())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn kinds_are_mapped_to_status_codes() {
+        let kinds = [
+            ExecuteErrorKind::Compacted,
+            ExecuteErrorKind::FutureRevision,
+            ExecuteErrorKind::LeaseNotFound,
+            ExecuteErrorKind::LeaseExist,
+            ExecuteErrorKind::LeaseTtlTooLarge,
+            ExecuteErrorKind::MemberNotFound,
+            ExecuteErrorKind::MemberExist,
+            ExecuteErrorKind::PeerUrlExist,
+            ExecuteErrorKind::MemberNotLearner,
+            ExecuteErrorKind::MemberBadUrls,
+        ];
+        for kind in kinds {
+            assert_eq!(ExecuteErrorKind::from_code(kind.code()), Some(kind));
+            let status = execute_error_status(kind.into());
+            assert_eq!(status.code(), kind.status_code());
+            assert_eq!(status.message(), kind.message());
+        }
+
+        let invalid = execute_error_status(ExecuteError::InvalidCommand("invalid".to_owned()));
+        assert_eq!(invalid.code(), tonic::Code::InvalidArgument);
+        let unknown = propose_error_status(ProposeError::Rejected {
+            code: 0,
+            message: "unknown".to_owned(),
+        });
+        assert_eq!(unknown.code(), tonic::Code::InvalidArgument);
+        let timeout = propose_error_status(ProposeError::Timeout);
+        assert_eq!(timeout.code(), tonic::Code::Unavailable);
+    }
+}
@@ -6,6 +6,17 @@ use parking_lot::Mutex;
 use super::revision::{KeyRevision, Revision};
 use crate::server::command::{KeyRange, RangeType};
 
+/// Revisions of the keys in a range found by `IndexOperate::range`
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct RangeRevisions {
+    /// Revisions of the matched keys in key order, at most `limit` ones
+    pub(crate) revisions: Vec<Revision>,
+    /// Number of the keys in the range, whether they are matched or not
+    pub(crate) count: usize,
+    /// If more keys are matched than `limit`
+    pub(crate) more: bool,
+}
+
 /// KV store inner
 #[derive(Debug)]
 pub(crate) struct Index {
@@ -21,6 +32,11 @@ impl Index {
         }
     }
 
+    /// Restore a `KeyRevision` of a key read from storage, revisions must be restored in order
+    pub(crate) fn restore(&self, key: Vec<u8>, revision: KeyRevision) {
+        self.index.lock().entry(key).or_default().push(revision);
+    }
+
     /// Filter out `KeyRevision` that is less than one revision and convert to `Revision`
     fn filter_revision(revs: &[KeyRevision], revision: i64) -> Vec<Revision> {
         revs.iter()
@@ -31,6 +47,11 @@ impl Index {
 
     /// Get specified or last `KeyRevision` if the key is not deleted, and convert to `Revision`
     fn get_revision(revs: &[KeyRevision], revision: i64) -> Option<Revision> {
+        Self::get_key_revision(revs, revision).map(KeyRevision::as_revision)
+    }
+
+    /// Get specified or last `KeyRevision` if the key is not deleted
+    fn get_key_revision(revs: &[KeyRevision], revision: i64) -> Option<&KeyRevision> {
         // TODO: handle future revision
         let rev = if revision <= 0 {
             revs.last()
@@ -47,7 +68,6 @@ impl Index {
             revs.get(idx)
         };
         rev.filter(|kr| !kr.is_deleted())
-            .map(KeyRevision::as_revision)
     }
 }
 
@@ -56,6 +76,19 @@ pub(crate) trait IndexOperate {
     /// Get `Revision` of keys, get the latest `Revision` when revision <= 0
     fn get(&self, key: &[u8], range_end: &[u8], revision: i64) -> Vec<Revision>;
 
+    /// Scan the keys in key order, or reversed key order if `descend` is true, and get the
+    /// `Revision`s of at most `limit` keys matching `filter`, a `limit` of 0 means no limit.
+    /// Only the index is read, so the keys can be counted without reading their values.
+    fn range(
+        &self,
+        key: &[u8],
+        range_end: &[u8],
+        revision: i64,
+        limit: usize,
+        descend: bool,
+        filter: &dyn Fn(&KeyRevision) -> bool,
+    ) -> RangeRevisions;
+
     /// Get `Revision` of keys from one revision
     fn get_from_rev(&self, key: &[u8], range_end: &[u8], revision: i64) -> Vec<Revision>;
 
@@ -76,7 +109,9 @@ pub(crate) trait IndexOperate {
         sub_revision: i64,
     ) -> KeyRevision;
 
-    // TODO: fn compact(rev:i64)
+    /// Remove the `KeyRevision`s superseded at `revision` and return them as `Revision`s. The
+    /// last `KeyRevision` of a key at `revision` is kept unless it's a deletion.
+    fn compact(&self, revision: i64) -> Vec<Revision>;
 }
 
 impl IndexOperate for Index {
@@ -102,6 +137,49 @@ impl IndexOperate for Index {
         }
     }
 
+    fn range(
+        &self,
+        key: &[u8],
+        range_end: &[u8],
+        revision: i64,
+        limit: usize,
+        descend: bool,
+        filter: &dyn Fn(&KeyRevision) -> bool,
+    ) -> RangeRevisions {
+        let index = self.index.lock();
+        let entries: Box<dyn DoubleEndedIterator<Item = &Vec<KeyRevision>>> =
+            match RangeType::get_range_type(key, range_end) {
+                RangeType::OneKey => Box::new(index.get(key).into_iter()),
+                RangeType::AllKeys => Box::new(index.values()),
+                RangeType::Range => Box::new(
+                    index
+                        .range(KeyRange {
+                            start: key.to_vec(),
+                            end: range_end.to_vec(),
+                        })
+                        .map(|(_k, revs)| revs),
+                ),
+            };
+        let entries: Box<dyn Iterator<Item = &Vec<KeyRevision>>> = if descend {
+            Box::new(entries.rev())
+        } else {
+            entries
+        };
+        let mut result = RangeRevisions::default();
+        for rev in entries.filter_map(|revs| Self::get_key_revision(revs, revision)) {
+            result.count = result.count.overflow_add(1);
+            if !filter(rev) {
+                continue;
+            }
+            if limit == 0 || result.revisions.len() < limit {
+                result.revisions.push(rev.as_revision());
+            } else {
+                result.more = true;
+            }
+        }
+        result
+    }
+
     fn get_from_rev(&self, key: &[u8], range_end: &[u8], revision: i64) -> Vec<Revision> {
         let index = self.index.lock();
         match RangeType::get_range_type(key, range_end) {
@@ -204,4 +282,94 @@ impl IndexOperate for Index {
             new_rev
         }
     }
+
+    fn compact(&self, revision: i64) -> Vec<Revision> {
+        let mut compacted = vec![];
+        self.index.lock().retain(|_k, revs| {
+            let pos = revs.partition_point(|rev| rev.mod_revision <= revision);
+            if pos == 0 {
+                return true;
+            }
+            let last = pos.overflow_sub(1);
+            let keep_from = match revs.get(last) {
+                Some(rev) if !rev.is_deleted() => last,
+                Some(_) | None => pos,
+            };
+            compacted.extend(revs.drain(..keep_from).map(|rev| rev.as_revision()));
+            !revs.is_empty()
+        });
+        compacted
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn compact_should_keep_latest_live_revision() {
+        let index = Index::new();
+        let _put_a2 = index.insert_or_update_revision(b"a", 2, 0);
+        let _put_b2 = index.insert_or_update_revision(b"b", 2, 1);
+        let _put_a3 = index.insert_or_update_revision(b"a", 3, 0);
+        let _del_b4 = index.delete(b"b", &[], 4, 0);
+        let _put_a5 = index.insert_or_update_revision(b"a", 5, 0);
+
+        let mut compacted = index.compact(4);
+        compacted.sort_by_key(|rev| (rev.revision(), rev.sub_revision()));
+        assert_eq!(
+            compacted,
+            vec![
+                Revision::new(2, 0),
+                Revision::new(2, 1),
+                Revision::new(4, 0)
+            ]
+        );
+        assert_eq!(index.get(b"a", &[], 4), vec![Revision::new(3, 0)]);
+        assert_eq!(index.get(b"a", &[], 0), vec![Revision::new(5, 0)]);
+        assert!(index.get(b"b", &[], 0).is_empty());
+        assert!(index.compact(4).is_empty());
+    }
+
+    #[test]
+    fn range_should_limit_and_count_keys() {
+        let index = Index::new();
+        let _put_a2 = index.insert_or_update_revision(b"a", 2, 0);
+        let _put_b3 = index.insert_or_update_revision(b"b", 3, 0);
+        let _put_c4 = index.insert_or_update_revision(b"c", 4, 0);
+        let _put_d5 = index.insert_or_update_revision(b"d", 5, 0);
+        let _del_c6 = index.delete(b"c", &[], 6, 0);
+
+        let all = |_rev: &KeyRevision| true;
+        assert_eq!(
+            index.range(b"a", b"e", 0, 2, false, &all),
+            RangeRevisions {
+                revisions: vec![Revision::new(2, 0), Revision::new(3, 0)],
+                count: 3,
+                more: true,
+            }
+        );
+        assert_eq!(
+            index.range(&[0], &[0], 5, 0, true, &all),
+            RangeRevisions {
+                revisions: vec![
+                    Revision::new(5, 0),
+                    Revision::new(4, 0),
+                    Revision::new(3, 0),
+                    Revision::new(2, 0)
+                ],
+                count: 4,
+                more: false,
+            }
+        );
+        let mod_after_2 = |rev: &KeyRevision| rev.mod_revision > 2;
+        assert_eq!(
+            index.range(b"a", b"e", 0, 1, false, &mod_after_2),
+            RangeRevisions {
+                revisions: vec![Revision::new(3, 0)],
+                count: 3,
+                more: true,
+            }
+        );
+    }
 }
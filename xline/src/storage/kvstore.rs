@@ -1,18 +1,31 @@
 use std::{cmp::Ordering, collections::HashMap, sync::Arc};
 
 use clippy_utilities::{Cast, OverflowArithmetic};
-use curp::cmd::ProposeId;
 use curp::error::ExecuteError;
+use curp::{cmd::ProposeId, LogIndex};
 use log::debug;
 use parking_lot::Mutex;
 use tokio::sync::{mpsc, oneshot};
 
 use super::index::IndexOperate;
-use super::{db::DB, index::Index, kvwatcher::KvWatcher};
+use super::{
+    db::{DB, KV_TABLE},
+    engine::StorageEngine,
+    error::ExecuteErrorKind,
+    index::Index,
+    kvwatcher::KvWatcher,
+    leasestore::{LeaseCollection, MAX_LEASE_TTL},
+    membertable::MemberTable,
+    revision::KeyRevision,
+};
 use crate::header_gen::HeaderGenerator;
 use crate::rpc::{
-    Compare, CompareResult, CompareTarget, DeleteRangeRequest, DeleteRangeResponse, Event,
-    EventType, KeyValue, PutRequest, PutResponse, RangeRequest, RangeResponse, RequestWithToken,
+    ClusterMemberAttrSetRequest, ClusterMemberAttrSetResponse, CompactionRequest,
+    CompactionResponse, Compare, CompareResult, CompareTarget, DeleteRangeRequest,
+    DeleteRangeResponse, Event, EventType, KeyValue, LeaseCheckpointRequest,
+    LeaseCheckpointResponse, LeaseGrantRequest, LeaseGrantResponse, LeaseRevokeRequest,
+    LeaseRevokeResponse, MemberAddResponse, MemberPromoteResponse, MemberRemoveResponse,
+    MemberUpdateResponse, PutRequest, PutResponse, RangeRequest, RangeResponse, RequestWithToken,
     RequestWrapper, ResponseWrapper, SortOrder, SortTarget, TargetUnion, TxnRequest, TxnResponse,
 };
 use crate::server::command::{
@@ -22,6 +35,12 @@ use crate::server::command::{
 /// Default channel size
 const CHANNEL_SIZE: usize = 128;
 
+/// Error message of a request on a revision that has been compacted
+pub(crate) const COMPACTED_ERROR: &str = "required revision has been compacted";
+
+/// Error message of a request on a revision that is greater than the current revision
+pub(crate) const FUTURE_REVISION_ERROR: &str = "required revision is a future revision";
+
 /// KV store
 #[allow(dead_code)]
 #[derive(Debug)]
@@ -46,22 +65,39 @@ pub(crate) struct KvStoreBackend {
     db: DB,
     /// Revision
     revision: Arc<Mutex<i64>>,
+    /// Revision that the kv store has been compacted to
+    compacted_revision: Mutex<i64>,
     /// Header generator
     header_gen: Arc<HeaderGenerator>,
     /// Speculative execution pool. Mapping from propose id to request
     sp_exec_pool: Mutex<HashMap<ProposeId, Vec<RequestWrapper>>>,
     /// KV update sender
     kv_update_tx: mpsc::Sender<(i64, Vec<Event>)>,
+    /// Leases that keys are attached to
+    lease_collection: Arc<LeaseCollection>,
+    /// Members of the cluster
+    member_table: Arc<MemberTable>,
 }
 
 impl KvStore {
     /// New `KvStore`
     #[allow(clippy::integer_arithmetic)] // Introduced by tokio::select!
-    pub(crate) fn new(header_gen: Arc<HeaderGenerator>) -> Self {
+    pub(crate) fn new(
+        header_gen: Arc<HeaderGenerator>,
+        engine: Arc<dyn StorageEngine>,
+        lease_collection: Arc<LeaseCollection>,
+        member_table: Arc<MemberTable>,
+    ) -> Self {
         let (exec_tx, mut exec_rx) = mpsc::channel(CHANNEL_SIZE);
         let (sync_tx, mut sync_rx) = mpsc::channel(CHANNEL_SIZE);
         let (kv_update_tx, kv_update_rx) = mpsc::channel(CHANNEL_SIZE);
-        let inner = Arc::new(KvStoreBackend::new(kv_update_tx, header_gen));
+        let inner = Arc::new(KvStoreBackend::new(
+            kv_update_tx,
+            header_gen,
+            engine,
+            lease_collection,
+            member_table,
+        ));
         let kv_watcher = Arc::new(KvWatcher::new(Arc::clone(&inner), kv_update_rx));
 
         let _handle = tokio::spawn({
@@ -106,9 +142,12 @@ impl KvStore {
         receiver
     }
 
-    /// Send sync request to KV store
-    pub(crate) async fn send_sync(&self, propose_id: ProposeId) -> oneshot::Receiver<SyncResponse> {
-        let (req, receiver) = SyncRequest::new(propose_id);
+    /// Send sync request of a batch of commands to KV store
+    pub(crate) async fn send_sync(
+        &self,
+        cmds: Vec<(ProposeId, LogIndex)>,
+    ) -> oneshot::Receiver<Vec<SyncResponse>> {
+        let (req, receiver) = SyncRequest::new(cmds);
         assert!(
             self.sync_tx.send(req).await.is_ok(),
             "Command receiver dropped"
@@ -120,21 +159,92 @@ impl KvStore {
     pub(crate) fn kv_watcher(&self) -> Arc<KvWatcher> {
         Arc::clone(&self.kv_watcher)
     }
+
+    /// Get revision of KV store
+    pub(crate) fn revision(&self) -> i64 {
+        self.inner.revision()
+    }
+
+    /// Get the revision that KV store has been compacted to
+    pub(crate) fn compacted_revision(&self) -> i64 {
+        self.inner.compacted_revision()
+    }
+
+    /// Record that all the commands at or before log `index` have been synced, for both the kv
+    /// store and the auth store
+    pub(crate) fn update_applied_index(&self, index: LogIndex) {
+        self.inner.db.update_applied_index(index);
+    }
+
+    /// Handle a serializable `RangeRequest` with the local data, without going through consensus
+    ///
+    /// # Errors
+    ///
+    /// Return `ExecuteErrorKind::Compacted` if the required revision has been compacted
+    pub(crate) fn serializable_range(
+        &self,
+        req: &RangeRequest,
+    ) -> Result<RangeResponse, ExecuteError> {
+        self.inner.serializable_range(req)
+    }
 }
 
 impl KvStoreBackend {
-    /// New `KvStoreBackend`
+    /// New `KvStoreBackend`, the index and revision are rebuilt from the data in `engine`
     pub(crate) fn new(
         kv_update_tx: mpsc::Sender<(i64, Vec<Event>)>,
         header_gen: Arc<HeaderGenerator>,
+        engine: Arc<dyn StorageEngine>,
+        lease_collection: Arc<LeaseCollection>,
+        member_table: Arc<MemberTable>,
     ) -> Self {
-        Self {
+        let backend = Self {
             index: Index::new(),
-            db: DB::new(),
+            db: DB::new(engine, KV_TABLE),
             revision: header_gen.revision_arc(),
+            compacted_revision: Mutex::new(0),
             header_gen,
             sp_exec_pool: Mutex::new(HashMap::new()),
             kv_update_tx,
+            lease_collection,
+            member_table,
+        };
+        backend.recover();
+        backend
+    }
+
+    /// Rebuild the index, revision, compacted revision and the keys attached to leases from
+    /// the `KeyValue`s in the db
+    fn recover(&self) {
+        *self.compacted_revision.lock() = self.db.compacted_revision();
+        let mut last_revision = None;
+        // leases of the live keys
+        let mut leases = HashMap::new();
+        for (revision, kv) in self.db.get_all() {
+            if kv.version == 0 || kv.lease == 0 {
+                let _prev = leases.remove(&kv.key);
+            } else {
+                let _prev = leases.insert(kv.key.clone(), kv.lease);
+            }
+            let key_rev = if kv.version == 0 {
+                KeyRevision::new_deletion(revision.revision(), revision.sub_revision())
+            } else {
+                KeyRevision::new(
+                    kv.create_revision,
+                    kv.version,
+                    revision.revision(),
+                    revision.sub_revision(),
+                )
+            };
+            last_revision = Some(revision.revision());
+            self.index.restore(kv.key, key_rev);
+        }
+        if let Some(revision) = last_revision {
+            let mut current = self.revision.lock();
+            *current = (*current).max(revision);
+        }
+        for (key, lease) in leases {
+            self.lease_collection.attach(lease, key);
         }
     }
 
@@ -143,6 +253,29 @@ impl KvStoreBackend {
         *self.revision.lock()
     }
 
+    /// Get the revision that KV store has been compacted to
+    pub(crate) fn compacted_revision(&self) -> i64 {
+        *self.compacted_revision.lock()
+    }
+
+    /// Check if the history at `revision` is still kept, revision <= 0 means the latest
+    fn check_revision(&self, revision: i64) -> Result<(), ExecuteError> {
+        if revision > 0 && revision < self.compacted_revision() {
+            return Err(ExecuteErrorKind::Compacted.into());
+        }
+        Ok(())
+    }
+
+    /// Handle a serializable `RangeRequest` with the local data
+    fn serializable_range(&self, req: &RangeRequest) -> Result<RangeResponse, ExecuteError> {
+        self.check_revision(req.revision)?;
+        let mut response = self.handle_range_request(req);
+        if let Some(header) = response.header.as_mut() {
+            header.revision = self.revision();
+        }
+        Ok(response)
+    }
+
     /// Notify KV changes to KV watcher
     async fn notify_updates(&self, revision: i64, updates: Vec<Event>) {
         assert!(
@@ -182,6 +315,7 @@ impl KvStoreBackend {
         let response = match *wrapper {
             RequestWrapper::RangeRequest(ref req) => {
                 debug!("Receive RangeRequest {:?}", req);
+                self.check_revision(req.revision)?;
                 self.handle_range_request(req).into()
             }
             RequestWrapper::PutRequest(ref req) => {
@@ -196,6 +330,42 @@ impl KvStoreBackend {
                 debug!("Receive TxnRequest {:?}", req);
                 self.handle_txn_request(id, req)?.into()
             }
+            RequestWrapper::CompactionRequest(ref req) => {
+                debug!("Receive CompactionRequest {:?}", req);
+                self.handle_compaction_request(req)?.into()
+            }
+            RequestWrapper::LeaseGrantRequest(ref req) => {
+                debug!("Receive LeaseGrantRequest {:?}", req);
+                self.handle_lease_grant_request(req)?.into()
+            }
+            RequestWrapper::LeaseRevokeRequest(ref req) => {
+                debug!("Receive LeaseRevokeRequest {:?}", req);
+                self.handle_lease_revoke_request(req)?.into()
+            }
+            RequestWrapper::LeaseCheckpointRequest(ref req) => {
+                debug!("Receive LeaseCheckpointRequest {:?}", req);
+                self.handle_lease_checkpoint_request(req).into()
+            }
+            RequestWrapper::ClusterMemberAttrSetRequest(ref req) => {
+                debug!("Receive ClusterMemberAttrSetRequest {:?}", req);
+                self.handle_cluster_member_attr_set_request(req).into()
+            }
+            RequestWrapper::MemberAddRequest(ref req) => {
+                debug!("Receive MemberAddRequest {:?}", req);
+                self.handle_member_add_request(wrapper)?.into()
+            }
+            RequestWrapper::MemberRemoveRequest(ref req) => {
+                debug!("Receive MemberRemoveRequest {:?}", req);
+                self.handle_member_remove_request(wrapper)?.into()
+            }
+            RequestWrapper::MemberUpdateRequest(ref req) => {
+                debug!("Receive MemberUpdateRequest {:?}", req);
+                self.handle_member_update_request(wrapper)?.into()
+            }
+            RequestWrapper::MemberPromoteRequest(ref req) => {
+                debug!("Receive MemberPromoteRequest {:?}", req);
+                self.handle_member_promote_request(wrapper)?.into()
+            }
             _ => unreachable!("Other request should not be sent to this store"),
         };
         Ok(response)
@@ -236,58 +406,78 @@ impl KvStoreBackend {
 
     /// Handle `RangeRequest`
     fn handle_range_request(&self, req: &RangeRequest) -> RangeResponse {
-        let key = &req.key;
-        let range_end = &req.range_end;
-        let mut kvs = self.get_range(key, range_end, req.revision);
-        debug!("handle_range_request kvs {:?}", kvs);
+        let limit: usize = req.limit.max(0).cast();
+        // keys are scanned in order by the index, so the limit can be pushed down to it when
+        // sorting by key, otherwise all the matched keys must be read and sorted
+        let sort_by_key = req.sort_target() == SortTarget::Key;
+        let scan = self.index.range(
+            &req.key,
+            &req.range_end,
+            req.revision,
+            if sort_by_key { limit } else { 0 },
+            sort_by_key && req.sort_order() == SortOrder::Descend,
+            // the revision filters are applied before the limit, the same as etcd
+            &|rev: &KeyRevision| !req.count_only && Self::match_revision_filters(req, rev),
+        );
         let mut response = RangeResponse {
             header: Some(self.header_gen.gen_header_without_revision()),
-            count: kvs.len().cast(),
+            count: scan.count.cast(),
+            more: scan.more,
             ..RangeResponse::default()
         };
-        if !req.count_only {
-            match (req.sort_target(), req.sort_order()) {
-                (SortTarget::Key, SortOrder::None) => {}
-                (SortTarget::Key, SortOrder::Ascend) => {
-                    kvs.sort_by(|a, b| a.key.cmp(&b.key));
-                }
-                (SortTarget::Key, SortOrder::Descend) => {
-                    kvs.sort_by(|a, b| b.key.cmp(&a.key));
-                }
-                (SortTarget::Version, SortOrder::Ascend | SortOrder::None) => {
-                    kvs.sort_by(|a, b| a.version.cmp(&b.version));
-                }
-                (SortTarget::Version, SortOrder::Descend) => {
-                    kvs.sort_by(|a, b| b.version.cmp(&a.version));
-                }
-                (SortTarget::Create, SortOrder::Ascend | SortOrder::None) => {
-                    kvs.sort_by(|a, b| a.create_revision.cmp(&b.create_revision));
-                }
-                (SortTarget::Create, SortOrder::Descend) => {
-                    kvs.sort_by(|a, b| b.create_revision.cmp(&a.create_revision));
-                }
-                (SortTarget::Mod, SortOrder::Ascend | SortOrder::None) => {
-                    kvs.sort_by(|a, b| a.mod_revision.cmp(&b.mod_revision));
-                }
-                (SortTarget::Mod, SortOrder::Descend) => {
-                    kvs.sort_by(|a, b| b.mod_revision.cmp(&a.mod_revision));
-                }
-                (SortTarget::Value, SortOrder::Ascend | SortOrder::None) => {
-                    kvs.sort_by(|a, b| a.value.cmp(&b.value));
-                }
-                (SortTarget::Value, SortOrder::Descend) => {
-                    kvs.sort_by(|a, b| b.value.cmp(&a.value));
-                }
+        if req.count_only {
+            return response;
+        }
+        let mut kvs = self.db.get_values(&scan.revisions);
+        debug!("handle_range_request kvs {:?}", kvs);
+        match (req.sort_target(), req.sort_order()) {
+            // already in key order
+            (SortTarget::Key, SortOrder::None | SortOrder::Ascend | SortOrder::Descend) => {}
+            (SortTarget::Version, SortOrder::Ascend | SortOrder::None) => {
+                kvs.sort_by(|a, b| a.version.cmp(&b.version));
+            }
+            (SortTarget::Version, SortOrder::Descend) => {
+                kvs.sort_by(|a, b| b.version.cmp(&a.version));
             }
-            if (req.limit > 0) && (kvs.len() > req.limit.cast()) {
-                response.more = true;
-                kvs.truncate(req.limit.cast());
+            (SortTarget::Create, SortOrder::Ascend | SortOrder::None) => {
+                kvs.sort_by(|a, b| a.create_revision.cmp(&b.create_revision));
             }
-            response.kvs = kvs;
+            (SortTarget::Create, SortOrder::Descend) => {
+                kvs.sort_by(|a, b| b.create_revision.cmp(&a.create_revision));
+            }
+            (SortTarget::Mod, SortOrder::Ascend | SortOrder::None) => {
+                kvs.sort_by(|a, b| a.mod_revision.cmp(&b.mod_revision));
+            }
+            (SortTarget::Mod, SortOrder::Descend) => {
+                kvs.sort_by(|a, b| b.mod_revision.cmp(&a.mod_revision));
+            }
+            (SortTarget::Value, SortOrder::Ascend | SortOrder::None) => {
+                kvs.sort_by(|a, b| a.value.cmp(&b.value));
+            }
+            (SortTarget::Value, SortOrder::Descend) => {
+                kvs.sort_by(|a, b| b.value.cmp(&a.value));
+            }
+        }
+        if limit > 0 && kvs.len() > limit {
+            response.more = true;
+            kvs.truncate(limit);
         }
+        if req.keys_only {
+            kvs.iter_mut().for_each(|kv| kv.value.clear());
+        }
+        response.kvs = kvs;
         response
     }
 
+    /// Check if `rev` matches the `min/max_mod_revision` and `min/max_create_revision` filters
+    /// of `req`, a filter of 0 is not applied
+    fn match_revision_filters(req: &RangeRequest, rev: &KeyRevision) -> bool {
+        (req.min_mod_revision <= 0 || rev.mod_revision >= req.min_mod_revision)
+            && (req.max_mod_revision <= 0 || rev.mod_revision <= req.max_mod_revision)
+            && (req.min_create_revision <= 0 || rev.create_revision >= req.min_create_revision)
+            && (req.max_create_revision <= 0 || rev.create_revision <= req.max_create_revision)
+    }
+
     /// Handle `PutRequest`
     fn handle_put_request(&self, req: &PutRequest) -> Result<PutResponse, ExecuteError> {
         let mut prev_kvs = self.get_range(&req.key, &[], 0);
@@ -307,6 +497,9 @@ impl KvStoreBackend {
                 "ignore_lease or ignore_value is set but there is no previous value".to_owned(),
             ));
         }
+        if req.lease != 0 && !self.lease_collection.contains(req.lease) {
+            return Err(ExecuteErrorKind::LeaseNotFound.into());
+        }
         let mut response = PutResponse {
             header: Some(self.header_gen.gen_header_without_revision()),
             ..PutResponse::default()
@@ -445,28 +638,194 @@ impl KvStoreBackend {
         })
     }
 
-    /// Sync a Command to storage and generate revision for Command.
-    async fn sync_cmd(&self, sync_req: SyncRequest) {
+    /// Handle `CompactionRequest`
+    fn handle_compaction_request(
+        &self,
+        req: &CompactionRequest,
+    ) -> Result<CompactionResponse, ExecuteError> {
+        if req.revision > self.revision() {
+            return Err(ExecuteErrorKind::FutureRevision.into());
+        }
+        if req.revision <= self.compacted_revision() {
+            return Err(ExecuteErrorKind::Compacted.into());
+        }
+        Ok(CompactionResponse {
+            header: Some(self.header_gen.gen_header_without_revision()),
+        })
+    }
+
+    /// Handle `LeaseGrantRequest`, the id and ttl are chosen by the server before proposing
+    fn handle_lease_grant_request(
+        &self,
+        req: &LeaseGrantRequest,
+    ) -> Result<LeaseGrantResponse, ExecuteError> {
+        if req.ttl > MAX_LEASE_TTL {
+            return Err(ExecuteErrorKind::LeaseTtlTooLarge.into());
+        }
+        if self.lease_collection.contains(req.id) {
+            return Err(ExecuteErrorKind::LeaseExist.into());
+        }
+        Ok(LeaseGrantResponse {
+            header: Some(self.header_gen.gen_header_without_revision()),
+            id: req.id,
+            ttl: req.ttl,
+            error: String::new(),
+        })
+    }
+
+    /// Handle `LeaseRevokeRequest`
+    fn handle_lease_revoke_request(
+        &self,
+        req: &LeaseRevokeRequest,
+    ) -> Result<LeaseRevokeResponse, ExecuteError> {
+        if !self.lease_collection.contains(req.id) {
+            return Err(ExecuteErrorKind::LeaseNotFound.into());
+        }
+        Ok(LeaseRevokeResponse {
+            header: Some(self.header_gen.gen_header_without_revision()),
+        })
+    }
+
+    /// Handle `LeaseCheckpointRequest`, the checkpoints of revoked leases are ignored
+    fn handle_lease_checkpoint_request(
+        &self,
+        _req: &LeaseCheckpointRequest,
+    ) -> LeaseCheckpointResponse {
+        LeaseCheckpointResponse {
+            header: Some(self.header_gen.gen_header_without_revision()),
+        }
+    }
+
+    /// Handle `ClusterMemberAttrSetRequest`, the attributes of unknown members are ignored
+    fn handle_cluster_member_attr_set_request(
+        &self,
+        _req: &ClusterMemberAttrSetRequest,
+    ) -> ClusterMemberAttrSetResponse {
+        ClusterMemberAttrSetResponse {
+            header: Some(self.header_gen.gen_header_without_revision()),
+        }
+    }
+
+    /// Handle `MemberAddRequest`, the member is only checked here and added when it's synced
+    fn handle_member_add_request(
+        &self,
+        wrapper: &RequestWrapper,
+    ) -> Result<MemberAddResponse, ExecuteError> {
+        let (member, members) = self.member_table.check(wrapper)?;
+        Ok(MemberAddResponse {
+            header: Some(self.header_gen.gen_header_without_revision()),
+            member,
+            members,
+        })
+    }
+
+    /// Handle `MemberRemoveRequest`
+    fn handle_member_remove_request(
+        &self,
+        wrapper: &RequestWrapper,
+    ) -> Result<MemberRemoveResponse, ExecuteError> {
+        let (_member, members) = self.member_table.check(wrapper)?;
+        Ok(MemberRemoveResponse {
+            header: Some(self.header_gen.gen_header_without_revision()),
+            members,
+        })
+    }
+
+    /// Handle `MemberUpdateRequest`
+    fn handle_member_update_request(
+        &self,
+        wrapper: &RequestWrapper,
+    ) -> Result<MemberUpdateResponse, ExecuteError> {
+        let (_member, members) = self.member_table.check(wrapper)?;
+        Ok(MemberUpdateResponse {
+            header: Some(self.header_gen.gen_header_without_revision()),
+            members,
+        })
+    }
+
+    /// Handle `MemberPromoteRequest`
+    fn handle_member_promote_request(
+        &self,
+        wrapper: &RequestWrapper,
+    ) -> Result<MemberPromoteResponse, ExecuteError> {
+        let (_member, members) = self.member_table.check(wrapper)?;
+        Ok(MemberPromoteResponse {
+            header: Some(self.header_gen.gen_header_without_revision()),
+            members,
+        })
+    }
+
+    /// Sync a batch of Commands to storage and generate revisions for them, the updates are
+    /// notified after the batch is committed
+    async fn sync_cmd(self: &Arc<Self>, sync_req: SyncRequest) {
         debug!("Receive SyncRequest {:?}", sync_req);
-        let (propose_id, res_sender) = sync_req.unpack();
-        let requests = self
-            .sp_exec_pool
-            .lock()
-            .remove(&propose_id)
-            .unwrap_or_else(|| {
-                panic!(
-                    "Failed to get speculative execution propose id {:?}",
-                    propose_id
-                );
-            });
-        let (revision, events) = self.sync_requests(requests.clone());
+        let (cmds, res_sender) = sync_req.unpack();
+        let cmds: Vec<_> = cmds
+            .into_iter()
+            .map(|(propose_id, index)| {
+                let requests = self
+                    .sp_exec_pool
+                    .lock()
+                    .remove(&propose_id)
+                    .unwrap_or_else(|| {
+                        panic!(
+                            "Failed to get speculative execution propose id {:?}",
+                            propose_id
+                        );
+                    });
+                (requests, index, propose_id)
+            })
+            .collect();
+        // the batch is committed to the storage with a sync, so it's not done on the runtime
+        let backend = Arc::clone(self);
+        let synced = tokio::task::spawn_blocking(move || backend.sync_batch(cmds))
+            .await
+            .unwrap_or_else(|e| panic!("Failed to sync the commands: {e}"));
+        let responses = synced
+            .iter()
+            .map(|&(revision, _)| SyncResponse::new(revision))
+            .collect();
         assert!(
-            res_sender.send(SyncResponse::new(revision)).is_ok(),
+            res_sender.send(responses).is_ok(),
             "Failed to send response"
         );
-        if let Some(events) = events {
-            self.notify_updates(revision, events).await;
+        for (revision, events) in synced {
+            if let Some(events) = events {
+                self.notify_updates(revision, events).await;
+            }
+        }
+    }
+
+    /// Sync the requests of the commands, each with its log index and propose id, in order
+    /// and commit their writes in one batch
+    fn sync_batch(
+        &self,
+        cmds: Vec<(Vec<RequestWrapper>, LogIndex, ProposeId)>,
+    ) -> Vec<(i64, Option<Vec<Event>>)> {
+        let synced = cmds
+            .into_iter()
+            .map(|(requests, index, id)| self.sync_indexed(requests, index, &id))
+            .collect();
+        self.db.flush();
+        synced
+    }
+
+    /// Sync the requests of the command `id` at log `index`, the writes are committed by the
+    /// next flush. A command that has been synced before the server restarted is skipped when
+    /// the leader replays it.
+    fn sync_indexed(
+        &self,
+        requests: Vec<RequestWrapper>,
+        index: LogIndex,
+        id: &ProposeId,
+    ) -> (i64, Option<Vec<Event>>) {
+        if self.db.is_applied(index, id) {
+            debug!("skip the command {id:?} at index {index}, it has been synced");
+            return (self.revision(), None);
         }
+        let res = self.sync_requests(requests);
+        self.db.record_applied(index, id);
+        res
     }
 
     /// Sync a vec of requests
@@ -512,6 +871,43 @@ impl KvStoreBackend {
                 debug!("Sync TxnRequest {:?}", req);
                 panic!("Sync for TxnRequest is impossible");
             }
+            RequestWrapper::CompactionRequest(req) => {
+                debug!("Sync CompactionRequest {:?}", req);
+                self.sync_compaction_request(&req)
+            }
+            RequestWrapper::LeaseGrantRequest(req) => {
+                debug!("Sync LeaseGrantRequest {:?}", req);
+                self.db
+                    .buffer([self.lease_collection.grant(req.id, req.ttl)]);
+                vec![]
+            }
+            RequestWrapper::LeaseRevokeRequest(req) => {
+                debug!("Sync LeaseRevokeRequest {:?}", req);
+                self.sync_lease_revoke_request(&req, revision, sub_revision)
+            }
+            RequestWrapper::LeaseCheckpointRequest(req) => {
+                debug!("Sync LeaseCheckpointRequest {:?}", req);
+                self.db
+                    .buffer(self.lease_collection.checkpoint(&req.checkpoints));
+                vec![]
+            }
+            RequestWrapper::ClusterMemberAttrSetRequest(req) => {
+                debug!("Sync ClusterMemberAttrSetRequest {:?}", req);
+                self.db.buffer(
+                    self.member_table
+                        .publish(req.member_id, req.member_attributes.unwrap_or_default()),
+                );
+                vec![]
+            }
+            req @ (RequestWrapper::MemberAddRequest(_)
+            | RequestWrapper::MemberRemoveRequest(_)
+            | RequestWrapper::MemberUpdateRequest(_)
+            | RequestWrapper::MemberPromoteRequest(_)) => {
+                debug!("Sync member change {:?}", req);
+                // re-checked since the leader syncs it even if the execution failed
+                self.db.buffer(self.member_table.apply(&req));
+                vec![]
+            }
             _ => {
                 unreachable!("Other request should not be sent to this store");
             }
@@ -523,6 +919,21 @@ impl KvStoreBackend {
         Vec::new()
     }
 
+    /// Sync `CompactionRequest`, compaction doesn't generate any event. A request rejected by
+    /// the execution is synced as well, it's checked again here since followers don't execute
+    /// it.
+    fn sync_compaction_request(&self, req: &CompactionRequest) -> Vec<Event> {
+        let revision = req.revision;
+        let mut compacted_revision = self.compacted_revision.lock();
+        if revision <= self.revision() && revision > *compacted_revision {
+            let revisions = self.index.compact(revision);
+            debug!("sync_compaction_request: revisions {:?}", revisions);
+            self.db.compact(&revisions, revision);
+            *compacted_revision = revision;
+        }
+        Vec::new()
+    }
+
     /// Sync `PutRequest` and return if kvstore is changed
     fn sync_put_request(&self, req: PutRequest, revision: i64, sub_revision: i64) -> Vec<Event> {
         let prev_kv = self.get_range(&req.key, &[], 0).first().cloned();
@@ -553,6 +964,13 @@ impl KvStoreBackend {
         }
 
         let _prev = self.db.insert(new_rev.as_revision(), kv.clone());
+        let prev_lease = prev_kv.as_ref().map_or(0, |prev| prev.lease);
+        if prev_lease != kv.lease {
+            self.lease_collection.detach(prev_lease, &kv.key);
+        }
+        if kv.lease != 0 {
+            self.lease_collection.attach(kv.lease, kv.key.clone());
+        }
         let event = Event {
             #[allow(clippy::as_conversions)] // This cast is always valid
             r#type: EventType::Put as i32,
@@ -594,13 +1012,215 @@ impl KvStoreBackend {
         let revisions = self.index.delete(&key, &range_end, revision, sub_revision);
         debug!("sync_delete_range_request: revisions {:?}", revisions);
         let prev_kv = self.db.mark_deletions(&revisions);
+        for prev in prev_kv.iter().filter(|prev| prev.lease != 0) {
+            self.lease_collection.detach(prev.lease, &prev.key);
+        }
+        Self::new_deletion_events(revision, prev_kv)
+    }
+
+    /// Sync `LeaseRevokeRequest`, the keys attached to the lease are deleted in the same batch
+    /// as the lease
+    fn sync_lease_revoke_request(
+        &self,
+        req: &LeaseRevokeRequest,
+        revision: i64,
+        sub_revision: i64,
+    ) -> Vec<Event> {
+        let mut keys = self
+            .lease_collection
+            .look_up(req.id)
+            .map(|lease| lease.keys())
+            .unwrap_or_default();
+        keys.sort();
+        let mut revisions = vec![];
+        for key in keys {
+            let key_sub_revision = sub_revision.overflow_add(revisions.len().cast());
+            revisions.append(&mut self.index.delete(&key, &[], revision, key_sub_revision));
+        }
+        debug!("sync_lease_revoke_request: revisions {:?}", revisions);
+        let prev_kv = self.db.mark_deletions(&revisions);
+        self.db.buffer([self.lease_collection.revoke(req.id)]);
         Self::new_deletion_events(revision, prev_kv)
     }
 }
 
 #[cfg(test)]
 mod test {
+    use super::*;
+    use crate::storage::engine::MemoryEngine;
+
+    /// Propose id of the command at log `index`
+    fn id(index: u64) -> ProposeId {
+        ProposeId::new(format!("id-{index}"))
+    }
+
+    /// Sync the requests of the command `id` at log `index` in a batch of its own
+    fn sync(
+        backend: &KvStoreBackend,
+        requests: Vec<RequestWrapper>,
+        index: LogIndex,
+        id: ProposeId,
+    ) -> (i64, Option<Vec<Event>>) {
+        backend.sync_batch(vec![(requests, index, id)]).remove(0)
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     //#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
     async fn test_all() {}
+
+    #[tokio::test]
+    async fn backend_should_recover_from_engine() {
+        let engine: Arc<dyn StorageEngine> = Arc::new(MemoryEngine::new());
+        let new_backend = || {
+            let (kv_update_tx, _kv_update_rx) = mpsc::channel(CHANNEL_SIZE);
+            let header_gen = Arc::new(HeaderGenerator::new(0, 0));
+            let lease_collection = Arc::new(LeaseCollection::new(engine.as_ref()));
+            let member_table = Arc::new(MemberTable::new(engine.as_ref(), []));
+            KvStoreBackend::new(
+                kv_update_tx,
+                header_gen,
+                Arc::clone(&engine),
+                lease_collection,
+                member_table,
+            )
+        };
+        let put = |key: &str, value: &str| {
+            RequestWrapper::PutRequest(PutRequest {
+                key: key.into(),
+                value: value.into(),
+                ..PutRequest::default()
+            })
+        };
+
+        let backend = new_backend();
+        let _rev2 = sync(&backend, vec![put("a", "1"), put("b", "2")], 1, id(1));
+        let _rev3 = sync(&backend, vec![put("b", "3")], 2, id(2));
+        let _rev4 = sync(
+            &backend,
+            vec![RequestWrapper::DeleteRangeRequest(DeleteRangeRequest {
+                key: "a".into(),
+                ..DeleteRangeRequest::default()
+            })],
+            3,
+            id(3),
+        );
+
+        let recovered = new_backend();
+        let values = |key: &[u8], revision: i64| -> Vec<(Vec<u8>, i64, i64)> {
+            recovered
+                .get_range(key, &[], revision)
+                .into_iter()
+                .map(|kv| (kv.value, kv.create_revision, kv.version))
+                .collect()
+        };
+        assert_eq!(recovered.revision(), 4);
+        assert!(values(b"a", 0).is_empty());
+        assert_eq!(values(b"b", 0), vec![(b"3".to_vec(), 2, 2)]);
+        assert_eq!(values(b"b", 2), vec![(b"2".to_vec(), 2, 1)]);
+
+        // the commands replayed by the leader are not synced again
+        let (revision, events) = sync(&recovered, vec![put("b", "3")], 2, id(2));
+        assert_eq!(revision, 4);
+        assert!(events.is_none());
+        assert_eq!(values(b"b", 0), vec![(b"3".to_vec(), 2, 2)]);
+
+        // a log entry may contain multiple commands
+        let _rev5 = sync(&recovered, vec![put("a", "4")], 3, id(4));
+        assert_eq!(values(b"a", 0), vec![(b"4".to_vec(), 5, 1)]);
+
+        // the commands of an after sync batch are committed together
+        let synced = recovered.sync_batch(vec![
+            (vec![put("c", "5")], 4, id(5)),
+            (vec![put("c", "6")], 5, id(6)),
+        ]);
+        let revisions: Vec<_> = synced.into_iter().map(|(rev, _)| rev).collect();
+        assert_eq!(revisions, vec![6, 7]);
+        let batched = new_backend();
+        assert!(batched.db.is_applied(4, &id(5)) && batched.db.is_applied(5, &id(6)));
+        let batched_values: Vec<_> = batched
+            .get_range(b"c", &[], 0)
+            .into_iter()
+            .map(|kv| kv.value)
+            .collect();
+        assert_eq!(batched_values, vec![b"6".to_vec()]);
+    }
+
+    #[tokio::test]
+    async fn revoking_lease_should_delete_attached_keys() {
+        let engine: Arc<dyn StorageEngine> = Arc::new(MemoryEngine::new());
+        let new_backend = || {
+            let (kv_update_tx, _kv_update_rx) = mpsc::channel(CHANNEL_SIZE);
+            let header_gen = Arc::new(HeaderGenerator::new(0, 0));
+            let lease_collection = Arc::new(LeaseCollection::new(engine.as_ref()));
+            let member_table = Arc::new(MemberTable::new(engine.as_ref(), []));
+            KvStoreBackend::new(
+                kv_update_tx,
+                header_gen,
+                Arc::clone(&engine),
+                lease_collection,
+                member_table,
+            )
+        };
+        let put = |key: &str, lease: i64| {
+            RequestWrapper::PutRequest(PutRequest {
+                key: key.into(),
+                value: b"v".to_vec(),
+                lease,
+                ..PutRequest::default()
+            })
+        };
+
+        let backend = new_backend();
+        let _grant = sync(
+            &backend,
+            vec![RequestWrapper::LeaseGrantRequest(LeaseGrantRequest {
+                ttl: 10,
+                id: 1,
+            })],
+            1,
+            id(1),
+        );
+        let _rev2 = sync(
+            &backend,
+            vec![put("a", 1), put("b", 1), put("c", 0)],
+            2,
+            id(2),
+        );
+        let _rev3 = sync(&backend, vec![put("b", 0)], 3, id(3));
+
+        // keys attached to the lease are rebuilt from the kv store
+        let recovered = new_backend();
+        let (revision, events) = sync(
+            &recovered,
+            vec![RequestWrapper::LeaseRevokeRequest(LeaseRevokeRequest {
+                id: 1,
+            })],
+            4,
+            id(4),
+        );
+        assert_eq!(revision, 4);
+        let deleted: Vec<_> = events
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|event| event.kv.map(|kv| kv.key))
+            .collect();
+        assert_eq!(deleted, vec![b"a".to_vec()]);
+        assert!(recovered.get_range(b"a", &[], 0).is_empty());
+        assert_eq!(recovered.get_range(b"b", &[], 0).len(), 1);
+        assert!(!recovered.lease_collection.contains(1));
+
+        // the lease is removed in the same batch as its keys
+        let revoked = new_backend();
+        assert!(revoked.get_range(b"a", &[], 0).is_empty());
+        assert!(!revoked.lease_collection.contains(1));
+
+        // a lease is not persisted before the command granting it is flushed
+        let _unflushed =
+            revoked.sync_requests(vec![RequestWrapper::LeaseGrantRequest(LeaseGrantRequest {
+                ttl: 10,
+                id: 2,
+            })]);
+        assert!(revoked.lease_collection.contains(2));
+        assert!(!new_backend().lease_collection.contains(2));
+    }
 }
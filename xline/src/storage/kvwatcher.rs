@@ -5,6 +5,7 @@ use std::{
     sync::Arc,
 };
 
+use log::debug;
 use parking_lot::Mutex;
 use tokio::sync::mpsc;
 
@@ -143,10 +144,10 @@ impl Watcher {
             events,
             revision,
         };
-        assert!(
-            self.inner.event_tx.send(watch_event).await.is_ok(),
-            "WatchEvent receiver is closed"
-        );
+        // the receiver may be dropped right after the watcher is canceled
+        if self.inner.event_tx.send(watch_event).await.is_err() {
+            debug!("WatchEvent receiver of watcher {id} is closed");
+        }
     }
 }
 
@@ -201,6 +202,16 @@ impl KvWatcher {
     pub(crate) fn cancel(&self, watcher: &Watcher) -> i64 {
         self.inner.cancel(watcher)
     }
+
+    /// Get revision of KV store
+    pub(crate) fn revision(&self) -> i64 {
+        self.inner.storage.revision()
+    }
+
+    /// Get the revision that KV store has been compacted to
+    pub(crate) fn compacted_revision(&self) -> i64 {
+        self.inner.storage.compacted_revision()
+    }
 }
 
 impl KvWatcherInner {
@@ -0,0 +1,99 @@
+use std::{
+    collections::HashSet,
+    time::{Duration, Instant},
+};
+
+use clippy_utilities::{Cast, OverflowArithmetic};
+
+/// A lease and the keys attached to it
+#[derive(Debug, Clone)]
+pub(crate) struct Lease {
+    /// Id of the lease
+    id: i64,
+    /// Granted ttl of the lease in seconds
+    ttl: i64,
+    /// Remaining ttl in seconds of the last checkpoint, 0 if the lease is not checkpointed
+    /// since it's granted or renewed
+    checkpointed_ttl: i64,
+    /// When the lease expires
+    expiry: Instant,
+    /// Keys attached to the lease
+    keys: HashSet<Vec<u8>>,
+}
+
+impl Lease {
+    /// New `Lease` which expires after `ttl` seconds from now
+    pub(crate) fn new(id: i64, ttl: i64) -> Self {
+        let mut lease = Self {
+            id,
+            ttl,
+            checkpointed_ttl: 0,
+            expiry: Instant::now(),
+            keys: HashSet::new(),
+        };
+        lease.refresh(Duration::ZERO);
+        lease
+    }
+
+    /// Get the id of the lease
+    pub(crate) fn id(&self) -> i64 {
+        self.id
+    }
+
+    /// Get the granted ttl of the lease in seconds
+    pub(crate) fn ttl(&self) -> i64 {
+        self.ttl
+    }
+
+    /// Get the remaining ttl of the last checkpoint
+    pub(crate) fn checkpointed_ttl(&self) -> i64 {
+        self.checkpointed_ttl
+    }
+
+    /// Set the remaining ttl of the last checkpoint
+    pub(crate) fn set_checkpointed_ttl(&mut self, checkpointed_ttl: i64) {
+        self.checkpointed_ttl = checkpointed_ttl;
+    }
+
+    /// Reset the expiry of the lease to `extend` plus the checkpointed ttl, or the granted ttl
+    /// if it's not checkpointed, from now
+    pub(crate) fn refresh(&mut self, extend: Duration) {
+        let ttl = if self.checkpointed_ttl > 0 {
+            self.checkpointed_ttl
+        } else {
+            self.ttl
+        };
+        self.expiry = Instant::now() + extend + Duration::from_secs(ttl.cast());
+    }
+
+    /// Check if the lease has expired
+    pub(crate) fn is_expired(&self) -> bool {
+        self.expiry <= Instant::now()
+    }
+
+    /// Get the remaining ttl of the lease in seconds, rounded up
+    pub(crate) fn remaining_ttl(&self) -> i64 {
+        let remaining = self.expiry.saturating_duration_since(Instant::now());
+        let secs: i64 = remaining.as_secs().cast();
+        if remaining.subsec_nanos() > 0 {
+            secs.overflow_add(1)
+        } else {
+            secs
+        }
+    }
+
+    /// Get the keys attached to the lease
+    pub(crate) fn keys(&self) -> Vec<Vec<u8>> {
+        self.keys.iter().cloned().collect()
+    }
+
+    /// Attach a key to the lease
+    pub(crate) fn insert_key(&mut self, key: Vec<u8>) {
+        let _new = self.keys.insert(key);
+    }
+
+    /// Detach a key from the lease
+    pub(crate) fn remove_key(&mut self, key: &[u8]) {
+        let _existed = self.keys.remove(key);
+    }
+}
@@ -0,0 +1,257 @@
+use std::{collections::HashMap, time::Duration};
+
+use parking_lot::{Mutex, RwLock};
+use prost::Message;
+
+use super::lease::Lease;
+use crate::{
+    rpc::{LeaseCheckpoint, PbLease},
+    storage::{
+        db::LEASE_TABLE,
+        engine::{StorageEngine, WriteOp},
+    },
+};
+
+/// Collection of the leases, the leases are persisted in the storage engine while the keys
+/// attached to them are rebuilt from the kv store. The changes are applied in memory at once,
+/// and the write operations to persist them are returned to be committed with the command
+/// that makes them.
+#[derive(Debug)]
+pub(crate) struct LeaseCollection {
+    /// Leases indexed by id
+    leases: RwLock<HashMap<i64, Lease>>,
+    /// Checkpoints that reset the remaining ttl of the leases renewed after being checkpointed
+    pending_resets: Mutex<Vec<LeaseCheckpoint>>,
+}
+
+impl LeaseCollection {
+    /// New `LeaseCollection`, the leases are recovered from `engine` and expire after their
+    /// checkpointed ttl, or their granted ttl if not checkpointed, from now
+    pub(crate) fn new(engine: &dyn StorageEngine) -> Self {
+        let leases = engine
+            .range(LEASE_TABLE, &[], &[])
+            .unwrap_or_else(|e| panic!("Failed to read from storage: {e}"))
+            .into_iter()
+            .map(|(_key, value)| {
+                let lease = PbLease::decode(value.as_slice())
+                    .unwrap_or_else(|e| panic!("Failed to decode Lease: {e}"));
+                let mut recovered = Lease::new(lease.id, lease.ttl);
+                recovered.set_checkpointed_ttl(lease.remaining_ttl);
+                recovered.refresh(Duration::ZERO);
+                (lease.id, recovered)
+            })
+            .collect();
+        Self {
+            leases: RwLock::new(leases),
+            pending_resets: Mutex::new(vec![]),
+        }
+    }
+
+    /// Encode the id of a lease as a key in the engine
+    fn encode_key(id: i64) -> Vec<u8> {
+        id.to_be_bytes().to_vec()
+    }
+
+    /// Check if the lease exists
+    pub(crate) fn contains(&self, id: i64) -> bool {
+        self.leases.read().contains_key(&id)
+    }
+
+    /// Grant a lease with `ttl` seconds, return the write operation to persist it
+    pub(crate) fn grant(&self, id: i64, ttl: i64) -> WriteOp {
+        let lease = PbLease {
+            id,
+            ttl,
+            remaining_ttl: 0,
+        };
+        let _prev = self.leases.write().insert(id, Lease::new(id, ttl));
+        WriteOp::Put {
+            table: LEASE_TABLE,
+            key: Self::encode_key(id),
+            value: lease.encode_to_vec(),
+        }
+    }
+
+    /// Revoke a lease, return the write operation to remove it from the engine
+    pub(crate) fn revoke(&self, id: i64) -> WriteOp {
+        let _lease = self.leases.write().remove(&id);
+        WriteOp::Delete {
+            table: LEASE_TABLE,
+            key: Self::encode_key(id),
+        }
+    }
+
+    /// Attach a key to a lease, it's ignored if the lease doesn't exist
+    pub(crate) fn attach(&self, id: i64, key: Vec<u8>) {
+        if let Some(lease) = self.leases.write().get_mut(&id) {
+            lease.insert_key(key);
+        }
+    }
+
+    /// Detach a key from a lease
+    pub(crate) fn detach(&self, id: i64, key: &[u8]) {
+        if let Some(lease) = self.leases.write().get_mut(&id) {
+            lease.remove_key(key);
+        }
+    }
+
+    /// Renew a lease that has not expired and return its ttl
+    pub(crate) fn renew(&self, id: i64) -> Option<i64> {
+        self.leases
+            .write()
+            .get_mut(&id)
+            .filter(|lease| !lease.is_expired())
+            .map(|lease| {
+                if lease.checkpointed_ttl() > 0 {
+                    // the other members still hold the checkpointed ttl
+                    lease.set_checkpointed_ttl(0);
+                    self.pending_resets.lock().push(LeaseCheckpoint {
+                        id,
+                        remaining_ttl: 0,
+                    });
+                }
+                lease.refresh(Duration::ZERO);
+                lease.ttl()
+            })
+    }
+
+    /// Record the remaining ttl of the leases so that they don't restart from their granted
+    /// ttl when the leader changes or the server restarts, return the write operations to
+    /// persist them
+    pub(crate) fn checkpoint(&self, checkpoints: &[LeaseCheckpoint]) -> Vec<WriteOp> {
+        let mut leases = self.leases.write();
+        checkpoints
+            .iter()
+            .filter_map(|checkpoint| {
+                let lease = leases.get_mut(&checkpoint.id)?;
+                lease.set_checkpointed_ttl(checkpoint.remaining_ttl);
+                let pb_lease = PbLease {
+                    id: lease.id(),
+                    ttl: lease.ttl(),
+                    remaining_ttl: checkpoint.remaining_ttl,
+                };
+                Some(WriteOp::Put {
+                    table: LEASE_TABLE,
+                    key: Self::encode_key(lease.id()),
+                    value: pb_lease.encode_to_vec(),
+                })
+            })
+            .collect()
+    }
+
+    /// Get the remaining ttl of all the leases that have not expired
+    pub(crate) fn checkpoints(&self) -> Vec<LeaseCheckpoint> {
+        self.leases
+            .read()
+            .values()
+            .filter(|lease| !lease.is_expired())
+            .map(|lease| LeaseCheckpoint {
+                id: lease.id(),
+                remaining_ttl: lease.remaining_ttl(),
+            })
+            .collect()
+    }
+
+    /// Take the checkpoints that reset the remaining ttl of the renewed leases
+    pub(crate) fn take_pending_resets(&self) -> Vec<LeaseCheckpoint> {
+        std::mem::take(&mut *self.pending_resets.lock())
+    }
+
+    /// Get a lease
+    pub(crate) fn look_up(&self, id: i64) -> Option<Lease> {
+        self.leases.read().get(&id).cloned()
+    }
+
+    /// Get the ids of all the leases
+    pub(crate) fn ids(&self) -> Vec<i64> {
+        let mut ids: Vec<_> = self.leases.read().keys().copied().collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    /// Get the ids of the expired leases
+    pub(crate) fn expired(&self) -> Vec<i64> {
+        self.leases
+            .read()
+            .values()
+            .filter(|lease| lease.is_expired())
+            .map(Lease::id)
+            .collect()
+    }
+
+    /// Refresh all the leases and extend them by `extend`, it's called when the server becomes
+    /// the leader because the keep alive requests were sent to the previous leader
+    pub(crate) fn promote(&self, extend: Duration) {
+        self.leases
+            .write()
+            .values_mut()
+            .for_each(|lease| lease.refresh(extend));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::storage::engine::MemoryEngine;
+
+    #[test]
+    fn leases_should_be_recovered_from_engine() -> Result<(), Box<dyn std::error::Error>> {
+        let engine = MemoryEngine::new();
+        let leases = LeaseCollection::new(&engine);
+        let grants = vec![leases.grant(1, 10), leases.grant(2, 20)];
+        engine.write_batch(grants, true)?;
+        leases.attach(1, b"a".to_vec());
+        assert_eq!(leases.renew(1), Some(10));
+        assert_eq!(leases.renew(3), None);
+        engine.write_batch(vec![leases.revoke(1)], true)?;
+        assert!(!leases.contains(1));
+        // the lease is not persisted until the write operation is committed
+        let _op = leases.grant(3, 30);
+
+        let recovered = LeaseCollection::new(&engine);
+        assert_eq!(recovered.ids(), vec![2]);
+        assert_eq!(recovered.look_up(2).map(|lease| lease.ttl()), Some(20));
+        assert!(recovered.expired().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn checkpointed_ttl_should_survive_recovery_until_renewed(
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let engine = MemoryEngine::new();
+        let leases = LeaseCollection::new(&engine);
+        engine.write_batch(vec![leases.grant(1, 100)], true)?;
+        let checkpoints = leases.checkpoint(&[LeaseCheckpoint {
+            id: 1,
+            remaining_ttl: 10,
+        }]);
+        engine.write_batch(checkpoints, true)?;
+
+        let recovered = LeaseCollection::new(&engine);
+        assert_eq!(
+            recovered.look_up(1).map(|lease| lease.remaining_ttl()),
+            Some(10)
+        );
+        recovered.promote(Duration::from_secs(5));
+        assert_eq!(
+            recovered.look_up(1).map(|lease| lease.remaining_ttl()),
+            Some(15)
+        );
+
+        assert_eq!(recovered.renew(1), Some(100));
+        assert_eq!(
+            recovered.look_up(1).map(|lease| lease.remaining_ttl()),
+            Some(100)
+        );
+        let resets = recovered.take_pending_resets();
+        assert_eq!(
+            resets,
+            vec![LeaseCheckpoint {
+                id: 1,
+                remaining_ttl: 0
+            }]
+        );
+        assert!(recovered.take_pending_resets().is_empty());
+        Ok(())
+    }
+}
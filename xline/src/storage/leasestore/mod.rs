@@ -0,0 +1,21 @@
+/// Lease and its attached keys
+mod lease;
+/// Collection of leases
+mod lease_collection;
+
+pub(crate) use self::lease_collection::LeaseCollection;
+
+/// Max ttl of a lease in seconds, the same as etcd
+pub(crate) const MAX_LEASE_TTL: i64 = 9_000_000_000;
+
+/// Min ttl of a lease in seconds, a lease with a smaller ttl is granted with this one
+pub(crate) const MIN_LEASE_TTL: i64 = 1;
+
+/// Error message of a request on a lease that doesn't exist
+pub(crate) const LEASE_NOT_FOUND_ERROR: &str = "requested lease not found";
+
+/// Error message of granting a lease that already exists
+pub(crate) const LEASE_EXIST_ERROR: &str = "lease already exists";
+
+/// Error message of granting a lease with a too large ttl
+pub(crate) const LEASE_TTL_TOO_LARGE_ERROR: &str = "too large lease TTL";
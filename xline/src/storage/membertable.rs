@@ -0,0 +1,362 @@
+use std::collections::BTreeMap;
+
+use curp::{error::ExecuteError, ServerId};
+use log::debug;
+use parking_lot::RwLock;
+use prost::Message;
+
+use crate::{
+    rpc::{Attributes, Member, RequestWrapper},
+    storage::{
+        db::MEMBER_TABLE,
+        engine::{StorageEngine, WriteOp},
+        error::ExecuteErrorKind,
+    },
+};
+
+/// Error message of changing a member that doesn't exist
+pub(crate) const MEMBER_NOT_FOUND_ERROR: &str = "member not found";
+
+/// Error message of adding a member whose id already exists
+pub(crate) const MEMBER_EXIST_ERROR: &str = "member ID already exists";
+
+/// Error message of adding or updating a member with the peer URLs of another member
+pub(crate) const PEER_URL_EXIST_ERROR: &str = "Peer URLs already exists";
+
+/// Error message of promoting a member that is not a learner
+pub(crate) const MEMBER_NOT_LEARNER_ERROR: &str = "can only promote a learner member";
+
+/// Error message of adding or updating a member with invalid peer URLs
+pub(crate) const MEMBER_BAD_URLS_ERROR: &str = "given member URLs are invalid";
+
+/// Offset basis of the FNV-1a hash
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+
+/// Prime of the FNV-1a hash
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+/// The FNV-1a hash of `bytes`, it's stable across platforms and versions
+pub(crate) fn fnv_hash(bytes: impl IntoIterator<Item = u8>) -> u64 {
+    bytes.into_iter().fold(FNV_OFFSET_BASIS, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME)
+    })
+}
+
+/// Table of the cluster members. The members the cluster starts with are known by every
+/// member, while the name and the client URLs of a member are published by itself through
+/// curp and persisted in the storage engine, the same as etcd. Members are added, removed,
+/// updated and promoted through curp as well, the table only records them: the peers of the
+/// curp server are configured when it starts.
+#[derive(Debug)]
+pub(crate) struct MemberTable {
+    /// Members indexed by id
+    members: RwLock<BTreeMap<ServerId, Member>>,
+}
+
+impl MemberTable {
+    /// New `MemberTable` of the `initial` members, the recorded members are recovered from
+    /// `engine` and replace the initial ones, since they may have been changed through curp
+    pub(crate) fn new(
+        engine: &dyn StorageEngine,
+        initial: impl IntoIterator<Item = Member>,
+    ) -> Self {
+        let mut members: BTreeMap<_, _> = initial
+            .into_iter()
+            .map(|member| (member.id, member))
+            .collect();
+        let published = engine
+            .range(MEMBER_TABLE, &[], &[])
+            .unwrap_or_else(|e| panic!("Failed to read from storage: {e}"));
+        for (key, value) in published {
+            // a removed member is recorded without a value
+            if value.is_empty() {
+                let id = key.try_into().map_or_else(
+                    |_e| panic!("Failed to decode member id"),
+                    ServerId::from_be_bytes,
+                );
+                let _prev = members.remove(&id);
+                continue;
+            }
+            let member = Member::decode(value.as_slice())
+                .unwrap_or_else(|e| panic!("Failed to decode Member: {e}"));
+            let _prev = members.insert(member.id, member);
+        }
+        Self {
+            members: RwLock::new(members),
+        }
+    }
+
+    /// Encode the id of a member as a key in the engine
+    fn encode_key(id: ServerId) -> Vec<u8> {
+        id.to_be_bytes().to_vec()
+    }
+
+    /// Set the attributes published by the member `id` and return the write operation to
+    /// persist them, it's ignored if the member doesn't exist
+    pub(crate) fn publish(&self, id: ServerId, attributes: Attributes) -> Option<WriteOp> {
+        let mut members = self.members.write();
+        let member = members.get_mut(&id)?;
+        member.name = attributes.name;
+        member.client_ur_ls = attributes.client_urls;
+        Some(WriteOp::Put {
+            table: MEMBER_TABLE,
+            key: Self::encode_key(id),
+            value: member.encode_to_vec(),
+        })
+    }
+
+    /// Get all the members sorted by id
+    pub(crate) fn members(&self) -> Vec<Member> {
+        self.members.read().values().cloned().collect()
+    }
+
+    /// The id of the member added with `peer_urls`, it's the same on every member
+    fn member_id(peer_urls: &[String]) -> ServerId {
+        let mut peer_urls = peer_urls.to_vec();
+        peer_urls.sort_unstable();
+        fnv_hash(peer_urls.join(",").into_bytes())
+    }
+
+    /// Check the peer URLs of the member `id`, they must be valid and not used by any other
+    /// member
+    fn check_peer_urls(
+        members: &BTreeMap<ServerId, Member>,
+        id: ServerId,
+        peer_urls: &[String],
+    ) -> Result<(), ExecuteError> {
+        let valid = |url: &String| {
+            url.strip_prefix("http://")
+                .or_else(|| url.strip_prefix("https://"))
+                .is_some_and(|host| !host.is_empty())
+        };
+        if peer_urls.is_empty() || !peer_urls.iter().all(valid) {
+            return Err(ExecuteErrorKind::MemberBadUrls.into());
+        }
+        let used = members
+            .values()
+            .filter(|member| member.id != id)
+            .any(|member| member.peer_ur_ls.iter().any(|url| peer_urls.contains(url)));
+        if used {
+            return Err(ExecuteErrorKind::PeerUrlExist.into());
+        }
+        Ok(())
+    }
+
+    /// Apply the member change `request` to `members`, return the id of the changed member
+    /// and the member after the change, `None` if it's removed
+    fn change(
+        members: &mut BTreeMap<ServerId, Member>,
+        request: &RequestWrapper,
+    ) -> Result<(ServerId, Option<Member>), ExecuteError> {
+        #[allow(clippy::wildcard_enum_match_arm)]
+        match *request {
+            RequestWrapper::MemberAddRequest(ref req) => {
+                let id = Self::member_id(&req.peer_ur_ls);
+                Self::check_peer_urls(members, id, &req.peer_ur_ls)?;
+                if members.contains_key(&id) {
+                    return Err(ExecuteErrorKind::MemberExist.into());
+                }
+                let member = Member {
+                    id,
+                    name: String::new(),
+                    peer_ur_ls: req.peer_ur_ls.clone(),
+                    client_ur_ls: vec![],
+                    is_learner: req.is_learner,
+                };
+                let _prev = members.insert(id, member.clone());
+                Ok((id, Some(member)))
+            }
+            RequestWrapper::MemberRemoveRequest(ref req) => members
+                .remove(&req.id)
+                .map(|_member| (req.id, None))
+                .ok_or_else(|| ExecuteErrorKind::MemberNotFound.into()),
+            RequestWrapper::MemberUpdateRequest(ref req) => {
+                Self::check_peer_urls(members, req.id, &req.peer_ur_ls)?;
+                let member = members
+                    .get_mut(&req.id)
+                    .ok_or(ExecuteErrorKind::MemberNotFound)?;
+                member.peer_ur_ls.clone_from(&req.peer_ur_ls);
+                Ok((req.id, Some(member.clone())))
+            }
+            RequestWrapper::MemberPromoteRequest(ref req) => {
+                let member = members
+                    .get_mut(&req.id)
+                    .ok_or(ExecuteErrorKind::MemberNotFound)?;
+                if !member.is_learner {
+                    return Err(ExecuteErrorKind::MemberNotLearner.into());
+                }
+                member.is_learner = false;
+                Ok((req.id, Some(member.clone())))
+            }
+            _ => unreachable!("Other request is not a member change"),
+        }
+    }
+
+    /// Check the member change `request` without applying it, return the changed member and
+    /// all the members after the change
+    pub(crate) fn check(
+        &self,
+        request: &RequestWrapper,
+    ) -> Result<(Option<Member>, Vec<Member>), ExecuteError> {
+        let mut members = self.members.read().clone();
+        let (_id, member) = Self::change(&mut members, request)?;
+        Ok((member, members.into_values().collect()))
+    }
+
+    /// Apply the member change `request` and return the write operation to persist it. A
+    /// change rejected by the check is ignored, since the followers don't execute it.
+    pub(crate) fn apply(&self, request: &RequestWrapper) -> Option<WriteOp> {
+        let (id, member) = Self::change(&mut self.members.write(), request)
+            .map_err(|e| debug!("member change {request:?} is ignored: {e}"))
+            .ok()?;
+        Some(WriteOp::Put {
+            table: MEMBER_TABLE,
+            key: Self::encode_key(id),
+            value: member.map_or_else(Vec::new, |m| m.encode_to_vec()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        rpc::{MemberAddRequest, MemberPromoteRequest, MemberRemoveRequest, MemberUpdateRequest},
+        storage::engine::MemoryEngine,
+    };
+
+    fn member(id: ServerId) -> Member {
+        Member {
+            id,
+            name: String::new(),
+            peer_ur_ls: vec![format!("http://127.0.0.1:{id}")],
+            client_ur_ls: vec![],
+            is_learner: false,
+        }
+    }
+
+    #[test]
+    fn published_members_should_be_recovered_from_engine() -> Result<(), Box<dyn std::error::Error>>
+    {
+        let engine = MemoryEngine::new();
+        let members = MemberTable::new(&engine, [member(1), member(2)]);
+        let attributes = |name: &str| Attributes {
+            name: name.to_owned(),
+            client_urls: vec!["http://127.0.0.1:2379".to_owned()],
+        };
+        let published = members.publish(1, attributes("node1"));
+        assert!(members.publish(3, attributes("node3")).is_none());
+        assert_eq!(members.members().len(), 2);
+        engine.write_batch(published.into_iter().collect(), true)?;
+
+        let recovered = MemberTable::new(&engine, [member(1), member(2)]);
+        let names: Vec<_> = recovered
+            .members()
+            .into_iter()
+            .map(|m| (m.id, m.name))
+            .collect();
+        assert_eq!(names, vec![(1, "node1".to_owned()), (2, String::new())]);
+        assert_eq!(
+            recovered.members()[0].client_ur_ls,
+            vec!["http://127.0.0.1:2379".to_owned()]
+        );
+        Ok(())
+    }
+
+    fn error_message(request: &RequestWrapper, members: &MemberTable) -> Option<String> {
+        members.check(request).err().map(|e| e.to_string())
+    }
+
+    #[test]
+    fn member_changes_should_be_checked() {
+        let members = MemberTable::new(&MemoryEngine::new(), [member(1), member(2)]);
+        let add = |urls: &[&str]| {
+            RequestWrapper::from(MemberAddRequest {
+                peer_ur_ls: urls.iter().map(|&url| url.to_owned()).collect(),
+                is_learner: true,
+            })
+        };
+        assert_eq!(
+            error_message(&add(&[]), &members),
+            Some(ExecuteErrorKind::MemberBadUrls.message().to_owned())
+        );
+        assert_eq!(
+            error_message(&add(&["127.0.0.1:3"]), &members),
+            Some(ExecuteErrorKind::MemberBadUrls.message().to_owned())
+        );
+        assert_eq!(
+            error_message(&add(&["http://127.0.0.1:2"]), &members),
+            Some(ExecuteErrorKind::PeerUrlExist.message().to_owned())
+        );
+        let remove = RequestWrapper::from(MemberRemoveRequest { id: 3 });
+        assert_eq!(
+            error_message(&remove, &members),
+            Some(ExecuteErrorKind::MemberNotFound.message().to_owned())
+        );
+        let update = RequestWrapper::from(MemberUpdateRequest {
+            id: 1,
+            peer_ur_ls: vec!["http://127.0.0.1:2".to_owned()],
+        });
+        assert_eq!(
+            error_message(&update, &members),
+            Some(ExecuteErrorKind::PeerUrlExist.message().to_owned())
+        );
+        let promote = RequestWrapper::from(MemberPromoteRequest { id: 1 });
+        assert_eq!(
+            error_message(&promote, &members),
+            Some(ExecuteErrorKind::MemberNotLearner.message().to_owned())
+        );
+        assert!(members.apply(&promote).is_none());
+
+        let (added, after) = members
+            .check(&add(&["http://127.0.0.1:3"]))
+            .unwrap_or_else(|e| panic!("failed to check: {e}"));
+        assert!(added.is_some_and(|m| m.is_learner));
+        assert_eq!(after.len(), 3);
+        // checking doesn't change the members
+        assert_eq!(members.members().len(), 2);
+        assert!(members.apply(&add(&["http://127.0.0.1:3"])).is_some());
+        assert_eq!(
+            error_message(&add(&["http://127.0.0.1:3"]), &members),
+            Some(ExecuteErrorKind::MemberExist.message().to_owned())
+        );
+    }
+
+    #[test]
+    fn changed_members_should_be_recovered_from_engine() -> Result<(), Box<dyn std::error::Error>> {
+        let engine = MemoryEngine::new();
+        let members = MemberTable::new(&engine, [member(1), member(2)]);
+        let add = RequestWrapper::from(MemberAddRequest {
+            peer_ur_ls: vec!["http://127.0.0.1:3".to_owned()],
+            is_learner: true,
+        });
+        let id = members
+            .check(&add)
+            .ok()
+            .and_then(|(m, _members)| m)
+            .map_or_else(|| panic!("failed to add member"), |m| m.id);
+        let ops = [
+            add,
+            RequestWrapper::from(MemberPromoteRequest { id }),
+            RequestWrapper::from(MemberRemoveRequest { id: 1 }),
+            RequestWrapper::from(MemberUpdateRequest {
+                id: 2,
+                peer_ur_ls: vec!["http://127.0.0.1:4".to_owned()],
+            }),
+        ]
+        .iter()
+        .filter_map(|request| members.apply(request))
+        .collect();
+        engine.write_batch(ops, true)?;
+
+        let recovered = MemberTable::new(&engine, [member(1), member(2)]);
+        assert_eq!(recovered.members(), members.members());
+        let ids: Vec<_> = recovered.members().into_iter().map(|m| m.id).collect();
+        assert!(!ids.contains(&1));
+        assert!(recovered
+            .members()
+            .iter()
+            .all(|m| !m.is_learner && m.peer_ur_ls.len() == 1));
+        Ok(())
+    }
+}
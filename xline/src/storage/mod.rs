@@ -4,9 +4,18 @@ pub(crate) mod kvstore;
 /// Storage for Auth
 pub(crate) mod authstore;
 
+/// Storage for Lease
+pub(crate) mod leasestore;
+
+/// Storage for the cluster members
+pub(crate) mod membertable;
+
 /// Datebase module
 pub(crate) mod db;
 
+/// Storage engine module
+pub(crate) mod engine;
+
 /// Revision module
 pub(crate) mod revision;
 
@@ -16,5 +25,10 @@ pub(crate) mod index;
 /// KV watcher module
 pub(crate) mod kvwatcher;
 
+/// Errors of executing commands
+pub(crate) mod error;
+
 pub(crate) use self::authstore::AuthStore;
 pub(crate) use self::kvstore::KvStore;
+pub(crate) use self::leasestore::LeaseCollection;
+pub(crate) use self::membertable::MemberTable;
@@ -32,6 +32,11 @@ impl Revision {
     pub(crate) fn revision(&self) -> i64 {
         self.revision
     }
+
+    /// Get sub revision
+    pub(crate) fn sub_revision(&self) -> i64 {
+        self.sub_revision
+    }
 }
 
 impl KeyRevision {
@@ -0,0 +1,93 @@
+mod common;
+
+use std::{error::Error, time::Duration};
+
+use etcd_client::{ClusterClient, MemberAddOptions};
+
+use crate::common::Cluster;
+
+/// Wait until the member the client connects to lists `count` members, the members are listed
+/// without going through consensus so the list may fall behind the changes for a while
+async fn wait_for_members(client: &mut ClusterClient, count: usize) -> Result<(), Box<dyn Error>> {
+    for _ in 0..50 {
+        if client.member_list().await?.members().len() == count {
+            return Ok(());
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    Err(format!("the members are not changed to {count}").into())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 10)]
+async fn test_member_list_should_be_consistent_with_headers() -> Result<(), Box<dyn Error>> {
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let addrs = cluster.addrs().to_vec();
+    let client = cluster.client().await;
+    let mut cluster_client = client.cluster_client();
+
+    // the members publish their names and client URLs after they start
+    let mut res = cluster_client.member_list().await?;
+    for _ in 0..50 {
+        if res.members().iter().all(|member| !member.name().is_empty()) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        res = cluster_client.member_list().await?;
+    }
+    let members = res.members();
+    assert_eq!(members.len(), 3);
+    for (i, member) in members.iter().enumerate() {
+        assert_eq!(member.id(), i as u64);
+        assert_eq!(member.name(), format!("server{i}"));
+        assert!(!member.is_learner());
+        let url = format!("http://{}", addrs[i]);
+        assert_eq!(member.peer_urls(), [url.clone()]);
+        assert_eq!(member.client_urls(), [url]);
+    }
+
+    let header = res.header().ok_or("member list returns no header")?;
+    assert_ne!(header.cluster_id(), 0);
+    let member = members
+        .iter()
+        .find(|member| member.id() == header.member_id())
+        .ok_or("the member in the header is not listed")?;
+    let put_res = client.kv_client().put("foo", "bar", None).await?;
+    let put_header = put_res.header().ok_or("put returns no header")?;
+    assert_eq!(put_header.cluster_id(), header.cluster_id());
+
+    let url = "http://127.0.0.1:1".to_owned();
+    let add_res = cluster_client
+        .member_add(
+            [url.clone()],
+            Some(MemberAddOptions::new().with_is_learner()),
+        )
+        .await?;
+    let added = add_res.member().ok_or("member add returns no member")?;
+    assert!(added.is_learner());
+    assert!(cluster_client.member_add([url], None).await.is_err());
+    wait_for_members(&mut cluster_client, 4).await?;
+
+    assert!(cluster_client.member_promote(member.id()).await.is_err());
+    let promote_res = cluster_client.member_promote(added.id()).await?;
+    assert!(promote_res
+        .members()
+        .iter()
+        .any(|m| m.id() == added.id() && !m.is_learner()));
+
+    let new_url = "http://127.0.0.1:2".to_owned();
+    let update_res = cluster_client
+        .member_update(added.id(), [new_url.clone()])
+        .await?;
+    assert!(update_res
+        .members()
+        .iter()
+        .any(|m| m.id() == added.id() && m.peer_urls() == [new_url.clone()]));
+
+    let remove_res = cluster_client.member_remove(added.id()).await?;
+    assert_eq!(remove_res.members().len(), 3);
+    assert!(cluster_client.member_remove(added.id()).await.is_err());
+    wait_for_members(&mut cluster_client, 3).await?;
+
+    Ok(())
+}
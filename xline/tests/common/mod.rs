@@ -1,12 +1,24 @@
-use std::{collections::BTreeMap, net::SocketAddr};
+use std::{
+    collections::{BTreeMap, HashMap},
+    future,
+    net::SocketAddr,
+    path::PathBuf,
+};
+
+use parking_lot::Mutex;
 
+use curp::{codec::WireFormat, quorum::QuorumConfig, test_utils::network::Network};
 use jsonwebtoken::{DecodingKey, EncodingKey};
 use tokio::{
     net::TcpListener,
     sync::broadcast::{self, Sender},
+    task::JoinHandle,
     time::{self, Duration},
 };
-use xline::{client::Client, server::XlineServer};
+use xline::{
+    client::Client,
+    server::{AutoCompactionConfig, StorageConfig, XlineServer},
+};
 
 /// Cluster
 pub struct Cluster {
@@ -45,25 +57,38 @@ impl Cluster {
 
     /// Start `Cluster`
     pub(crate) async fn start(&mut self) {
+        self.start_with_auto_compaction(None).await;
+    }
+
+    /// Start `Cluster` whose members compact the kv store automatically
+    pub(crate) async fn start_with_auto_compaction(
+        &mut self,
+        auto_compaction: Option<AutoCompactionConfig>,
+    ) {
         let (stop_tx, _) = broadcast::channel(1);
         for i in 0..self.size {
-            let mut peers = self.addrs.clone();
-            peers.remove(i);
+            let mut peers = self.members();
+            let id = i as u64;
+            let self_addr = peers.remove(&id).unwrap();
             let name = format!("server{}", i);
             let is_leader = i == 0;
-            let leader_addr = self.addrs[0];
-            let self_addr = self.addrs[i];
             let mut rx = stop_tx.subscribe();
             let listener = self.listeners.remove(&i).unwrap();
 
             tokio::spawn(async move {
                 let server = XlineServer::new(
                     name,
+                    id,
                     peers,
                     is_leader,
-                    leader_addr,
+                    0,
                     self_addr,
+                    HashMap::new(),
+                    QuorumConfig::default(),
                     Self::test_key_pair(),
+                    StorageConfig::Memory,
+                    auto_compaction,
+                    WireFormat::default(),
                 )
                 .await;
                 let signal = async {
@@ -83,7 +108,7 @@ impl Cluster {
     /// Create or get the client with the specified index
     pub(crate) async fn client(&mut self) -> &mut Client {
         if self.client.is_none() {
-            let client = Client::new(0, self.addrs.clone(), true)
+            let client = Client::new(0, self.members(), true)
                 .await
                 .unwrap_or_else(|e| {
                     panic!("Client connect error: {:?}", e);
@@ -98,6 +123,15 @@ impl Cluster {
         &self.addrs
     }
 
+    /// Ids and addresses of members, the id of a member is its index in `addrs`
+    pub fn members(&self) -> HashMap<u64, SocketAddr> {
+        self.addrs
+            .iter()
+            .enumerate()
+            .map(|(id, &addr)| (id as u64, addr))
+            .collect()
+    }
+
     fn test_key_pair() -> Option<(EncodingKey, DecodingKey)> {
         let private_key = include_bytes!("../private.pem");
         let public_key = include_bytes!("../public.pem");
@@ -106,3 +140,152 @@ impl Cluster {
         Some((encoding_key, decoding_key))
     }
 }
+
+/// A cluster whose members talk to each other and to the clients through a `Network`, so that
+/// the members can be partitioned, killed and restarted. Member 0 is the initial leader. The
+/// members of a persistent cluster keep their data in `data_dir` across restarts.
+#[allow(dead_code)] // used in test but get warning
+pub struct FaultyCluster {
+    pub network: Network,
+    /// Tasks serving the running members
+    servers: Mutex<HashMap<u64, JoinHandle<()>>>,
+    /// Dir of the data of the members, they keep their data in memory if it's `None`
+    data_dir: Option<PathBuf>,
+}
+
+#[allow(dead_code)] // used in test but get warning
+impl FaultyCluster {
+    /// Start a `FaultyCluster` of `size` members
+    pub(crate) async fn start(size: usize) -> Self {
+        Self::start_with_data_dir(size, None).await
+    }
+
+    /// Start a `FaultyCluster` of `size` members that persist their data in a temp dir
+    pub(crate) async fn start_persistent(size: usize) -> Self {
+        let data_dir = std::env::temp_dir().join(format!("xline-cluster-{}", uuid::Uuid::new_v4()));
+        Self::start_with_data_dir(size, Some(data_dir)).await
+    }
+
+    /// Start a `FaultyCluster` of `size` members
+    async fn start_with_data_dir(size: usize, data_dir: Option<PathBuf>) -> Self {
+        let mut listeners = Vec::with_capacity(size);
+        for _ in 0..size {
+            listeners.push(TcpListener::bind("127.0.0.1:0").await.unwrap());
+        }
+        let addrs = listeners
+            .iter()
+            .enumerate()
+            .map(|(id, l)| (id as u64, l.local_addr().unwrap()))
+            .collect();
+        let cluster = Self {
+            network: Network::new(&addrs).await.unwrap(),
+            servers: Mutex::new(HashMap::new()),
+            data_dir,
+        };
+        for (id, listener) in listeners.into_iter().enumerate() {
+            cluster.start_member(id as u64, 0, listener).await;
+        }
+        // Sleep 30ms, wait for the servers to start
+        time::sleep(Duration::from_millis(30)).await;
+        cluster
+    }
+
+    /// Start the member `id` on `listener`, the member `leader` is the current leader
+    async fn start_member(&self, id: u64, leader: u64, listener: TcpListener) {
+        let peers = self.network.peer_addrs(id);
+        let self_addr = listener.local_addr().unwrap();
+        let storage = self.data_dir.as_ref().map_or(StorageConfig::Memory, |dir| {
+            StorageConfig::Disk(dir.join(id.to_string()))
+        });
+        let server = XlineServer::new(
+            format!("server{id}"),
+            id,
+            peers,
+            id == leader,
+            leader,
+            self_addr,
+            HashMap::new(),
+            QuorumConfig::default(),
+            Cluster::test_key_pair(),
+            storage,
+            None,
+            WireFormat::default(),
+        )
+        .await;
+        let handle = tokio::spawn(async move {
+            let result = server
+                .start_from_listener_shoutdown(listener, future::pending())
+                .await;
+            if let Err(e) = result {
+                panic!("Server start error: {e}");
+            }
+        });
+        self.servers.lock().insert(id, handle);
+    }
+
+    /// Kill the member `id`, its state is lost unless the cluster is persistent
+    pub fn kill(&self, id: u64) {
+        self.network.isolate(id);
+        if let Some(handle) = self.servers.lock().remove(&id) {
+            handle.abort();
+        }
+    }
+
+    /// Restart the killed member `id` as a follower of `leader`, its state is recovered if the
+    /// cluster is persistent, otherwise it's empty
+    pub async fn restart(&self, id: u64, leader: u64) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        self.network.redirect(id, listener.local_addr().unwrap());
+        self.start_member(id, leader, listener).await;
+        self.network.heal(id);
+    }
+
+    /// Create a client of the cluster whose leader is `leader`
+    pub async fn client(&self, leader: u64) -> Client {
+        Client::new(leader, self.network.client_addrs(), true)
+            .await
+            .unwrap_or_else(|e| {
+                panic!("Client connect error: {:?}", e);
+            })
+    }
+
+    /// Wait until the running members other than `old` agree on a leader other than `old`
+    pub async fn wait_for_new_leader(&self, old: u64) -> u64 {
+        let addrs = self.network.client_addrs();
+        time::timeout(Duration::from_secs(10), async {
+            loop {
+                let running: Vec<_> = self.servers.lock().keys().copied().collect();
+                let mut leaders = vec![];
+                for id in running.iter().filter(|&&id| id != old) {
+                    let leader = match etcd_client::Client::connect([addrs[id].to_string()], None)
+                        .await
+                    {
+                        Ok(mut client) => client.status().await.map(|status| status.leader()).ok(),
+                        Err(_) => None,
+                    };
+                    leaders.push(leader);
+                }
+                if let Some(&Some(leader)) = leaders.first() {
+                    // a member without a leader reports 0, so member 0 is never the new leader
+                    if leader != old && leader != 0 && leaders.iter().all(|&l| l == Some(leader)) {
+                        return leader;
+                    }
+                }
+                time::sleep(Duration::from_millis(100)).await;
+            }
+        })
+        .await
+        .expect("a new leader should be elected")
+    }
+}
+
+impl Drop for FaultyCluster {
+    fn drop(&mut self) {
+        for (_id, handle) in self.servers.lock().drain() {
+            handle.abort();
+        }
+        if let Some(ref dir) = self.data_dir {
+            let _ignore = std::fs::remove_dir_all(dir);
+        }
+    }
+}
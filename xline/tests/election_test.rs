@@ -0,0 +1,90 @@
+mod common;
+
+use std::{error::Error, time::Duration};
+
+use etcd_client::{ProclaimOptions, ResignOptions};
+use tokio::time::timeout;
+
+use crate::common::Cluster;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 10)]
+async fn test_election_campaign_proclaim_and_resign() -> Result<(), Box<dyn Error>> {
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let client = cluster.client().await;
+    let mut election_client = client.election_client();
+    let mut lease_client = client.lease_client();
+
+    assert!(election_client.leader("election").await.is_err());
+
+    let lease1 = lease_client.grant(60, None).await?.id();
+    let lease2 = lease_client.grant(60, None).await?.id();
+    let res = election_client.campaign("election", "v1", lease1).await?;
+    let leader = res.leader().cloned().ok_or("campaign returns no leader")?;
+    let mut observer = election_client.observe("election").await?;
+    let res = election_client.leader("election").await?;
+    assert_eq!(res.kv().map(|kv| kv.value()), Some(b"v1".as_slice()));
+
+    let mut campaigner = client.election_client();
+    let handle = tokio::spawn(async move { campaigner.campaign("election", "v2", lease2).await });
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert!(!handle.is_finished());
+
+    let _res = election_client
+        .proclaim(
+            "v3",
+            Some(ProclaimOptions::new().with_leader(leader.clone())),
+        )
+        .await?;
+    let _res = election_client
+        .resign(Some(ResignOptions::new().with_leader(leader)))
+        .await?;
+    let res = timeout(Duration::from_secs(3), handle).await???;
+    assert_eq!(res.leader().map(|leader| leader.lease()), Some(lease2));
+
+    let mut values = vec![];
+    while values.len() < 3 {
+        let res = timeout(Duration::from_secs(3), observer.message())
+            .await??
+            .ok_or("observe stream is closed")?;
+        if let Some(kv) = res.kv() {
+            values.push(kv.value().to_vec());
+        }
+    }
+    assert_eq!(values, vec![b"v1".to_vec(), b"v3".to_vec(), b"v2".to_vec()]);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 10)]
+async fn test_election_should_skip_canceled_campaigners() -> Result<(), Box<dyn Error>> {
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let client = cluster.client().await;
+    let mut election_client = client.election_client();
+    let mut lease_client = client.lease_client();
+
+    let lease1 = lease_client.grant(60, None).await?.id();
+    let lease3 = lease_client.grant(60, None).await?.id();
+    let res = election_client.campaign("election", "v1", lease1).await?;
+    let leader = res.leader().cloned().ok_or("campaign returns no leader")?;
+
+    // the canceled campaigner runs in a session of the server
+    let mut canceled = client.election_client();
+    let canceled_handle = tokio::spawn(async move { canceled.campaign("election", "v2", 0).await });
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    canceled_handle.abort();
+
+    let mut campaigner = client.election_client();
+    let handle = tokio::spawn(async move { campaigner.campaign("election", "v3", lease3).await });
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert!(!handle.is_finished());
+
+    let _res = election_client
+        .resign(Some(ResignOptions::new().with_leader(leader)))
+        .await?;
+    let res = timeout(Duration::from_secs(3), handle).await???;
+    assert_eq!(res.leader().map(|leader| leader.lease()), Some(lease3));
+
+    Ok(())
+}
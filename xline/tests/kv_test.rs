@@ -1,9 +1,11 @@
 mod common;
 
-use std::error::Error;
+use std::{error::Error, time::Duration};
 
-use xline::client::kv_types::{
-    DeleteRangeRequest, PutRequest, RangeRequest, SortOrder, SortTarget,
+use etcd_client::{GetOptions, WatchOptions};
+use xline::{
+    client::kv_types::{DeleteRangeRequest, PutRequest, RangeRequest, SortOrder, SortTarget},
+    server::AutoCompactionConfig,
 };
 
 use crate::common::Cluster;
@@ -63,10 +65,10 @@ async fn test_kv_get() -> Result<(), Box<dyn Error>> {
             req: RangeRequest::new("a"),
             want_kvs: &want_kvs[..1],
         },
-        // TestCase {
-        //     req: RangeRequest::new("a").with_serializable(true),
-        //     want_kvs: &want_kvs[..1],
-        // },
+        TestCase {
+            req: RangeRequest::new("a").with_serializable(true),
+            want_kvs: &want_kvs[..1],
+        },
         TestCase {
             req: RangeRequest::new("a").with_range_end("c"),
             want_kvs: &want_kvs[..2],
@@ -138,6 +140,28 @@ async fn test_kv_get() -> Result<(), Box<dyn Error>> {
                 .with_sort_order(SortOrder::Descend),
             want_kvs: &reversed_kvs[..],
         },
+        TestCase {
+            req: RangeRequest::new("").with_prefix().with_keys_only(true),
+            want_kvs: &want_kvs[..],
+        },
+        TestCase {
+            req: RangeRequest::new("")
+                .with_prefix()
+                .with_min_mod_revision(7)
+                .with_limit(2),
+            want_kvs: &want_kvs[3..5],
+        },
+        TestCase {
+            req: RangeRequest::new("").with_prefix().with_max_mod_revision(6),
+            want_kvs: &want_kvs[..3],
+        },
+        TestCase {
+            req: RangeRequest::new("")
+                .with_prefix()
+                .with_min_create_revision(4)
+                .with_max_create_revision(8),
+            want_kvs: &want_kvs[2..5],
+        },
     ];
 
     for key in kvs {
@@ -155,6 +179,11 @@ async fn test_kv_get() -> Result<(), Box<dyn Error>> {
         assert!(is_identical);
     }
 
+    let res = client
+        .range(RangeRequest::new("").with_prefix().with_keys_only(true))
+        .await?;
+    assert!(res.kvs.iter().all(|kv| kv.value.is_empty()));
+
     Ok(())
 }
 
@@ -229,3 +258,76 @@ async fn test_kv_delete() -> Result<(), Box<dyn Error>> {
 
     Ok(())
 }
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 10)]
+async fn test_kv_compact() -> Result<(), Box<dyn Error>> {
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let client = cluster.client().await;
+
+    for value in ["v1", "v2", "v3"] {
+        client.put(PutRequest::new("foo", value)).await?;
+    }
+    let mut kv_client = client.kv_client();
+    let _res = kv_client.compact(3, None).await?;
+
+    let res = kv_client
+        .get("foo", Some(GetOptions::new().with_revision(2)))
+        .await;
+    assert!(matches!(res, Err(ref e) if e.to_string().contains("compacted")));
+    let res = kv_client
+        .get("foo", Some(GetOptions::new().with_revision(3)))
+        .await?;
+    assert_eq!(res.kvs().first().map(|kv| kv.value()), Some(&b"v2"[..]));
+    let res = kv_client.get("foo", None).await?;
+    assert_eq!(res.kvs().first().map(|kv| kv.value()), Some(&b"v3"[..]));
+
+    assert!(kv_client.compact(3, None).await.is_err());
+    assert!(kv_client.compact(10, None).await.is_err());
+
+    let (_watcher, mut stream) = client
+        .watch_client()
+        .watch("foo", Some(WatchOptions::new().with_start_revision(2)))
+        .await?;
+    let mut compact_revision = 0;
+    while let Some(res) = stream.message().await? {
+        if res.canceled() {
+            compact_revision = res.compact_revision();
+            break;
+        }
+    }
+    assert_eq!(compact_revision, 3);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 10)]
+async fn test_kv_auto_compact_in_revision_mode() -> Result<(), Box<dyn Error>> {
+    let mut cluster = Cluster::new(3).await;
+    cluster
+        .start_with_auto_compaction(Some(AutoCompactionConfig::Revision(
+            2,
+            Duration::from_secs(1),
+        )))
+        .await;
+    let client = cluster.client().await;
+
+    for value in ["v1", "v2", "v3", "v4", "v5"] {
+        client.put(PutRequest::new("foo", value)).await?;
+    }
+    // the revision is 6, the leader compacts to revision 4 at the next check
+    tokio::time::sleep(Duration::from_secs(3)).await;
+
+    let mut kv_client = client.kv_client();
+    let res = kv_client
+        .get("foo", Some(GetOptions::new().with_revision(3)))
+        .await;
+    assert!(matches!(res, Err(ref e) if e.to_string().contains("compacted")));
+    let res = kv_client
+        .get("foo", Some(GetOptions::new().with_revision(4)))
+        .await?;
+    assert_eq!(res.kvs().first().map(|kv| kv.value()), Some(&b"v3"[..]));
+    assert!(kv_client.compact(4, None).await.is_err());
+
+    Ok(())
+}
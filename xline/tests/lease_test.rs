@@ -0,0 +1,106 @@
+mod common;
+
+use std::{error::Error, time::Duration};
+
+use etcd_client::{LeaseTimeToLiveOptions, PutOptions};
+
+use crate::common::{Cluster, FaultyCluster};
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 10)]
+async fn test_lease_revoke_should_delete_attached_keys() -> Result<(), Box<dyn Error>> {
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let client = cluster.client().await;
+    let mut lease_client = client.lease_client();
+    let mut kv_client = client.kv_client();
+
+    let lease_id = lease_client.grant(60, None).await?.id();
+    let _res = kv_client
+        .put("foo", "bar", Some(PutOptions::new().with_lease(lease_id)))
+        .await?;
+    assert!(kv_client
+        .put(
+            "bar",
+            "foo",
+            Some(PutOptions::new().with_lease(lease_id.wrapping_add(1)))
+        )
+        .await
+        .is_err());
+
+    let res = lease_client
+        .time_to_live(lease_id, Some(LeaseTimeToLiveOptions::new().with_keys()))
+        .await?;
+    assert_eq!(res.granted_ttl(), 60);
+    assert_eq!(res.keys(), &[b"foo".to_vec()]);
+    let res = lease_client.leases().await?;
+    assert!(res.leases().iter().any(|lease| lease.id() == lease_id));
+
+    let (mut keeper, mut stream) = lease_client.keep_alive(lease_id).await?;
+    keeper.keep_alive().await?;
+    assert_eq!(stream.message().await?.map(|res| res.ttl()), Some(60));
+
+    let _res = lease_client.revoke(lease_id).await?;
+    assert!(kv_client.get("foo", None).await?.kvs().is_empty());
+    assert!(lease_client.revoke(lease_id).await.is_err());
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 10)]
+async fn test_lease_should_expire_without_keep_alive() -> Result<(), Box<dyn Error>> {
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let client = cluster.client().await;
+    let mut lease_client = client.lease_client();
+    let mut kv_client = client.kv_client();
+
+    let lease_id = lease_client.grant(1, None).await?.id();
+    let _res = kv_client
+        .put("foo", "bar", Some(PutOptions::new().with_lease(lease_id)))
+        .await?;
+
+    tokio::time::sleep(Duration::from_secs(3)).await;
+    assert!(kv_client.get("foo", None).await?.kvs().is_empty());
+    assert_eq!(lease_client.time_to_live(lease_id, None).await?.ttl(), -1);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 10)]
+async fn test_lease_keep_alive_should_be_forwarded_to_leader() -> Result<(), Box<dyn Error>> {
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let addrs = cluster.addrs().to_vec();
+    let client = cluster.client().await;
+    let mut lease_client = client.lease_client();
+    let lease_id = lease_client.grant(60, None).await?.id();
+
+    // server0 is the leader, the others forward the keep alive requests to it
+    for addr in addrs.iter().skip(1) {
+        let mut follower = etcd_client::Client::connect([addr.to_string()], None).await?;
+        let (mut keeper, mut stream) = follower.lease_keep_alive(lease_id).await?;
+        keeper.keep_alive().await?;
+        assert_eq!(stream.message().await?.map(|res| res.ttl()), Some(60));
+    }
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 10)]
+async fn test_lease_should_survive_leader_failure() -> Result<(), Box<dyn Error>> {
+    let cluster = FaultyCluster::start(3).await;
+    let addrs = cluster.network.client_addrs();
+    let mut leader = etcd_client::Client::connect([addrs[&0].to_string()], None).await?;
+    let lease_id = leader.lease_grant(3, None).await?.id();
+
+    cluster.kill(0);
+    let new_leader = cluster.wait_for_new_leader(0).await;
+    // the lease would have expired if the new leader didn't extend it
+    tokio::time::sleep(Duration::from_secs(4)).await;
+    let mut new_leader =
+        etcd_client::Client::connect([addrs[&new_leader].to_string()], None).await?;
+    let ttl = new_leader.lease_time_to_live(lease_id, None).await?.ttl();
+    assert!(ttl > 0 && ttl <= 3 + 2, "unexpected ttl {ttl}");
+
+    Ok(())
+}
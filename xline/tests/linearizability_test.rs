@@ -0,0 +1,185 @@
+mod common;
+
+use std::{
+    error::Error,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use curp::test_utils::linearizability::{History, KvInput, KvOutput};
+use rand::Rng;
+use tokio::{
+    task::JoinHandle,
+    time::{self, Duration},
+};
+use xline::client::{
+    kv_types::{PutRequest, RangeRequest},
+    Client,
+};
+
+use crate::common::{Cluster, FaultyCluster};
+
+/// Number of concurrent clients
+const N_CLIENTS: usize = 4;
+/// Number of operations issued by each client
+const N_OPS: usize = 30;
+/// Keys operated on, few of them so that operations conflict often
+const KEYS: [&str; 2] = ["a", "b"];
+/// Timeout of an operation, an operation that times out is left uncompleted in the history
+const OP_TIMEOUT: Duration = Duration::from_secs(5);
+/// Pause between two operations of a client when failures are injected, so that the workload
+/// lasts until the cluster recovers
+const OP_INTERVAL: Duration = Duration::from_millis(20);
+/// Pause of a client after its operation fails. An uncompleted put may take effect at any time
+/// after it's invoked, so the clients back off to keep the history small enough to check.
+const FAILURE_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Issue a random operation on `client` and record it in `history`, returns whether it
+/// completes. An operation that fails is left uncompleted in the history.
+async fn random_op(client: &mut Client, history: &History, value: String) -> bool {
+    let (key, is_put) = {
+        let mut rng = rand::thread_rng();
+        (KEYS[rng.gen_range(0..KEYS.len())].to_owned(), rng.gen())
+    };
+    if is_put {
+        let op = history.invoke(KvInput::Put {
+            key: key.clone(),
+            value: value.clone(),
+        });
+        let res = time::timeout(OP_TIMEOUT, client.put(PutRequest::new(key, value))).await;
+        if let Ok(Ok(_)) = res {
+            history.complete(op, KvOutput::Put);
+            return true;
+        }
+    } else {
+        let op = history.invoke(KvInput::Get { key: key.clone() });
+        let res = time::timeout(OP_TIMEOUT, client.range(RangeRequest::new(key))).await;
+        if let Ok(Ok(res)) = res {
+            let value = res
+                .kvs
+                .first()
+                .map(|kv| String::from_utf8(kv.value.clone()).unwrap());
+            history.complete(op, KvOutput::Get(value));
+            return true;
+        }
+    }
+    false
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 10)]
+async fn test_kv_linearizable() -> Result<(), Box<dyn Error>> {
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let history = History::new();
+
+    let mut handles = vec![];
+    for c in 0..N_CLIENTS {
+        let mut client = Client::new(0, cluster.members(), true).await?;
+        let history = history.clone();
+        handles.push(tokio::spawn(async move {
+            for i in 0..N_OPS {
+                let _completed = random_op(&mut client, &history, format!("{c}-{i}")).await;
+            }
+        }));
+    }
+    for handle in handles {
+        handle.await?;
+    }
+
+    assert_eq!(history.len(), N_CLIENTS * N_OPS);
+    assert!(history.is_linearizable());
+
+    Ok(())
+}
+
+/// Spawn clients issuing random operations to the cluster until they are stopped, the clients
+/// reconnect when `leader` changes
+fn spawn_workload(
+    cluster: &Arc<FaultyCluster>,
+    leader: &Arc<AtomicU64>,
+    history: &History,
+) -> (Arc<AtomicBool>, Vec<JoinHandle<()>>) {
+    let stop = Arc::new(AtomicBool::new(false));
+    let handles = (0..N_CLIENTS)
+        .map(|c| {
+            let cluster = Arc::clone(cluster);
+            let leader = Arc::clone(leader);
+            let history = history.clone();
+            let stop = Arc::clone(&stop);
+            tokio::spawn(async move {
+                let mut connected = leader.load(Ordering::Relaxed);
+                let mut client = cluster.client(connected).await;
+                let mut i = 0;
+                while !stop.load(Ordering::Relaxed) {
+                    let current = leader.load(Ordering::Relaxed);
+                    if current != connected {
+                        connected = current;
+                        client = cluster.client(connected).await;
+                    }
+                    let completed = random_op(&mut client, &history, format!("{c}-{i}")).await;
+                    i += 1;
+                    time::sleep(if completed {
+                        OP_INTERVAL
+                    } else {
+                        FAILURE_BACKOFF
+                    })
+                    .await;
+                }
+            })
+        })
+        .collect();
+    (stop, handles)
+}
+
+/// Stop the workload and wait for it
+async fn stop_workload(stop: &AtomicBool, handles: Vec<JoinHandle<()>>) {
+    stop.store(true, Ordering::Relaxed);
+    for handle in handles {
+        handle.await.unwrap();
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 10)]
+async fn test_kv_linearizable_with_partitioned_leader() {
+    let cluster = Arc::new(FaultyCluster::start(3).await);
+    time::sleep(Duration::from_secs(1)).await;
+    let leader = Arc::new(AtomicU64::new(0));
+    let history = History::new();
+    let (stop, handles) = spawn_workload(&cluster, &leader, &history);
+
+    time::sleep(Duration::from_millis(500)).await;
+    cluster.network.isolate(0);
+    leader.store(cluster.wait_for_new_leader(0).await, Ordering::Relaxed);
+    time::sleep(Duration::from_millis(500)).await;
+    // the old leader rejoins as a follower
+    cluster.network.heal(0);
+    time::sleep(Duration::from_millis(500)).await;
+    stop_workload(&stop, handles).await;
+
+    assert!(!history.is_empty());
+    assert!(history.is_linearizable());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 10)]
+async fn test_kv_linearizable_with_restarted_leader() {
+    let cluster = Arc::new(FaultyCluster::start(3).await);
+    time::sleep(Duration::from_secs(1)).await;
+    let leader = Arc::new(AtomicU64::new(0));
+    let history = History::new();
+    let (stop, handles) = spawn_workload(&cluster, &leader, &history);
+
+    time::sleep(Duration::from_millis(500)).await;
+    cluster.kill(0);
+    let new_leader = cluster.wait_for_new_leader(0).await;
+    leader.store(new_leader, Ordering::Relaxed);
+    time::sleep(Duration::from_millis(500)).await;
+    // the restarted leader catches up with the new leader as a follower
+    cluster.restart(0, new_leader).await;
+    time::sleep(Duration::from_millis(1000)).await;
+    stop_workload(&stop, handles).await;
+
+    assert!(!history.is_empty());
+    assert!(history.is_linearizable());
+}
@@ -0,0 +1,129 @@
+mod common;
+
+use std::{error::Error, time::Duration};
+
+use etcd_client::LockOptions;
+use tokio::time::timeout;
+
+use crate::common::Cluster;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 10)]
+async fn test_lock_should_be_acquired_in_order() -> Result<(), Box<dyn Error>> {
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let client = cluster.client().await;
+    let mut lock_client = client.lock_client();
+    let mut lease_client = client.lease_client();
+
+    let lease1 = lease_client.grant(60, None).await?.id();
+    let lease2 = lease_client.grant(60, None).await?.id();
+    let res = lock_client
+        .lock("lock", Some(LockOptions::new().with_lease(lease1)))
+        .await?;
+    let key1 = res.key().to_vec();
+    assert!(key1.starts_with(b"lock/"));
+
+    let mut waiter = client.lock_client();
+    let handle = tokio::spawn(async move {
+        waiter
+            .lock("lock", Some(LockOptions::new().with_lease(lease2)))
+            .await
+    });
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert!(!handle.is_finished());
+
+    let _res = lock_client.unlock(key1.clone()).await?;
+    let res = timeout(Duration::from_secs(3), handle).await???;
+    assert!(res.key().starts_with(b"lock/"));
+    assert_ne!(res.key(), key1.as_slice());
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 10)]
+async fn test_lock_should_be_released_when_lease_expires() -> Result<(), Box<dyn Error>> {
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let client = cluster.client().await;
+    let mut lock_client = client.lock_client();
+    let mut lease_client = client.lease_client();
+
+    let lease1 = lease_client.grant(1, None).await?.id();
+    let lease2 = lease_client.grant(60, None).await?.id();
+    let _res = lock_client
+        .lock("lock", Some(LockOptions::new().with_lease(lease1)))
+        .await?;
+    let res = timeout(
+        Duration::from_secs(5),
+        lock_client.lock("lock", Some(LockOptions::new().with_lease(lease2))),
+    )
+    .await??;
+    assert!(res.key().starts_with(b"lock/"));
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 10)]
+async fn test_lock_should_skip_canceled_waiters() -> Result<(), Box<dyn Error>> {
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let client = cluster.client().await;
+    let mut lock_client = client.lock_client();
+    let mut lease_client = client.lease_client();
+
+    let lease1 = lease_client.grant(60, None).await?.id();
+    let lease2 = lease_client.grant(60, None).await?.id();
+    let lease3 = lease_client.grant(60, None).await?.id();
+    let res = lock_client
+        .lock("lock", Some(LockOptions::new().with_lease(lease1)))
+        .await?;
+    let key1 = res.key().to_vec();
+
+    let mut canceled = client.lock_client();
+    let canceled_handle = tokio::spawn(async move {
+        canceled
+            .lock("lock", Some(LockOptions::new().with_lease(lease2)))
+            .await
+    });
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    canceled_handle.abort();
+
+    let mut waiter = client.lock_client();
+    let handle = tokio::spawn(async move {
+        waiter
+            .lock("lock", Some(LockOptions::new().with_lease(lease3)))
+            .await
+    });
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert!(!handle.is_finished());
+
+    // the key of the canceled waiter doesn't block the next waiter until its lease expires
+    let _res = lock_client.unlock(key1).await?;
+    let res = timeout(Duration::from_secs(3), handle).await???;
+    assert!(res.key().starts_with(b"lock/"));
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 10)]
+async fn test_session_lease_should_be_revoked_after_unlock() -> Result<(), Box<dyn Error>> {
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let client = cluster.client().await;
+    let mut lock_client = client.lock_client();
+    let mut lease_client = client.lease_client();
+
+    let res = lock_client.lock("lock", None).await?;
+    let key = res.key().to_vec();
+    let suffix = std::str::from_utf8(key.strip_prefix(b"lock/").unwrap_or_default())?;
+    let lease = i64::from_str_radix(suffix, 16)?;
+    let res = lease_client.time_to_live(lease, None).await?;
+    assert!(res.ttl() > 0);
+
+    let _res = lock_client.unlock(key).await?;
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let res = lease_client.time_to_live(lease, None).await?;
+    assert_eq!(res.ttl(), -1);
+
+    Ok(())
+}
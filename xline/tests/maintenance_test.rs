@@ -0,0 +1,27 @@
+mod common;
+
+use std::{error::Error, time::Duration};
+
+use crate::common::Cluster;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 10)]
+async fn test_status_should_report_the_consensus_state() -> Result<(), Box<dyn Error>> {
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let client = cluster.client().await;
+    let put_res = client.kv_client().put("foo", "bar", None).await?;
+    let put_header = put_res.header().ok_or("put returns no header")?;
+
+    // wait for the followers to learn the commit index
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let res = client.maintenance_client().status().await?;
+    let header = res.header().ok_or("status returns no header")?;
+    assert_eq!(header.cluster_id(), put_header.cluster_id());
+    assert_eq!(res.raft_term(), put_header.raft_term());
+    assert!(res.raft_index() >= 1);
+    assert!(res.raft_applied_index() >= 1);
+    assert!(!res.version().is_empty());
+    assert!(!res.is_learner());
+
+    Ok(())
+}
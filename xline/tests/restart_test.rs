@@ -0,0 +1,56 @@
+mod common;
+
+use std::error::Error;
+
+use tokio::time::{self, Duration};
+use xline::client::kv_types::{PutRequest, RangeRequest};
+
+use crate::common::FaultyCluster;
+
+/// Number of members of the cluster
+const N_MEMBERS: u64 = 3;
+/// Timeout of an operation
+const OP_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Put `value` to `key` through any member until one of them leads the cluster, return the
+/// leader
+async fn put_to_leader(cluster: &FaultyCluster, key: &str, value: &str) -> u64 {
+    time::timeout(Duration::from_secs(20), async {
+        for leader in (0..N_MEMBERS).cycle() {
+            let mut client = cluster.client(leader).await;
+            let put = client.put(PutRequest::new(key, value));
+            if let Ok(Ok(_)) = time::timeout(OP_TIMEOUT, put).await {
+                return leader;
+            }
+            time::sleep(Duration::from_millis(200)).await;
+        }
+        unreachable!("the members are cycled forever")
+    })
+    .await
+    .expect("a leader should be elected")
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 10)]
+async fn writes_survive_a_restart_of_every_member() -> Result<(), Box<dyn Error>> {
+    let cluster = FaultyCluster::start_persistent(N_MEMBERS as usize).await;
+    put_to_leader(&cluster, "foo", "bar").await;
+    // the put is replicated to every member before they are killed
+    time::sleep(Duration::from_secs(1)).await;
+
+    for id in 0..N_MEMBERS {
+        cluster.kill(id);
+    }
+    // the initial leader starts an election once it's back, so it's restarted last
+    for id in (0..N_MEMBERS).rev() {
+        cluster.restart(id, 0).await;
+    }
+    let leader = put_to_leader(&cluster, "baz", "qux").await;
+
+    let mut client = cluster.client(leader).await;
+    for (key, value) in [("foo", "bar"), ("baz", "qux")] {
+        let res = client.range(RangeRequest::new(key)).await?;
+        assert_eq!(res.kvs.len(), 1);
+        assert_eq!(res.kvs[0].value, value.as_bytes());
+    }
+    Ok(())
+}